@@ -0,0 +1,76 @@
+use crate::{Client, ClientBuilder, Result, RetryPolicy};
+
+use protocol::{Request, RpcError};
+
+use tokio::net::ToSocketAddrs;
+use tokio::runtime::{Builder, Runtime};
+
+/// A [`Client`] for callers that aren't async, e.g. a command line tool or
+/// an FFI boundary. Every call blocks the calling thread until its response
+/// is in.
+///
+/// The connection is driven by a current-thread runtime of its own, which
+/// only runs while a call is waiting on it, and is shared by every call.
+/// Any number of threads can call at once; the calls still go out
+/// multiplexed over the one connection.
+///
+/// None of its methods may be called from within an async context, as
+/// blocking there would stall the runtime; that includes dropping it.
+pub struct BlockingClient {
+    client: Client,
+    runtime: Runtime,
+}
+
+impl BlockingClient {
+    pub fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        Self::connect_with(Client::builder(), addr)
+    }
+
+    /// Like [`BlockingClient::connect`], but sets up the connection through
+    /// `builder`.
+    pub fn connect_with(builder: ClientBuilder, addr: impl ToSocketAddrs) -> Result<Self> {
+        let runtime = Builder::new_current_thread().enable_all().build()?;
+        let client = runtime.block_on(builder.connect(addr))?;
+        Ok(Self { client, runtime })
+    }
+
+    /// The async client underneath, for what this one doesn't offer.
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Sends `req` and waits for its response, see [`Client::call`].
+    pub fn call<Req: Request>(&self, req: Req) -> Result<Req::Resp> {
+        self.runtime.block_on(self.client.call(req))
+    }
+
+    /// See [`Client::call_with_retry`].
+    pub fn call_with_retry<Req: Request>(
+        &self,
+        req: Req,
+        policy: &RetryPolicy,
+    ) -> Result<Req::Resp> {
+        self.runtime
+            .block_on(self.client.call_with_retry(req, policy))
+    }
+
+    /// See [`Client::call_batch`].
+    pub fn call_batch<Req: Request>(
+        &self,
+        reqs: Vec<Req>,
+    ) -> Result<Vec<Result<Req::Resp, RpcError>>> {
+        self.runtime.block_on(self.client.call_batch(reqs))
+    }
+
+    /// Closes the connection once the calls made on it are answered, see
+    /// [`Client::close`]. Dropping the client does the same.
+    pub fn close(self) {
+        drop(self);
+    }
+}
+
+impl Drop for BlockingClient {
+    fn drop(&mut self) {
+        self.runtime.block_on(self.client.close());
+    }
+}
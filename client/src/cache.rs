@@ -0,0 +1,123 @@
+use protocol::codec::WireFormat;
+
+use bytes::Bytes;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Responses to idempotent calls, kept so [`Client::call_cached`] can answer
+/// a repeat of a call without sending it, see
+/// [`ClientBuilder::response_cache`].
+///
+/// Calls are told apart by their encoded request, so two calls only share a
+/// response if they are the same request with the same fields. Only requests
+/// marked [idempotent](protocol::Request::IDEMPOTENT) are kept, and only
+/// successful responses.
+///
+/// Holds at most `capacity` responses, dropping the least recently used one
+/// to make room, each for at most `ttl`. Clones share the same responses,
+/// also between clients.
+///
+/// [`Client::call_cached`]: crate::Client::call_cached
+/// [`ClientBuilder::response_cache`]: crate::ClientBuilder::response_cache
+#[derive(Debug, Clone)]
+pub struct ResponseCache {
+    inner: Arc<Mutex<Inner>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+type Key = (WireFormat, Vec<u8>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<Key, Entry>,
+    /// Every key, least recently used first.
+    by_use: BTreeMap<u64, Key>,
+    uses: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    used: u64,
+    /// The encoded response.
+    body: Bytes,
+    expires: Instant,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Arc::default(),
+            capacity: capacity.max(1),
+            ttl,
+        }
+    }
+
+    /// Number of responses held, including expired ones not yet dropped.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every response, so the next call of each is sent again.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.by_use.clear();
+    }
+
+    /// The response kept for `request`, encoded in `wire_format`, unless it
+    /// has expired.
+    pub(crate) fn get(&self, wire_format: WireFormat, request: &[u8]) -> Option<Bytes> {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        // Keyed by value, so the lookup has to build one.
+        let key = (wire_format, request.to_vec());
+        let entry = inner.entries.get_mut(&key)?;
+        if entry.expires <= Instant::now() {
+            inner.by_use.remove(&entry.used);
+            inner.entries.remove(&key);
+            return None;
+        }
+
+        inner.uses += 1;
+        inner.by_use.remove(&entry.used);
+        entry.used = inner.uses;
+        let body = entry.body.clone();
+        inner.by_use.insert(inner.uses, key);
+        Some(body)
+    }
+
+    /// Keeps `body` as the response to `request`, in place of any kept
+    /// before.
+    pub(crate) fn insert(&self, wire_format: WireFormat, request: Vec<u8>, body: Bytes) {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        let key = (wire_format, request);
+        if let Some(old) = inner.entries.remove(&key) {
+            inner.by_use.remove(&old.used);
+        }
+        while inner.entries.len() >= self.capacity {
+            let Some((_, oldest)) = inner.by_use.pop_first() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+        }
+
+        inner.uses += 1;
+        inner.by_use.insert(inner.uses, key.clone());
+        inner.entries.insert(
+            key,
+            Entry {
+                used: inner.uses,
+                body,
+                expires: Instant::now() + self.ttl,
+            },
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use protocol::codec::{CodecError, Limited, WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Header, encode_frame};
+use protocol::{Context, DecodeFailure, Request, RpcError, with_frame};
+
+use bytes::Bytes;
+use futures::FutureExt;
+use futures::future::BoxFuture;
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Answers the calls the server makes to the client, see
+/// [`ClientBuilder::callbacks`](crate::ClientBuilder::callbacks).
+#[derive(Clone)]
+pub(crate) struct CallbackHandlers(
+    Arc<dyn Fn(Limited<WireFormat>, Header, Bytes) -> Answer + Send + Sync>,
+);
+
+/// The frame answering a call, if it could be encoded at all.
+type Answer = BoxFuture<'static, Option<Bytes>>;
+
+impl fmt::Debug for CallbackHandlers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CallbackHandlers")
+    }
+}
+
+impl CallbackHandlers {
+    /// Handles calls with `Req`.
+    pub(crate) fn of<Req: Request>() -> Self {
+        Self(Arc::new(|codec, header, body| {
+            answer::<Req>(codec, header, body).boxed()
+        }))
+    }
+
+    /// Handles the call in `header` and `body`, encoding its answer with
+    /// `codec`.
+    pub(crate) fn answer(&self, codec: Limited<WireFormat>, header: Header, body: Bytes) -> Answer {
+        (self.0)(codec, header, body)
+    }
+}
+
+async fn answer<Req: Request>(
+    codec: Limited<WireFormat>,
+    header: Header,
+    body: Bytes,
+) -> Option<Bytes> {
+    let ctx = Context::default();
+    let resp = handle::<Req>(&codec.codec, &header, &body, ctx.clone()).await;
+
+    let error = |err: &RpcError| {
+        let reply = Header::new(header.id, FrameKind::Error).with_reverse(true);
+        encode_frame(&codec, &reply, err)
+    };
+    let frame = match resp {
+        Ok(resp) => {
+            let reply = Header::new(header.id, FrameKind::Response)
+                .with_trailers(ctx.take_trailers())
+                .with_reverse(true);
+            encode_frame(&codec, &reply, &resp).or_else(|e| match e {
+                CodecError::TooLarge { limit } => error(&RpcError::ResponseTooLarge {
+                    limit: limit as u64,
+                }),
+                e => error(&RpcError::Internal(format!(
+                    "failed to encode response: {e}"
+                ))),
+            })
+        }
+        Err(err) => error(&err),
+    };
+    frame.ok().map(Bytes::from)
+}
+
+/// Runs the handler for the call, turning it away like the server would if
+/// `Req` can't handle it.
+async fn handle<Req: Request>(
+    codec: &WireFormat,
+    header: &Header,
+    body: &Bytes,
+    ctx: Context,
+) -> Result<Req::Resp, RpcError> {
+    if let Some(method) = &header.method {
+        let supported = Req::versions(&method.name);
+        if !supported.is_empty() && !supported.contains(&method.version) {
+            return Err(RpcError::VersionMismatch {
+                name: method.name.clone(),
+                requested: method.version,
+                supported,
+            });
+        }
+    }
+    let req: Req =
+        with_frame(body, || codec.decode(body)).map_err(
+            |e| match DecodeFailure::from_codec_error(&e) {
+                Some(failure) => RpcError::Decode(failure),
+                None => RpcError::Internal(e.to_string()),
+            },
+        )?;
+
+    req.authorize(&ctx).await?;
+    req.validate().await?;
+    let (name, handler) = req.into_handler(ctx);
+    // Running it as its own task keeps a panic from taking down the
+    // connection.
+    tokio::spawn(handler)
+        .await
+        .map_err(|_| RpcError::Internal(format!("handler for {name} panicked")))
+}
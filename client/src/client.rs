@@ -0,0 +1,1691 @@
+use crate::cache::ResponseCache;
+use crate::callbacks::CallbackHandlers;
+use crate::request_id::new_request_id;
+use crate::retry::{FailedRequests, with_retry};
+use crate::{Error, FailedRequest, Result, RetryPolicy};
+
+use protocol::codec::{CodecError, Limited, WireCodec, WireFormat};
+use protocol::compression::{Compression, Compressions, Upgrade};
+use protocol::frame::{
+    FrameKind, Framing, GoAway, GoAwayReason, Header, TraceContext, Trailers, decode_header,
+    encode_frame, encode_raw_frame,
+};
+use protocol::hello::{CALLBACKS_FEATURE, FEATURES, Hello, HelloAck, UPGRADE_FEATURE};
+use protocol::info::{CONN_STATS_METHOD, ConnStats, Health, INFO_METHOD, PING_METHOD, ServerInfo};
+use protocol::schema::{RpcSchema, SCHEMA_METHOD};
+use protocol::{Request, RpcError, with_frame};
+
+use bincode::{Decode, Encode};
+use futures::stream::BoxStream;
+use futures::{SinkExt, Stream, StreamExt};
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpStream, ToSocketAddrs};
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio_util::codec::{Decoder, Encoder, Framed, LengthDelimitedCodec};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use bytes::{Bytes, BytesMut};
+use socket2::{SockRef, TcpKeepalive};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, ready};
+use std::time::{Duration, Instant};
+
+const CALL_QUEUE_CAPACITY: usize = 64;
+
+const DEFAULT_CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Chunks buffered for a [`ChunkedReader`] before the connection stops
+/// reading.
+const CHUNK_QUEUE_CAPACITY: usize = 16;
+
+/// Events buffered for an [`EventStream`] before the connection stops
+/// reading.
+const EVENT_QUEUE_CAPACITY: usize = 64;
+
+/// Partial results buffered for a [`PartialResults`] before the connection
+/// stops reading.
+const PARTIAL_QUEUE_CAPACITY: usize = 64;
+
+struct Call {
+    id: u64,
+    frame: Bytes,
+    /// `None` for frames going on with a call already sent, like the pieces
+    /// of an upload.
+    reply: Option<Pending>,
+    /// What the connection switches to once the server has answered, for
+    /// the calls of [`Client::upgrade`].
+    upgrade: Option<Arc<dyn Compression>>,
+}
+
+/// Where the response to a call goes.
+enum Pending {
+    Unary(oneshot::Sender<Reply>),
+    Chunked(mpsc::Sender<Chunk>),
+    /// Waiting for the server to start the subscription, see
+    /// [`FrameKind::Event`].
+    Subscribing {
+        started: oneshot::Sender<Reply>,
+        events: mpsc::Sender<Chunk>,
+    },
+    Subscribed(mpsc::Sender<Chunk>),
+    /// See [`FrameKind::Partial`].
+    WithPartials {
+        partials: mpsc::Sender<Chunk>,
+        reply: oneshot::Sender<Reply>,
+    },
+}
+
+/// The next piece of a chunked response or event of a subscription, or
+/// `None` once it has ended.
+type Chunk = Result<Option<Bytes>>;
+
+struct Reply {
+    kind: FrameKind,
+    body: Bytes,
+    trailers: Trailers,
+}
+
+/// What the server has announced about a connection, see
+/// [`Client::state_changes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// Calls are sent and answered.
+    Open,
+    /// The server is shutting down: it reads no more calls, but still
+    /// answers those it has read. Further calls fail with
+    /// [`Error::ServerShuttingDown`], so they can go to another server.
+    Draining,
+    /// The server reads no more calls on this connection, see [`GoAway`].
+    GoingAway(GoAwayReason),
+    /// The connection is gone.
+    Closed,
+}
+
+/// A handle to a single server connection.
+///
+/// Calls are multiplexed: any number of them can be outstanding at once, and
+/// every response is routed back to its caller by the id in its header. The
+/// handle is cheap to clone; all clones share the same connection.
+#[derive(Clone)]
+pub struct Client {
+    calls: mpsc::Sender<Call>,
+    cancels: mpsc::UnboundedSender<u64>,
+    going_away: Arc<OnceLock<GoAwayReason>>,
+    state: watch::Receiver<ConnectionState>,
+    in_flight: Arc<AtomicUsize>,
+    next_id: Arc<AtomicU64>,
+    wire_format: WireFormat,
+    max_frame_bytes: usize,
+    features: Arc<[String]>,
+    trace: Option<TraceSource>,
+    cache: Option<ResponseCache>,
+    failed_requests: Option<FailedRequests>,
+    compressions: Compressions,
+    /// Sent along with every request, see [`Client::namespaced`].
+    namespace: Option<String>,
+    /// Cancelled by [`Client::close`].
+    closing: CancellationToken,
+    /// Cancelled once the connection is gone.
+    finished: CancellationToken,
+}
+
+/// Where requests get their [`TraceContext`] from, see
+/// [`ClientBuilder::trace_context`].
+#[derive(Clone)]
+struct TraceSource(Arc<dyn Fn() -> Option<TraceContext> + Send + Sync>);
+
+impl fmt::Debug for TraceSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TraceSource")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    wire_formats: Vec<WireFormat>,
+    framing: Framing,
+    nodelay: bool,
+    keepalive: Option<Duration>,
+    hello_timeout: Option<Duration>,
+    close_timeout: Duration,
+    sequence_numbers: bool,
+    trace: Option<TraceSource>,
+    cache: Option<ResponseCache>,
+    callbacks: Option<CallbackHandlers>,
+    failed_requests: Option<FailedRequests>,
+    compressions: Compressions,
+    protocol_versions: Option<RangeInclusive<u32>>,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            wire_formats: vec![WireFormat::FALLBACK],
+            framing: Framing::default(),
+            nodelay: true,
+            keepalive: None,
+            hello_timeout: None,
+            close_timeout: DEFAULT_CLOSE_TIMEOUT,
+            sequence_numbers: false,
+            trace: None,
+            cache: None,
+            callbacks: None,
+            failed_requests: None,
+            compressions: Compressions::default(),
+            protocol_versions: None,
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Encodings to offer the server, most preferred first. The server falls
+    /// back to [`WireFormat::FALLBACK`] if it accepts none of them.
+    pub fn wire_formats(mut self, wire_formats: impl IntoIterator<Item = WireFormat>) -> Self {
+        self.wire_formats = wire_formats.into_iter().collect();
+        self
+    }
+
+    /// Versions of the protocol the client may talk to a server in, see
+    /// [`PROTOCOL_VERSION`](protocol::hello::PROTOCOL_VERSION). The handshake fails with an
+    /// [`Error::IncompatibleVersion`] with a server speaking any other,
+    /// rather than the calls failing in stranger ways later. Any by default.
+    pub fn protocol_versions(mut self, supported: RangeInclusive<u32>) -> Self {
+        self.protocol_versions = Some(supported);
+        self
+    }
+
+    /// Width in bytes of the length prefix on every frame: 2, 3, 4 or 8.
+    /// Has to match the server. Defaults to 4.
+    pub fn length_field_length(mut self, length_field_length: usize) -> Self {
+        self.framing.length_field_length = length_field_length;
+        self
+    }
+
+    /// Whether the length prefix on every frame is big-endian, rather than
+    /// little-endian. Has to match the server. Defaults to `true`.
+    pub fn length_field_is_big_endian(mut self, big_endian: bool) -> Self {
+        self.framing.length_field_big_endian = big_endian;
+        self
+    }
+
+    /// Largest frame accepted from the server. Has to fit the length field.
+    /// Defaults to 8 MiB.
+    pub fn max_frame_bytes(mut self, max_frame_bytes: usize) -> Self {
+        self.framing.max_frame_bytes = max_frame_bytes;
+        self
+    }
+
+    /// How large responses are expected to be, so the buffer they are read
+    /// into starts out large enough for one rather than growing into it.
+    /// Only a hint, limiting nothing. Defaults to 8 KiB.
+    pub fn expected_frame_bytes(mut self, expected_frame_bytes: usize) -> Self {
+        self.framing.expected_frame_bytes = expected_frame_bytes;
+        self
+    }
+
+    /// Sets `TCP_NODELAY` on the connection. On by default.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Enables TCP keepalive, probing after the connection has been idle for
+    /// `idle`. Off by default.
+    pub fn keepalive(mut self, idle: Option<Duration>) -> Self {
+        self.keepalive = idle;
+        self
+    }
+
+    /// How long to wait for the server to answer the opening handshake.
+    /// Without an answer by then the connection goes on with the defaults:
+    /// [`WireFormat::FALLBACK`], this side's frame limit and no optional
+    /// features. Waits indefinitely by default.
+    pub fn hello_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.hello_timeout = timeout;
+        self
+    }
+
+    /// How long [`Client::close`] waits for the calls still in flight before
+    /// failing them with [`Error::Closed`]. Defaults to 10 seconds.
+    pub fn close_timeout(mut self, timeout: Duration) -> Self {
+        self.close_timeout = timeout;
+        self
+    }
+
+    /// Numbers every frame sent, see
+    /// [`Header::seq`](protocol::frame::Header::seq), so the server closes
+    /// the connection as soon as a frame goes missing or is misread, rather
+    /// than answering whatever it took the garbage for. Off by default.
+    pub fn sequence_numbers(mut self, enabled: bool) -> Self {
+        self.sequence_numbers = enabled;
+        self
+    }
+
+    /// Asks `current` for the trace each request is sent from, to tell the
+    /// server so its span can be linked to the caller's. With
+    /// `tracing-opentelemetry` that is the context of the current span, as
+    /// `otel::current_trace` returns with the `otel` feature.
+    pub fn trace_context(
+        mut self,
+        current: impl Fn() -> Option<TraceContext> + Send + Sync + 'static,
+    ) -> Self {
+        self.trace = Some(TraceSource(Arc::new(current)));
+        self
+    }
+
+    /// Hands every call made with [`Client::call_with_retry`] or
+    /// [`ClientPool::call_with_retry`](crate::ClientPool::call_with_retry)
+    /// that fails for good, having run out of attempts or failed in a way
+    /// not worth retrying, to `hook`, e.g. to keep a dead letter log of them.
+    pub fn on_failed_request(
+        mut self,
+        hook: impl Fn(&FailedRequest<'_>) + Send + Sync + 'static,
+    ) -> Self {
+        self.failed_requests = Some(FailedRequests::new(hook));
+        self
+    }
+
+    pub(crate) fn failed_requests(&self) -> Option<&FailedRequests> {
+        self.failed_requests.as_ref()
+    }
+
+    pub(crate) fn framing(&self) -> Framing {
+        self.framing
+    }
+
+    /// Lets [`Client::upgrade`] switch connections over to `compression`, by
+    /// its [`name`](Compression::name), and servers compress the bodies of
+    /// large frames with it, see
+    /// [`Header::compressed`](protocol::frame::Header::compressed). Call it
+    /// once for every compression to use.
+    pub fn compression(mut self, compression: impl Compression) -> Self {
+        self.compressions.insert(compression);
+        self
+    }
+
+    /// Keeps the responses to [`Client::call_cached`] in `cache`, to answer
+    /// repeats of the same call from. Give several clients of the same
+    /// service clones of the same cache to share it between them. Off by default, which has
+    /// `call_cached` send every call.
+    pub fn response_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Answers the calls the server makes to this client, see
+    /// [`Callbacks`](protocol::Callbacks), with `Req`, e.g. the enum
+    /// generated by `#[rpc]` for them. Their handlers get a
+    /// [`Context`](protocol::Context) of their own, and only plain responses
+    /// go back; chunked ones and subscriptions fail. Without it, the server
+    /// is told the client answers none and makes no calls.
+    pub fn callbacks<Req: Request>(mut self) -> Self {
+        self.callbacks = Some(CallbackHandlers::of::<Req>());
+        self
+    }
+
+    pub async fn connect(self, addr: impl ToSocketAddrs) -> Result<Client> {
+        self.framing.validate()?;
+        let stream = TcpStream::connect(addr).await?;
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(idle) = self.keepalive {
+            SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+        }
+        self.handshake(stream).await
+    }
+
+    /// Agrees on the connection's settings over an already established
+    /// transport, see [`protocol::hello`], and spawns the task driving it.
+    pub async fn handshake(
+        self,
+        socket: impl AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    ) -> Result<Client> {
+        self.framing.validate()?;
+        let mut framed = Framed::with_capacity(
+            socket,
+            HandshakeCodec {
+                codec: self.framing.codec(),
+                framing: self.framing,
+            },
+            self.framing.read_buffer_capacity(),
+        );
+
+        let max_frame_bytes = self.framing.max_frame_bytes;
+        let hello = Hello {
+            wire_formats: self.wire_formats.iter().map(|f| f.id()).collect(),
+            max_frame_bytes: max_frame_bytes as u64,
+            features: FEATURES
+                .iter()
+                .filter(|&&f| f != CALLBACKS_FEATURE || self.callbacks.is_some())
+                .map(|&f| f.to_owned())
+                .collect(),
+            compressions: {
+                let mut names: Vec<_> = self.compressions.names().map(str::to_owned).collect();
+                names.sort_unstable();
+                names
+            },
+            ..Hello::default()
+        };
+        let hello = self.framing.with_handshake_magic(&hello.encode());
+        framed.send(Bytes::from(hello)).await?;
+
+        let answer = framed.next();
+        let answer = match self.hello_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, answer).await.ok(),
+            None => Some(answer.await),
+        };
+        let ack = match answer {
+            Some(answer) => {
+                let answer = answer.ok_or(Error::Closed)??;
+                match (HelloAck::decode(&answer), &*answer) {
+                    (Some(ack), _) => ack,
+                    // A server predating `Hello` answers with just the format.
+                    (None, &[id]) => HelloAck {
+                        wire_format: id,
+                        ..HelloAck::default()
+                    },
+                    (None, _) => return Err(Error::Handshake),
+                }
+            }
+            None => HelloAck::default(),
+        };
+        if let Some(supported) = self.protocol_versions
+            && !supported.contains(&ack.protocol_version)
+        {
+            return Err(Error::IncompatibleVersion {
+                server: ack.protocol_version,
+                supported,
+            });
+        }
+        let wire_format = ack.wire_format().ok_or(Error::Handshake)?;
+        let max_frame_bytes = usize::try_from(ack.max_frame_bytes)
+            .unwrap_or(usize::MAX)
+            .min(max_frame_bytes);
+
+        let (calls, rx) = mpsc::channel(CALL_QUEUE_CAPACITY);
+        let (cancels, cancelled) = mpsc::unbounded_channel();
+        let going_away = Arc::new(OnceLock::new());
+        let (state_tx, state) = watch::channel(ConnectionState::Open);
+        let closing = CancellationToken::new();
+        let finished = CancellationToken::new();
+        tokio::spawn(drive(
+            framed.map_codec(|handshake| handshake.codec),
+            wire_format,
+            rx,
+            cancelled,
+            going_away.clone(),
+            Closing {
+                requested: closing.clone(),
+                timeout: self.close_timeout,
+                finished: finished.clone(),
+            },
+            Driving {
+                sequence_numbers: self.sequence_numbers,
+                callbacks: self
+                    .callbacks
+                    .filter(|_| ack.has_feature(CALLBACKS_FEATURE)),
+                answer_codec: Limited {
+                    codec: wire_format,
+                    limit: max_frame_bytes,
+                },
+                body_compression: ack
+                    .compression
+                    .as_ref()
+                    .and_then(|name| self.compressions.get(name).cloned()),
+                state: state_tx,
+            },
+        ));
+
+        Ok(Client {
+            calls,
+            cancels,
+            going_away,
+            state,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            next_id: Arc::new(AtomicU64::new(1)),
+            wire_format,
+            max_frame_bytes,
+            features: ack.features.into(),
+            trace: self.trace,
+            cache: self.cache,
+            failed_requests: self.failed_requests,
+            compressions: self.compressions,
+            namespace: None,
+            closing,
+            finished,
+        })
+    }
+}
+
+impl Client {
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::default()
+    }
+
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self> {
+        Self::builder().connect(addr).await
+    }
+
+    /// A handle to the same connection whose requests go to the ones the
+    /// server mounted under `namespace`, e.g. with `Router::mount` on a
+    /// server hosting several services. Clones of it keep the namespace.
+    pub fn namespaced(&self, namespace: impl Into<String>) -> Self {
+        Self {
+            namespace: Some(namespace.into()),
+            ..self.clone()
+        }
+    }
+
+    /// Encoding agreed on with the server for this connection.
+    pub fn wire_format(&self) -> WireFormat {
+        self.wire_format
+    }
+
+    /// Optional features both this client and the server understand, as
+    /// named in [`protocol::hello::FEATURES`]. Empty for a server predating
+    /// the [`Hello`] exchange.
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    /// Largest frame either side sends on this connection.
+    pub fn max_frame_bytes(&self) -> usize {
+        self.max_frame_bytes
+    }
+
+    /// Sends `req` and waits for its response.
+    ///
+    /// Dropping the returned future before it completes tells the server the
+    /// response is no longer wanted, so a handler watching its
+    /// [`Context`](protocol::Context) can stop early.
+    pub async fn call<Req: Request>(&self, req: Req) -> Result<Req::Resp> {
+        self.call_ref(&req).await
+    }
+
+    pub(crate) async fn call_ref<Req: Request>(&self, req: &Req) -> Result<Req::Resp> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::request(id, req.name(), req.version());
+        self.round_trip(header, req).await
+    }
+
+    /// Like [`Client::call`], but answers an [idempotent](Request::IDEMPOTENT)
+    /// `req` from the client's [response cache](ClientBuilder::response_cache)
+    /// if it has a response to the same request, with the same fields, that
+    /// hasn't expired yet, without sending it. Otherwise the response is
+    /// kept there for next time, unless it is an error.
+    pub async fn call_cached<Req: Request>(&self, req: Req) -> Result<Req::Resp> {
+        let Some(cache) = self.cache.as_ref().filter(|_| req.idempotent()) else {
+            return self.call(req).await;
+        };
+        let key = self.wire_format.encode(&req)?;
+        if let Some(resp) = cache.get(self.wire_format, &key) {
+            return Ok(with_frame(&resp, || self.wire_format.decode(&resp))?);
+        }
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::request(id, req.name(), req.version());
+        let decode = |resp: Bytes| {
+            let decoded = with_frame(&resp, || self.wire_format.decode(&resp))?;
+            Ok((decoded, resp))
+        };
+        let ((decoded, resp), _) = self.round_trip_encoded(header, &req, None, decode).await?;
+        cache.insert(self.wire_format, key, resp);
+        Ok(decoded)
+    }
+
+    /// Like [`Client::call`], but streams everything `upload` yields to the
+    /// handler after `req`, which reads it as an [`Upload`](protocol::Upload),
+    /// e.g. a dataset too large for a single frame. Pieces larger than a
+    /// frame allows are split further.
+    ///
+    /// The server may answer before the upload is done, e.g. with an error,
+    /// which stops it there. Dropping the returned future abandons the
+    /// upload along with the call, cancelling the handler's context.
+    pub async fn call_upload<Req: Request>(
+        &self,
+        req: Req,
+        upload: impl Stream<Item = Bytes> + Send,
+    ) -> Result<Req::Resp> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut header = Header::request(id, req.name(), req.version());
+        header.kind = FrameKind::Upload;
+        let decode = |resp: Bytes| with_frame(&resp, || self.wire_format.decode(&resp));
+        let (resp, _) = self
+            .round_trip_encoded(header, &req, Some(upload.boxed()), decode)
+            .await?;
+        Ok(resp)
+    }
+
+    /// Like [`Client::call`], but sends `key` along, so a server with an
+    /// idempotency cache answers a repeat of the call, made with the same
+    /// key, with the response to the first rather than handling it again.
+    /// Only requests marked [idempotent](Request::IDEMPOTENT) are answered
+    /// that way.
+    pub async fn call_with_idempotency_key<Req: Request>(
+        &self,
+        req: Req,
+        key: impl Into<String>,
+    ) -> Result<Req::Resp> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header =
+            Header::request(id, req.name(), req.version()).with_idempotency_key(Some(key.into()));
+        self.round_trip(header, &req).await
+    }
+
+    /// Like [`Client::call`], but also returns the trailers the handler set
+    /// with [`Context::set_trailer`](protocol::Context::set_trailer), empty
+    /// if it set none.
+    pub async fn call_with_trailers<Req: Request>(
+        &self,
+        req: Req,
+    ) -> Result<(Req::Resp, Trailers)> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::request(id, req.name(), req.version());
+        self.round_trip_with_trailers(header, &req).await
+    }
+
+    /// Like [`Client::call`], but gives the server `timeout` to answer in.
+    /// The handler can check what is left of it with
+    /// [`Context::deadline_remaining`](protocol::Context::deadline_remaining),
+    /// and is aborted if it runs past it, failing the call with an
+    /// [`RpcError::DeadlineExceeded`]. The call fails the same way if no
+    /// answer has come by then, e.g. from a server that predates deadlines.
+    pub async fn call_with_deadline<Req: Request>(
+        &self,
+        req: Req,
+        timeout: Duration,
+    ) -> Result<Req::Resp> {
+        self.call_ref_with_deadline(&req, timeout).await
+    }
+
+    pub(crate) async fn call_ref_with_deadline<Req: Request>(
+        &self,
+        req: &Req,
+        timeout: Duration,
+    ) -> Result<Req::Resp> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let deadline_ms = u64::try_from(timeout.as_millis()).unwrap_or(u64::MAX);
+        let header =
+            Header::request(id, req.name(), req.version()).with_deadline_ms(Some(deadline_ms));
+        tokio::time::timeout(timeout, self.round_trip(header, req))
+            .await
+            .unwrap_or_else(|_| {
+                Err(Error::Rpc(RpcError::DeadlineExceeded(
+                    req.name().to_owned(),
+                )))
+            })
+    }
+
+    /// Like [`Client::call`], but asks for `req` to be handled at
+    /// `priority`, higher sooner, by a server that schedules requests by
+    /// priority. The server may cap it, and others ignore it.
+    pub async fn call_with_priority<Req: Request>(
+        &self,
+        req: Req,
+        priority: u8,
+    ) -> Result<Req::Resp> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::request(id, req.name(), req.version()).with_priority(Some(priority));
+        self.round_trip(header, &req).await
+    }
+
+    /// Like [`Client::call`], but tries an idempotent `req` again, as
+    /// `policy` allows, when the server is too busy for it. Connection
+    /// failures aren't retried, as this connection won't come back;
+    /// [`ClientPool::call_with_retry`](crate::ClientPool::call_with_retry)
+    /// moves on to a fresh one.
+    pub async fn call_with_retry<Req: Request>(
+        &self,
+        req: Req,
+        policy: &RetryPolicy,
+    ) -> Result<Req::Resp> {
+        let retryable = |_: &Error| !self.is_closed();
+        let failed = self.failed_requests.as_ref();
+        with_retry(policy, &req, retryable, failed, || self.call_ref(&req)).await
+    }
+
+    /// Sends `reqs` in a single frame and waits for all of their responses.
+    ///
+    /// The server may handle the requests concurrently, but the responses come
+    /// back in request order. A failing request only fails its own entry.
+    pub async fn call_batch<Req: Request>(
+        &self,
+        reqs: Vec<Req>,
+    ) -> Result<Vec<Result<Req::Resp, RpcError>>> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::new(id, FrameKind::Batch);
+        self.round_trip(header, &reqs).await
+    }
+
+    /// Asks the server to describe every request it understands.
+    pub async fn schema(&self) -> Result<RpcSchema> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::request(id, SCHEMA_METHOD, 1);
+        self.round_trip(header, &()).await
+    }
+
+    /// Asks the server for its version, uptime and load. Servers answer this
+    /// ahead of other requests unless configured not to.
+    pub async fn server_info(&self) -> Result<ServerInfo> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::request(id, INFO_METHOD, 1);
+        self.round_trip(header, &()).await
+    }
+
+    /// Asks the server how much has gone over this connection so far:
+    /// bytes each way, requests answered and still running, and for how
+    /// long. Answered like [`Client::server_info`].
+    pub async fn conn_stats(&self) -> Result<ConnStats> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::request(id, CONN_STATS_METHOD, 1);
+        self.round_trip(header, &()).await
+    }
+
+    /// Checks the server is alive, returning how long it took to answer.
+    /// Servers answer this ahead of other requests, whatever they handle.
+    pub async fn ping(&self) -> Result<Duration> {
+        let started = Instant::now();
+        self.health().await?;
+        Ok(started.elapsed())
+    }
+
+    /// Asks the server whether it is ready for requests, as well as alive.
+    /// Answered like [`Client::ping`].
+    pub async fn health(&self) -> Result<Health> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::request(id, PING_METHOD, 1);
+        self.round_trip(header, &()).await
+    }
+
+    /// Sends the request in `header` and `body`, from within the current
+    /// trace, and waits for its response.
+    async fn round_trip<T, R>(&self, header: Header, body: &T) -> Result<R>
+    where
+        T: Encode + Serialize,
+        R: Decode<()> + DeserializeOwned,
+    {
+        let (resp, _) = self.round_trip_with_trailers(header, body).await?;
+        Ok(resp)
+    }
+
+    /// Logs the call under a fresh request id, which the server logs it
+    /// under as well.
+    async fn round_trip_with_trailers<T, R>(
+        &self,
+        header: Header,
+        body: &T,
+    ) -> Result<(R, Trailers)>
+    where
+        T: Encode + Serialize,
+        R: Decode<()> + DeserializeOwned,
+    {
+        let decode = |resp: Bytes| with_frame(&resp, || self.wire_format.decode(&resp));
+        self.round_trip_encoded(header, body, None, decode).await
+    }
+
+    /// Like [`Client::round_trip_with_trailers`], but hands the encoded
+    /// response to `decode`, and sends `upload` after the request.
+    async fn round_trip_encoded<T, R>(
+        &self,
+        header: Header,
+        body: &T,
+        upload: Option<BoxStream<'_, Bytes>>,
+        decode: impl FnOnce(Bytes) -> Result<R, CodecError>,
+    ) -> Result<(R, Trailers)>
+    where
+        T: Encode + Serialize,
+    {
+        let request_id = new_request_id();
+        let header = header
+            .with_trace(self.current_trace())
+            .with_request_id(Some(request_id.clone()))
+            .with_namespace(self.namespace.clone());
+        debug!(%request_id, request.name = request_name(&header), "sending request");
+        self.exchange(header, body, upload)
+            .await
+            .and_then(|(resp, trailers)| Ok((decode(resp)?, trailers)))
+            .inspect_err(|e| debug!(%request_id, %e, "call failed"))
+    }
+
+    /// Sends the request, and `upload` after it, and waits for the encoded
+    /// response.
+    async fn exchange<T>(
+        &self,
+        header: Header,
+        body: &T,
+        upload: Option<BoxStream<'_, Bytes>>,
+    ) -> Result<(Bytes, Trailers)>
+    where
+        T: Encode + Serialize,
+    {
+        let frame = encode_frame(&self.wire_format, &header, body)?;
+        if frame.len() > self.max_frame_bytes {
+            return Err(Error::FrameTooLarge {
+                len: frame.len(),
+                max: self.max_frame_bytes,
+            });
+        }
+
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        let _guard = InFlightGuard(&self.in_flight);
+
+        let mut cancel = CancelOnDrop {
+            id: header.id,
+            cancels: &self.cancels,
+            armed: true,
+        };
+
+        let (reply, mut rx) = oneshot::channel();
+        let call = Call {
+            id: header.id,
+            frame: frame.into(),
+            reply: Some(Pending::Unary(reply)),
+            upgrade: None,
+        };
+        self.send(call).await?;
+        let reply = match upload {
+            None => rx.await,
+            // The server may answer before the upload is done, e.g. with an
+            // error, which ends it.
+            Some(upload) => {
+                let mut sending = std::pin::pin!(self.send_upload(header.id, upload));
+                tokio::select! {
+                    reply = &mut rx => reply,
+                    sent = &mut sending => {
+                        sent?;
+                        rx.await
+                    }
+                }
+            }
+        };
+        cancel.armed = false;
+        let Reply {
+            kind,
+            body,
+            trailers,
+        } = reply.map_err(|_| self.closed())?;
+
+        match kind {
+            FrameKind::Response => Ok((body, trailers)),
+            FrameKind::Error => Err(Error::Rpc(self.wire_format.decode::<RpcError>(&body)?)),
+            FrameKind::Request
+            | FrameKind::VersionedRequest
+            | FrameKind::Batch
+            | FrameKind::Cancel
+            | FrameKind::GoAway
+            | FrameKind::Chunk
+            | FrameKind::ChunkEnd
+            | FrameKind::Event
+            | FrameKind::SubscriptionEnd
+            | FrameKind::Upload
+            | FrameKind::UploadChunk
+            | FrameKind::UploadEnd
+            | FrameKind::Upgrade
+            | FrameKind::Partial
+            | FrameKind::Draining => Err(Error::UnexpectedFrame(kind)),
+        }
+    }
+
+    /// Sends every piece of `upload` as the upload for call `id`, split to
+    /// fit the frame limit, and then ends it.
+    async fn send_upload(&self, id: u64, mut upload: BoxStream<'_, Bytes>) -> Result<()> {
+        let chunk_header = Header::new(id, FrameKind::UploadChunk);
+        let overhead = encode_raw_frame(&self.wire_format, &chunk_header, &[])?.len();
+        let max_chunk = self.max_frame_bytes.saturating_sub(overhead).max(1);
+
+        while let Some(mut chunk) = upload.next().await {
+            while !chunk.is_empty() {
+                let piece = chunk.split_to(max_chunk.min(chunk.len()));
+                let frame = encode_raw_frame(&self.wire_format, &chunk_header, &piece)?;
+                self.send(Call {
+                    id,
+                    frame: frame.into(),
+                    reply: None,
+                    upgrade: None,
+                })
+                .await?;
+            }
+        }
+
+        let frame = encode_frame(
+            &self.wire_format,
+            &Header::new(id, FrameKind::UploadEnd),
+            &(),
+        )?;
+        self.send(Call {
+            id,
+            frame: frame.into(),
+            reply: None,
+            upgrade: None,
+        })
+        .await
+    }
+
+    /// Sends `req`, whose handler answers with a
+    /// [`ChunkedResponse`](protocol::ChunkedResponse), and returns a reader
+    /// over the response as it comes in.
+    ///
+    /// Only a few chunks are buffered: while the reader isn't read from, no
+    /// other response on this connection is read either. Dropping the reader
+    /// before the end cancels the call.
+    pub async fn call_chunked<Req: Request>(&self, req: Req) -> Result<ChunkedReader> {
+        let (chunks, rx) = mpsc::channel(CHUNK_QUEUE_CAPACITY);
+        let id = self.start_stream(req, Pending::Chunked(chunks)).await?;
+        Ok(ChunkedReader {
+            id,
+            chunks: rx,
+            chunk: Bytes::new(),
+            ended: false,
+            cancels: self.cancels.clone(),
+            in_flight: self.in_flight.clone(),
+        })
+    }
+
+    /// Sends `req`, whose handler answers with a
+    /// [`Subscription`](protocol::Subscription) to events of type `T`, and
+    /// returns a stream of those events as the server sends them.
+    ///
+    /// Returns once the server has started the subscription, so every event
+    /// from then on is in the stream. The stream ends once the server has no
+    /// more events for it. Events not yet taken from the stream are
+    /// buffered, but only a few dozen: past those, no other response on this
+    /// connection is read either. Dropping the stream cancels the
+    /// subscription.
+    pub async fn subscribe<T, Req: Request>(&self, req: Req) -> Result<EventStream<T>> {
+        let (started, rx_started) = oneshot::channel();
+        let (events, rx) = mpsc::channel(EVENT_QUEUE_CAPACITY);
+        let id = self
+            .start_stream(req, Pending::Subscribing { started, events })
+            .await?;
+        // Made right away, so a subscription given up on before it started
+        // is cancelled as well.
+        let stream = EventStream {
+            id,
+            events: rx,
+            ended: false,
+            wire_format: self.wire_format,
+            going_away: self.going_away.clone(),
+            cancels: self.cancels.clone(),
+            in_flight: self.in_flight.clone(),
+            event: PhantomData,
+        };
+
+        let Reply { kind, body, .. } = rx_started.await.map_err(|_| self.closed())?;
+        match kind {
+            FrameKind::Response => Ok(stream),
+            FrameKind::Error => Err(Error::Rpc(self.wire_format.decode::<RpcError>(&body)?)),
+            kind => Err(Error::UnexpectedFrame(kind)),
+        }
+    }
+
+    /// Sends `req`, whose handler sends partial results of type `P` as it
+    /// goes, see [`Context::send_partial`](protocol::Context::send_partial),
+    /// and returns them as they arrive, followed by its response.
+    ///
+    /// Partial results not yet taken are buffered, but only a few dozen:
+    /// past those, no other response on this connection is read either.
+    /// Dropping the handle before the response has arrived cancels the call.
+    /// Servers predating partial results send none, but still answer.
+    pub async fn call_with_partials<P, Req: Request>(
+        &self,
+        req: Req,
+    ) -> Result<PartialResults<P, Req::Resp>> {
+        let (partials, rx) = mpsc::channel(PARTIAL_QUEUE_CAPACITY);
+        let (reply, rx_reply) = oneshot::channel();
+        let id = self
+            .start_stream(req, Pending::WithPartials { partials, reply })
+            .await?;
+        Ok(PartialResults {
+            id,
+            partials: rx,
+            reply: rx_reply,
+            ended: false,
+            wire_format: self.wire_format,
+            going_away: self.going_away.clone(),
+            cancels: self.cancels.clone(),
+            in_flight: self.in_flight.clone(),
+            types: PhantomData,
+        })
+    }
+
+    /// Sends `req`, whose response is streamed to `reply`, and returns its
+    /// id. The call counts as in flight until the stream is dropped.
+    async fn start_stream<Req: Request>(&self, req: Req, reply: Pending) -> Result<u64> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let request_id = new_request_id();
+        let header = Header::request(id, req.name(), req.version())
+            .with_trace(self.current_trace())
+            .with_request_id(Some(request_id.clone()))
+            .with_namespace(self.namespace.clone());
+        debug!(%request_id, request.name = req.name(), "sending request");
+        let frame = encode_frame(&self.wire_format, &header, &req)?;
+        if frame.len() > self.max_frame_bytes {
+            return Err(Error::FrameTooLarge {
+                len: frame.len(),
+                max: self.max_frame_bytes,
+            });
+        }
+
+        let call = Call {
+            id,
+            frame: frame.into(),
+            reply: Some(reply),
+            upgrade: None,
+        };
+        self.send(call).await?;
+
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        Ok(id)
+    }
+
+    /// Switches the connection over to the compression registered as `name`
+    /// with [`ClientBuilder::compression`], on both ends, see
+    /// [`protocol::compression`]. Nothing else is sent until the server has
+    /// answered, so calls made meanwhile wait their turn.
+    ///
+    /// Fails with [`Error::UpgradeUnsupported`] if the server doesn't take
+    /// upgrades or the compression isn't registered here, and with
+    /// [`Error::Rpc`] if the server doesn't know it; the connection goes on
+    /// as it was either way.
+    pub async fn upgrade(&self, name: &str) -> Result<()> {
+        let compression = self
+            .compressions
+            .get(name)
+            .filter(|_| self.features.iter().any(|f| f == UPGRADE_FEATURE))
+            .ok_or_else(|| Error::UpgradeUnsupported(name.to_owned()))?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let upgrade = Upgrade {
+            compression: name.to_owned(),
+        };
+        let frame = encode_frame(
+            &self.wire_format,
+            &Header::new(id, FrameKind::Upgrade),
+            &upgrade,
+        )?;
+
+        let (reply, rx) = oneshot::channel();
+        self.send(Call {
+            id,
+            frame: frame.into(),
+            reply: Some(Pending::Unary(reply)),
+            upgrade: Some(compression.clone()),
+        })
+        .await?;
+        let Reply { kind, body, .. } = rx.await.map_err(|_| self.closed())?;
+        match kind {
+            FrameKind::Upgrade => Ok(()),
+            FrameKind::Error => Err(Error::Rpc(self.wire_format.decode::<RpcError>(&body)?)),
+            kind => Err(Error::UnexpectedFrame(kind)),
+        }
+    }
+
+    /// Hands `call` to the task driving the connection, unless the client
+    /// is closing.
+    async fn send(&self, call: Call) -> Result<()> {
+        if self.closing.is_cancelled() {
+            return Err(Error::Closed);
+        }
+        self.calls.send(call).await.map_err(|_| self.closed())
+    }
+
+    fn current_trace(&self) -> Option<TraceContext> {
+        self.trace.as_ref().and_then(|current| (current.0)())
+    }
+
+    /// Number of calls on this connection still waiting for a response.
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Whether the connection has gone away, or the server has announced it
+    /// will. Every further call will fail.
+    pub fn is_closed(&self) -> bool {
+        self.calls.is_closed() || self.going_away.get().is_some() || self.closing.is_cancelled()
+    }
+
+    /// Closes the connection for every clone of this client.
+    ///
+    /// New calls fail with [`Error::Closed`] right away, while those already
+    /// made are sent and waited for, for up to the
+    /// [close timeout](ClientBuilder::close_timeout); any still unanswered
+    /// then fail with [`Error::Closed`] too. The connection is then shut down
+    /// for writing, so the server sees it end cleanly, and dropped.
+    ///
+    /// Dropping every clone without closing also closes the connection once
+    /// the remaining calls are answered, but logs a warning.
+    pub async fn close(&self) {
+        self.closing.cancel();
+        self.finished.cancelled().await;
+    }
+
+    /// Why the server has announced it reads no more requests on this
+    /// connection, if it has. A server that is draining it has done so
+    /// over [`GoAwayReason::Shutdown`].
+    pub fn going_away(&self) -> Option<GoAwayReason> {
+        self.going_away.get().copied()
+    }
+
+    /// What the server last announced about this connection.
+    pub fn state(&self) -> ConnectionState {
+        *self.state.borrow()
+    }
+
+    /// Sees every change of [`Client::state`] as it happens, e.g. to
+    /// connect elsewhere as soon as the server starts draining, while the
+    /// calls already made here are still answered.
+    pub fn state_changes(&self) -> watch::Receiver<ConnectionState> {
+        self.state.clone()
+    }
+
+    /// The error for a call the connection dropped.
+    fn closed(&self) -> Error {
+        closed(&self.going_away)
+    }
+}
+
+/// What to log a request as: its method, or what it holds otherwise.
+fn request_name(header: &Header) -> &str {
+    match &header.method {
+        Some(method) => &method.name,
+        None => "batch",
+    }
+}
+
+fn closed(going_away: &OnceLock<GoAwayReason>) -> Error {
+    match going_away.get() {
+        Some(GoAwayReason::Shutdown) => Error::ServerShuttingDown,
+        _ => Error::Closed,
+    }
+}
+
+struct InFlightGuard<'a>(&'a AtomicUsize);
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// The response to [`Client::call_chunked`], read as it arrives.
+///
+/// Fails with [`io::ErrorKind::UnexpectedEof`] if the connection closes
+/// before the response has ended, and with an error wrapping
+/// [`Error::Rpc`] if the server ends it with an error instead.
+pub struct ChunkedReader {
+    id: u64,
+    chunks: mpsc::Receiver<Chunk>,
+    chunk: Bytes,
+    ended: bool,
+    cancels: mpsc::UnboundedSender<u64>,
+    in_flight: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for ChunkedReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.chunk.is_empty() && !self.ended {
+            match ready!(self.chunks.poll_recv(cx)) {
+                Some(Ok(Some(chunk))) => self.chunk = chunk,
+                Some(Ok(None)) => self.ended = true,
+                Some(Err(e)) => {
+                    self.ended = true;
+                    return Poll::Ready(Err(io::Error::other(e)));
+                }
+                None => {
+                    self.ended = true;
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "connection closed before the response ended",
+                    )));
+                }
+            }
+        }
+
+        let n = self.chunk.len().min(buf.remaining());
+        buf.put_slice(&self.chunk.split_to(n));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Drop for ChunkedReader {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        if !self.ended {
+            let _ = self.cancels.send(self.id);
+        }
+    }
+}
+
+/// The events of a subscription started by [`Client::subscribe`], as they
+/// arrive.
+///
+/// Fails with [`Error::Rpc`] if the server ends the subscription with an
+/// error, as it does when shutting down, and with [`Error::Closed`] if the
+/// connection closes before it has ended; either way, the stream ends after
+/// that.
+pub struct EventStream<T> {
+    id: u64,
+    events: mpsc::Receiver<Chunk>,
+    ended: bool,
+    wire_format: WireFormat,
+    going_away: Arc<OnceLock<GoAwayReason>>,
+    cancels: mpsc::UnboundedSender<u64>,
+    in_flight: Arc<AtomicUsize>,
+    event: PhantomData<fn() -> T>,
+}
+
+impl<T: Decode<()> + DeserializeOwned> Stream for EventStream<T> {
+    type Item = Result<T>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<T>>> {
+        if self.ended {
+            return Poll::Ready(None);
+        }
+        let item = match ready!(self.events.poll_recv(cx)) {
+            Some(Ok(Some(event))) => {
+                let event = with_frame(&event, || self.wire_format.decode(&event));
+                return Poll::Ready(Some(event.map_err(Error::from)));
+            }
+            Some(Ok(None)) => None,
+            Some(Err(e)) => Some(Err(e)),
+            None => Some(Err(closed(&self.going_away))),
+        };
+        self.ended = true;
+        Poll::Ready(item)
+    }
+}
+
+impl<T> Drop for EventStream<T> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        if !self.ended {
+            let _ = self.cancels.send(self.id);
+        }
+    }
+}
+
+/// The partial results of a call made with [`Client::call_with_partials`],
+/// as they arrive, and its response once they have; the stream ends before
+/// it.
+///
+/// Fails with [`Error::Rpc`] if a partial result doesn't decode as a `P`,
+/// and goes on with the next.
+pub struct PartialResults<P, Resp> {
+    id: u64,
+    partials: mpsc::Receiver<Chunk>,
+    reply: oneshot::Receiver<Reply>,
+    /// Whether the response has been taken.
+    ended: bool,
+    wire_format: WireFormat,
+    going_away: Arc<OnceLock<GoAwayReason>>,
+    cancels: mpsc::UnboundedSender<u64>,
+    in_flight: Arc<AtomicUsize>,
+    types: PhantomData<fn() -> (P, Resp)>,
+}
+
+impl<P: Decode<()> + DeserializeOwned, Resp: Decode<()> + DeserializeOwned>
+    PartialResults<P, Resp>
+{
+    /// Waits for the response, once every partial result not taken yet has
+    /// arrived, handing those back along with it.
+    pub async fn finish(mut self) -> Result<(Vec<P>, Resp)> {
+        let mut partials = Vec::new();
+        while let Some(partial) = self.next().await {
+            partials.push(partial?);
+        }
+
+        self.ended = true;
+        let Reply { kind, body, .. } = (&mut self.reply)
+            .await
+            .map_err(|_| closed(&self.going_away))?;
+        match kind {
+            FrameKind::Response => {
+                let resp = with_frame(&body, || self.wire_format.decode(&body))?;
+                Ok((partials, resp))
+            }
+            FrameKind::Error => Err(Error::Rpc(self.wire_format.decode::<RpcError>(&body)?)),
+            kind => Err(Error::UnexpectedFrame(kind)),
+        }
+    }
+}
+
+impl<P: Decode<()> + DeserializeOwned, Resp> Stream for PartialResults<P, Resp> {
+    type Item = Result<P>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<P>>> {
+        // The queue closes once the response has arrived, or the connection
+        // has.
+        match ready!(self.partials.poll_recv(cx)) {
+            Some(Ok(Some(partial))) => {
+                let partial = with_frame(&partial, || self.wire_format.decode(&partial));
+                Poll::Ready(Some(partial.map_err(Error::from)))
+            }
+            Some(Ok(None)) | None => Poll::Ready(None),
+            Some(Err(e)) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
+
+impl<P, Resp> Drop for PartialResults<P, Resp> {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        // Cancelling a call already answered would do nothing anyway.
+        if !self.ended && self.reply.try_recv().is_err() {
+            let _ = self.cancels.send(self.id);
+        }
+    }
+}
+
+/// Cancels the call `id` unless disarmed before it is dropped.
+struct CancelOnDrop<'a> {
+    id: u64,
+    cancels: &'a mpsc::UnboundedSender<u64>,
+    armed: bool,
+}
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            let _ = self.cancels.send(self.id);
+        }
+    }
+}
+
+/// Splits frames like `codec`, after checking the server's answer to the
+/// `Hello` has its length prefix in the same byte order, see
+/// [`Framing::check_byte_order`].
+struct HandshakeCodec {
+    codec: LengthDelimitedCodec,
+    framing: Framing,
+}
+
+impl Decoder for HandshakeCodec {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+        match self.framing.check_byte_order(src) {
+            Some(checked) => {
+                checked.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                self.codec.decode(src)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl Encoder<Bytes> for HandshakeCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        self.codec.encode(frame, dst)
+    }
+}
+
+/// How the task driving a connection goes about it.
+struct Driving {
+    /// See [`ClientBuilder::sequence_numbers`].
+    sequence_numbers: bool,
+    /// See [`ClientBuilder::callbacks`].
+    callbacks: Option<CallbackHandlers>,
+    /// What answers to the server's calls are encoded with.
+    answer_codec: Limited<WireFormat>,
+    /// What the server compresses bodies flagged
+    /// [`Header::compressed`] with, if it does.
+    body_compression: Option<Arc<dyn Compression>>,
+    /// Where changes of the connection's state go, see
+    /// [`Client::state_changes`].
+    state: watch::Sender<ConnectionState>,
+}
+
+/// An upgrade sent, waiting for the server's answer, see [`Client::upgrade`].
+struct Upgrading {
+    id: u64,
+    compression: Arc<dyn Compression>,
+    /// Gets what to compress frames with from now on, or `None` if the
+    /// server turned the upgrade down.
+    switched: oneshot::Sender<Option<Arc<dyn Compression>>>,
+}
+
+/// How the task driving a connection learns it is to close.
+struct Closing {
+    requested: CancellationToken,
+    timeout: Duration,
+    /// Cancelled once the task is done.
+    finished: CancellationToken,
+}
+
+async fn drive<T>(
+    framed: Framed<T, LengthDelimitedCodec>,
+    codec: impl WireCodec,
+    mut calls: mpsc::Receiver<Call>,
+    mut cancels: mpsc::UnboundedReceiver<u64>,
+    going_away: Arc<OnceLock<GoAwayReason>>,
+    closing: Closing,
+    driving: Driving,
+) where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    let _finished = closing.finished.drop_guard();
+    let (mut sink, mut stream) = framed.split();
+    let pending = Mutex::new(HashMap::new());
+    // Answers to the server's calls, see `Driving::callbacks`.
+    let (answers, mut answered) = mpsc::channel(CALL_QUEUE_CAPACITY);
+    let upgrading: Mutex<Option<Upgrading>> = Mutex::new(None);
+
+    // Sending and receiving run side by side, so a server that is slow to
+    // read our requests still gets its responses read in the meantime.
+    {
+        let writer = async {
+            let mut draining = false;
+            // The next frame's sequence number, if they are sent.
+            let mut seq = driving.sequence_numbers.then_some(1);
+            // What frames are compressed with, once upgraded.
+            let mut compression: Option<Arc<dyn Compression>> = None;
+            loop {
+                // Held back until the server has answered the upgrade being
+                // sent, if it is one.
+                let mut switched = None;
+                // The call the frame belongs to, if it is one of ours.
+                let (id, frame) = tokio::select! {
+                    // Calls already queued are still sent.
+                    () = closing.requested.cancelled(), if !draining => {
+                        calls.close();
+                        draining = true;
+                        continue;
+                    }
+
+                    call = calls.recv() => match call {
+                        // The server won't read it anyway; dropping `reply` fails
+                        // the call with `Error::Closed`.
+                        Some(_) if going_away.get().is_some() => continue,
+                        Some(Call { id, frame, reply: Some(reply), upgrade }) => {
+                            pending.lock().unwrap().insert(id, reply);
+                            if let Some(compression) = upgrade {
+                                let (tx, rx) = oneshot::channel();
+                                *upgrading.lock().unwrap() = Some(Upgrading { id, compression, switched: tx });
+                                switched = Some(rx);
+                            }
+                            (Some(id), frame)
+                        }
+                        // The rest of a call that was answered, or cancelled,
+                        // would only be dropped by the server.
+                        Some(Call { id, frame, reply: None, .. }) => {
+                            if !pending.lock().unwrap().contains_key(&id) {
+                                continue;
+                            }
+                            (Some(id), frame)
+                        }
+                        None => {
+                            if !draining {
+                                warn!("client dropped without being closed");
+                            }
+                            break;
+                        }
+                    },
+
+                    Some(id) = cancels.recv() => {
+                        // Calls that were answered, or never made it out, have
+                        // nothing to cancel.
+                        if pending.lock().unwrap().remove(&id).is_none() {
+                            continue;
+                        }
+                        match encode_frame(&codec, &Header::new(id, FrameKind::Cancel), &()) {
+                            Ok(frame) => (Some(id), Bytes::from(frame)),
+                            Err(_) => continue,
+                        }
+                    }
+
+                    Some(frame) = answered.recv() => (None, frame),
+                };
+                let forget = || {
+                    if let Some(id) = id {
+                        pending.lock().unwrap().remove(&id);
+                        upgrading.lock().unwrap().take_if(|u| u.id == id);
+                    }
+                };
+
+                // Numbered only now, as frames go out in the order they are
+                // taken here rather than the one they were made in.
+                let frame = match seq.map(|seq| with_seq(&codec, &frame, seq)) {
+                    None => frame,
+                    Some(Ok(frame)) => frame,
+                    Some(Err(_)) => {
+                        forget();
+                        continue;
+                    }
+                };
+                let frame = match &compression {
+                    Some(compression) => Bytes::from(compression.compress(&frame)),
+                    None => frame,
+                };
+
+                match sink.send(frame).await {
+                    Ok(()) => {
+                        if let Some(seq) = &mut seq {
+                            *seq += 1;
+                        }
+                        // See `protocol::compression`.
+                        if let Some(switched) = switched {
+                            match switched.await {
+                                Ok(Some(switch)) => compression = Some(switch),
+                                Ok(None) => {}
+                                Err(_) => break,
+                            }
+                        }
+                    }
+                    // The codec refused the frame before writing any of it, so
+                    // only this call fails.
+                    Err(e) if e.kind() == io::ErrorKind::InvalidInput => forget(),
+                    Err(_) => break,
+                }
+            }
+        };
+        let mut writer = std::pin::pin!(writer);
+        let mut writing = true;
+        let give_up = async {
+            closing.requested.cancelled().await;
+            tokio::time::sleep(closing.timeout).await;
+        };
+        let mut give_up = std::pin::pin!(give_up);
+        // What frames read are compressed with, once upgraded.
+        let mut decompression: Option<Arc<dyn Compression>> = None;
+
+        while writing || !pending.lock().unwrap().is_empty() {
+            tokio::select! {
+                () = &mut writer, if writing => writing = false,
+
+                () = &mut give_up => break,
+
+                maybe_segment = stream.next() => match maybe_segment {
+                    Some(Ok(segment)) => {
+                        let segment = segment.freeze();
+                        let segment = match &decompression {
+                            Some(compression) => match compression.decompress(&segment, driving.answer_codec.limit) {
+                                Ok(frame) => Bytes::from(frame),
+                                Err(_) => break,
+                            },
+                            None => segment,
+                        };
+                        // A frame we can't attribute to a call can't be answered
+                        // either, so it is dropped.
+                        let Ok((header, body)) = decode_header(&codec, &segment) else {
+                            continue;
+                        };
+                        let body = if header.compressed {
+                            // Can't be read, nor can anything after it be
+                            // trusted to.
+                            let Some(compression) = &driving.body_compression else {
+                                break;
+                            };
+                            match compression.decompress(body, driving.answer_codec.limit) {
+                                Ok(body) => Bytes::from(body),
+                                Err(_) => break,
+                            }
+                        } else {
+                            segment.slice_ref(body)
+                        };
+                        if header.kind == FrameKind::GoAway {
+                            // Servers from before reasons only ever went away
+                            // over the request limit, with an empty body.
+                            let reason = codec
+                                .decode::<GoAway>(&body)
+                                .map_or(GoAwayReason::RequestLimit, |go_away| go_away.reason);
+                            let _ = going_away.set(reason);
+                            driving.state.send_replace(ConnectionState::GoingAway(reason));
+                            continue;
+                        }
+                        if header.kind == FrameKind::Draining {
+                            // Calls already sent are still answered.
+                            let _ = going_away.set(GoAwayReason::Shutdown);
+                            driving.state.send_replace(ConnectionState::Draining);
+                            continue;
+                        }
+                        if header.reverse {
+                            // A call from the server, answered on a task of
+                            // its own so reading goes on meanwhile.
+                            if let Some(callbacks) = &driving.callbacks
+                                && matches!(header.kind, FrameKind::Request | FrameKind::VersionedRequest)
+                            {
+                                let answer = callbacks.answer(driving.answer_codec, header, body);
+                                let answers = answers.clone();
+                                tokio::spawn(async move {
+                                    if let Some(frame) = answer.await {
+                                        let _ = answers.send(frame).await;
+                                    }
+                                });
+                            }
+                            continue;
+                        }
+                        // Frames after the answer to an upgrade it took are
+                        // compressed.
+                        let upgraded = upgrading.lock().unwrap().take_if(|u| u.id == header.id);
+                        if let Some(Upgrading { compression, switched, .. }) = upgraded {
+                            let switch = (header.kind == FrameKind::Upgrade).then_some(compression);
+                            if let Some(switch) = &switch {
+                                decompression = Some(switch.clone());
+                            }
+                            let _ = switched.send(switch);
+                        }
+                        let routed = route(&mut pending.lock().unwrap(), &codec, &header, body);
+                        // Waits for the reader to make room; a reader that is gone
+                        // has cancelled the call already.
+                        if let Some((chunks, chunk)) = routed
+                            && chunks.send(chunk).await.is_err()
+                        {
+                            pending.lock().unwrap().remove(&header.id);
+                        }
+                    }
+                    Some(Err(_)) | None => break,
+                },
+            }
+        }
+    }
+
+    // Nothing is written after this point, so the server can tell the
+    // connection was closed on purpose. Bounded, as a server that stopped
+    // reading would never let it finish.
+    let _ = tokio::time::timeout(closing.timeout, sink.close()).await;
+    driving.state.send_replace(ConnectionState::Closed);
+
+    // Dropping the receiver marks every clone of the handle as closed, and
+    // dropping the pending senders fails their calls with `Error::Closed`.
+}
+
+/// `frame` with sequence number `seq` in its header.
+fn with_seq(codec: &impl WireCodec, frame: &[u8], seq: u64) -> Result<Bytes, CodecError> {
+    let (header, body) = decode_header(codec, frame)?;
+    let frame = encode_raw_frame(codec, &header.with_seq(Some(seq)), body)?;
+    Ok(frame.into())
+}
+
+/// Hands a response frame to the call waiting for it. Chunks of a chunked
+/// response and events of a subscription are returned instead, along with
+/// where they go, as sending them may have to wait.
+fn route(
+    pending: &mut HashMap<u64, Pending>,
+    codec: &impl WireCodec,
+    header: &Header,
+    body: Bytes,
+) -> Option<(mpsc::Sender<Chunk>, Chunk)> {
+    // What the stream goes on with, what ends it, and how it stays pending.
+    let (chunks, more, end, stream): (_, _, _, fn(_) -> _) = match pending.remove(&header.id)? {
+        // Only calls taking partial results get them.
+        Pending::Unary(reply) if header.kind == FrameKind::Partial => {
+            pending.insert(header.id, Pending::Unary(reply));
+            return None;
+        }
+        Pending::WithPartials { partials, reply } => {
+            if header.kind == FrameKind::Partial {
+                let chunks = partials.clone();
+                pending.insert(header.id, Pending::WithPartials { partials, reply });
+                return Some((chunks, Ok(Some(body))));
+            }
+            // Dropping the queue ends the partial results.
+            let _ = reply.send(Reply {
+                kind: header.kind,
+                body,
+                trailers: header.trailers.clone(),
+            });
+            return None;
+        }
+        Pending::Unary(reply) => {
+            let _ = reply.send(Reply {
+                kind: header.kind,
+                body,
+                trailers: header.trailers.clone(),
+            });
+            return None;
+        }
+        Pending::Subscribing { started, events } => {
+            if header.kind == FrameKind::Response {
+                pending.insert(header.id, Pending::Subscribed(events));
+            }
+            let _ = started.send(Reply {
+                kind: header.kind,
+                body,
+                trailers: header.trailers.clone(),
+            });
+            return None;
+        }
+        Pending::Chunked(chunks) => (
+            chunks,
+            FrameKind::Chunk,
+            FrameKind::ChunkEnd,
+            Pending::Chunked,
+        ),
+        Pending::Subscribed(events) => (
+            events,
+            FrameKind::Event,
+            FrameKind::SubscriptionEnd,
+            Pending::Subscribed,
+        ),
+    };
+
+    let err = match header.kind {
+        kind if kind == more => {
+            // Stays pending until the stream has ended.
+            pending.insert(header.id, stream(chunks.clone()));
+            return Some((chunks, Ok(Some(body))));
+        }
+        kind if kind == end => return Some((chunks, Ok(None))),
+        FrameKind::Error => match codec.decode::<RpcError>(&body) {
+            Ok(err) => Error::Rpc(err),
+            Err(e) => Error::Codec(e),
+        },
+        kind => Error::UnexpectedFrame(kind),
+    };
+    Some((chunks, Err(err)))
+}
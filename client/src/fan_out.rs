@@ -0,0 +1,113 @@
+use crate::{Client, Error, Result};
+
+use protocol::Request;
+
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+
+use std::time::Duration;
+
+/// How many servers [`fan_out`] waits to hear back from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WaitFor {
+    /// Every server, whether it answers or fails.
+    #[default]
+    All,
+    /// The first this many servers to answer successfully.
+    First(usize),
+    /// A majority of the servers answering successfully.
+    Quorum,
+}
+
+impl WaitFor {
+    /// Successful answers needed out of `servers`.
+    fn needed(self, servers: usize) -> usize {
+        match self {
+            WaitFor::All => servers,
+            WaitFor::First(count) => count.min(servers),
+            WaitFor::Quorum => servers / 2 + 1,
+        }
+    }
+}
+
+/// How a request is sent to several servers by [`fan_out`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FanOutPolicy {
+    pub wait_for: WaitFor,
+    /// How long each server has to answer, as with
+    /// [`Client::call_with_deadline`]. No limit if `None`.
+    pub timeout: Option<Duration>,
+}
+
+/// What came back from each server a request was fanned out to.
+#[derive(Debug)]
+pub struct FanOutResults<Resp> {
+    /// Per server, in the order they were given: its response or what the
+    /// call failed with, or `None` if the policy was met before it answered.
+    pub results: Vec<Option<Result<Resp>>>,
+    needed: usize,
+}
+
+impl<Resp> FanOutResults<Resp> {
+    /// Whether as many servers answered successfully as the policy waits
+    /// for.
+    pub fn is_met(&self) -> bool {
+        self.responses().count() >= self.needed
+    }
+
+    /// Successful responses, by the index of the server they came from.
+    pub fn responses(&self) -> impl Iterator<Item = (usize, &Resp)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| Some((index, result.as_ref()?.as_ref().ok()?)))
+    }
+
+    /// Failed calls, by the index of the server they were sent to.
+    pub fn errors(&self) -> impl Iterator<Item = (usize, &Error)> {
+        self.results
+            .iter()
+            .enumerate()
+            .filter_map(|(index, result)| Some((index, result.as_ref()?.as_ref().err()?)))
+    }
+}
+
+/// Sends `req` to every one of `servers` at once, over the connections the
+/// clients already have, and collects the responses until `policy` is met.
+///
+/// A server failing, or not answering within the policy's timeout, only
+/// fails its own entry of the results; check
+/// [`FanOutResults::is_met`] for whether enough of them answered. Calls
+/// still outstanding once the policy is met are abandoned.
+pub async fn fan_out<Req: Request>(
+    servers: &[Client],
+    req: Req,
+    policy: &FanOutPolicy,
+) -> FanOutResults<Req::Resp> {
+    let needed = policy.wait_for.needed(servers.len());
+    let mut results: Vec<_> = servers.iter().map(|_| None).collect();
+
+    let req = &req;
+    let mut calls: FuturesUnordered<_> = servers
+        .iter()
+        .enumerate()
+        .map(|(index, client)| async move {
+            let result = match policy.timeout {
+                Some(timeout) => client.call_ref_with_deadline(req, timeout).await,
+                None => client.call_ref(req).await,
+            };
+            (index, result)
+        })
+        .collect();
+
+    let mut answered = 0;
+    while answered < needed {
+        let Some((index, result)) = calls.next().await else {
+            break;
+        };
+        answered += usize::from(result.is_ok());
+        results[index] = Some(result);
+    }
+
+    FanOutResults { results, needed }
+}
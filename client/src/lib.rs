@@ -0,0 +1,72 @@
+mod blocking;
+mod cache;
+mod callbacks;
+mod client;
+mod fan_out;
+#[cfg(feature = "otel")]
+pub mod otel;
+mod pool;
+mod request_id;
+mod retry;
+pub mod testing;
+pub mod text;
+
+pub use blocking::BlockingClient;
+pub use cache::ResponseCache;
+pub use client::{
+    ChunkedReader, Client, ClientBuilder, ConnectionState, EventStream, PartialResults,
+};
+pub use fan_out::{FanOutPolicy, FanOutResults, WaitFor, fan_out};
+pub use pool::{ClientPool, Strategy};
+pub use retry::{FailedRequest, RetryPolicy};
+
+use protocol::RpcError;
+use protocol::codec::CodecError;
+use protocol::frame::{FrameKind, FramingError};
+
+use std::ops::RangeInclusive;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("codec error: {0}")]
+    Codec(#[from] CodecError),
+
+    #[error("invalid framing: {0}")]
+    Framing(#[from] FramingError),
+
+    #[error("request frame of {len} bytes exceeds the {max} byte limit")]
+    FrameTooLarge { len: usize, max: usize },
+
+    #[error("Server sent an invalid handshake response")]
+    Handshake,
+
+    #[error("Connection closed")]
+    Closed,
+
+    /// The connection was closed because the server is shutting down, as
+    /// opposed to failing; another server may take the call.
+    #[error("Server is shutting down")]
+    ServerShuttingDown,
+
+    #[error("server returned an error: {0}")]
+    Rpc(RpcError),
+
+    #[error("Unexpected {0:?} frame from server")]
+    UnexpectedFrame(FrameKind),
+
+    /// See [`Client::upgrade`].
+    #[error("can't upgrade the connection to {0:?} compression")]
+    UpgradeUnsupported(String),
+
+    /// See [`ClientBuilder::protocol_versions`].
+    #[error("server speaks protocol version {server}, this client only {supported:?}")]
+    IncompatibleVersion {
+        server: u32,
+        supported: RangeInclusive<u32>,
+    },
+}
+
+pub type Result<T, E = Error> = ::core::result::Result<T, E>;
@@ -1,15 +1,20 @@
-use protocol::{Request, Response};
+use client::text::{
+    arg_prompt, assemble_request, describe_method, format_request, format_response, guided_method,
+    parse_request,
+};
+use client::{Client, Error};
 
-use futures::{SinkExt, StreamExt};
-use tokio::net::TcpStream;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use protocol::Request;
+use protocol::schema::MethodSchema;
 
+use anyhow::{Context, bail};
 use rustyline::Editor;
 use rustyline::error::ReadlineError;
 
-use bincode::config::BigEndian;
-const BINCODE_CONFIG: bincode::config::Configuration<BigEndian> =
-    bincode::config::standard().with_big_endian();
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, IsTerminal};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
 
 type Result<T, E = anyhow::Error> = core::result::Result<T, E>;
 
@@ -41,11 +46,44 @@ fn Pong() -> String {
 #[tokio::main]
 async fn main() -> Result<()> {
     let addr = "127.0.0.1:8080";
-    let stream = TcpStream::connect(addr).await?;
-    let codec = LengthDelimitedCodec::new();
-    let mut framed = Framed::new(stream, codec);
+    let script = parse_args()?;
+    let client = Client::connect(addr).await?;
+
+    let res = match script {
+        Some(path) => run_script(&client, BufReader::new(File::open(path)?)).await,
+        None if io::stdin().is_terminal() => run_repl(&client).await,
+        None => run_script(&client, io::stdin().lock()).await,
+    };
+    client.close().await;
+    res
+}
+
+/// Returns the path given with `--file`, if any.
+fn parse_args() -> Result<Option<PathBuf>> {
+    let mut args = std::env::args_os().skip(1);
+    let mut script = None;
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("-f" | "--file") => {
+                let path = args.next().context("--file needs a path")?;
+                script = Some(path.into());
+            }
+            _ => bail!("unexpected argument {arg:?}\nusage: client [--file <requests.json5>]"),
+        }
+    }
+    Ok(script)
+}
 
+/// A request sent this session, and what came back, both as JSON5.
+struct Exchange {
+    request: String,
+    response: String,
+}
+
+async fn run_repl(client: &Client) -> Result<()> {
     let mut rl = Editor::<(), _>::new()?;
+    let mut history = Vec::new();
+    let schema = AppRequest::schema();
 
     loop {
         let input_line = match rl.readline(">> ") {
@@ -62,29 +100,153 @@ async fn main() -> Result<()> {
             }
         };
 
-        let req: AppRequest = match json5::from_str(input_line.trim()) {
-            Ok(req) => req,
-            Err(e) => {
-                eprintln!("Failed to parse JSON input: {e}");
-                continue;
-            }
+        let sent = match guided_method(&schema, &input_line) {
+            Some(method) => match prompt_request(&mut rl, method)? {
+                Some(req) => send(client, &mut history, req).await?,
+                None => ControlFlow::Continue(()),
+            },
+            None => send_line(client, &mut history, &input_line).await?,
         };
-        let req_bytes = bincode::encode_to_vec(req, BINCODE_CONFIG)?;
+        if sent.is_break() {
+            break;
+        }
+    }
 
-        framed.send(req_bytes.into()).await?;
+    Ok(())
+}
 
-        if let Some(resp_bytes) = framed.next().await {
-            let resp_bytes = resp_bytes?;
+/// Asks for the arguments of `method` one by one, after showing what they
+/// are. `None` if the request is given up on with Ctrl-C, or doesn't parse.
+fn prompt_request(
+    rl: &mut Editor<(), rustyline::history::DefaultHistory>,
+    method: &MethodSchema,
+) -> Result<Option<AppRequest>> {
+    println!("{}", describe_method(method));
+    let mut answers = Vec::new();
+    for arg in &method.args {
+        match rl.readline(&arg_prompt(arg)) {
+            Ok(answer) => answers.push(answer),
+            Err(ReadlineError::Interrupted | ReadlineError::Eof) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+    }
 
-            let resp: AppResponse =
-                bincode::decode_from_slice(&resp_bytes, BINCODE_CONFIG).map(|(val, _)| val)?;
-            let resp_str = json5::to_string(&resp)?;
-            println!("{resp_str}");
-        } else {
-            println!("Server closed connection or no response received.");
+    match assemble_request(method, &answers) {
+        Ok(req) => Ok(Some(req)),
+        Err(e) => {
+            eprintln!("Failed to parse arguments: {e}");
+            Ok(None)
+        }
+    }
+}
+
+/// Sends one request per non-empty line of `input` until it runs out, e.g. for
+/// `cat requests.json5 | client`.
+async fn run_script(client: &Client, input: impl BufRead) -> Result<()> {
+    let mut history = Vec::new();
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if send_line(client, &mut history, &line).await?.is_break() {
             break;
         }
     }
 
     Ok(())
 }
+
+/// Parses `line` as a request, or a command if it starts with `:`, and runs
+/// it. Breaks once the connection is gone.
+async fn send_line(
+    client: &Client,
+    history: &mut Vec<Exchange>,
+    line: &str,
+) -> Result<ControlFlow<()>> {
+    let line = line.trim();
+    if let Some(command) = line.strip_prefix(':') {
+        return run_command(client, history, command).await;
+    }
+
+    match parse_request(line) {
+        Ok(req) => send(client, history, req).await,
+        Err(e) => {
+            eprintln!("Failed to parse JSON input: {e}");
+            Ok(ControlFlow::Continue(()))
+        }
+    }
+}
+
+const COMMANDS: &str = "\
+<method>       prompt for the arguments of <method> one by one
+:history       list the requests sent so far, with their responses
+:last          show the last request and its response
+:replay <n>    send request <n> from :history again
+:help          show this";
+
+async fn run_command(
+    client: &Client,
+    history: &mut Vec<Exchange>,
+    command: &str,
+) -> Result<ControlFlow<()>> {
+    let mut words = command.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("history"), None, _) => {
+            for (n, exchange) in history.iter().enumerate() {
+                println!("{}: {} => {}", n + 1, exchange.request, exchange.response);
+            }
+        }
+        (Some("last"), None, _) => match history.last() {
+            Some(exchange) => println!("{} => {}", exchange.request, exchange.response),
+            None => eprintln!("No requests sent yet"),
+        },
+        (Some("replay"), Some(n), None) => {
+            let Some(exchange) = n
+                .parse::<usize>()
+                .ok()
+                .and_then(|n| history.get(n.checked_sub(1)?))
+            else {
+                eprintln!("No request {n} in :history");
+                return Ok(ControlFlow::Continue(()));
+            };
+            let req = parse_request(&exchange.request)?;
+            return send(client, history, req).await;
+        }
+        (Some("help"), None, _) => println!("{COMMANDS}"),
+        _ => eprintln!("Unknown command :{command}\n{COMMANDS}"),
+    }
+    Ok(ControlFlow::Continue(()))
+}
+
+/// Sends `req`, prints the response and records both in `history`.
+async fn send(
+    client: &Client,
+    history: &mut Vec<Exchange>,
+    req: AppRequest,
+) -> Result<ControlFlow<()>> {
+    let request = format_request(&req)?;
+    let response = match client.call(req).await {
+        Ok(resp) => {
+            let resp_str = format_response::<AppResponse>(&resp)?;
+            println!("{resp_str}");
+            resp_str
+        }
+        Err(Error::Closed) => {
+            println!("Server closed connection or no response received.");
+            return Ok(ControlFlow::Break(()));
+        }
+        Err(Error::ServerShuttingDown) => {
+            println!("Server is shutting down.");
+            return Ok(ControlFlow::Break(()));
+        }
+        Err(Error::Rpc(e)) => {
+            eprintln!("Request failed: {e}");
+            format!("error: {e}")
+        }
+        Err(e) => return Err(e.into()),
+    };
+    history.push(Exchange { request, response });
+
+    Ok(ControlFlow::Continue(()))
+}
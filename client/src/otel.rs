@@ -0,0 +1,22 @@
+//! Sending requests from within OpenTelemetry traces, for the server's
+//! `otel` feature to parent its spans to the caller's.
+
+use protocol::frame::TraceContext;
+
+use opentelemetry::trace::TraceContextExt;
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// The trace of the current span, as `tracing-opentelemetry` sees it, to
+/// pass to [`ClientBuilder::trace_context`](crate::ClientBuilder::trace_context).
+/// `None` outside of any trace.
+pub fn current_trace() -> Option<TraceContext> {
+    let context = Span::current().context();
+    let span = context.span();
+    let span = span.span_context();
+    span.is_valid().then(|| TraceContext {
+        trace_id: u128::from_be_bytes(span.trace_id().to_bytes()),
+        span_id: u64::from_be_bytes(span.span_id().to_bytes()),
+        sampled: span.is_sampled(),
+    })
+}
@@ -0,0 +1,133 @@
+use crate::retry::with_retry;
+use crate::{Client, ClientBuilder, Result, RetryPolicy};
+
+use protocol::Request;
+
+use std::sync::RwLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// How a [`ClientPool`] picks the connection for the next call.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Cycle through the connections in order.
+    RoundRobin,
+    /// Pick the connection with the fewest calls awaiting a response.
+    #[default]
+    LeastInFlight,
+}
+
+/// A fixed-size set of connections to one server.
+///
+/// Connections that have gone away are replaced with a fresh one the next
+/// time they are picked, so callers only ever see the [`ClientPool::call`]
+/// API.
+pub struct ClientPool {
+    addr: String,
+    builder: ClientBuilder,
+    strategy: Strategy,
+    slots: Vec<RwLock<Client>>,
+    next: AtomicUsize,
+}
+
+impl ClientPool {
+    pub async fn connect(addr: impl Into<String>, size: usize, strategy: Strategy) -> Result<Self> {
+        Self::connect_with(Client::builder(), addr, size, strategy).await
+    }
+
+    /// Like [`ClientPool::connect`], but every connection, including
+    /// replacements, is set up through `builder`.
+    pub async fn connect_with(
+        builder: ClientBuilder,
+        addr: impl Into<String>,
+        size: usize,
+        strategy: Strategy,
+    ) -> Result<Self> {
+        let addr = addr.into();
+        assert!(size > 0, "a client pool needs at least one connection");
+
+        let mut slots = Vec::with_capacity(size);
+        for _ in 0..size {
+            slots.push(RwLock::new(builder.clone().connect(&addr).await?));
+        }
+
+        Ok(Self {
+            addr,
+            builder,
+            strategy,
+            slots,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    pub async fn call<Req: Request>(&self, req: Req) -> Result<Req::Resp> {
+        let client = self.checkout().await?;
+        client.call(req).await
+    }
+
+    /// Like [`ClientPool::call`], but tries an idempotent `req` again, as
+    /// `policy` allows, after a transient failure. A connection that went
+    /// away is replaced before the next attempt.
+    pub async fn call_with_retry<Req: Request>(
+        &self,
+        req: Req,
+        policy: &RetryPolicy,
+    ) -> Result<Req::Resp> {
+        with_retry(
+            policy,
+            &req,
+            |_| true,
+            self.builder.failed_requests(),
+            || async {
+                let client = self.checkout().await?;
+                client.call_ref(&req).await
+            },
+        )
+        .await
+    }
+
+    pub fn size(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Calls awaiting a response, per connection.
+    pub fn in_flight(&self) -> Vec<usize> {
+        self.slots
+            .iter()
+            .map(|slot| read(slot).in_flight())
+            .collect()
+    }
+
+    async fn checkout(&self) -> Result<Client> {
+        let index = self.select();
+        let client = read(&self.slots[index]);
+        if !client.is_closed() {
+            return Ok(client);
+        }
+
+        let fresh = self.builder.clone().connect(&self.addr).await?;
+        let mut slot = self.slots[index].write().unwrap_or_else(|e| e.into_inner());
+        // Another caller may have replaced the connection while we were
+        // connecting; keep theirs if it is still alive.
+        if slot.is_closed() {
+            *slot = fresh;
+        }
+        Ok(slot.clone())
+    }
+
+    fn select(&self) -> usize {
+        match self.strategy {
+            Strategy::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % self.slots.len(),
+            Strategy::LeastInFlight => self
+                .slots
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, slot)| read(slot).in_flight())
+                .map(|(index, _)| index)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn read(slot: &RwLock<Client>) -> Client {
+    slot.read().unwrap_or_else(|e| e.into_inner()).clone()
+}
@@ -0,0 +1,35 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Crockford's base32, as ULIDs are written in.
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A fresh ULID for a call, see
+/// [`Header::request_id`](protocol::frame::Header::request_id): 26
+/// characters, the first 10 of them the time in milliseconds, so ids sort
+/// by when their calls were made.
+///
+/// The other 80 bits only need to tell calls apart, not be unpredictable,
+/// so they come from std's randomly keyed hasher rather than a random
+/// number generator.
+pub(crate) fn new_request_id() -> String {
+    static CALLS: AtomicU64 = AtomicU64::new(0);
+
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(CALLS.fetch_add(1, Ordering::Relaxed));
+    let high = hasher.finish();
+    hasher.write_u64(high);
+    let low = hasher.finish();
+
+    let random = u128::from(high) << 16 | u128::from(low & 0xffff);
+    let ulid = u128::from(millis & ((1 << 48) - 1)) << 80 | random;
+    (0..26)
+        .rev()
+        .map(|i| char::from(ALPHABET[(ulid >> (5 * i)) as usize & 31]))
+        .collect()
+}
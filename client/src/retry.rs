@@ -0,0 +1,134 @@
+use crate::{Error, Result};
+
+use protocol::{Request, RpcError};
+
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often, and how far apart, a failed call is tried again.
+///
+/// Only requests declared [`IDEMPOTENT`](protocol::Request::IDEMPOTENT) are
+/// retried, and only after a [transient](Error::is_transient) failure; any
+/// other request is sent exactly once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Attempts in total, counting the first one.
+    pub max_attempts: u32,
+    /// Wait before the second attempt, doubled for every one after it.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A call that failed for good, as handed to
+/// [`ClientBuilder::on_failed_request`](crate::ClientBuilder::on_failed_request).
+#[derive(Debug)]
+pub struct FailedRequest<'a> {
+    /// [`Request::name`] of the request.
+    pub name: &'static str,
+    pub request: &'a dyn fmt::Debug,
+    /// What the last attempt failed with.
+    pub error: &'a Error,
+    /// Attempts made in total, counting the first one.
+    pub attempts: u32,
+}
+
+/// Where calls that failed for good are reported, see
+/// [`ClientBuilder::on_failed_request`](crate::ClientBuilder::on_failed_request).
+#[derive(Clone)]
+pub(crate) struct FailedRequests(Arc<dyn Fn(&FailedRequest<'_>) + Send + Sync>);
+
+impl fmt::Debug for FailedRequests {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FailedRequests")
+    }
+}
+
+impl FailedRequests {
+    pub(crate) fn new(hook: impl Fn(&FailedRequest<'_>) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    pub(crate) fn report(&self, req: &impl Request, error: &Error, attempts: u32) {
+        (self.0)(&FailedRequest {
+            name: req.name(),
+            request: req,
+            error,
+            attempts,
+        });
+    }
+}
+
+impl Error {
+    /// Whether the same call may succeed if tried again: the server was too
+    /// busy for it or rate limited it, or the connection went away before it
+    /// was answered.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            Error::Io(_)
+                | Error::Closed
+                | Error::ServerShuttingDown
+                | Error::Rpc(RpcError::Busy(_) | RpcError::RateLimited { .. })
+        )
+    }
+}
+
+/// Runs `call` for `req` until it succeeds, fails for good, or `policy` runs
+/// out of attempts, reporting the last failure to `failed` in the latter two
+/// cases. `retryable` has the final say over each failure.
+pub(crate) async fn with_retry<Req, T, F, Fut>(
+    policy: &RetryPolicy,
+    req: &Req,
+    retryable: impl Fn(&Error) -> bool,
+    failed: Option<&FailedRequests>,
+    mut call: F,
+) -> Result<T>
+where
+    Req: Request,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let attempts = if req.idempotent() {
+        policy.max_attempts.max(1)
+    } else {
+        1
+    };
+    let mut backoff = policy.initial_backoff;
+
+    let mut attempt = 1;
+    loop {
+        match call().await {
+            Err(e) if attempt < attempts && e.is_transient() && retryable(&e) => {
+                // No sooner than a rate limiting server asks for.
+                let wait = match e {
+                    Error::Rpc(RpcError::RateLimited {
+                        retry_after_ms: Some(ms),
+                    }) => backoff.max(Duration::from_millis(ms)),
+                    _ => backoff,
+                };
+                tokio::time::sleep(wait).await;
+                backoff = (backoff * 2).min(policy.max_backoff);
+                attempt += 1;
+            }
+            Err(e) => {
+                if let Some(failed) = failed {
+                    failed.report(req, &e, attempt);
+                }
+                return Err(e);
+            }
+            res => return res,
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Stand-ins for a server, for testing code that uses the client.
+
+use crate::{Client, ClientBuilder, Result};
+
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+use protocol::hello::Hello;
+use protocol::{Request, RpcError, Wraps, with_frame};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+use tokio_util::codec::Framed;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Bytes buffered each way between a client and a [`MockServer`].
+const TRANSPORT_CAPACITY: usize = 64 * 1024;
+
+/// A server answering the requests of `Req`, an enum generated by `#[rpc]`,
+/// with what a test stubbed for them instead of running their handlers,
+/// over an in-memory transport.
+///
+/// Requests are stubbed by type with [`MockServer::expect`], clients are
+/// connected with [`MockServer::connect`], and [`MockServer::verify`] checks
+/// every expectation was met. A request no expectation takes is answered
+/// with an [`RpcError::Internal`], and fails `verify`. Only plain calls are
+/// answered; chunked responses, subscriptions, uploads and the like aren't.
+/// Clones share the expectations, and the clients connected already.
+pub struct MockServer<Req: Request> {
+    state: Arc<Mutex<State<Req>>>,
+}
+
+struct State<Req: Request> {
+    expectations: Vec<Expectation<Req>>,
+    /// Requests no expectation took, for `verify` to report.
+    unexpected: Vec<String>,
+    /// Requests received, by name.
+    calls: HashMap<&'static str, usize>,
+}
+
+/// A stub, type-erased down to the enum it is a variant of.
+struct Expectation<Req: Request> {
+    name: &'static str,
+    takes: Matcher<Req>,
+    answer: Answer<Req>,
+    times: Option<usize>,
+    delay: Duration,
+    calls: usize,
+}
+
+type Matcher<R> = Box<dyn Fn(&R) -> bool + Send>;
+
+type Answer<Req> = Box<dyn Fn(&Req) -> Result<<Req as Request>::Resp, RpcError> + Send>;
+
+impl<Req: Request> Clone for MockServer<Req> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<Req: Request> Default for MockServer<Req> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Req: Request> fmt::Debug for MockServer<Req> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("MockServer")
+            .field("expectations", &state.expectations.len())
+            .field("calls", &state.calls)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<Req: Request> MockServer<Req> {
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                expectations: Vec::new(),
+                unexpected: Vec::new(),
+                calls: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Starts stubbing requests of type `R`, e.g.
+    /// `mock.expect::<Add>().returning(|req| req.lhs + req.rhs)`. Each
+    /// request goes to the first expectation set up that takes it.
+    pub fn expect<R: Request>(&self) -> Expect<'_, Req, R>
+    where
+        Req: Wraps<R>,
+    {
+        Expect {
+            mock: self,
+            matchers: Vec::new(),
+            times: None,
+            delay: Duration::ZERO,
+            _request: PhantomData,
+        }
+    }
+
+    /// A client connected to the mock with default settings.
+    pub async fn connect(&self) -> Result<Client> {
+        self.connect_with(Client::builder()).await
+    }
+
+    /// A client built by `builder`, connected to the mock, which uses the
+    /// same framing.
+    pub async fn connect_with(&self, builder: ClientBuilder) -> Result<Client> {
+        let (ours, theirs) = tokio::io::duplex(TRANSPORT_CAPACITY);
+        tokio::spawn(serve(theirs, builder.framing(), self.state.clone()));
+        builder.handshake(ours).await
+    }
+
+    /// Number of requests of type `R` received, whether expected or not.
+    pub fn calls<R: Request>(&self) -> usize
+    where
+        Req: Wraps<R>,
+    {
+        let state = self.state.lock().unwrap();
+        state.calls.get(R::NAME).copied().unwrap_or(0)
+    }
+
+    /// Panics unless every expectation took as many requests as it was set
+    /// to with [`Expect::times`], or at least one otherwise, and every
+    /// request received was expected.
+    #[track_caller]
+    pub fn verify(&self) {
+        let state = self.state.lock().unwrap();
+        let mut unmet = Vec::new();
+        for expectation in &state.expectations {
+            let Expectation { name, calls, .. } = expectation;
+            match expectation.times {
+                Some(times) if *calls != times => {
+                    unmet.push(format!("{name} expected {times} times, called {calls}"));
+                }
+                None if *calls == 0 => unmet.push(format!("{name} expected, never called")),
+                _ => {}
+            }
+        }
+        unmet.extend(
+            state
+                .unexpected
+                .iter()
+                .map(|req| format!("unexpected {req}")),
+        );
+        assert!(
+            unmet.is_empty(),
+            "mock server expectations not met:\n{}",
+            unmet.join("\n")
+        );
+    }
+}
+
+/// An expectation being set up, see [`MockServer::expect`]. Takes effect
+/// once given its answer, with [`Expect::returning`] or [`Expect::failing`].
+#[must_use = "an expectation takes effect once given its answer"]
+pub struct Expect<'a, Req: Request, R> {
+    mock: &'a MockServer<Req>,
+    matchers: Vec<Matcher<R>>,
+    times: Option<usize>,
+    delay: Duration,
+    _request: PhantomData<fn(&R)>,
+}
+
+impl<Req: Wraps<R>, R: Request> Expect<'_, Req, R> {
+    /// Only takes requests `matcher` accepts, e.g. `|req| req.lhs > 0`.
+    /// Several of them all have to.
+    pub fn with(mut self, matcher: impl Fn(&R) -> bool + Send + 'static) -> Self {
+        self.matchers.push(Box::new(matcher));
+        self
+    }
+
+    /// Takes `times` requests, leaving the ones after to the expectations
+    /// set up after it. Takes any number otherwise.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    /// Waits `delay` before answering each request, e.g. to have calls time
+    /// out.
+    pub fn delayed(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Answers every request taken with what `answer` makes of it.
+    pub fn returning(self, answer: impl Fn(&R) -> R::Resp + Send + 'static) {
+        self.answer(move |req| Ok(answer(req)));
+    }
+
+    /// Answers every request taken with the error `answer` makes of it, as
+    /// if it had failed on the server.
+    pub fn failing(self, answer: impl Fn(&R) -> RpcError + Send + 'static) {
+        self.answer(move |req| Err(answer(req)));
+    }
+
+    fn answer(self, answer: impl Fn(&R) -> Result<R::Resp, RpcError> + Send + 'static) {
+        let matchers = self.matchers;
+        let expectation = Expectation {
+            name: R::NAME,
+            takes: Box::new(move |req: &Req| {
+                req.wrapped()
+                    .is_some_and(|req| matchers.iter().all(|matches| matches(req)))
+            }),
+            answer: Box::new(move |req: &Req| {
+                let req = req.wrapped().expect("only requests taken are answered");
+                answer(req).map(Req::wrap_response)
+            }),
+            times: self.times,
+            delay: self.delay,
+            calls: 0,
+        };
+        let mut state = self.mock.state.lock().unwrap();
+        state.expectations.push(expectation);
+    }
+}
+
+/// Answers the client on `socket` until it goes away.
+async fn serve<Req: Request>(
+    socket: DuplexStream,
+    framing: Framing,
+    state: Arc<Mutex<State<Req>>>,
+) {
+    let mut framed = Framed::new(socket, framing.codec());
+    let Some(Ok(hello)) = framed.next().await else {
+        return;
+    };
+    let Some(hello) = Hello::decode(&hello) else {
+        return;
+    };
+    let formats = [WireFormat::Bincode, WireFormat::Json];
+    let ack = hello.answer(&formats, framing.max_frame_bytes as u64, &[]);
+    let codec = ack.wire_format().unwrap_or(WireFormat::FALLBACK);
+    if framed.send(Bytes::from(ack.encode())).await.is_err() {
+        return;
+    }
+
+    // Answers go out from a task of their own, as delayed ones may be
+    // overtaken.
+    let (mut sink, mut stream) = framed.split();
+    let (answers, mut answered) = mpsc::unbounded_channel::<Bytes>();
+    tokio::spawn(async move {
+        while let Some(frame) = answered.recv().await {
+            if sink.send(frame).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(Ok(frame)) = stream.next().await {
+        let frame = frame.freeze();
+        let Ok((header, body)) = decode_header(&codec, &frame) else {
+            break;
+        };
+        // Cancellations and the like have nothing to answer.
+        if !matches!(
+            header.kind,
+            FrameKind::Request | FrameKind::VersionedRequest
+        ) {
+            continue;
+        }
+        let body = frame.slice_ref(body);
+        let (delay, resp) = answer(&state, &codec, &header, &body);
+        let frame = match resp {
+            Ok(resp) => encode_frame(&codec, &Header::new(header.id, FrameKind::Response), &resp)
+                .map_err(|e| RpcError::Internal(format!("failed to encode response: {e}"))),
+            Err(err) => Err(err),
+        };
+        let frame = frame.unwrap_or_else(|err| {
+            encode_frame(&codec, &Header::new(header.id, FrameKind::Error), &err)
+                .expect("errors always encode")
+        });
+
+        let answers = answers.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            let _ = answers.send(Bytes::from(frame));
+        });
+    }
+}
+
+/// What the request in `header` and `body` is answered with, and how long
+/// after.
+fn answer<Req: Request>(
+    state: &Mutex<State<Req>>,
+    codec: &WireFormat,
+    header: &Header,
+    body: &Bytes,
+) -> (Duration, Result<Req::Resp, RpcError>) {
+    let name = header.method.as_ref().map_or("", |m| m.name.as_str());
+    if Req::versions(name).is_empty() {
+        return (
+            Duration::ZERO,
+            Err(RpcError::UnknownMethod(name.to_owned())),
+        );
+    }
+    let req: Req = match with_frame(body, || codec.decode(body)) {
+        Ok(req) => req,
+        Err(e) => {
+            let err = RpcError::Internal(format!("mock server failed to decode {name}: {e}"));
+            return (Duration::ZERO, Err(err));
+        }
+    };
+
+    let mut state = state.lock().unwrap();
+    *state.calls.entry(req.name()).or_default() += 1;
+    let expectation = state.expectations.iter_mut().find(|expectation| {
+        expectation
+            .times
+            .is_none_or(|times| expectation.calls < times)
+            && (expectation.takes)(&req)
+    });
+    match expectation {
+        Some(expectation) => {
+            expectation.calls += 1;
+            (expectation.delay, (expectation.answer)(&req))
+        }
+        None => {
+            let err = RpcError::Internal(format!("no expectation takes {req:?}"));
+            state.unexpected.push(format!("{req:?}"));
+            (Duration::ZERO, Err(err))
+        }
+    }
+}
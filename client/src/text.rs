@@ -0,0 +1,98 @@
+//! The JSON5 text form of requests and responses, as typed into and printed
+//! by the client's REPL.
+//!
+//! Requests are read as JSON5, so keys may go unquoted and trailing commas
+//! are fine. Responses are written as plain JSON, which is valid JSON5 too.
+//!
+//! A request can also be put together one argument at a time, guided by its
+//! [`MethodSchema`]: see [`guided_method`] and [`assemble_request`].
+
+use protocol::Request;
+use protocol::schema::{ArgSchema, MethodSchema, RpcSchema};
+
+use serde::Serialize;
+
+/// Parses a single request, e.g. `{ type: "Add", lhs: 1, rhs: 2 }` for an
+/// `#[rpc]` enum tagged with `#[serde(tag = "type")]`.
+pub fn parse_request<Req: Request>(input: &str) -> Result<Req, json5::Error> {
+    json5::from_str(input.trim())
+}
+
+/// Formats a request so that [`parse_request`] reads it back unchanged.
+pub fn format_request<Req: Request>(req: &Req) -> Result<String, json5::Error> {
+    json5::to_string(req)
+}
+
+pub fn format_response<Resp: Serialize>(resp: &Resp) -> Result<String, json5::Error> {
+    json5::to_string(resp)
+}
+
+/// The method `input` names on its own, without any arguments, for the REPL
+/// to prompt for them one by one rather than parse `input` as a request.
+pub fn guided_method<'a>(schema: &'a RpcSchema, input: &str) -> Option<&'a MethodSchema> {
+    schema.method(input.trim())
+}
+
+/// The arguments `method` takes and what it returns, e.g.
+/// `Add { lhs: i32, rhs: i32 } -> i32`.
+pub fn describe_method(method: &MethodSchema) -> String {
+    let args: Vec<_> = method
+        .args
+        .iter()
+        .map(|arg| format!("{}: {}", arg.name, arg.ty))
+        .collect();
+    if args.is_empty() {
+        format!("{} -> {}", method.name, method.response)
+    } else {
+        let args = args.join(", ");
+        format!("{} {{ {args} }} -> {}", method.name, method.response)
+    }
+}
+
+/// What to prompt with for the value of `arg`, e.g. `lhs (i32): `.
+pub fn arg_prompt(arg: &ArgSchema) -> String {
+    format!("{} ({}): ", arg.name, arg.ty)
+}
+
+/// Puts together a request for `method` from `answers`, the values of its
+/// arguments in order.
+///
+/// Every answer is a JSON5 value, but text arguments may go without quotes,
+/// and an empty answer to any other argument stands for `null`, e.g. for an
+/// `Option` left out. Parses as [`parse_request`] would, so the enum has to
+/// be tagged with `type`, and its variants named like their requests.
+pub fn assemble_request<Req: Request>(
+    method: &MethodSchema,
+    answers: &[impl AsRef<str>],
+) -> Result<Req, json5::Error> {
+    if answers.len() != method.args.len() {
+        return Err(json5::Error::Message {
+            msg: format!(
+                "{} takes {} arguments, not {}",
+                method.name,
+                method.args.len(),
+                answers.len()
+            ),
+            location: None,
+        });
+    }
+
+    let mut fields = vec![format!("type: {}", json5::to_string(&method.name)?)];
+    for (arg, answer) in method.args.iter().zip(answers) {
+        let answer = answer.as_ref().trim();
+        let value = if is_text(&arg.ty) && !answer.starts_with(['"', '\'']) {
+            json5::to_string(&answer)?
+        } else if answer.is_empty() {
+            "null".to_owned()
+        } else {
+            answer.to_owned()
+        };
+        fields.push(format!("{}: {value}", json5::to_string(&arg.name)?));
+    }
+    parse_request(&format!("{{ {} }}", fields.join(", ")))
+}
+
+/// Whether `ty`, as spelled in the schema, is a string type.
+fn is_text(ty: &str) -> bool {
+    ty == "String" || ty.ends_with("str") || ty.ends_with("str>")
+}
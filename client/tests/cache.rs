@@ -0,0 +1,182 @@
+//! Idempotent calls answered from the client's response cache, against a
+//! server faked over an in-memory transport that counts what it is sent.
+
+#![allow(non_snake_case)]
+
+use client::{Client, ResponseCache};
+
+use protocol::RpcError;
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio_util::codec::Framed;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Lookup(Lookup),
+    Bump(Bump),
+}
+
+// Never run: the fake server answers for them.
+#[request(idempotent)]
+fn Lookup(key: String) -> String {
+    key
+}
+
+#[request]
+fn Bump(key: String) -> String {
+    key
+}
+
+/// Answers every request with its key and how many frames came before it,
+/// failing lookups of "missing", and counting the frames in `seen`.
+async fn fake_server(socket: DuplexStream, seen: Arc<AtomicUsize>) {
+    let codec = WireFormat::FALLBACK;
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    framed.next().await.unwrap().unwrap();
+    framed.send(Bytes::from(vec![codec.id()])).await.unwrap();
+
+    while let Some(Ok(frame)) = framed.next().await {
+        let (header, body) = decode_header(&codec, &frame).unwrap();
+        let n = seen.fetch_add(1, Ordering::SeqCst);
+        let frame = match codec.decode(body).unwrap() {
+            AppRequest::Lookup(Lookup { key }) if key == "missing" => {
+                let err = RpcError::Handler("no such key".into());
+                encode_frame(&codec, &Header::new(header.id, FrameKind::Error), &err)
+            }
+            AppRequest::Lookup(Lookup { key }) => {
+                let resp = AppResponse::Lookup(format!("{key}#{n}"));
+                encode_frame(&codec, &Header::new(header.id, FrameKind::Response), &resp)
+            }
+            AppRequest::Bump(Bump { key }) => {
+                let resp = AppResponse::Bump(format!("{key}#{n}"));
+                encode_frame(&codec, &Header::new(header.id, FrameKind::Response), &resp)
+            }
+        };
+        framed.send(Bytes::from(frame.unwrap())).await.unwrap();
+    }
+}
+
+async fn connect(cache: &ResponseCache) -> (Client, Arc<AtomicUsize>) {
+    let (ours, theirs) = tokio::io::duplex(64 * 1024);
+    let seen = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(fake_server(theirs, seen.clone()));
+    let client = Client::builder()
+        .response_cache(cache.clone())
+        .handshake(ours)
+        .await
+        .unwrap();
+    (client, seen)
+}
+
+fn lookup(key: &str) -> AppRequest {
+    AppRequest::Lookup(Lookup { key: key.into() })
+}
+
+#[tokio::test]
+async fn repeats_are_answered_without_sending_them() {
+    let cache = ResponseCache::new(8, Duration::from_secs(60));
+    let (client, seen) = connect(&cache).await;
+
+    let first = client.call_cached(lookup("a")).await;
+    assert!(
+        matches!(&first, Ok(AppResponse::Lookup(s)) if s == "a#0"),
+        "{first:?}"
+    );
+    let second = client.call_cached(lookup("a")).await;
+    assert!(
+        matches!(&second, Ok(AppResponse::Lookup(s)) if s == "a#0"),
+        "{second:?}"
+    );
+    assert_eq!(seen.load(Ordering::SeqCst), 1);
+
+    // Other fields make another request.
+    let other = client.call_cached(lookup("b")).await;
+    assert!(
+        matches!(&other, Ok(AppResponse::Lookup(s)) if s == "b#1"),
+        "{other:?}"
+    );
+    assert_eq!(seen.load(Ordering::SeqCst), 2);
+    assert_eq!(cache.len(), 2);
+
+    // Plain calls go past the cache.
+    let fresh = client.call(lookup("a")).await;
+    assert!(
+        matches!(&fresh, Ok(AppResponse::Lookup(s)) if s == "a#2"),
+        "{fresh:?}"
+    );
+
+    // Clients sharing the cache share the responses.
+    let (other_client, other_seen) = connect(&cache).await;
+    let shared = other_client.call_cached(lookup("a")).await;
+    assert!(
+        matches!(&shared, Ok(AppResponse::Lookup(s)) if s == "a#0"),
+        "{shared:?}"
+    );
+    assert_eq!(other_seen.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn only_idempotent_successes_are_kept() {
+    let cache = ResponseCache::new(8, Duration::from_secs(60));
+    let (client, seen) = connect(&cache).await;
+
+    for _ in 0..2 {
+        let bump = client
+            .call_cached(AppRequest::Bump(Bump { key: "a".into() }))
+            .await;
+        assert!(matches!(bump, Ok(AppResponse::Bump(_))), "{bump:?}");
+        let missing = client.call_cached(lookup("missing")).await;
+        assert!(
+            matches!(missing, Err(client::Error::Rpc(RpcError::Handler(_)))),
+            "{missing:?}"
+        );
+    }
+    assert_eq!(seen.load(Ordering::SeqCst), 4);
+    assert!(cache.is_empty());
+}
+
+#[tokio::test]
+async fn responses_expire() {
+    let cache = ResponseCache::new(8, Duration::from_millis(50));
+    let (client, seen) = connect(&cache).await;
+
+    client.call_cached(lookup("a")).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let expired = client.call_cached(lookup("a")).await;
+    assert!(
+        matches!(&expired, Ok(AppResponse::Lookup(s)) if s == "a#1"),
+        "{expired:?}"
+    );
+    assert_eq!(seen.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn the_least_recently_used_makes_room() {
+    let cache = ResponseCache::new(2, Duration::from_secs(60));
+    let (client, seen) = connect(&cache).await;
+
+    // "a" was used last, so "b" makes way for "c".
+    client.call_cached(lookup("a")).await.unwrap();
+    client.call_cached(lookup("b")).await.unwrap();
+    client.call_cached(lookup("a")).await.unwrap();
+    client.call_cached(lookup("c")).await.unwrap();
+    assert_eq!((cache.len(), seen.load(Ordering::SeqCst)), (2, 3));
+    client.call_cached(lookup("a")).await.unwrap();
+    assert_eq!(seen.load(Ordering::SeqCst), 3);
+    client.call_cached(lookup("b")).await.unwrap();
+    assert_eq!(seen.load(Ordering::SeqCst), 4);
+
+    cache.clear();
+    client.call_cached(lookup("b")).await.unwrap();
+    assert_eq!(seen.load(Ordering::SeqCst), 5);
+}
@@ -0,0 +1,108 @@
+//! Closing a client with calls still in flight, against a server faked over
+//! an in-memory transport.
+
+#![allow(non_snake_case)]
+
+use client::Client;
+
+use protocol::codec::WireFormat;
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio::sync::oneshot;
+use tokio_util::codec::Framed;
+
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+}
+
+// Never run: the fake server answers for it.
+#[request]
+fn Echo(n: u32) -> u32 {
+    n
+}
+
+/// Answers the first request with 7 once `answer` fires, if it does, then
+/// reports whether the client went on to end the connection cleanly.
+async fn fake_server(socket: DuplexStream, answer: oneshot::Receiver<()>) -> bool {
+    let codec = WireFormat::FALLBACK;
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    framed.next().await.unwrap().unwrap();
+    framed.send(Bytes::from(vec![codec.id()])).await.unwrap();
+
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, _) = decode_header(&codec, &frame).unwrap();
+    if answer.await.is_ok() {
+        let resp = AppResponse::Echo(7);
+        let frame = encode_frame(&codec, &Header::new(header.id, FrameKind::Response), &resp);
+        framed.send(Bytes::from(frame.unwrap())).await.unwrap();
+    }
+    framed.next().await.is_none()
+}
+
+async fn wait_in_flight(client: &Client) {
+    while client.in_flight() == 0 {
+        tokio::task::yield_now().await;
+    }
+}
+
+#[tokio::test]
+async fn waits_for_calls_in_flight() {
+    let (ours, theirs) = tokio::io::duplex(64 * 1024);
+    let (answer, answered) = oneshot::channel();
+    let server = tokio::spawn(fake_server(theirs, answered));
+    let client = Client::builder().handshake(ours).await.unwrap();
+
+    let call = tokio::spawn({
+        let client = client.clone();
+        async move { client.call(AppRequest::Echo(Echo { n: 7 })).await }
+    });
+    wait_in_flight(&client).await;
+
+    let closing = tokio::spawn({
+        let client = client.clone();
+        async move { client.close().await }
+    });
+    while !client.is_closed() {
+        tokio::task::yield_now().await;
+    }
+    let resp = client.call(AppRequest::Echo(Echo { n: 1 })).await;
+    assert!(matches!(resp, Err(client::Error::Closed)), "{resp:?}");
+
+    answer.send(()).unwrap();
+    let resp = call.await.unwrap();
+    assert!(matches!(resp, Ok(AppResponse::Echo(7))), "{resp:?}");
+    closing.await.unwrap();
+    assert!(server.await.unwrap(), "connection not ended cleanly");
+}
+
+#[tokio::test]
+async fn fails_calls_still_unanswered_after_the_timeout() {
+    let (ours, theirs) = tokio::io::duplex(64 * 1024);
+    let (_answer, answered) = oneshot::channel();
+    tokio::spawn(fake_server(theirs, answered));
+    let client = Client::builder()
+        .close_timeout(Duration::from_millis(20))
+        .handshake(ours)
+        .await
+        .unwrap();
+
+    let call = tokio::spawn({
+        let client = client.clone();
+        async move { client.call(AppRequest::Echo(Echo { n: 7 })).await }
+    });
+    wait_in_flight(&client).await;
+
+    tokio::time::timeout(Duration::from_secs(5), client.close())
+        .await
+        .expect("close waited past its timeout");
+    let resp = call.await.unwrap();
+    assert!(matches!(resp, Err(client::Error::Closed)), "{resp:?}");
+}
@@ -0,0 +1,102 @@
+//! Requests fanned out to several servers, collected as the policy asks.
+
+#![allow(non_snake_case)]
+
+use client::testing::MockServer;
+use client::{FanOutPolicy, WaitFor, fan_out};
+
+use protocol::RpcError;
+
+use macros::{request, rpc};
+
+use std::time::Duration;
+
+#[rpc(response = "StoreResponse")]
+enum StoreRequest {
+    Get(Get),
+}
+
+// Never run: the mocks answer for it.
+#[request]
+fn Get(key: String) -> u64 {
+    key.len() as u64
+}
+
+/// A server answering `Get` with `value`, after `delay`.
+async fn replica(value: u64, delay: Duration) -> (MockServer<StoreRequest>, client::Client) {
+    let mock = MockServer::<StoreRequest>::new();
+    mock.expect::<Get>()
+        .delayed(delay)
+        .returning(move |_| value);
+    let client = mock.connect().await.unwrap();
+    (mock, client)
+}
+
+fn get() -> StoreRequest {
+    StoreRequest::Get(Get { key: "k".into() })
+}
+
+#[tokio::test]
+async fn all_collects_every_response() {
+    let (first, a) = replica(1, Duration::ZERO).await;
+    let (second, b) = replica(2, Duration::from_millis(20)).await;
+
+    let results = fan_out(&[a, b], get(), &FanOutPolicy::default()).await;
+    assert!(results.is_met());
+    let values: Vec<_> = results
+        .responses()
+        .map(|(index, resp)| match resp {
+            StoreResponse::Get(value) => (index, *value),
+        })
+        .collect();
+    assert_eq!(values, [(0, 1), (1, 2)]);
+    first.verify();
+    second.verify();
+}
+
+#[tokio::test]
+async fn first_stops_waiting_once_enough_have_answered() {
+    let (_fast, a) = replica(1, Duration::ZERO).await;
+    let (_slow, b) = replica(2, Duration::from_secs(60)).await;
+
+    let policy = FanOutPolicy {
+        wait_for: WaitFor::First(1),
+        ..FanOutPolicy::default()
+    };
+    let results = tokio::time::timeout(Duration::from_secs(5), fan_out(&[a, b], get(), &policy))
+        .await
+        .unwrap();
+    assert!(results.is_met());
+    assert!(matches!(
+        results.results[0],
+        Some(Ok(StoreResponse::Get(1)))
+    ));
+    assert!(results.results[1].is_none());
+}
+
+#[tokio::test]
+async fn failures_are_reported_per_server() {
+    let (_up, a) = replica(1, Duration::ZERO).await;
+    let (_late, b) = replica(2, Duration::from_secs(60)).await;
+    let down = MockServer::<StoreRequest>::new();
+    down.expect::<Get>()
+        .failing(|_| RpcError::Handler("replica down".into()));
+    let c = down.connect().await.unwrap();
+
+    let policy = FanOutPolicy {
+        wait_for: WaitFor::Quorum,
+        timeout: Some(Duration::from_millis(50)),
+    };
+    let results = fan_out(&[a, b, c], get(), &policy).await;
+    assert!(!results.is_met());
+    let errors: Vec<_> = results.errors().map(|(index, _)| index).collect();
+    assert_eq!(errors, [1, 2]);
+    assert!(
+        matches!(
+            &results.results[1],
+            Some(Err(client::Error::Rpc(RpcError::DeadlineExceeded(name)))) if name == "Get"
+        ),
+        "{:?}",
+        results.results[1]
+    );
+}
@@ -0,0 +1,139 @@
+//! Client code tested against a mock server stubbing its responses.
+
+#![allow(non_snake_case)]
+
+use client::RetryPolicy;
+use client::testing::MockServer;
+
+use protocol::RpcError;
+
+use macros::{request, rpc};
+
+use std::time::Duration;
+
+#[rpc(response = "CalcResponse")]
+enum CalcRequest {
+    Add(Add),
+    Divide(Divide),
+    Total(Total),
+}
+
+// Never run: the mock answers for them.
+#[request]
+fn Add(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+#[request]
+fn Divide(dividend: i32, divisor: i32) -> i32 {
+    dividend / divisor
+}
+
+#[request(idempotent)]
+fn Total() -> i32 {
+    0
+}
+
+#[tokio::test]
+async fn stubs_answer_calls() {
+    let mock = MockServer::<CalcRequest>::new();
+    mock.expect::<Add>().returning(|req| req.lhs + req.rhs);
+    let client = mock.connect().await.unwrap();
+
+    for (lhs, rhs) in [(2, 3), (-1, 1)] {
+        let resp = client.call(CalcRequest::Add(Add { lhs, rhs })).await;
+        assert!(
+            matches!(resp, Ok(CalcResponse::Add(sum)) if sum == lhs + rhs),
+            "{resp:?}"
+        );
+    }
+    assert_eq!(mock.calls::<Add>(), 2);
+    mock.verify();
+}
+
+#[tokio::test]
+async fn matchers_pick_the_stub() {
+    let mock = MockServer::<CalcRequest>::new();
+    mock.expect::<Divide>()
+        .with(|req| req.divisor == 0)
+        .times(1)
+        .failing(|_| RpcError::ValidationFailed("division by zero".into()));
+    mock.expect::<Divide>()
+        .returning(|req| req.dividend / req.divisor);
+    let client = mock.connect().await.unwrap();
+
+    let resp = client
+        .call(CalcRequest::Divide(Divide {
+            dividend: 1,
+            divisor: 0,
+        }))
+        .await;
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::ValidationFailed(_)))),
+        "{resp:?}"
+    );
+    let resp = client
+        .call(CalcRequest::Divide(Divide {
+            dividend: 9,
+            divisor: 3,
+        }))
+        .await;
+    assert!(matches!(resp, Ok(CalcResponse::Divide(3))), "{resp:?}");
+    mock.verify();
+}
+
+#[tokio::test]
+async fn retried_calls_go_through_the_stubs_in_turn() {
+    let mock = MockServer::<CalcRequest>::new();
+    mock.expect::<Total>()
+        .times(2)
+        .failing(|_| RpcError::Busy("Total".into()));
+    mock.expect::<Total>().times(1).returning(|_| 42);
+    let client = mock.connect().await.unwrap();
+
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        initial_backoff: Duration::from_millis(1),
+        max_backoff: Duration::from_millis(10),
+    };
+    let resp = client
+        .call_with_retry(CalcRequest::Total(Total {}), &policy)
+        .await;
+    assert!(matches!(resp, Ok(CalcResponse::Total(42))), "{resp:?}");
+    assert_eq!(mock.calls::<Total>(), 3);
+    mock.verify();
+}
+
+#[tokio::test]
+async fn delayed_answers_let_calls_time_out() {
+    let mock = MockServer::<CalcRequest>::new();
+    mock.expect::<Total>()
+        .delayed(Duration::from_secs(60))
+        .returning(|_| 0);
+    let client = mock.connect().await.unwrap();
+
+    let resp = tokio::time::timeout(
+        Duration::from_millis(50),
+        client.call(CalcRequest::Total(Total {})),
+    )
+    .await;
+    assert!(resp.is_err(), "{resp:?}");
+    mock.verify();
+}
+
+#[tokio::test]
+#[should_panic(expected = "unexpected Add")]
+async fn unexpected_calls_fail_verification() {
+    let mock = MockServer::<CalcRequest>::new();
+    mock.expect::<Divide>()
+        .times(0)
+        .returning(|req| req.dividend / req.divisor);
+    let client = mock.connect().await.unwrap();
+
+    let resp = client.call(CalcRequest::Add(Add { lhs: 1, rhs: 2 })).await;
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::Internal(_)))),
+        "{resp:?}"
+    );
+    mock.verify();
+}
@@ -0,0 +1,157 @@
+//! Calls spread over the connections of a pool, against a server faked over
+//! TCP that answers with the number of the connection it got them on.
+
+#![allow(non_snake_case)]
+
+use client::{ClientPool, Strategy};
+
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{Notify, mpsc};
+use tokio_util::codec::Framed;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Which(Which),
+    Hold(Hold),
+    Quit(Quit),
+}
+
+// Never run: the fake server answers for them.
+#[request]
+fn Which() -> usize {
+    0
+}
+
+#[request]
+fn Hold() -> usize {
+    0
+}
+
+#[request]
+fn Quit() -> usize {
+    0
+}
+
+/// Accepts connections, numbering them from 0 in the order they come in.
+/// `Hold` is answered once `release` is notified, and `Quit` closes the
+/// connection without an answer.
+async fn fake_server(release: Arc<Notify>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        for conn in 0.. {
+            let (socket, _) = listener.accept().await.unwrap();
+            tokio::spawn(serve(socket, conn, release.clone()));
+        }
+    });
+    addr
+}
+
+async fn serve(socket: TcpStream, conn: usize, release: Arc<Notify>) {
+    let codec = WireFormat::FALLBACK;
+    let (mut sink, mut stream) = Framed::new(socket, Framing::default().codec()).split();
+    stream.next().await.unwrap().unwrap();
+    sink.send(Bytes::from(vec![codec.id()])).await.unwrap();
+
+    let (answers, mut answered) = mpsc::unbounded_channel::<Vec<u8>>();
+    let writer = tokio::spawn(async move {
+        while let Some(frame) = answered.recv().await {
+            if sink.send(Bytes::from(frame)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    while let Some(Ok(frame)) = stream.next().await {
+        let (header, body) = decode_header(&codec, &frame).unwrap();
+        let resp = match codec.decode(body).unwrap() {
+            AppRequest::Which(_) => AppResponse::Which(conn),
+            AppRequest::Hold(_) => AppResponse::Hold(conn),
+            AppRequest::Quit(_) => break,
+        };
+        let answers = answers.clone();
+        let release = release.clone();
+        tokio::spawn(async move {
+            if matches!(resp, AppResponse::Hold(_)) {
+                release.notified().await;
+            }
+            let header = Header::new(header.id, FrameKind::Response);
+            let _ = answers.send(encode_frame(&codec, &header, &resp).unwrap());
+        });
+    }
+    writer.abort();
+}
+
+async fn which(pool: &ClientPool) -> usize {
+    match pool.call(AppRequest::Which(Which {})).await.unwrap() {
+        AppResponse::Which(conn) => conn,
+        resp => panic!("unexpected {resp:?}"),
+    }
+}
+
+#[tokio::test]
+async fn round_robin_takes_every_connection_in_turn() {
+    let addr = fake_server(Arc::default()).await;
+    let pool = ClientPool::connect(addr.to_string(), 3, Strategy::RoundRobin)
+        .await
+        .unwrap();
+
+    let mut conns = Vec::new();
+    for _ in 0..6 {
+        conns.push(which(&pool).await);
+    }
+    assert_eq!(conns, [0, 1, 2, 0, 1, 2]);
+}
+
+#[tokio::test]
+async fn least_in_flight_picks_an_idle_connection() {
+    let release = Arc::new(Notify::new());
+    let addr = fake_server(release.clone()).await;
+    let pool = Arc::new(
+        ClientPool::connect(addr.to_string(), 2, Strategy::LeastInFlight)
+            .await
+            .unwrap(),
+    );
+
+    let held = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.call(AppRequest::Hold(Hold {})).await }
+    });
+    while pool.in_flight() == [0, 0] {
+        tokio::task::yield_now().await;
+    }
+    assert_eq!(pool.in_flight(), [1, 0]);
+
+    for _ in 0..3 {
+        assert_eq!(which(&pool).await, 1);
+    }
+
+    release.notify_one();
+    let resp = held.await.unwrap();
+    assert!(matches!(resp, Ok(AppResponse::Hold(0))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn closed_connections_are_replaced() {
+    let addr = fake_server(Arc::default()).await;
+    let pool = ClientPool::connect(addr.to_string(), 1, Strategy::RoundRobin)
+        .await
+        .unwrap();
+    assert_eq!(which(&pool).await, 0);
+
+    let resp = pool.call(AppRequest::Quit(Quit {})).await;
+    assert!(resp.is_err(), "{resp:?}");
+
+    assert_eq!(which(&pool).await, 1);
+    assert_eq!(which(&pool).await, 1);
+}
@@ -0,0 +1,184 @@
+//! Retrying idempotent calls, against a server faked over an in-memory
+//! transport that fails some attempts on purpose.
+
+#![allow(non_snake_case)]
+
+use client::{Client, ClientBuilder, RetryPolicy};
+
+use protocol::codec::WireFormat;
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+use protocol::{Request, RpcError};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio_util::codec::Framed;
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Balance(Balance),
+    Charge(Charge),
+}
+
+// Never run: the fake server answers for them.
+#[request(idempotent)]
+fn Balance() -> u64 {
+    0
+}
+
+#[request]
+fn Charge(amount: u64) -> u64 {
+    amount
+}
+
+const POLICY: RetryPolicy = RetryPolicy {
+    max_attempts: 3,
+    initial_backoff: Duration::from_millis(1),
+    max_backoff: Duration::from_millis(10),
+};
+
+/// Answers each request with the next of `replies`, counting the requests
+/// in `seen`.
+async fn fake_server(
+    socket: DuplexStream,
+    mut replies: VecDeque<Result<AppResponse, RpcError>>,
+    seen: Arc<AtomicUsize>,
+) {
+    let codec = WireFormat::FALLBACK;
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    framed.next().await.unwrap().unwrap();
+    framed.send(Bytes::from(vec![codec.id()])).await.unwrap();
+
+    while let Some(Ok(frame)) = framed.next().await {
+        let (header, _) = decode_header(&codec, &frame).unwrap();
+        seen.fetch_add(1, Ordering::SeqCst);
+        let frame = match replies.pop_front().expect("more requests than replies") {
+            Ok(resp) => encode_frame(&codec, &Header::new(header.id, FrameKind::Response), &resp),
+            Err(err) => encode_frame(&codec, &Header::new(header.id, FrameKind::Error), &err),
+        };
+        framed.send(Bytes::from(frame.unwrap())).await.unwrap();
+    }
+}
+
+async fn connect(replies: Vec<Result<AppResponse, RpcError>>) -> (Client, Arc<AtomicUsize>) {
+    connect_with(Client::builder(), replies).await
+}
+
+async fn connect_with(
+    builder: ClientBuilder,
+    replies: Vec<Result<AppResponse, RpcError>>,
+) -> (Client, Arc<AtomicUsize>) {
+    let (ours, theirs) = tokio::io::duplex(64 * 1024);
+    let seen = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(fake_server(theirs, replies.into(), seen.clone()));
+    let client = builder.handshake(ours).await.unwrap();
+    (client, seen)
+}
+
+fn busy() -> Result<AppResponse, RpcError> {
+    Err(RpcError::Busy(Balance::NAME.into()))
+}
+
+#[tokio::test]
+async fn retries_idempotent_calls() {
+    let (client, seen) = connect(vec![busy(), Ok(AppResponse::Balance(7))]).await;
+
+    let resp = client
+        .call_with_retry(AppRequest::Balance(Balance {}), &POLICY)
+        .await;
+
+    assert!(matches!(resp, Ok(AppResponse::Balance(7))), "{resp:?}");
+    assert_eq!(seen.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn gives_up_after_max_attempts() {
+    let (client, seen) = connect(vec![busy(), busy(), busy(), Ok(AppResponse::Balance(7))]).await;
+
+    let resp = client
+        .call_with_retry(AppRequest::Balance(Balance {}), &POLICY)
+        .await;
+
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::Busy(_)))),
+        "{resp:?}"
+    );
+    assert_eq!(seen.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn never_retries_other_calls() {
+    let (client, seen) = connect(vec![busy(), Ok(AppResponse::Charge(0))]).await;
+
+    let resp = client
+        .call_with_retry(AppRequest::Charge(Charge { amount: 5 }), &POLICY)
+        .await;
+
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::Busy(_)))),
+        "{resp:?}"
+    );
+    assert_eq!(seen.load(Ordering::SeqCst), 1);
+    assert!(!AppRequest::Charge(Charge { amount: 5 }).idempotent());
+    assert!(AppRequest::Balance(Balance {}).idempotent());
+}
+
+#[tokio::test]
+async fn reports_calls_that_fail_for_good() {
+    // Name, request, error and attempts of every call reported.
+    let failed = Arc::new(Mutex::new(Vec::new()));
+    let builder = Client::builder().on_failed_request({
+        let failed = failed.clone();
+        move |f| {
+            let report = (
+                f.name,
+                format!("{:?}", f.request),
+                f.error.to_string(),
+                f.attempts,
+            );
+            failed.lock().unwrap().push(report);
+        }
+    });
+    let replies = vec![
+        busy(),
+        busy(),
+        busy(),
+        busy(),
+        Ok(AppResponse::Balance(7)),
+        busy(),
+    ];
+    let (client, _) = connect_with(builder, replies).await;
+
+    let resp = client
+        .call_with_retry(AppRequest::Balance(Balance {}), &POLICY)
+        .await;
+    assert!(resp.is_err(), "{resp:?}");
+    // Succeeding on a retry isn't a failure.
+    let resp = client
+        .call_with_retry(AppRequest::Balance(Balance {}), &POLICY)
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Balance(7))), "{resp:?}");
+    let resp = client
+        .call_with_retry(AppRequest::Charge(Charge { amount: 5 }), &POLICY)
+        .await;
+    assert!(resp.is_err(), "{resp:?}");
+
+    let failed = failed.lock().unwrap();
+    assert!(
+        matches!(
+            &failed[..],
+            [
+                (Balance::NAME, balance, _, 3),
+                (Charge::NAME, charge, error, 1),
+            ] if balance.contains("Balance") && charge.contains("amount: 5") && error.contains("busy"),
+        ),
+        "{failed:?}"
+    );
+}
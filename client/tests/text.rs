@@ -0,0 +1,207 @@
+//! Parsing and formatting requests and responses as text.
+
+#![allow(non_snake_case)]
+
+use client::text::{
+    arg_prompt, assemble_request, describe_method, format_request, format_response, guided_method,
+    parse_request,
+};
+
+use protocol::Request;
+
+use macros::{request, rpc};
+
+#[rpc(response = "AppResponse")]
+#[serde(tag = "type")]
+enum AppRequest {
+    Ping(Ping),
+    Add(Add),
+    Greet(Greet),
+    Search(Search),
+}
+
+#[request]
+fn Add(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+#[request]
+fn Ping() -> String {
+    "You have been pinged".into()
+}
+
+#[request]
+fn Greet(name: String, times: Option<u8>) -> String {
+    name.repeat(times.unwrap_or(1).into())
+}
+
+#[request]
+fn Search(
+    query: String,
+    #[default] offset: u32,
+    #[default = 10] limit: u32,
+    #[default = String::from("relevance")] order: String,
+) -> Vec<String> {
+    vec![format!("{query} {offset} {limit} {order}")]
+}
+
+#[test]
+fn parses_json5_requests() {
+    let req: AppRequest = parse_request("  { type: 'Add', lhs: 1, rhs: -2, }  ").unwrap();
+    assert!(
+        matches!(req, AppRequest::Add(Add { lhs: 1, rhs: -2 })),
+        "{req:?}"
+    );
+
+    let req: AppRequest = parse_request(r#"{"type": "Ping"}"#).unwrap();
+    assert!(matches!(req, AppRequest::Ping(Ping {})), "{req:?}");
+}
+
+#[test]
+fn left_out_fields_take_their_defaults() {
+    let req: AppRequest = parse_request("{ type: 'Search', query: 'rust' }").unwrap();
+    assert!(
+        matches!(&req, AppRequest::Search(s) if s.query == "rust" && s.offset == 0 && s.limit == 10 && s.order == "relevance"),
+        "{req:?}"
+    );
+
+    let req: AppRequest =
+        parse_request("{ type: 'Search', query: 'rust', limit: 3, order: 'new' }").unwrap();
+    assert!(
+        matches!(&req, AppRequest::Search(s) if s.offset == 0 && s.limit == 3 && s.order == "new"),
+        "{req:?}"
+    );
+
+    // Without a default, a field still has to be there.
+    let req = parse_request::<AppRequest>("{ type: 'Search', limit: 3 }");
+    assert!(req.is_err(), "{req:?}");
+}
+
+#[test]
+fn formatted_requests_parse_back() {
+    let text = format_request(&AppRequest::Add(Add { lhs: 3, rhs: 4 })).unwrap();
+    let req: AppRequest = parse_request(&text).unwrap();
+    assert!(
+        matches!(req, AppRequest::Add(Add { lhs: 3, rhs: 4 })),
+        "{req:?}"
+    );
+}
+
+#[test]
+fn formats_responses() {
+    assert_eq!(
+        format_response(&AppResponse::Add(7)).unwrap(),
+        r#"{"Add":7}"#
+    );
+    assert_eq!(
+        format_response(&AppResponse::Ping("hi".into())).unwrap(),
+        r#"{"Ping":"hi"}"#
+    );
+}
+
+#[test]
+fn rejects_malformed_requests() {
+    for input in [
+        "",
+        "{",
+        "not json",
+        "{ type: 'Add', lhs: 1 }",
+        "{ type: 'Add', lhs: 'one', rhs: 2 }",
+        "{ type: 'Divide', lhs: 1, rhs: 2 }",
+        "{ lhs: 1, rhs: 2 }",
+        "[1, 2]",
+    ] {
+        let parsed = parse_request::<AppRequest>(input);
+        assert!(parsed.is_err(), "{input:?} parsed as {parsed:?}");
+    }
+}
+
+#[rpc(response = "TaggedResponse")]
+#[serde(tag = "type")]
+#[serde(rename_all = "snake_case")]
+#[response(serde(tag = "type", content = "value"))]
+enum TaggedRequest {
+    GetUser(GetUser),
+    Ping(Ping),
+}
+
+#[request]
+fn GetUser(id: u32) -> String {
+    format!("user {id}")
+}
+
+#[test]
+fn tagged_requests_round_trip() {
+    let req: TaggedRequest = parse_request("{ type: 'get_user', id: 3 }").unwrap();
+    assert!(
+        matches!(req, TaggedRequest::GetUser(GetUser { id: 3 })),
+        "{req:?}"
+    );
+
+    let text = format_request(&req).unwrap();
+    assert_eq!(text, r#"{"type":"get_user","id":3}"#);
+}
+
+#[test]
+fn tagged_responses_round_trip() {
+    let text = format_response(&TaggedResponse::GetUser("ann".into())).unwrap();
+    assert_eq!(text, r#"{"type":"get_user","value":"ann"}"#);
+
+    let resp: TaggedResponse = json5::from_str(&text).unwrap();
+    assert!(
+        matches!(&resp, TaggedResponse::GetUser(name) if name == "ann"),
+        "{resp:?}"
+    );
+}
+
+#[test]
+fn guides_through_the_arguments() {
+    let schema = AppRequest::schema();
+    assert!(guided_method(&schema, "{ type: 'Add', lhs: 1, rhs: 2 }").is_none());
+    assert!(guided_method(&schema, "Divide").is_none());
+
+    let add = guided_method(&schema, " Add ").unwrap();
+    assert_eq!(describe_method(add), "Add { lhs: i32, rhs: i32 } -> i32");
+    let prompts: Vec<_> = add.args.iter().map(arg_prompt).collect();
+    assert_eq!(prompts, ["lhs (i32): ", "rhs (i32): "]);
+    let req: AppRequest = assemble_request(add, &["1", " -2 "]).unwrap();
+    assert!(
+        matches!(req, AppRequest::Add(Add { lhs: 1, rhs: -2 })),
+        "{req:?}"
+    );
+
+    let ping = guided_method(&schema, "Ping").unwrap();
+    assert_eq!(describe_method(ping), "Ping -> String");
+    let req: AppRequest = assemble_request(ping, &[] as &[&str]).unwrap();
+    assert!(matches!(req, AppRequest::Ping(Ping {})), "{req:?}");
+}
+
+#[test]
+fn text_goes_without_quotes_and_blanks_are_null() {
+    let schema = AppRequest::schema();
+    let greet = guided_method(&schema, "Greet").unwrap();
+
+    for (answers, name, times) in [
+        (["ada", ""], "ada", None),
+        (["'ada'", "2"], "ada", Some(2)),
+        (["\"a, b\"", "null"], "a, b", None),
+        (["a: b } {", "3"], "a: b } {", Some(3)),
+    ] {
+        let req: AppRequest = assemble_request(greet, &answers).unwrap();
+        assert!(
+            matches!(&req, AppRequest::Greet(g) if g.name == name && g.times == times),
+            "{answers:?} gave {req:?}"
+        );
+    }
+}
+
+#[test]
+fn rejects_bad_arguments() {
+    let schema = AppRequest::schema();
+    let add = guided_method(&schema, "Add").unwrap();
+
+    for answers in [&["1"][..], &["1", "2", "3"], &["one", "2"], &["1", ""]] {
+        let req = assemble_request::<AppRequest>(add, answers);
+        assert!(req.is_err(), "{answers:?} gave {req:?}");
+    }
+}
@@ -1,26 +1,64 @@
 use proc_macro::TokenStream;
-use quote::{format_ident, quote};
+use quote::{ToTokens, format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
 use syn::{
-    Ident, ItemEnum, ItemFn, LitStr, Result, Token,
+    Ident, ItemEnum, ItemFn, LitInt, LitStr, Result, Token,
     parse::{Parse, ParseStream},
     parse_macro_input,
 };
 
+use std::collections::HashMap;
+
 struct RequestArgs {
-    name: Option<Ident>,
+    name: Option<LitStr>,
+    version: Option<LitInt>,
+    idempotent: bool,
+    handler: Option<syn::Path>,
+    /// In milliseconds, set along with `cacheable`.
+    cache_ttl: Option<u64>,
 }
 
 impl Parse for RequestArgs {
     fn parse(input: ParseStream) -> Result<Self> {
         let mut name = None;
+        let mut version = None;
+        let mut idempotent = false;
+        let mut handler = None;
+        let mut cacheable = None;
+        let mut ttl = None;
         while !input.is_empty() {
             let lookahead = input.lookahead1();
             if lookahead.peek(Ident) {
                 let ident: Ident = input.parse()?;
+                if ident == "idempotent" || ident == "cacheable" {
+                    if ident == "idempotent" {
+                        idempotent = true;
+                    } else {
+                        cacheable = Some(ident);
+                    }
+                    if input.peek(Token![,]) {
+                        input.parse::<Token![,]>()?;
+                    }
+                    continue;
+                }
                 input.parse::<Token![=]>()?;
-                let value: LitStr = input.parse()?;
                 if ident == "name" {
-                    name = Some(Ident::new(&value.value(), value.span()));
+                    let value: LitStr = input.parse()?;
+                    validate_name(&value)?;
+                    name = Some(value);
+                } else if ident == "version" {
+                    let value: LitInt = input.parse()?;
+                    value.base10_parse::<u32>()?;
+                    version = Some(value);
+                } else if ident == "handler" {
+                    let value: LitStr = input.parse()?;
+                    let path = value.parse::<syn::Path>().map_err(|_| {
+                        syn::Error::new(value.span(), "handler must be a path to a function")
+                    })?;
+                    handler = Some(path);
+                } else if ident == "ttl" {
+                    let value: LitStr = input.parse()?;
+                    ttl = Some((parse_ttl(&value)?, value));
                 } else {
                     return Err(syn::Error::new_spanned(ident, "Unknown attribute key"));
                 }
@@ -31,14 +69,96 @@ impl Parse for RequestArgs {
                 return Err(lookahead.error());
             }
         }
-        Ok(RequestArgs { name })
+        let cache_ttl = match (cacheable, ttl) {
+            (Some(_), Some((ms, _))) => Some(ms),
+            (None, None) => None,
+            (Some(cacheable), None) => {
+                return Err(syn::Error::new_spanned(
+                    cacheable,
+                    "cacheable requests need a ttl, e.g. `ttl = \"60s\"`",
+                ));
+            }
+            (None, Some((_, ttl))) => {
+                return Err(syn::Error::new_spanned(
+                    ttl,
+                    "ttl only applies to cacheable requests",
+                ));
+            }
+        };
+        Ok(RequestArgs {
+            name,
+            version,
+            idempotent,
+            handler,
+            cache_ttl,
+        })
     }
 }
 
+/// Milliseconds in a duration like `"500ms"`, `"60s"`, `"5m"` or `"1h"`.
+fn parse_ttl(ttl: &LitStr) -> Result<u64> {
+    let value = ttl.value();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(digits);
+    let per_unit = match unit {
+        "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        _ => 0,
+    };
+    match amount
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(per_unit))
+    {
+        Some(ms) if ms > 0 => Ok(ms),
+        _ => Err(syn::Error::new(
+            ttl.span(),
+            "ttl must be a positive number of ms, s, m or h, e.g. `\"60s\"`",
+        )),
+    }
+}
+
+/// Request names go on the wire as-is, so they may contain dots to namespace
+/// methods (`billing.charge`), but nothing a log line or a REPL would mangle.
+fn validate_name(name: &LitStr) -> Result<()> {
+    let value = name.value();
+    let reason = if value.is_empty() {
+        "request name must not be empty"
+    } else if value.starts_with("__") {
+        "request names starting with `__` are reserved"
+    } else if value.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        "request name must not contain whitespace or control characters"
+    } else if value.split('.').any(str::is_empty) {
+        "request name must not have empty `.` separated segments"
+    } else {
+        return Ok(());
+    };
+    Err(syn::Error::new(name.span(), reason))
+}
+
 #[proc_macro_attribute]
 pub fn request(attr: TokenStream, item: TokenStream) -> TokenStream {
     let args = parse_macro_input!(attr as RequestArgs);
-    let input_fn = parse_macro_input!(item as ItemFn);
+    let mut input_fn = match request_fn(item, args.handler.as_ref()) {
+        Ok(input_fn) => input_fn,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let validator = match hook(&mut input_fn.attrs, "validate") {
+        Ok(validator) => validator,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let authorizer = match hook(&mut input_fn.attrs, "authorize") {
+        Ok(authorizer) => authorizer,
+        Err(e) => return e.to_compile_error().into(),
+    };
+    let defaults = match field_defaults(&mut input_fn.sig) {
+        Ok(defaults) => defaults,
+        Err(e) => return e.to_compile_error().into(),
+    };
 
     let vis = &input_fn.vis;
     let sig = &input_fn.sig;
@@ -46,9 +166,19 @@ pub fn request(attr: TokenStream, item: TokenStream) -> TokenStream {
     let default_name = &sig.ident;
     let fn_name = format_ident!("__{}", default_name);
 
-    let struct_name = args.name.clone().unwrap_or_else(|| default_name.clone());
+    // The struct takes the request name when that is a valid identifier, and
+    // keeps the function's otherwise, e.g. for dotted names.
+    let struct_name = args
+        .name
+        .as_ref()
+        .and_then(|name| name.parse::<Ident>().ok())
+        .unwrap_or_else(|| default_name.clone());
+    let request_name = match &args.name {
+        Some(name) => name.to_token_stream(),
+        None => quote! { stringify!(#struct_name) },
+    };
 
-    let (arg_names, arg_types): (Vec<_>, Vec<_>) = sig
+    let fn_args = sig
         .inputs
         .iter()
         .map(|arg| match arg {
@@ -67,35 +197,139 @@ pub fn request(attr: TokenStream, item: TokenStream) -> TokenStream {
                 "Unsupported function argument",
             )),
         })
-        .collect::<Result<Vec<_>>>()
-        .unwrap()
-        .into_iter()
+        .collect::<Result<Vec<_>>>();
+    let fn_args = match fn_args {
+        Ok(fn_args) => fn_args,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    // `Context` and `Upload` arguments are filled in by the server rather
+    // than sent in the request, so they aren't fields of it.
+    let call_args = fn_args.iter().map(|(name, ty)| {
+        if is_context(ty) {
+            quote! { _ctx.clone() }
+        } else if is_upload(ty) {
+            quote! { _ctx.take_upload() }
+        } else {
+            quote! { #name }
+        }
+    });
+    let (all_arg_names, all_arg_types): (Vec<_>, Vec<_>) = fn_args.iter().cloned().unzip();
+    let (arg_names, arg_types): (Vec<_>, Vec<_>) = fn_args
+        .iter()
+        .filter(|(_, ty)| !is_context(ty) && !is_upload(ty))
+        .cloned()
         .unzip();
 
+    if let Some((name, _)) = defaults.iter().find(|(name, _)| !arg_names.contains(name)) {
+        return syn::Error::new_spanned(name, "only arguments sent in the request take a default")
+            .to_compile_error()
+            .into();
+    }
+    // Fields left out of a request read as text, e.g. JSON, take their
+    // default; bincode still sends every one.
+    let mut default_fns = Vec::new();
+    let field_attrs: Vec<_> = arg_names
+        .iter()
+        .zip(&arg_types)
+        .map(
+            |(name, ty)| match defaults.iter().find(|(arg, _)| arg == name) {
+                None => quote! {},
+                Some((_, None)) => quote! { #[serde(default)] },
+                Some((_, Some(expr))) => {
+                    let default_fn = format_ident!("__{}_default_{}", struct_name, name);
+                    default_fns.push(quote! {
+                        #[allow(non_snake_case)]
+                        fn #default_fn() -> #ty {
+                            #expr
+                        }
+                    });
+                    let default_fn = default_fn.to_string();
+                    quote! { #[serde(default = #default_fn)] }
+                }
+            },
+        )
+        .collect();
+
     let return_type = match &sig.output {
         syn::ReturnType::Type(_, ty) => quote! { #ty },
         syn::ReturnType::Default => quote! { () },
     };
 
+    // Fallible handlers answer with `Result<T, RpcError>`, so the client can
+    // handle their errors the same way for every request.
+    let (resp_type, into_resp) = match &sig.output {
+        syn::ReturnType::Type(_, ty) => match result_types(ty) {
+            Some((_, err)) if is_rpc_error(err) => (quote! { #return_type }, quote! {}),
+            Some((ok, _)) => (
+                quote! { ::core::result::Result<#ok, ::protocol::RpcError> },
+                quote! { .map_err(|e| ::protocol::RpcError::Handler(e.to_string())) },
+            ),
+            None => (quote! { #return_type }, quote! {}),
+        },
+        syn::ReturnType::Default => (quote! { () }, quote! {}),
+    };
+
+    let fields: Vec<_> = arg_names
+        .iter()
+        .zip(&arg_types)
+        .map(|(name, ty)| (name.to_string(), type_string(ty)))
+        .collect();
+    let resp_type_str = match &sig.output {
+        syn::ReturnType::Type(_, ty) => match result_types(ty) {
+            Some((ok, err)) if !is_rpc_error(err) => {
+                format!("Result<{}, RpcError>", type_string(ok))
+            }
+            _ => type_string(ty),
+        },
+        syn::ReturnType::Default => "()".to_owned(),
+    };
+    let items = request_items(
+        &args,
+        &request_name,
+        validator,
+        authorizer,
+        &fields,
+        &resp_type_str,
+    );
+
+    // The request travels to the thread running its handler, and is
+    // borrowed across it while validated, so its fields have to be `Send`
+    // and `Sync`, too.
+    let arg_checks = arg_types.iter().map(|ty| {
+        quote_spanned! {ty.span()=>
+            const _: fn() = || {
+                fn assert_send_sync<T: ::core::marker::Send + ::core::marker::Sync + 'static>() {}
+                assert_send_sync::<#ty>();
+            };
+        }
+    });
+
     let expanded = quote! {
+        #(#arg_checks)*
+
         #[allow(non_snake_case)]
         #[warn(non_camel_case_types)]
-        #vis async fn #fn_name(#(#arg_names: #arg_types),*) -> #return_type {
+        #vis async fn #fn_name(#(#all_arg_names: #all_arg_types),*) -> #return_type {
             #fn_block
         }
 
+        #(#default_fns)*
+
         #[derive(Debug, ::bincode::Encode, ::bincode::Decode, ::serde::Serialize, ::serde::Deserialize)]
         #vis struct #struct_name {
-            #(pub #arg_names: #arg_types),*
+            #(#field_attrs pub #arg_names: #arg_types),*
         }
 
         #[async_trait::async_trait]
         impl ::protocol::Request for #struct_name {
-            type Resp = #return_type;
+            type Resp = #resp_type;
+
+            #items
 
-            async fn handle(self) -> Self::Resp {
+            async fn handle(self, _ctx: ::protocol::Context) -> Self::Resp {
                 let #struct_name { #(#arg_names),* } = self;
-                #fn_name(#(#arg_names),*).await
+                #fn_name(#(#call_args),*).await #into_resp
             }
         }
     };
@@ -103,6 +337,373 @@ pub fn request(attr: TokenStream, item: TokenStream) -> TokenStream {
     TokenStream::from(expanded)
 }
 
+/// Implements `protocol::Request` for a type whose handler lives elsewhere,
+/// for requests `#[request]` can't declare, like generic ones. The type
+/// derives `Debug` and the bincode and serde traits itself, as derives can't
+/// add others, and has to be `'static`.
+///
+/// `#[resp(Type)]` names the response and `#[handler(path)]` an `async fn`
+/// taking the request and its `Context`, returning it. `#[validate(path)]`,
+/// `#[authorize(path)]` and `#[request_args(...)]`, taking what
+/// `#[request(...)]` does, are optional. Generic types are requests whenever
+/// their parameters make them wire types; bounds their handler needs go on
+/// the type.
+#[proc_macro_derive(Request, attributes(resp, handler, validate, authorize, request_args))]
+pub fn derive_request(item: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(item as syn::DeriveInput);
+    derive_request_impl(input).unwrap_or_else(|e| e.to_compile_error().into())
+}
+
+fn derive_request_impl(input: syn::DeriveInput) -> Result<TokenStream> {
+    let mut attrs = input.attrs.clone();
+    let args = match attrs
+        .iter()
+        .find(|attr| attr.path().is_ident("request_args"))
+    {
+        Some(attr) => attr.parse_args::<RequestArgs>()?,
+        None => syn::parse::<RequestArgs>(TokenStream::new())?,
+    };
+    let resp = match attrs.iter().find(|attr| attr.path().is_ident("resp")) {
+        Some(attr) => attr.parse_args::<syn::Type>()?,
+        None => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "a derived request needs its response, as in `#[resp(Type)]`",
+            ));
+        }
+    };
+    let handler = match (hook(&mut attrs, "handler")?, args.handler.clone()) {
+        (Some(handler), _) | (None, Some(handler)) => handler,
+        (None, None) => {
+            return Err(syn::Error::new_spanned(
+                &input.ident,
+                "a derived request needs its handler, as in `#[handler(path::to::fn)]`",
+            ));
+        }
+    };
+    let validator = hook(&mut attrs, "validate")?;
+    let authorizer = hook(&mut attrs, "authorize")?;
+
+    let name = &input.ident;
+    let request_name = match &args.name {
+        Some(name) => name.to_token_stream(),
+        None => quote! { stringify!(#name) },
+    };
+
+    // Only named fields have names to describe them by.
+    let fields: Vec<_> = match &input.data {
+        syn::Data::Struct(data) => data
+            .fields
+            .iter()
+            .filter_map(|field| Some((field.ident.as_ref()?.to_string(), type_string(&field.ty))))
+            .collect(),
+        syn::Data::Enum(_) | syn::Data::Union(_) => Vec::new(),
+    };
+    let items = request_items(
+        &args,
+        &request_name,
+        validator,
+        authorizer,
+        &fields,
+        &type_string(&resp),
+    );
+
+    // Generic requests are only requests for the parameters making them
+    // wire types, and their responses responses.
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let mut where_clause = where_clause.cloned();
+    if !input.generics.params.is_empty() {
+        let predicates = &mut where_clause
+            .get_or_insert_with(|| syn::parse_quote! { where })
+            .predicates;
+        predicates.push(syn::parse_quote! {
+            Self: ::protocol::WireType
+                + ::core::marker::Send
+                + ::core::marker::Sync
+                + 'static
+        });
+        predicates.push(syn::parse_quote! { #resp: ::protocol::Response });
+    }
+
+    Ok(TokenStream::from(quote! {
+        #[async_trait::async_trait]
+        impl #impl_generics ::protocol::Request for #name #ty_generics #where_clause {
+            type Resp = #resp;
+
+            #items
+
+            async fn handle(self, ctx: ::protocol::Context) -> Self::Resp {
+                #handler(self, ctx).await
+            }
+        }
+    }))
+}
+
+/// The `Request` items `#[request]` and `#[derive(Request)]` generate alike:
+/// the name, what `args` set, the `validate` and `authorize` hooks, and the
+/// schema of `fields`, as names and types, and of the response.
+fn request_items(
+    args: &RequestArgs,
+    request_name: &impl ToTokens,
+    validator: Option<syn::Path>,
+    authorizer: Option<syn::Path>,
+    fields: &[(String, String)],
+    resp_type_str: &str,
+) -> proc_macro2::TokenStream {
+    let version = args.version.as_ref().map(|version| {
+        quote! {
+            const VERSION: u32 = #version;
+        }
+    });
+
+    let idempotent = args.idempotent.then(|| {
+        quote! {
+            const IDEMPOTENT: bool = true;
+        }
+    });
+
+    let cache_ttl = args.cache_ttl.map(|ms| {
+        quote! {
+            const CACHE_TTL: ::core::option::Option<::std::time::Duration> =
+                ::core::option::Option::Some(::std::time::Duration::from_millis(#ms));
+        }
+    });
+
+    // Validators may fail with any `Display` error, like fallible handlers.
+    let validate = validator.map(|path| {
+        quote! {
+            async fn validate(&self) -> ::core::result::Result<(), ::protocol::RpcError> {
+                #path(self)
+                    .await
+                    .map_err(|e| ::protocol::RpcError::ValidationFailed(e.to_string()))
+            }
+        }
+    });
+
+    // So may authorizers, which get the context to tell who is asking.
+    let authorize = authorizer.map(|path| {
+        quote! {
+            async fn authorize(
+                &self,
+                ctx: &::protocol::Context,
+            ) -> ::core::result::Result<(), ::protocol::RpcError> {
+                #path(self, ctx)
+                    .await
+                    .map_err(|e| ::protocol::RpcError::Unauthorized(e.to_string()))
+            }
+        }
+    });
+
+    let (arg_name_strs, arg_type_strs): (Vec<_>, Vec<_>) = fields.iter().cloned().unzip();
+    quote! {
+        const NAME: &'static str = #request_name;
+
+        #version
+
+        #idempotent
+
+        #cache_ttl
+
+        #validate
+
+        #authorize
+
+        fn schema() -> ::protocol::schema::RpcSchema {
+            ::protocol::schema::RpcSchema {
+                methods: vec![::protocol::schema::MethodSchema {
+                    name: Self::NAME.to_owned(),
+                    version: Self::VERSION,
+                    args: vec![#(::protocol::schema::ArgSchema {
+                        name: #arg_name_strs.to_owned(),
+                        ty: #arg_type_strs.to_owned(),
+                    }),*],
+                    response: #resp_type_str.to_owned(),
+                }],
+            }
+        }
+    }
+}
+
+/// The function a request is made from. With `handler = "path::to::fn"`
+/// it is declared by its signature alone, as in `fn Add(a: i32, b: i32) ->
+/// i32;`, and gets a body calling the handler with its arguments, awaiting it
+/// if the declaration is `async`. The handler's signature has to match.
+fn request_fn(item: TokenStream, handler: Option<&syn::Path>) -> Result<ItemFn> {
+    if let Ok(input_fn) = syn::parse::<ItemFn>(item.clone()) {
+        return match handler {
+            Some(handler) => Err(syn::Error::new_spanned(
+                handler,
+                "a request with a handler is declared without a body, as in `fn Name(args) -> Resp;`",
+            )),
+            None => Ok(input_fn),
+        };
+    }
+
+    let decl = match syn::parse::<syn::ForeignItemFn>(item.clone()) {
+        Ok(decl) => decl,
+        Err(_) => {
+            let item = syn::parse::<syn::Item>(item)?;
+            return Err(syn::Error::new_spanned(
+                item,
+                "#[request] goes on a function, or with a handler on a signature like \
+                 `fn Name(args) -> Resp;`, to take the request's fields and response from",
+            ));
+        }
+    };
+    let Some(handler) = handler else {
+        return Err(syn::Error::new_spanned(
+            &decl.sig,
+            "a request without a body needs `#[request(handler = \"path::to::fn\")]`",
+        ));
+    };
+
+    let arg_names = decl
+        .sig
+        .inputs
+        .iter()
+        .map(|arg| match arg {
+            syn::FnArg::Typed(pat_type) => match &*pat_type.pat {
+                syn::Pat::Ident(pat_ident) => Ok(&pat_ident.ident),
+                pat => Err(syn::Error::new_spanned(pat, "Unsupported argument pattern")),
+            },
+            _ => Err(syn::Error::new_spanned(
+                arg,
+                "Unsupported function argument",
+            )),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let await_handler = decl.sig.asyncness.map(|_| quote! { .await });
+    let block = syn::parse_quote! {{
+        #handler(#(#arg_names),*) #await_handler
+    }};
+
+    Ok(ItemFn {
+        attrs: decl.attrs,
+        vis: decl.vis,
+        sig: decl.sig,
+        block: Box::new(block),
+    })
+}
+
+/// Takes an attribute like `#[validate(path::to::fn)]` off a `#[request]`
+/// function, if it has one. The function named is awaited before the request
+/// is handled, and returns `Result<(), E>` for any `E: Display`: one for
+/// `validate` with the request, one for `authorize` with the request and its
+/// `&Context`. Like the `#[rpc]` attributes, it has to come after
+/// `#[request]`.
+fn hook(attrs: &mut Vec<syn::Attribute>, name: &str) -> Result<Option<syn::Path>> {
+    let mut hook = None;
+    let mut kept = Vec::new();
+    for attr in attrs.drain(..) {
+        if !attr.path().is_ident(name) {
+            kept.push(attr);
+            continue;
+        }
+        if hook.is_some() {
+            return Err(syn::Error::new_spanned(
+                attr,
+                format!("Duplicate {name} attribute"),
+            ));
+        }
+        hook = Some(attr.parse_args::<syn::Path>()?);
+    }
+    *attrs = kept;
+    Ok(hook)
+}
+
+/// Takes `#[default]` and `#[default = expr]` off the arguments of a
+/// `#[request]` function, returning the arguments that had one along with
+/// the expression, if any. Left out of a request read as text, an argument
+/// with a bare `#[default]` takes `Default::default()`, and one with an
+/// expression takes that.
+fn field_defaults(sig: &mut syn::Signature) -> Result<Vec<(Ident, Option<syn::Expr>)>> {
+    let mut defaults = Vec::new();
+    for arg in &mut sig.inputs {
+        let syn::FnArg::Typed(pat_type) = arg else {
+            continue;
+        };
+        let mut default = None;
+        let mut kept = Vec::new();
+        for attr in pat_type.attrs.drain(..) {
+            if !attr.path().is_ident("default") {
+                kept.push(attr);
+                continue;
+            }
+            if default.is_some() {
+                return Err(syn::Error::new_spanned(attr, "Duplicate default attribute"));
+            }
+            default = Some(match attr.meta {
+                syn::Meta::Path(_) => None,
+                syn::Meta::NameValue(value) => Some(value.value),
+                syn::Meta::List(list) => {
+                    return Err(syn::Error::new_spanned(
+                        list,
+                        "expected `#[default]` or `#[default = expr]`",
+                    ));
+                }
+            });
+        }
+        pat_type.attrs = kept;
+        if let (Some(default), syn::Pat::Ident(pat_ident)) = (default, &*pat_type.pat) {
+            defaults.push((pat_ident.ident.clone(), default));
+        }
+    }
+    Ok(defaults)
+}
+
+/// The `T` and `E` of a return type spelled `Result<T, E>`. Aliases like
+/// `io::Result<T>` are left alone, since their error type isn't visible here.
+fn result_types(ty: &syn::Type) -> Option<(&syn::Type, &syn::Type)> {
+    let syn::Type::Path(path) = ty else {
+        return None;
+    };
+    let segment = path.path.segments.last()?;
+    if segment.ident != "Result" {
+        return None;
+    }
+    let syn::PathArguments::AngleBracketed(args) = &segment.arguments else {
+        return None;
+    };
+    match args.args.iter().collect::<Vec<_>>()[..] {
+        [
+            syn::GenericArgument::Type(ok),
+            syn::GenericArgument::Type(err),
+        ] => Some((ok, err)),
+        _ => None,
+    }
+}
+
+fn is_context(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(path) if path.path.segments.last().is_some_and(|s| s.ident == "Context"))
+}
+
+fn is_upload(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(path) if path.path.segments.last().is_some_and(|s| s.ident == "Upload"))
+}
+
+fn is_rpc_error(ty: &syn::Type) -> bool {
+    matches!(ty, syn::Type::Path(path) if path.path.segments.last().is_some_and(|s| s.ident == "RpcError"))
+}
+
+/// Spells out a type the way it would be written by hand, rather than with a
+/// space between every token.
+fn type_string(ty: &impl ToTokens) -> String {
+    let mut spelled = ty.to_token_stream().to_string();
+    for (spaced, tight) in [
+        (" <", "<"),
+        ("< ", "<"),
+        (" >", ">"),
+        (" ::", "::"),
+        (":: ", "::"),
+        (" ,", ","),
+        ("& ", "&"),
+    ] {
+        spelled = spelled.replace(spaced, tight);
+    }
+    // `, ::path` lost its space along with the one before `::`.
+    spelled.replace(",", ", ").replace(",  ", ", ")
+}
+
 struct RpcArgs {
     response: Ident,
 }
@@ -130,53 +731,322 @@ impl Parse for RpcArgs {
     }
 }
 
+/// Splits the attributes on an `#[rpc]` enum into those for the response
+/// enum and those to keep. `#[response(...)]` goes on the response enum
+/// alone, unwrapped; `#[serde(rename_all = ...)]` goes on both, as variants
+/// share their names. Everything else stays on the request enum. Attributes
+/// have to come after `#[rpc]`, as the ones before are resolved ahead of the
+/// derives it adds.
+fn response_attrs(attrs: &mut Vec<syn::Attribute>) -> Result<Vec<syn::Attribute>> {
+    type Metas = syn::punctuated::Punctuated<syn::Meta, Token![,]>;
+
+    let mut forwarded = Vec::new();
+    let mut kept = Vec::new();
+    for attr in attrs.drain(..) {
+        if attr.path().is_ident("response") {
+            let metas = attr.parse_args_with(Metas::parse_terminated)?;
+            forwarded.extend(metas.iter().map(|meta| syn::parse_quote! { #[#meta] }));
+            continue;
+        }
+        if attr.path().is_ident("serde") {
+            let renames_only = attr
+                .parse_args_with(Metas::parse_terminated)
+                .is_ok_and(|metas| {
+                    metas.iter().all(|meta| {
+                        matches!(meta, syn::Meta::NameValue(nv) if nv.path.is_ident("rename_all"))
+                    })
+                });
+            if renames_only {
+                forwarded.push(attr.clone());
+            }
+        }
+        kept.push(attr);
+    }
+
+    *attrs = kept;
+    Ok(forwarded)
+}
+
 #[proc_macro_attribute]
 pub fn rpc(attr: TokenStream, item: TokenStream) -> TokenStream {
     let args = parse_macro_input!(attr as RpcArgs);
-    let input_enum = parse_macro_input!(item as ItemEnum);
+    let mut input_enum = parse_macro_input!(item as ItemEnum);
+    let response_attrs = match response_attrs(&mut input_enum.attrs) {
+        Ok(attrs) => attrs,
+        Err(e) => return e.to_compile_error().into(),
+    };
 
     let enum_name = &input_enum.ident;
     let variants = &input_enum.variants;
     let response_name = &args.response;
 
-    let response_variants = variants.iter().map(|v| {
+    let variant_types = variants
+        .iter()
+        .map(|v| match &v.fields {
+            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => Ok(&fields.unnamed[0].ty),
+            _ => Err(syn::Error::new_spanned(
+                v,
+                "Variants must be tuple variants with a single field",
+            )),
+        })
+        .collect::<Result<Vec<_>>>();
+    let variant_types = match variant_types {
+        Ok(variant_types) => variant_types,
+        Err(e) => return e.to_compile_error().into(),
+    };
+
+    // Everything asked of a variant's type goes through `Request` by path, so
+    // a type that isn't one fails with the same error throughout rather than
+    // with a missing method in each generated impl. The handler calls are
+    // spanned to the type, to point there too; the response variants aren't,
+    // as the response enum is public while the request types may not be.
+    let response_variants = variants.iter().zip(&variant_types).map(|(v, ty)| {
+        let variant_name = &v.ident;
+        quote! {
+            #variant_name(<#ty as ::protocol::Request>::Resp)
+        }
+    });
+
+    let match_arms = variants.iter().zip(&variant_types).map(|(v, ty)| {
+        let variant_name = &v.ident;
+        quote_spanned! {ty.span()=>
+            #enum_name::#variant_name(req) => #response_name::#variant_name(
+                <#ty as ::protocol::Request>::handle(req, ctx).await
+            ),
+        }
+    });
+
+    let name_arms = variants.iter().zip(&variant_types).map(|(v, ty)| {
+        let variant_name = &v.ident;
+        quote_spanned! {ty.span()=>
+            #enum_name::#variant_name(req) => <#ty as ::protocol::Request>::name(req),
+        }
+    });
+
+    let version_arms = variants.iter().zip(&variant_types).map(|(v, ty)| {
+        let variant_name = &v.ident;
+        quote_spanned! {ty.span()=>
+            #enum_name::#variant_name(req) => <#ty as ::protocol::Request>::version(req),
+        }
+    });
+
+    let idempotent_arms = variants.iter().zip(&variant_types).map(|(v, ty)| {
+        let variant_name = &v.ident;
+        quote_spanned! {ty.span()=>
+            #enum_name::#variant_name(req) => <#ty as ::protocol::Request>::idempotent(req),
+        }
+    });
+
+    let cache_ttl_arms = variants.iter().zip(&variant_types).map(|(v, ty)| {
+        let variant_name = &v.ident;
+        quote_spanned! {ty.span()=>
+            #enum_name::#variant_name(req) => <#ty as ::protocol::Request>::cache_ttl(req),
+        }
+    });
+
+    let validate_arms = variants.iter().zip(&variant_types).map(|(v, ty)| {
+        let variant_name = &v.ident;
+        quote_spanned! {ty.span()=>
+            #enum_name::#variant_name(req) => <#ty as ::protocol::Request>::validate(req).await,
+        }
+    });
+
+    let authorize_arms = variants.iter().zip(&variant_types).map(|(v, ty)| {
+        let variant_name = &v.ident;
+        quote_spanned! {ty.span()=>
+            #enum_name::#variant_name(req) => <#ty as ::protocol::Request>::authorize(req, ctx).await,
+        }
+    });
+
+    let chunk_arms = variants.iter().map(|v| {
+        let variant_name = &v.ident;
+        quote! {
+            #response_name::#variant_name(resp) => {
+                ::protocol::Response::into_chunks(resp).map_err(#response_name::#variant_name)
+            }
+        }
+    });
+
+    let event_arms = variants.iter().map(|v| {
         let variant_name = &v.ident;
-        let ty = match &v.fields {
-            syn::Fields::Unnamed(fields) if fields.unnamed.len() == 1 => &fields.unnamed[0].ty,
-            _ => panic!("Variants must be tuple variants with a single field"),
-        };
         quote! {
-            #variant_name(<#ty as Request>::Resp)
+            #response_name::#variant_name(resp) => {
+                ::protocol::Response::into_events(resp).map_err(#response_name::#variant_name)
+            }
+        }
+    });
+
+    // Handlers are spawned onto other threads, so every variant has to be a
+    // `Request`, which is `Send + Sync + 'static`. Checked up front to point errors
+    // at the variant rather than into the generated impls.
+    let variant_checks = variant_types.iter().map(|ty| {
+        quote_spanned! {ty.span()=>
+            const _: fn() = || {
+                fn assert_request<R: ::protocol::Request>() {}
+                assert_request::<#ty>();
+            };
         }
     });
+    let variant_checks = quote! { #(#variant_checks)* };
 
-    let match_arms = variants.iter().map(|v| {
+    // `AppRequest::from(add)` has to know which variant to pick, so every
+    // variant needs a type of its own.
+    let mut wrapped = HashMap::new();
+    for (v, ty) in variants.iter().zip(&variant_types) {
+        if let Some(first) = wrapped.insert(quote!(#ty).to_string(), &v.ident) {
+            let msg = format!(
+                "`{}` is wrapped by both `{first}` and `{}`; each variant needs a request type of its own",
+                quote!(#ty),
+                v.ident,
+            );
+            return syn::Error::new_spanned(ty, msg).to_compile_error().into();
+        }
+    }
+    let from_impls = variants.iter().zip(&variant_types).map(|(v, ty)| {
         let variant_name = &v.ident;
         quote! {
-            #enum_name::#variant_name(req) => #response_name::#variant_name(req.handle().await),
+            impl ::core::convert::From<#ty> for #enum_name {
+                fn from(req: #ty) -> Self {
+                    Self::#variant_name(req)
+                }
+            }
+
+            impl ::protocol::Wraps<#ty> for #enum_name {
+                // Unreachable for enums of a single variant.
+                #[allow(unreachable_patterns)]
+                fn wrapped(&self) -> ::core::option::Option<&#ty> {
+                    match self {
+                        Self::#variant_name(req) => ::core::option::Option::Some(req),
+                        _ => ::core::option::Option::None,
+                    }
+                }
+
+                fn wrap_response(resp: <#ty as ::protocol::Request>::Resp) -> #response_name {
+                    #response_name::#variant_name(resp)
+                }
+            }
         }
     });
 
+    // Written out instead of derived: the derived `BorrowDecode` can't be
+    // satisfied by responses like `Cow<'static, str>`, and responses are only
+    // ever decoded into owned values anyway. The layout matches the derive.
+    let variant_indices = (0..variants.len() as u32).collect::<Vec<_>>();
+    let response_variant_names = variants.iter().map(|v| &v.ident);
+    let max_variant = variants.len().saturating_sub(1) as u32;
+    let response_decode = quote! {
+        impl ::bincode::Decode<()> for #response_name {
+            fn decode<__D: ::bincode::de::Decoder<Context = ()>>(
+                decoder: &mut __D,
+            ) -> ::core::result::Result<Self, ::bincode::error::DecodeError> {
+                match <u32 as ::bincode::Decode<()>>::decode(decoder)? {
+                    #(#variant_indices => ::core::result::Result::Ok(
+                        Self::#response_variant_names(::bincode::Decode::decode(decoder)?)
+                    ),)*
+                    found => ::core::result::Result::Err(::bincode::error::DecodeError::UnexpectedVariant {
+                        found,
+                        type_name: stringify!(#response_name),
+                        allowed: &::bincode::error::AllowedEnumVariants::Range { min: 0, max: #max_variant },
+                    }),
+                }
+            }
+        }
+
+        ::bincode::impl_borrow_decode_with_context!(#response_name, ());
+    };
+
     let expanded = quote! {
         #[derive(Debug, ::bincode::Encode, ::bincode::Decode, ::serde::Deserialize, ::serde::Serialize)]
         #input_enum
 
-        #[derive(Debug, ::bincode::Encode, ::bincode::Decode, ::serde::Deserialize, ::serde::Serialize)]
+        #variant_checks
+
+        #(#from_impls)*
+
+        #[derive(Debug, ::bincode::Encode, ::serde::Deserialize, ::serde::Serialize)]
+        #(#response_attrs)*
         pub enum #response_name {
             #(#response_variants),*
         }
 
-        impl ::protocol::Response for #response_name {}
+        #response_decode
+
+        impl ::protocol::Response for #response_name {
+            fn into_chunks(self) -> ::core::result::Result<::protocol::ChunkedResponse, Self> {
+                match self {
+                    #(#chunk_arms)*
+                }
+            }
+
+            fn into_events(self) -> ::core::result::Result<::protocol::Events, Self> {
+                match self {
+                    #(#event_arms)*
+                }
+            }
+        }
 
         #[async_trait::async_trait]
         impl ::protocol::Request for #enum_name {
             type Resp = #response_name;
 
-            async fn handle(self) -> Self::Resp {
+            const NAME: &'static str = stringify!(#enum_name);
+
+            async fn validate(&self) -> ::core::result::Result<(), ::protocol::RpcError> {
+                match self {
+                    #(#validate_arms)*
+                }
+            }
+
+            async fn authorize(
+                &self,
+                ctx: &::protocol::Context,
+            ) -> ::core::result::Result<(), ::protocol::RpcError> {
+                match self {
+                    #(#authorize_arms)*
+                }
+            }
+
+            async fn handle(self, ctx: ::protocol::Context) -> Self::Resp {
                 match self {
                     #(#match_arms)*
                 }
             }
+
+            fn name(&self) -> &'static str {
+                match self {
+                    #(#name_arms)*
+                }
+            }
+
+            fn version(&self) -> u32 {
+                match self {
+                    #(#version_arms)*
+                }
+            }
+
+            fn idempotent(&self) -> bool {
+                match self {
+                    #(#idempotent_arms)*
+                }
+            }
+
+            fn cache_ttl(&self) -> ::core::option::Option<::std::time::Duration> {
+                match self {
+                    #(#cache_ttl_arms)*
+                }
+            }
+
+            fn versions(name: &str) -> Vec<u32> {
+                let mut versions = Vec::new();
+                #(versions.extend(<#variant_types as ::protocol::Request>::versions(name));)*
+                versions
+            }
+
+            fn schema() -> ::protocol::schema::RpcSchema {
+                let mut schema = ::protocol::schema::RpcSchema::default();
+                #(schema.methods.extend(<#variant_types as ::protocol::Request>::schema().methods);)*
+                schema
+            }
         }
     };
 
@@ -0,0 +1,7 @@
+//! Compile errors the macros give for input they don't take.
+
+#[test]
+fn ui() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,23 @@
+#![allow(non_snake_case)]
+
+use macros::{request, rpc};
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// Data a request might take, but not a request itself.
+#[derive(Debug, Encode, Decode, Serialize, Deserialize)]
+struct NotARequest;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+    Other(NotARequest),
+}
+
+#[request]
+fn Add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+fn main() {}
@@ -0,0 +1,15 @@
+#![allow(non_snake_case)]
+
+use macros::{request, rpc};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add { add: Add },
+}
+
+#[request]
+fn Add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+fn main() {}
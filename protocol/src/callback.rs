@@ -0,0 +1,139 @@
+use crate::codec::{Limited, WireCodec, WireFormat};
+use crate::frame::{FrameKind, Header, encode_frame};
+use crate::{Request, RpcError, with_frame};
+
+use bytes::Bytes;
+use tokio::sync::{mpsc, oneshot};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Calls from the server to the client on the other end of a connection,
+/// answered by the handlers the client registered with
+/// `ClientBuilder::callbacks`, e.g. to ask it for something while handling
+/// one of its requests.
+///
+/// Handlers get one from [`Context::callbacks`](crate::Context::callbacks)
+/// if the client announced
+/// [`CALLBACKS_FEATURE`](crate::hello::CALLBACKS_FEATURE). Every frame of
+/// these calls has [`Header::reverse`] set, so their ids never mix with the
+/// client's. Clones share the connection.
+#[derive(Clone)]
+pub struct Callbacks {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    codec: Limited<WireFormat>,
+    frames: mpsc::Sender<Vec<u8>>,
+    /// `None` once the connection has closed.
+    pending: Mutex<Option<HashMap<u64, Reply>>>,
+    next_id: AtomicU64,
+}
+
+impl fmt::Debug for Callbacks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Callbacks")
+            .field("codec", &self.inner.codec)
+            .field(
+                "pending",
+                &self
+                    .inner
+                    .pending
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(HashMap::len),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Callbacks {
+    /// Calls going out as frames encoded with `codec` on `frames`, whose
+    /// answers are handed back with [`Callbacks::answer`].
+    pub fn new(codec: Limited<WireFormat>, frames: mpsc::Sender<Vec<u8>>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                codec,
+                frames,
+                pending: Mutex::new(Some(HashMap::new())),
+                next_id: AtomicU64::new(1),
+            }),
+        }
+    }
+
+    /// Has the client handle `req`, and waits for its response. Fails with
+    /// what the client answered with instead, or with an
+    /// [`RpcError::Internal`] if the connection closes first.
+    ///
+    /// Dropping the future leaves the client to finish handling it anyway,
+    /// and drops its response.
+    pub async fn call<Req: Request>(&self, req: Req) -> Result<Req::Resp, RpcError> {
+        let inner = &*self.inner;
+        let id = inner.next_id.fetch_add(1, Ordering::Relaxed);
+        let header = Header::request(id, req.name(), req.version()).with_reverse(true);
+        let frame = encode_frame(&inner.codec, &header, &req)
+            .map_err(|e| RpcError::Internal(format!("failed to encode callback: {e}")))?;
+
+        let closed = || RpcError::Internal("connection closed".into());
+        let (reply, answered) = oneshot::channel();
+        match &mut *inner.pending.lock().unwrap() {
+            Some(pending) => pending.insert(id, reply),
+            None => return Err(closed()),
+        };
+        let _forget = Forget(inner, id);
+        inner.frames.send(frame).await.map_err(|_| closed())?;
+        let (kind, body) = tokio::select! {
+            answer = answered => answer.map_err(|_| closed())?,
+            () = inner.frames.closed() => return Err(closed()),
+        };
+
+        let codec = inner.codec.codec;
+        match kind {
+            FrameKind::Response => with_frame(&body, || codec.decode(&body)).map_err(|e| {
+                RpcError::Internal(format!("failed to decode callback response: {e}"))
+            }),
+            FrameKind::Error => Err(codec
+                .decode(&body)
+                .unwrap_or_else(|e| RpcError::Internal(format!("failed to decode error: {e}")))),
+            kind => Err(RpcError::Internal(format!(
+                "unexpected {kind:?} frame answering a callback"
+            ))),
+        }
+    }
+
+    /// Hands the client's answer in `header` and `body` to the call waiting
+    /// for it. Answers nobody waits for any more are dropped.
+    pub fn answer(&self, header: &Header, body: Bytes) {
+        let reply = match &mut *self.inner.pending.lock().unwrap() {
+            Some(pending) => pending.remove(&header.id),
+            None => None,
+        };
+        if let Some(reply) = reply {
+            let _ = reply.send((header.kind, body));
+        }
+    }
+
+    /// Fails every call still waiting, and every one made from now on, as
+    /// the connection has closed.
+    pub fn close(&self) {
+        self.inner.pending.lock().unwrap().take();
+    }
+}
+
+/// Where the kind and body of the frame answering a call go.
+type Reply = oneshot::Sender<(FrameKind, Bytes)>;
+
+/// Stops waiting for the answer to call `.1` once dropped.
+struct Forget<'a>(&'a Inner, u64);
+
+impl Drop for Forget<'_> {
+    fn drop(&mut self) {
+        if let Some(pending) = &mut *self.0.pending.lock().unwrap() {
+            pending.remove(&self.1);
+        }
+    }
+}
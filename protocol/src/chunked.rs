@@ -0,0 +1,73 @@
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+use bytes::Bytes;
+use futures::stream::{BoxStream, Stream, StreamExt};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+
+use std::fmt;
+use std::io;
+
+const NOT_ENCODED: &str = "chunked responses are sent as chunk frames, not encoded";
+
+/// A response too large to buffer, sent as a series of
+/// [`FrameKind::Chunk`](crate::frame::FrameKind::Chunk) frames while it is
+/// being produced.
+///
+/// Requests answered this way are called with `Client::call_chunked`. The
+/// response can't take part in a batch, and has no encoding of its own.
+pub struct ChunkedResponse {
+    chunks: BoxStream<'static, io::Result<Bytes>>,
+}
+
+impl ChunkedResponse {
+    /// Sends every piece of `chunks` in turn. Pieces larger than a frame
+    /// allows are split further; an error ends the response with it.
+    pub fn from_stream(chunks: impl Stream<Item = io::Result<Bytes>> + Send + 'static) -> Self {
+        Self {
+            chunks: chunks.boxed(),
+        }
+    }
+
+    /// Sends everything read from `reader`.
+    pub fn from_reader(reader: impl AsyncRead + Send + 'static) -> Self {
+        Self::from_stream(ReaderStream::new(reader))
+    }
+
+    pub fn into_stream(self) -> BoxStream<'static, io::Result<Bytes>> {
+        self.chunks
+    }
+}
+
+impl fmt::Debug for ChunkedResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChunkedResponse").finish_non_exhaustive()
+    }
+}
+
+impl Encode for ChunkedResponse {
+    fn encode<E: Encoder>(&self, _: &mut E) -> Result<(), EncodeError> {
+        Err(EncodeError::Other(NOT_ENCODED))
+    }
+}
+
+impl<Context> Decode<Context> for ChunkedResponse {
+    fn decode<D: Decoder<Context = Context>>(_: &mut D) -> Result<Self, DecodeError> {
+        Err(DecodeError::Other(NOT_ENCODED))
+    }
+}
+
+impl Serialize for ChunkedResponse {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(serde::ser::Error::custom(NOT_ENCODED))
+    }
+}
+
+impl<'de> Deserialize<'de> for ChunkedResponse {
+    fn deserialize<D: Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Err(serde::de::Error::custom(NOT_ENCODED))
+    }
+}
@@ -0,0 +1,470 @@
+use bincode::de::{Decoder, DecoderImpl, read::Reader};
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use std::io;
+
+use bincode::config::{BigEndian, Configuration, Limit, Varint};
+pub const BINCODE_CONFIG: Configuration<BigEndian> = bincode::config::standard().with_big_endian();
+
+/// Most memory a single bincode decode may set aside for containers before
+/// reading them. Bincode allocates by the length prefix, so without a limit a
+/// forged one aborts the process on a failed allocation instead of failing to
+/// decode. Byte strings count in full, so no message can carry more than this
+/// in them.
+pub const BINCODE_DECODE_LIMIT: usize = 1 << 30;
+
+const BINCODE_DECODE_CONFIG: Configuration<BigEndian, Varint, Limit<BINCODE_DECODE_LIMIT>> =
+    BINCODE_CONFIG.with_limit::<BINCODE_DECODE_LIMIT>();
+
+#[derive(thiserror::Error, Debug)]
+pub enum CodecError {
+    #[error("bincode decode error at byte {offset}: {source}")]
+    BincodeDecode {
+        source: bincode::error::DecodeError,
+        offset: usize,
+    },
+
+    #[error("bincode encode error: {0}")]
+    BincodeEncode(#[from] bincode::error::EncodeError),
+
+    #[error("JSON decode error at byte {offset}: {source}")]
+    JsonDecode {
+        source: serde_json::Error,
+        offset: usize,
+    },
+
+    #[error("JSON encode error: {0}")]
+    JsonEncode(#[from] serde_json::Error),
+
+    #[error("MessagePack decode error at byte {offset}: {source}")]
+    MessagePackDecode {
+        source: rmp_serde::decode::Error,
+        offset: usize,
+    },
+
+    #[error("MessagePack encode error: {0}")]
+    MessagePackEncode(#[from] rmp_serde::encode::Error),
+
+    #[error("frame ended before its header did")]
+    Truncated,
+
+    #[error("frame header does not fit its length prefix")]
+    HeaderTooLarge,
+
+    #[error("encoded value is larger than the {limit} byte limit")]
+    TooLarge { limit: usize },
+
+    #[error("decoded value ended at byte {consumed} of {len}")]
+    TrailingBytes { consumed: usize, len: usize },
+}
+
+/// Turns requests and responses into frame payloads and back.
+///
+/// Framing is handled separately, so an implementation only ever sees the
+/// bytes of a single message.
+pub trait WireCodec: Send + Sync {
+    fn encode<T: Encode + Serialize>(&self, val: &T) -> Result<Vec<u8>, CodecError>;
+
+    fn decode<T: Decode<()> + DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError>;
+
+    /// Like [`WireCodec::decode`], but fails with
+    /// [`CodecError::TrailingBytes`] if the value ends before `bytes` do,
+    /// rather than ignoring the rest.
+    ///
+    /// The provided implementation doesn't check; that is left to codecs
+    /// that would otherwise ignore the rest, like [`BincodeCodec`]. JSON has
+    /// nothing but whitespace follow a value either way.
+    fn decode_exact<T: Decode<()> + DeserializeOwned>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<T, CodecError> {
+        self.decode(bytes)
+    }
+
+    /// Like [`WireCodec::encode`], but fails with [`CodecError::TooLarge`]
+    /// if `val` takes more than `limit` bytes.
+    ///
+    /// The provided implementation encodes `val` in full before checking;
+    /// the codecs in this module stop as soon as they go past the limit.
+    fn encode_limited<T: Encode + Serialize>(
+        &self,
+        val: &T,
+        limit: usize,
+    ) -> Result<Vec<u8>, CodecError> {
+        let bytes = self.encode(val)?;
+        if bytes.len() > limit {
+            return Err(CodecError::TooLarge { limit });
+        }
+        Ok(bytes)
+    }
+}
+
+/// A codec whose [`encode`](WireCodec::encode) is
+/// [`encode_limited`](WireCodec::encode_limited) to `limit` bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limited<C> {
+    pub codec: C,
+    pub limit: usize,
+}
+
+impl<C: WireCodec> WireCodec for Limited<C> {
+    fn encode<T: Encode + Serialize>(&self, val: &T) -> Result<Vec<u8>, CodecError> {
+        self.codec.encode_limited(val, self.limit)
+    }
+
+    fn decode<T: Decode<()> + DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        self.codec.decode(bytes)
+    }
+
+    fn decode_exact<T: Decode<()> + DeserializeOwned>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<T, CodecError> {
+        self.codec.decode_exact(bytes)
+    }
+
+    fn encode_limited<T: Encode + Serialize>(
+        &self,
+        val: &T,
+        limit: usize,
+    ) -> Result<Vec<u8>, CodecError> {
+        self.codec.encode_limited(val, limit.min(self.limit))
+    }
+}
+
+/// Counts the bytes written through it, failing any write that would take
+/// it past `limit`.
+struct CountingWriter<W> {
+    inner: W,
+    written: usize,
+    limit: usize,
+    exceeded: bool,
+}
+
+impl<W> CountingWriter<W> {
+    fn new(inner: W, limit: usize) -> Self {
+        Self {
+            inner,
+            written: 0,
+            limit,
+            exceeded: false,
+        }
+    }
+}
+
+impl<W: io::Write> io::Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() > self.limit - self.written {
+            self.exceeded = true;
+            return Err(io::Error::other("write limit exceeded"));
+        }
+        let n = self.inner.write(buf)?;
+        self.written += n;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Compact binary encoding used between Rust peers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+impl WireCodec for BincodeCodec {
+    fn encode<T: Encode + Serialize>(&self, val: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(bincode::encode_to_vec(val, BINCODE_CONFIG)?)
+    }
+
+    fn encode_limited<T: Encode + Serialize>(
+        &self,
+        val: &T,
+        limit: usize,
+    ) -> Result<Vec<u8>, CodecError> {
+        let mut writer = CountingWriter::new(Vec::new(), limit);
+        match bincode::encode_into_std_write(val, &mut writer, BINCODE_CONFIG) {
+            Ok(_) => Ok(writer.inner),
+            Err(_) if writer.exceeded => Err(CodecError::TooLarge { limit }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn decode<T: Decode<()> + DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        bincode_decode(bytes).map(|(val, _)| val)
+    }
+
+    fn decode_exact<T: Decode<()> + DeserializeOwned>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<T, CodecError> {
+        let (val, consumed) = bincode_decode(bytes)?;
+        if consumed != bytes.len() {
+            return Err(CodecError::TrailingBytes {
+                consumed,
+                len: bytes.len(),
+            });
+        }
+        Ok(val)
+    }
+}
+
+/// Decodes a `T` from the start of `bytes`, returning it along with the
+/// number of bytes it took up.
+fn bincode_decode<T: Decode<()>>(bytes: &[u8]) -> Result<(T, usize), CodecError> {
+    let reader = TrackingReader { bytes, offset: 0 };
+    let mut decoder = DecoderImpl::new(reader, BINCODE_DECODE_CONFIG, ());
+    match T::decode(&mut decoder) {
+        Ok(val) => Ok((val, decoder.reader().offset)),
+        Err(source) => Err(CodecError::BincodeDecode {
+            source,
+            offset: decoder.reader().offset,
+        }),
+    }
+}
+
+/// A slice reader that remembers how far it got, so decode errors can point
+/// at the offending byte.
+struct TrackingReader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl Reader for TrackingReader<'_> {
+    fn read(&mut self, bytes: &mut [u8]) -> Result<(), bincode::error::DecodeError> {
+        let remaining = &self.bytes[self.offset..];
+        if bytes.len() > remaining.len() {
+            return Err(bincode::error::DecodeError::UnexpectedEnd {
+                additional: bytes.len() - remaining.len(),
+            });
+        }
+        bytes.copy_from_slice(&remaining[..bytes.len()]);
+        self.offset += bytes.len();
+        Ok(())
+    }
+
+    fn peek_read(&mut self, n: usize) -> Option<&[u8]> {
+        self.bytes.get(self.offset..self.offset.checked_add(n)?)
+    }
+
+    fn consume(&mut self, n: usize) {
+        self.offset += n;
+    }
+}
+
+/// JSON encoding for peers that can't speak bincode.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl WireCodec for JsonCodec {
+    fn encode<T: Encode + Serialize>(&self, val: &T) -> Result<Vec<u8>, CodecError> {
+        Ok(serde_json::to_vec(val)?)
+    }
+
+    fn encode_limited<T: Encode + Serialize>(
+        &self,
+        val: &T,
+        limit: usize,
+    ) -> Result<Vec<u8>, CodecError> {
+        let mut writer = CountingWriter::new(Vec::new(), limit);
+        match serde_json::to_writer(&mut writer, val) {
+            Ok(()) => Ok(writer.inner),
+            Err(_) if writer.exceeded => Err(CodecError::TooLarge { limit }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn decode<T: Decode<()> + DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        serde_json::from_slice(bytes).map_err(|source| CodecError::JsonDecode {
+            offset: json_offset(bytes, source.line(), source.column()),
+            source,
+        })
+    }
+}
+
+/// Converts serde_json's 1-based line and column into a byte offset.
+fn json_offset(bytes: &[u8], line: usize, column: usize) -> usize {
+    let line_start: usize = bytes
+        .split_inclusive(|&b| b == b'\n')
+        .take(line.saturating_sub(1))
+        .map(<[u8]>::len)
+        .sum();
+    (line_start + column.saturating_sub(1)).min(bytes.len())
+}
+
+/// MessagePack with every struct field keyed by its name, for services whose
+/// messages change between releases of peers that can't all be upgraded at
+/// once. Bincode lays fields out by position, so adding or removing one
+/// misreads everything after it; keyed fields don't depend on where they
+/// are, and take far less room than JSON.
+///
+/// Between peers whose types differ only in this way, messages keep
+/// decoding:
+///
+/// - Fields a peer doesn't know are skipped, unless its type is
+///   `#[serde(deny_unknown_fields)]`, so newer peers may add fields.
+/// - Missing `Option` fields decode as `None`, and missing
+///   `#[serde(default)]` ones as their default, so newer peers may read
+///   messages from older ones, and older peers may drop such fields.
+/// - Fields may be reordered, and enum variants too, as variants are keyed
+///   by name as well.
+///
+/// Renaming a field or variant, changing a field's type, adding a field
+/// older peers' messages lack without a default, or sending a variant a
+/// peer doesn't know still fails to decode, with a [`CodecError`] like any
+/// other. Tuples, tuple structs and tuple variants are still laid out by
+/// position.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+impl WireCodec for MessagePackCodec {
+    fn encode<T: Encode + Serialize>(&self, val: &T) -> Result<Vec<u8>, CodecError> {
+        let mut bytes = Vec::new();
+        messagepack_encode(&mut bytes, val)?;
+        Ok(bytes)
+    }
+
+    fn encode_limited<T: Encode + Serialize>(
+        &self,
+        val: &T,
+        limit: usize,
+    ) -> Result<Vec<u8>, CodecError> {
+        let mut writer = CountingWriter::new(Vec::new(), limit);
+        match messagepack_encode(&mut writer, val) {
+            Ok(()) => Ok(writer.inner),
+            Err(_) if writer.exceeded => Err(CodecError::TooLarge { limit }),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn decode<T: Decode<()> + DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        messagepack_decode(bytes).map(|(val, _)| val)
+    }
+
+    fn decode_exact<T: Decode<()> + DeserializeOwned>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<T, CodecError> {
+        let (val, consumed) = messagepack_decode(bytes)?;
+        if consumed != bytes.len() {
+            return Err(CodecError::TrailingBytes {
+                consumed,
+                len: bytes.len(),
+            });
+        }
+        Ok(val)
+    }
+}
+
+/// Writes `val` to `writer` with its struct fields keyed by name.
+fn messagepack_encode<T: Serialize>(
+    writer: &mut impl io::Write,
+    val: &T,
+) -> Result<(), rmp_serde::encode::Error> {
+    val.serialize(&mut rmp_serde::Serializer::new(writer).with_struct_map())
+}
+
+/// Decodes a `T` from the start of `bytes`, returning it along with the
+/// number of bytes it took up.
+///
+/// Read through a cursor, which only ever sets aside as much for a string
+/// or byte string as `bytes` holds, whatever its length prefix says.
+fn messagepack_decode<T: DeserializeOwned>(bytes: &[u8]) -> Result<(T, usize), CodecError> {
+    let mut deserializer = rmp_serde::Deserializer::new(io::Cursor::new(bytes));
+    let val = T::deserialize(&mut deserializer);
+    let offset = deserializer.position() as usize;
+    match val {
+        Ok(val) => Ok((val, offset)),
+        Err(source) => Err(CodecError::MessagePackDecode { source, offset }),
+    }
+}
+
+/// A codec picked at runtime, e.g. from configuration.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WireFormat {
+    #[default]
+    Bincode,
+    Json,
+    /// See [`MessagePackCodec`].
+    MessagePack,
+}
+
+impl WireCodec for WireFormat {
+    fn encode<T: Encode + Serialize>(&self, val: &T) -> Result<Vec<u8>, CodecError> {
+        match self {
+            WireFormat::Bincode => BincodeCodec.encode(val),
+            WireFormat::Json => JsonCodec.encode(val),
+            WireFormat::MessagePack => MessagePackCodec.encode(val),
+        }
+    }
+
+    fn decode<T: Decode<()> + DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, CodecError> {
+        match self {
+            WireFormat::Bincode => BincodeCodec.decode(bytes),
+            WireFormat::Json => JsonCodec.decode(bytes),
+            WireFormat::MessagePack => MessagePackCodec.decode(bytes),
+        }
+    }
+
+    fn decode_exact<T: Decode<()> + DeserializeOwned>(
+        &self,
+        bytes: &[u8],
+    ) -> Result<T, CodecError> {
+        match self {
+            WireFormat::Bincode => BincodeCodec.decode_exact(bytes),
+            WireFormat::Json => JsonCodec.decode_exact(bytes),
+            WireFormat::MessagePack => MessagePackCodec.decode_exact(bytes),
+        }
+    }
+
+    fn encode_limited<T: Encode + Serialize>(
+        &self,
+        val: &T,
+        limit: usize,
+    ) -> Result<Vec<u8>, CodecError> {
+        match self {
+            WireFormat::Bincode => BincodeCodec.encode_limited(val, limit),
+            WireFormat::Json => JsonCodec.encode_limited(val, limit),
+            WireFormat::MessagePack => MessagePackCodec.encode_limited(val, limit),
+        }
+    }
+}
+
+impl WireFormat {
+    /// Every peer understands this format, so negotiation can always fall
+    /// back to it.
+    pub const FALLBACK: WireFormat = WireFormat::Bincode;
+
+    /// Identifier used for this format during negotiation.
+    pub fn id(self) -> u8 {
+        match self {
+            WireFormat::Bincode => 0,
+            WireFormat::Json => 1,
+            WireFormat::MessagePack => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(WireFormat::Bincode),
+            1 => Some(WireFormat::Json),
+            2 => Some(WireFormat::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Picks the first format in `offered` (the client's preference order)
+    /// that is also `accepted`, falling back to [`WireFormat::FALLBACK`].
+    ///
+    /// Unknown identifiers are skipped so newer clients can offer formats
+    /// this side doesn't know about yet.
+    pub fn negotiate(offered: &[u8], accepted: &[WireFormat]) -> WireFormat {
+        offered
+            .iter()
+            .filter_map(|&id| WireFormat::from_id(id))
+            .find(|format| accepted.contains(format))
+            .unwrap_or(WireFormat::FALLBACK)
+    }
+}
@@ -0,0 +1,113 @@
+//! Switching a connection over to compressed frames once it is going, with
+//! a [`FrameKind::Upgrade`] frame.
+//!
+//! 1. The client sends an `Upgrade` naming the [`Compression`], and holds
+//!    back every other frame until it is answered.
+//! 2. The server decompresses every frame it reads after that one. It
+//!    answers with an `Upgrade` of its own, with the same id, and compresses
+//!    every frame it writes after that one; or it answers with an
+//!    [`Error`](crate::frame::FrameKind::Error) frame, and nothing changes.
+//! 3. The client decompresses every frame it reads after the answer, and
+//!    compresses every one it writes from then on.
+//!
+//! A frame is compressed whole, inside its length prefix, as it is written.
+//! So the switch falls in between two frames each way, and a frame encoded
+//! before it, like the response to a request still being handled, is
+//! compressed or not by which side of the switch it is written on.
+//!
+//! Apart from that, a server can compress the bodies of large frames only,
+//! with a compression agreed on in the [`Hello`](crate::hello::Hello),
+//! flagging each one in [`Header::compressed`](crate::frame::Header::compressed).
+//!
+//! The `deflate` feature adds [`Deflate`]; any other compression both ends
+//! register under the same name works the same way.
+//!
+//! [`FrameKind::Upgrade`]: crate::frame::FrameKind::Upgrade
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+#[cfg(feature = "deflate")]
+use std::io::Read;
+use std::sync::Arc;
+
+/// A way to compress frames, registered by [`Compression::name`] on both
+/// ends.
+pub trait Compression: fmt::Debug + Send + Sync + 'static {
+    /// What an [`Upgrade`] asks for it by.
+    fn name(&self) -> &str;
+
+    fn compress(&self, frame: &[u8]) -> Vec<u8>;
+
+    /// Fails with [`io::ErrorKind::InvalidData`] on anything `compress`
+    /// didn't make, or that takes more than `limit` bytes decompressed.
+    fn decompress(&self, frame: &[u8], limit: usize) -> io::Result<Vec<u8>>;
+}
+
+/// The body of a [`FrameKind::Upgrade`](crate::frame::FrameKind::Upgrade)
+/// frame.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct Upgrade {
+    /// [`Compression::name`] of the compression to switch to.
+    pub compression: String,
+}
+
+/// The compressions a peer can switch to, by name. Clones share them.
+#[derive(Debug, Clone, Default)]
+pub struct Compressions(Arc<HashMap<String, Arc<dyn Compression>>>);
+
+impl Compressions {
+    /// Adds `compression`, replacing any by the same name.
+    pub fn insert(&mut self, compression: impl Compression) {
+        let name = compression.name().to_owned();
+        Arc::make_mut(&mut self.0).insert(name, Arc::new(compression));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Arc<dyn Compression>> {
+        self.0.get(name)
+    }
+
+    /// Names of every compression, in no particular order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.0.keys().map(String::as_str)
+    }
+}
+
+/// Raw deflate (RFC 1951) at the default level, named `"deflate"`.
+#[cfg(feature = "deflate")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Deflate;
+
+#[cfg(feature = "deflate")]
+impl Compression for Deflate {
+    fn name(&self) -> &str {
+        "deflate"
+    }
+
+    fn compress(&self, frame: &[u8]) -> Vec<u8> {
+        let mut compressed = Vec::new();
+        flate2::read::DeflateEncoder::new(frame, flate2::Compression::default())
+            .read_to_end(&mut compressed)
+            .expect("compressing from memory never fails");
+        compressed
+    }
+
+    fn decompress(&self, frame: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+        let mut decompressed = Vec::new();
+        // One byte past the limit tells a frame just over it apart.
+        flate2::read::DeflateDecoder::new(frame)
+            .take(limit.saturating_add(1) as u64)
+            .read_to_end(&mut decompressed)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if decompressed.len() > limit {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "frame over the limit",
+            ));
+        }
+        Ok(decompressed)
+    }
+}
@@ -0,0 +1,201 @@
+use crate::frame::Trailers;
+use crate::{Callbacks, Partials, RpcError, Upload};
+
+use bincode::Encode;
+use serde::Serialize;
+
+use tokio_util::sync::{CancellationToken, WaitForCancellationFuture};
+
+use std::any::Any;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// What a handler gets to know about the call it is handling.
+///
+/// `#[request]` passes it to functions taking an argument of this type;
+/// others never see it.
+#[derive(Clone, Default)]
+pub struct Context {
+    cancelled: CancellationToken,
+    connection: Option<Arc<dyn Any + Send + Sync>>,
+    peer: Arc<Peer>,
+    trailers: Arc<Mutex<Trailers>>,
+    upload: Arc<Mutex<Option<Upload>>>,
+    callbacks: Option<Callbacks>,
+    deadline: Option<Instant>,
+    partials: Option<Partials>,
+}
+
+/// Who is on the other end of the connection a request came in on, as
+/// found out once the connection was accepted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Peer {
+    /// `None` if the server was handed the connection rather than accepting
+    /// it itself, as it can't tell then.
+    pub addr: Option<SocketAddr>,
+    /// `None` unless the connection is secured with TLS and the client
+    /// presented a certificate.
+    pub certificate: Option<PeerCertificate>,
+}
+
+/// The certificate a client presented in the TLS handshake.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerCertificate {
+    /// The subject's distinguished name, e.g. `CN=billing,O=Example`.
+    pub subject: String,
+    /// The whole certificate, DER encoded.
+    pub der: Vec<u8>,
+}
+
+impl fmt::Debug for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Context")
+            .field("cancelled", &self.cancelled)
+            .field("connection", &self.connection.as_ref().map(|_| ..))
+            .field("peer", &self.peer)
+            .field("trailers", &self.trailers)
+            .field("upload", &self.upload.lock().unwrap().as_ref().map(|_| ..))
+            .field("callbacks", &self.callbacks)
+            .field("deadline", &self.deadline)
+            .field("partials", &self.partials)
+            .finish()
+    }
+}
+
+impl Context {
+    pub fn new(cancelled: CancellationToken) -> Self {
+        Self {
+            cancelled,
+            connection: None,
+            peer: Arc::default(),
+            trailers: Arc::default(),
+            upload: Arc::default(),
+            callbacks: None,
+            deadline: None,
+            partials: None,
+        }
+    }
+
+    /// Attaches state shared by every request on the same connection.
+    pub fn with_connection_state(mut self, state: Option<Arc<dyn Any + Send + Sync>>) -> Self {
+        self.connection = state;
+        self
+    }
+
+    /// Attaches who sent the request.
+    pub fn with_peer(mut self, peer: Arc<Peer>) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    /// Attaches what the client streams after the request.
+    pub fn with_upload(self, upload: Upload) -> Self {
+        *self.upload.lock().unwrap() = Some(upload);
+        self
+    }
+
+    /// Attaches the way back to the client, for calls to it.
+    pub fn with_callbacks(mut self, callbacks: Option<Callbacks>) -> Self {
+        self.callbacks = callbacks;
+        self
+    }
+
+    /// Attaches when the caller stops waiting for the response.
+    pub fn with_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Attaches where partial results go.
+    pub fn with_partials(mut self, partials: Option<Partials>) -> Self {
+        self.partials = partials;
+        self
+    }
+
+    /// Who sent the request, e.g. for authorization or logging.
+    pub fn peer(&self) -> &Peer {
+        &self.peer
+    }
+
+    /// State the server set up for this request's connection, e.g. in its
+    /// `on_connect` hook. `None` if there is none, or it isn't a `T`.
+    pub fn connection_state<T: Any>(&self) -> Option<&T> {
+        self.connection.as_deref()?.downcast_ref()
+    }
+
+    /// Calls to the client that sent the request, see [`Callbacks`]. `None`
+    /// unless it answers any.
+    pub fn callbacks(&self) -> Option<&Callbacks> {
+        self.callbacks.as_ref()
+    }
+
+    /// Whether the caller has given up on the response, or the connection is
+    /// closing. Handlers doing lengthy work can check this to stop early;
+    /// whatever they return is dropped.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.is_cancelled()
+    }
+
+    /// When the caller stops waiting for the response, if it said. The
+    /// server aborts handlers still running by then, answering with an
+    /// [`RpcError::DeadlineExceeded`](crate::RpcError::DeadlineExceeded).
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// What is left of the time until [`Context::deadline`], e.g. to skip
+    /// optional work or take a cheaper path when it runs short. `None` if
+    /// the caller set no deadline.
+    pub fn deadline_remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Sends `item` to the caller right away, as a partial result of the
+    /// request, e.g. each match of a search as it is found. The caller still
+    /// gets the handler's return value once it is done, after every partial
+    /// result sent before.
+    ///
+    /// Partial results are dropped if the caller doesn't take them, as from
+    /// a batch or a client predating them, or has gone away. Fails if `item`
+    /// can't be encoded, or doesn't fit in a frame.
+    pub async fn send_partial<T: Encode + Serialize>(&self, item: &T) -> Result<(), RpcError> {
+        match &self.partials {
+            Some(partials) => partials.send(item).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Completes once [`Context::is_cancelled`] turns true.
+    pub fn cancelled(&self) -> WaitForCancellationFuture<'_> {
+        self.cancelled.cancelled()
+    }
+
+    /// Attaches `value` to the response under `key`, replacing what was set
+    /// for it before. Clients read it apart from the response itself, e.g.
+    /// with `Client::call_with_trailers`; those that don't never see it.
+    ///
+    /// Only plain responses carry trailers, not chunked ones or errors.
+    /// Requests in a batch share theirs.
+    pub fn set_trailer(&self, key: impl Into<String>, value: impl Into<String>) {
+        self.trailers
+            .lock()
+            .unwrap()
+            .insert(key.into(), value.into());
+    }
+
+    /// What the client streams after the request, see [`Upload`]. Only the
+    /// first call gets it; an empty one is returned after that, and for
+    /// requests sent without one.
+    pub fn take_upload(&self) -> Upload {
+        self.upload.lock().unwrap().take().unwrap_or_default()
+    }
+
+    /// Everything set with [`Context::set_trailer`] so far, leaving nothing
+    /// behind.
+    pub fn take_trailers(&self) -> Trailers {
+        std::mem::take(&mut self.trailers.lock().unwrap())
+    }
+}
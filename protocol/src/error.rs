@@ -0,0 +1,199 @@
+use crate::codec::CodecError;
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+
+/// An error sent to the client in place of a response: the body of every
+/// [`FrameKind::Error`](crate::frame::FrameKind::Error) frame, and what
+/// handlers return to fail in a way clients can match on.
+///
+/// New variants go at the end, as bincode encodes a variant by its index.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub enum RpcError {
+    #[error("failed to decode request: {0}")]
+    Decode(DecodeFailure),
+
+    #[error("server doesn't support version {requested} of {name}, only {supported:?}")]
+    VersionMismatch {
+        name: String,
+        requested: u32,
+        supported: Vec<u32>,
+    },
+
+    #[error("server has no handler for {0}")]
+    UnknownMethod(String),
+
+    #[error("internal server error: {0}")]
+    Internal(String),
+
+    /// The handler itself failed. Requests generated by `#[request]` from a
+    /// function returning `Result<T, E>` respond with
+    /// `Result<T, RpcError>`, carrying `E` formatted with `Display` here.
+    #[error("{0}")]
+    Handler(String),
+
+    /// As many requests of this type as the server allows are already
+    /// running; trying again later may succeed.
+    #[error("server is too busy to handle {0}")]
+    Busy(String),
+
+    /// The response took more than the server's `limit` bytes to encode.
+    #[error("response is larger than the server's {limit} byte limit")]
+    ResponseTooLarge { limit: u64 },
+
+    /// The request was turned away by its
+    /// [`validate`](crate::Request::validate) before being handled.
+    #[error("invalid request: {0}")]
+    ValidationFailed(String),
+
+    /// The request took longer than the server, or the handler, allows.
+    #[error("request timed out: {0}")]
+    Timeout(String),
+
+    /// The caller has sent more requests than it is allowed to for now.
+    /// Trying again once `retry_after_ms` have passed, if given, may
+    /// succeed.
+    #[error("rate limited{}", retry_after_ms.map(|ms| format!(", retry after {ms} ms")).unwrap_or_default())]
+    RateLimited { retry_after_ms: Option<u64> },
+
+    /// The caller isn't allowed to make this request.
+    #[error("unauthorized: {0}")]
+    Unauthorized(String),
+
+    /// The request named was still being handled when the deadline the
+    /// caller set for it passed, see
+    /// [`Header::deadline_ms`](crate::frame::Header::deadline_ms).
+    #[error("deadline exceeded handling {0}")]
+    DeadlineExceeded(String),
+}
+
+/// Why the server couldn't decode a request, in a form that stays stable
+/// across codec and bincode versions.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct DecodeFailure {
+    pub kind: DecodeErrorKind,
+    /// How far into the request body decoding got before failing, if known.
+    pub offset: Option<u64>,
+    /// Human-readable description from the underlying codec.
+    pub message: String,
+}
+
+impl fmt::Display for DecodeFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "{:?} at byte {offset}: {}", self.kind, self.message),
+            None => write!(f, "{:?}: {}", self.kind, self.message),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub enum DecodeErrorKind {
+    /// The body ended before the value did, e.g. a field was added on the
+    /// client but not on the server.
+    UnexpectedEnd,
+    /// An enum discriminant the server doesn't know, e.g. a request the
+    /// server doesn't implement.
+    UnknownVariant,
+    /// Bytes that don't make a valid value of the expected type.
+    InvalidValue,
+    /// A length or size outside of what the server allows.
+    LimitExceeded,
+    /// Malformed input for a text codec.
+    Syntax,
+    Other,
+    /// The value ended before the body did, on a server with strict framing.
+    /// The offset is where it ended.
+    TrailingBytes,
+}
+
+impl From<&bincode::error::DecodeError> for DecodeErrorKind {
+    fn from(err: &bincode::error::DecodeError) -> Self {
+        use bincode::error::DecodeError as E;
+        match err {
+            E::UnexpectedEnd { .. } => Self::UnexpectedEnd,
+            E::UnexpectedVariant { .. } | E::EmptyEnum { .. } => Self::UnknownVariant,
+            E::InvalidIntegerType { .. }
+            | E::NonZeroTypeIsZero { .. }
+            | E::Utf8 { .. }
+            | E::InvalidCharEncoding(_)
+            | E::InvalidBooleanValue(_)
+            | E::ArrayLengthMismatch { .. }
+            | E::InvalidDuration { .. }
+            | E::InvalidSystemTime { .. }
+            | E::CStringNulError { .. } => Self::InvalidValue,
+            E::LimitExceeded | E::OutsideUsizeRange(_) => Self::LimitExceeded,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl From<&serde_json::Error> for DecodeErrorKind {
+    fn from(err: &serde_json::Error) -> Self {
+        use serde_json::error::Category;
+        match err.classify() {
+            Category::Eof => Self::UnexpectedEnd,
+            Category::Syntax => Self::Syntax,
+            Category::Data => Self::InvalidValue,
+            Category::Io => Self::Other,
+        }
+    }
+}
+
+impl From<&rmp_serde::decode::Error> for DecodeErrorKind {
+    fn from(err: &rmp_serde::decode::Error) -> Self {
+        use rmp_serde::decode::Error as E;
+        match err {
+            E::InvalidMarkerRead(e) | E::InvalidDataRead(e)
+                if e.kind() == std::io::ErrorKind::UnexpectedEof =>
+            {
+                Self::UnexpectedEnd
+            }
+            E::TypeMismatch(_)
+            | E::OutOfRange
+            | E::LengthMismatch(_)
+            | E::Utf8Error(_)
+            | E::Syntax(_) => Self::InvalidValue,
+            E::DepthLimitExceeded => Self::LimitExceeded,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl DecodeFailure {
+    /// Describes `err` if it happened while decoding, as opposed to encoding.
+    pub fn from_codec_error(err: &CodecError) -> Option<Self> {
+        let (kind, offset, message) = match err {
+            CodecError::BincodeDecode { source, offset } => {
+                (source.into(), Some(*offset as u64), source.to_string())
+            }
+            CodecError::JsonDecode { source, offset } => {
+                (source.into(), Some(*offset as u64), source.to_string())
+            }
+            CodecError::MessagePackDecode { source, offset } => {
+                (source.into(), Some(*offset as u64), source.to_string())
+            }
+            CodecError::Truncated => (DecodeErrorKind::UnexpectedEnd, None, err.to_string()),
+            CodecError::TrailingBytes { consumed, .. } => (
+                DecodeErrorKind::TrailingBytes,
+                Some(*consumed as u64),
+                err.to_string(),
+            ),
+            CodecError::BincodeEncode(_)
+            | CodecError::JsonEncode(_)
+            | CodecError::MessagePackEncode(_)
+            | CodecError::HeaderTooLarge
+            | CodecError::TooLarge { .. } => {
+                return None;
+            }
+        };
+
+        Some(Self {
+            kind,
+            offset,
+            message,
+        })
+    }
+}
@@ -0,0 +1,614 @@
+//! Every frame is laid out as
+//!
+//! ```text
+//! [u16 header length, big-endian][header][body]
+//! ```
+//!
+//! where both the header and the body are encoded with the connection's
+//! [`WireCodec`]. Keeping the header separate lets a peer find out what a frame
+//! is, and which call it belongs to, even when the body fails to decode.
+//!
+//! Frames themselves are delimited by a length prefix, see [`Framing`].
+//! Anything relaying frames between peers, like a proxy, can pass them on as
+//! they are with [`read_frame`] and [`write_frame`], peeking at where they go
+//! with [`decode_header`].
+
+use crate::codec::{CodecError, WireCodec};
+
+use bincode::de::Decoder;
+use bincode::error::DecodeError;
+use bincode::{Decode, Encode};
+use bytes::{Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use std::collections::HashMap;
+
+use std::{fmt, io};
+
+/// Opens every [`Hello`](crate::hello::Hello) and
+/// [`HelloAck`](crate::hello::HelloAck), in the same byte order as the length
+/// prefix, so the peer can tell which order that is, see
+/// [`Framing::check_byte_order`]. Neither byte is a `{`, so it can't be
+/// mistaken for the JSON that follows, or that peers predating it send alone.
+pub const HANDSHAKE_MAGIC: u16 = 0x7270;
+
+/// How frames are delimited on the stream.
+///
+/// This is not negotiated, so both peers have to be configured alike, apart
+/// from [`Framing::expected_frame_bytes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Framing {
+    /// Width in bytes of the length prefix: 2, 3, 4 or 8.
+    pub length_field_length: usize,
+    /// Whether the length prefix is big-endian, rather than little-endian.
+    pub length_field_big_endian: bool,
+    /// Largest frame either side accepts.
+    pub max_frame_bytes: usize,
+    /// How large frames are expected to be, for the read buffer to start out
+    /// with room for one. Only a hint: larger frames still grow it, and it
+    /// doesn't limit anything.
+    pub expected_frame_bytes: usize,
+}
+
+impl Default for Framing {
+    fn default() -> Self {
+        Self {
+            length_field_length: 4,
+            length_field_big_endian: true,
+            max_frame_bytes: 8 * 1024 * 1024,
+            expected_frame_bytes: 8 * 1024,
+        }
+    }
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq)]
+pub enum FramingError {
+    #[error("length field must be 2, 3, 4 or 8 bytes, not {0}")]
+    LengthFieldLength(usize),
+
+    #[error(
+        "a {length_field_length} byte length field can't hold frames of {max_frame_bytes} bytes"
+    )]
+    MaxFrameTooLarge {
+        length_field_length: usize,
+        max_frame_bytes: usize,
+    },
+
+    #[error("peer sends {} length fields", byte_order(.big_endian))]
+    ByteOrderMismatch { big_endian: bool },
+}
+
+fn byte_order(big_endian: &bool) -> &'static str {
+    if *big_endian {
+        "big-endian"
+    } else {
+        "little-endian"
+    }
+}
+
+impl Framing {
+    pub fn validate(&self) -> Result<(), FramingError> {
+        let bits = match self.length_field_length {
+            2 | 3 | 4 | 8 => 8 * self.length_field_length as u32,
+            n => return Err(FramingError::LengthFieldLength(n)),
+        };
+        if bits < u64::BITS && self.max_frame_bytes as u64 >= 1 << bits {
+            return Err(FramingError::MaxFrameTooLarge {
+                length_field_length: self.length_field_length,
+                max_frame_bytes: self.max_frame_bytes,
+            });
+        }
+        Ok(())
+    }
+
+    /// Codec splitting a stream into frames. Call [`Framing::validate`]
+    /// first, as `LengthDelimitedCodec` panics on length fields wider than
+    /// 8 bytes.
+    pub fn codec(&self) -> LengthDelimitedCodec {
+        let mut builder = LengthDelimitedCodec::builder();
+        builder
+            .length_field_length(self.length_field_length)
+            .max_frame_length(self.max_frame_bytes);
+        if !self.length_field_big_endian {
+            builder.little_endian();
+        }
+        builder.new_codec()
+    }
+
+    /// [`HANDSHAKE_MAGIC`] in the byte order of the length prefix.
+    pub fn handshake_magic(&self) -> [u8; 2] {
+        if self.length_field_big_endian {
+            HANDSHAKE_MAGIC.to_be_bytes()
+        } else {
+            HANDSHAKE_MAGIC.to_le_bytes()
+        }
+    }
+
+    /// `frame`, a [`Hello`](crate::hello::Hello) or
+    /// [`HelloAck`](crate::hello::HelloAck), with the
+    /// [`Framing::handshake_magic`] put in front.
+    pub fn with_handshake_magic(&self, frame: &[u8]) -> Vec<u8> {
+        let mut framed = Vec::with_capacity(2 + frame.len());
+        framed.extend_from_slice(&self.handshake_magic());
+        framed.extend_from_slice(frame);
+        framed
+    }
+
+    /// Fails if `src`, the start of the first frame on a connection, opens
+    /// with [`HANDSHAKE_MAGIC`] in the other byte order, meaning the peer
+    /// writes its length prefixes in that order too, which would otherwise
+    /// have the connection wait for bytes that never come, or split the
+    /// frames that do in the wrong places. `None` until enough of the frame
+    /// is there to tell.
+    ///
+    /// Frames without the magic, from peers predating it, pass.
+    pub fn check_byte_order(&self, src: &[u8]) -> Option<Result<(), FramingError>> {
+        let prefix = src.get(..self.length_field_length)?;
+        let read = |len: u64, &byte: &u8| len << 8 | u64::from(byte);
+        let len = if self.length_field_big_endian {
+            prefix.iter().fold(0, read)
+        } else {
+            prefix.iter().rev().fold(0, read)
+        };
+        // Too short to hold the magic, read our way, so it was written our
+        // way too: the other way round it would read as far longer.
+        if len < 2 {
+            return Some(Ok(()));
+        }
+        let magic = src.get(prefix.len()..prefix.len() + 2)?;
+        let mut flipped = self.handshake_magic();
+        flipped.reverse();
+        if magic == flipped {
+            return Some(Err(FramingError::ByteOrderMismatch {
+                big_endian: !self.length_field_big_endian,
+            }));
+        }
+        Some(Ok(()))
+    }
+
+    /// Bytes to start the read buffer with, to pass to
+    /// [`Framed::with_capacity`](tokio_util::codec::Framed::with_capacity).
+    pub fn read_buffer_capacity(&self) -> usize {
+        self.expected_frame_bytes
+            .min(self.max_frame_bytes)
+            .saturating_add(self.length_field_length)
+    }
+}
+
+/// New kinds are only ever appended, so a peer that doesn't know a kind fails
+/// to decode the header instead of misreading the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub enum FrameKind {
+    /// The body is a request, without any version information.
+    Request,
+    /// The body is the response to the request with the same id.
+    Response,
+    /// The body is an [`RpcError`](crate::RpcError) answering the request
+    /// with the same id.
+    Error,
+    /// The body is a request of the [`Method`] named in the header.
+    ///
+    /// Servers from before request versioning reject this kind outright,
+    /// rather than decoding a body whose layout they may not match.
+    VersionedRequest,
+    /// The body is a list of requests, answered by a single
+    /// [`Response`](FrameKind::Response) frame holding one
+    /// `Result<Resp, RpcError>` per request, in the same order.
+    Batch,
+    /// Tells the server the client no longer waits for the response to the
+    /// request with the same id. The body is empty.
+    Cancel,
+    /// Tells the client the server reads no more requests on this
+    /// connection, and closes it soon. The id is 0 and the body is a
+    /// [`GoAway`].
+    GoAway,
+    /// The body is the next piece of the chunked response to the request
+    /// with the same id, as raw bytes rather than encoded.
+    Chunk,
+    /// Ends the chunked response to the request with the same id. The body
+    /// is empty. An [`Error`](FrameKind::Error) frame ends it as well, but
+    /// with a failure.
+    ChunkEnd,
+    /// The body is the next event of the subscription started by the request
+    /// with the same id, see [`Subscription`](crate::Subscription). Sent
+    /// whenever the server has one, until the subscription ends.
+    ///
+    /// The server answers the request with an empty
+    /// [`Response`](FrameKind::Response) frame first, once the subscription
+    /// has started, so the client knows it gets every event from then on.
+    Event,
+    /// Ends the subscription started by the request with the same id, as the
+    /// server has no more events for it. The body is empty. An
+    /// [`Error`](FrameKind::Error) frame ends it as well, but with a failure,
+    /// e.g. when it is cancelled by the server shutting down. A client
+    /// cancels with a [`Cancel`](FrameKind::Cancel) frame, and gets either
+    /// one in answer.
+    SubscriptionEnd,
+    /// Like [`VersionedRequest`](FrameKind::VersionedRequest), but followed
+    /// by an upload for the request's handler, see [`Upload`](crate::Upload):
+    /// [`UploadChunk`](FrameKind::UploadChunk) frames with the same id, up
+    /// to an [`UploadEnd`](FrameKind::UploadEnd). A
+    /// [`Cancel`](FrameKind::Cancel) frame abandons the upload along with
+    /// the request. The server may answer before the upload has ended.
+    Upload,
+    /// The body is the next piece of the upload for the request with the
+    /// same id, as raw bytes rather than encoded.
+    UploadChunk,
+    /// Ends the upload for the request with the same id. The body is empty.
+    UploadEnd,
+    /// Switches the connection to the compression named in the body, an
+    /// [`Upgrade`](crate::compression::Upgrade). The server answers with one
+    /// of its own, with the same id, once it has switched, or with an
+    /// [`Error`](FrameKind::Error) frame if it can't. See
+    /// [`compression`](crate::compression) for where the switch falls.
+    Upgrade,
+    /// The body is a partial result of the request with the same id, sent
+    /// by its handler as it goes, see
+    /// [`Context::send_partial`](crate::Context::send_partial). The request
+    /// is still answered with a [`Response`](FrameKind::Response) or
+    /// [`Error`](FrameKind::Error) frame once its handler returns, after
+    /// every partial result. Only sent to clients announcing
+    /// [`PARTIALS_FEATURE`](crate::hello::PARTIALS_FEATURE).
+    Partial,
+    /// Tells the client the server is shutting down, and reads no more
+    /// requests on this connection, but still answers those it has read
+    /// before closing it. The id is 0 and the body is empty. Only sent to
+    /// clients announcing
+    /// [`DRAINING_FEATURE`](crate::hello::DRAINING_FEATURE); others get a
+    /// [`GoAway`](FrameKind::GoAway) instead.
+    Draining,
+}
+
+/// Metadata a handler attaches to its response, next to the value it
+/// returns, see [`Context::set_trailer`](crate::Context::set_trailer).
+pub type Trailers = HashMap<String, String>;
+
+/// Decoded by hand, see [`Header::trace`]; the layout matches the derive.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Serialize, Deserialize)]
+pub struct Header {
+    /// Chosen by the client, echoed back by the server.
+    pub id: u64,
+    pub kind: FrameKind,
+    /// Set on [`FrameKind::VersionedRequest`] and [`FrameKind::Upload`] frames
+    /// only.
+    pub method: Option<Method>,
+    /// The caller's trace, on request frames sent from within one.
+    ///
+    /// Added after the other fields, so peers predating it skip over it, and
+    /// headers from those peers decode with `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+    /// What the handler attached to its response, on
+    /// [`FrameKind::Response`] frames. Added after [`Header::trace`], the
+    /// same way.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub trailers: Trailers,
+    /// Chosen by the client for a request it may send more than once, so
+    /// the server can answer the repeats without handling them again.
+    /// Added after [`Header::trailers`], the same way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// Counts the frames a client sends on a connection, from 1, for the
+    /// server to notice one that was lost or misread. Only sent by clients
+    /// that opt in. Added after [`Header::idempotency_key`], the same way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+    /// Whether the body is compressed, with the compression the handshake
+    /// agreed on for them, see
+    /// [`HelloAck::compression`](crate::hello::HelloAck::compression). The
+    /// header itself never is. Added after [`Header::seq`], the same way.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub compressed: bool,
+    /// Names the call in logs on both sides, unlike [`Header::id`], which
+    /// is only unique on one connection and only for as long as the call
+    /// runs. Added after [`Header::compressed`], the same way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Set on every frame of a call the server makes to the client, see
+    /// [`Callbacks`](crate::Callbacks), whose ids the server picks apart
+    /// from the client's. Added after [`Header::request_id`], the same way.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub reverse: bool,
+    /// How long the caller waits for the response, in milliseconds from
+    /// when it sent the request. The server gives up on the request once
+    /// that has passed, see
+    /// [`Context::deadline`](crate::Context::deadline). Added after
+    /// [`Header::reverse`], the same way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+    /// Which of the sets of requests a server mounted the request is for,
+    /// e.g. `"billing"`, or `None` for those it serves by default. Added
+    /// after [`Header::deadline_ms`], the same way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<String>,
+    /// How soon the caller wants the request handled next to others, higher
+    /// first, on a server that schedules by priority. The server may cap
+    /// it. Added after [`Header::namespace`], the same way.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+}
+
+impl<C> Decode<C> for Header {
+    fn decode<D: Decoder<Context = C>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let id = Decode::decode(decoder)?;
+        let kind = Decode::decode(decoder)?;
+        let method = Decode::decode(decoder)?;
+        let trace = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => None,
+            trace => trace?,
+        };
+        let trailers = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => Trailers::new(),
+            trailers => trailers?,
+        };
+        let idempotency_key = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => None,
+            key => key?,
+        };
+        let seq = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => None,
+            seq => seq?,
+        };
+        let compressed = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => false,
+            compressed => compressed?,
+        };
+        let request_id = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => None,
+            request_id => request_id?,
+        };
+        let reverse = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => false,
+            reverse => reverse?,
+        };
+        let deadline_ms = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => None,
+            deadline_ms => deadline_ms?,
+        };
+        let namespace = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => None,
+            namespace => namespace?,
+        };
+        let priority = match Decode::decode(decoder) {
+            Err(DecodeError::UnexpectedEnd { .. }) => None,
+            priority => priority?,
+        };
+        Ok(Self {
+            id,
+            kind,
+            method,
+            trace,
+            trailers,
+            idempotency_key,
+            seq,
+            compressed,
+            request_id,
+            reverse,
+            deadline_ms,
+            namespace,
+            priority,
+        })
+    }
+}
+
+bincode::impl_borrow_decode!(Header);
+
+/// Where in a distributed trace a request was sent from, as in a W3C
+/// `traceparent`: the handling of the request belongs to trace `trace_id`,
+/// as a child of the caller's span `span_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    /// Whether the caller records the trace.
+    pub sampled: bool,
+}
+
+impl fmt::Display for TraceContext {
+    /// Formats the context as a `traceparent` header value.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = u8::from(self.sampled);
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{flags:02x}",
+            self.trace_id, self.span_id
+        )
+    }
+}
+
+/// Body of a [`FrameKind::GoAway`] frame.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct GoAway {
+    pub reason: GoAwayReason,
+}
+
+/// Why the server stops reading requests. New reasons are only ever
+/// appended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub enum GoAwayReason {
+    /// The connection has served as many requests as the server allows.
+    /// Requests read until then are still answered.
+    RequestLimit,
+    /// The server is shutting down. Requests still running are not
+    /// answered, unless it sends this as it starts draining, to a client
+    /// that doesn't take [`FrameKind::Draining`] frames; reconnecting,
+    /// possibly elsewhere, may succeed.
+    Shutdown,
+}
+
+/// Which request, in which version, a frame body holds.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct Method {
+    pub name: String,
+    pub version: u32,
+}
+
+impl Header {
+    pub fn new(id: u64, kind: FrameKind) -> Self {
+        Self {
+            id,
+            kind,
+            method: None,
+            trace: None,
+            trailers: Trailers::new(),
+            idempotency_key: None,
+            seq: None,
+            compressed: false,
+            request_id: None,
+            reverse: false,
+            deadline_ms: None,
+            namespace: None,
+            priority: None,
+        }
+    }
+
+    pub fn request(id: u64, name: &str, version: u32) -> Self {
+        Self {
+            id,
+            kind: FrameKind::VersionedRequest,
+            method: Some(Method {
+                name: name.to_owned(),
+                version,
+            }),
+            trace: None,
+            trailers: Trailers::new(),
+            idempotency_key: None,
+            seq: None,
+            compressed: false,
+            request_id: None,
+            reverse: false,
+            deadline_ms: None,
+            namespace: None,
+            priority: None,
+        }
+    }
+
+    pub fn with_trace(mut self, trace: Option<TraceContext>) -> Self {
+        self.trace = trace;
+        self
+    }
+
+    pub fn with_trailers(mut self, trailers: Trailers) -> Self {
+        self.trailers = trailers;
+        self
+    }
+
+    pub fn with_idempotency_key(mut self, key: Option<String>) -> Self {
+        self.idempotency_key = key;
+        self
+    }
+
+    pub fn with_seq(mut self, seq: Option<u64>) -> Self {
+        self.seq = seq;
+        self
+    }
+
+    pub fn with_compressed(mut self, compressed: bool) -> Self {
+        self.compressed = compressed;
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    pub fn with_reverse(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+
+    pub fn with_deadline_ms(mut self, deadline_ms: Option<u64>) -> Self {
+        self.deadline_ms = deadline_ms;
+        self
+    }
+
+    pub fn with_namespace(mut self, namespace: Option<String>) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    pub fn with_priority(mut self, priority: Option<u8>) -> Self {
+        self.priority = priority;
+        self
+    }
+}
+
+pub fn encode_frame<T>(
+    codec: &impl WireCodec,
+    header: &Header,
+    body: &T,
+) -> Result<Vec<u8>, CodecError>
+where
+    T: Encode + Serialize,
+{
+    encode_raw_frame(codec, header, &codec.encode(body)?)
+}
+
+/// Like [`encode_frame`], but with a body that is already encoded, or isn't
+/// encoded at all like that of a [`FrameKind::Chunk`].
+pub fn encode_raw_frame(
+    codec: &impl WireCodec,
+    header: &Header,
+    body: &[u8],
+) -> Result<Vec<u8>, CodecError> {
+    let header_bytes = codec.encode(header)?;
+    let header_len = u16::try_from(header_bytes.len()).map_err(|_| CodecError::HeaderTooLarge)?;
+
+    let mut frame = header_len.to_be_bytes().to_vec();
+    frame.extend_from_slice(&header_bytes);
+    frame.extend_from_slice(body);
+    Ok(frame)
+}
+
+/// Decodes the header of `frame`, returning it along with the still encoded
+/// body.
+pub fn decode_header<'a>(
+    codec: &impl WireCodec,
+    frame: &'a [u8],
+) -> Result<(Header, &'a [u8]), CodecError> {
+    let (len, rest) = frame
+        .split_first_chunk::<2>()
+        .ok_or(CodecError::Truncated)?;
+    let len = u16::from_be_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(CodecError::Truncated);
+    }
+
+    let (header_bytes, body) = rest.split_at(len);
+    Ok((codec.decode(header_bytes)?, body))
+}
+
+/// Reads the next frame off `framed`, a stream split with
+/// [`Framing::codec`], as it was sent: its header and body still encoded.
+/// `None` once the peer has closed the stream.
+///
+/// The first frame each way is the [`Hello`](crate::hello::Hello) exchange,
+/// JSON rather than a header and body; the
+/// [`HelloAck`](crate::hello::HelloAck) has the wire format to decode the
+/// headers of the rest with. Frames sent after an
+/// [upgrade](crate::compression) are compressed as a whole, so only get
+/// passed on.
+///
+/// Cancel safe, so it can be raced against reading the other way.
+pub async fn read_frame<T: AsyncRead + Unpin>(
+    framed: &mut Framed<T, LengthDelimitedCodec>,
+) -> io::Result<Option<Bytes>> {
+    framed
+        .next()
+        .await
+        .transpose()
+        .map(|frame| frame.map(BytesMut::freeze))
+}
+
+/// Writes `frame` to `framed`, length prefix first, and flushes it: one
+/// read with [`read_frame`], or encoded with [`encode_frame`].
+pub async fn write_frame<T: AsyncWrite + Unpin>(
+    framed: &mut Framed<T, LengthDelimitedCodec>,
+    frame: Bytes,
+) -> io::Result<()> {
+    framed.send(frame).await
+}
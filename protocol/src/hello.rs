@@ -0,0 +1,209 @@
+//! The exchange that opens every connection, agreeing on everything both
+//! sides have to see alike in one round trip.
+//!
+//! The client sends a [`Hello`] listing what it can do, and the server
+//! answers with a [`HelloAck`] holding what the connection will use. Both are
+//! JSON, whatever the wire format they settle on, and unknown fields are
+//! ignored, so either side can add fields that older peers skip over. Each
+//! opens with the [`HANDSHAKE_MAGIC`], for the peer to check the byte order of
+//! the length prefix against; peers predating it send the JSON alone.
+//!
+//! Before this exchange the client sent the identifiers of its wire formats
+//! as raw bytes, and the server answered with the single byte of the one it
+//! picked. A `Hello` starts with the magic or `{`, neither of which is a
+//! format's identifier, so a server reading one the old way falls back to
+//! [`WireFormat::FALLBACK`] and answers the old way, which a client can tell
+//! apart from a `HelloAck`.
+
+use crate::codec::WireFormat;
+use crate::frame::HANDSHAKE_MAGIC;
+
+use serde::{Deserialize, Serialize};
+
+/// Version of the exchange itself; later ones only ever add fields.
+pub const HELLO_VERSION: u32 = 1;
+
+/// Version of the protocol spoken after the exchange, bumped on changes
+/// peers of other versions would misread rather than ignore. Servers tell
+/// clients theirs in [`HelloAck::protocol_version`], for those pinned to a
+/// range of them to check.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// Features this crate implements, as named in [`Hello::features`].
+pub const FEATURES: &[&str] = &[
+    "batch",
+    CALLBACKS_FEATURE,
+    "cancel",
+    "chunked",
+    DRAINING_FEATURE,
+    "go_away_reason",
+    "info",
+    PARTIALS_FEATURE,
+    "schema",
+    "subscriptions",
+    UPGRADE_FEATURE,
+    "uploads",
+];
+
+/// Announced by clients that answer calls from the server, see
+/// [`Callbacks`](crate::Callbacks). Clients without handlers for them leave
+/// it out, so the server makes none.
+pub const CALLBACKS_FEATURE: &str = "callbacks";
+
+/// Announced by clients that take [`FrameKind::Draining`] frames, without
+/// which a draining server tells them it goes away instead.
+///
+/// [`FrameKind::Draining`]: crate::frame::FrameKind::Draining
+pub const DRAINING_FEATURE: &str = "draining";
+
+/// Announced by clients that take [`FrameKind::Partial`] frames, without
+/// which handlers' partial results are dropped.
+///
+/// [`FrameKind::Partial`]: crate::frame::FrameKind::Partial
+pub const PARTIALS_FEATURE: &str = "partials";
+
+/// Announced by servers that take [`FrameKind::Upgrade`] frames, see
+/// [`compression`](crate::compression).
+///
+/// [`FrameKind::Upgrade`]: crate::frame::FrameKind::Upgrade
+pub const UPGRADE_FEATURE: &str = "upgrade";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hello {
+    pub version: u32,
+    /// Identifiers of the wire formats the client speaks, most preferred
+    /// first.
+    pub wire_formats: Vec<u8>,
+    /// Largest frame the client accepts.
+    pub max_frame_bytes: u64,
+    /// Optional features the client understands.
+    pub features: Vec<String>,
+    /// [`Compression::name`](crate::compression::Compression::name)s of
+    /// the compressions the client decompresses bodies with, see
+    /// [`Header::compressed`](crate::frame::Header::compressed).
+    pub compressions: Vec<String>,
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Self {
+            version: HELLO_VERSION,
+            wire_formats: vec![WireFormat::FALLBACK.id()],
+            max_frame_bytes: u64::MAX,
+            features: Vec::new(),
+            compressions: Vec::new(),
+        }
+    }
+}
+
+/// What the connection uses from here on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HelloAck {
+    pub version: u32,
+    pub wire_format: u8,
+    /// Largest frame either side sends: the smaller of the two limits.
+    pub max_frame_bytes: u64,
+    /// Features both sides understand.
+    pub features: Vec<String>,
+    /// Which of [`Hello::compressions`] the server compresses the bodies of
+    /// large frames with, setting
+    /// [`Header::compressed`](crate::frame::Header::compressed) on them, or
+    /// `None` if it compresses none.
+    pub compression: Option<String>,
+    /// The server's [`PROTOCOL_VERSION`].
+    pub protocol_version: u32,
+}
+
+impl Default for HelloAck {
+    fn default() -> Self {
+        Self {
+            version: HELLO_VERSION,
+            wire_format: WireFormat::FALLBACK.id(),
+            max_frame_bytes: u64::MAX,
+            features: Vec::new(),
+            compression: None,
+            // What servers predating the field speak.
+            protocol_version: 1,
+        }
+    }
+}
+
+impl Hello {
+    /// Reads a `Hello`, or `None` if `frame` isn't one, as from a client
+    /// predating it.
+    pub fn decode(frame: &[u8]) -> Option<Self> {
+        let json = without_magic(frame);
+        if json.first() != Some(&b'{') {
+            return None;
+        }
+        serde_json::from_slice(json).ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("a Hello always serializes")
+    }
+
+    /// The server's answer, given the formats it `accepts`, the largest
+    /// frame it takes and the features it has.
+    pub fn answer(
+        &self,
+        accepted: &[WireFormat],
+        max_frame_bytes: u64,
+        features: &[&str],
+    ) -> HelloAck {
+        HelloAck {
+            version: HELLO_VERSION.min(self.version),
+            wire_format: WireFormat::negotiate(&self.wire_formats, accepted).id(),
+            max_frame_bytes: max_frame_bytes.min(self.max_frame_bytes),
+            features: self
+                .features
+                .iter()
+                .filter(|feature| features.contains(&feature.as_str()))
+                .cloned()
+                .collect(),
+            compression: None,
+            protocol_version: PROTOCOL_VERSION,
+        }
+    }
+}
+
+impl HelloAck {
+    /// Reads a `HelloAck`, or `None` if `frame` isn't one, as from a server
+    /// predating it.
+    pub fn decode(frame: &[u8]) -> Option<Self> {
+        let json = without_magic(frame);
+        if json.first() != Some(&b'{') {
+            return None;
+        }
+        serde_json::from_slice(json).ok()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("a HelloAck always serializes")
+    }
+
+    /// The agreed wire format, if this side knows it.
+    pub fn wire_format(&self) -> Option<WireFormat> {
+        WireFormat::from_id(self.wire_format)
+    }
+
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+}
+
+/// `frame` past the [`HANDSHAKE_MAGIC`], in either byte order, if it opens
+/// with it.
+fn without_magic(frame: &[u8]) -> &[u8] {
+    match frame {
+        [a, b, json @ ..]
+            if [*a, *b] == HANDSHAKE_MAGIC.to_be_bytes()
+                || [*a, *b] == HANDSHAKE_MAGIC.to_le_bytes() =>
+        {
+            json
+        }
+        _ => frame,
+    }
+}
@@ -0,0 +1,73 @@
+//! Requests every server answers, whatever else it handles, so clients can
+//! check on it without knowing its API.
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// Method name a client sends to ask the server for its [`ServerInfo`].
+/// No request may be called this.
+pub const INFO_METHOD: &str = "__info";
+
+/// Method name a client sends to ask the server for the [`ConnStats`] of the
+/// connection it sends it on. No request may be called this either.
+pub const CONN_STATS_METHOD: &str = "__conn_stats";
+
+/// Method name a client sends to check the server is alive, answered with
+/// its [`Health`]. Unlike the others, servers always answer it.
+pub const PING_METHOD: &str = "__ping";
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct ServerInfo {
+    /// Version of the server crate.
+    pub version: String,
+    /// Commit the server was built from, if `GIT_HASH` was set at build time.
+    pub git_hash: Option<String>,
+    /// Time since the first server in the process was bound.
+    pub uptime_ms: u64,
+    /// Requests being handled across all connections.
+    pub in_flight: u64,
+}
+
+/// What has gone over a single connection since its handshake.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct ConnStats {
+    /// Bytes of the frames read from the client, length prefixes aside.
+    pub bytes_in: u64,
+    /// Bytes of the frames written to the client, the same way, up to the
+    /// response to this request.
+    pub bytes_out: u64,
+    /// Requests answered, a batch counting as one. These control requests
+    /// don't count.
+    pub requests_served: u64,
+    /// Requests still being handled, including streamed responses still
+    /// being sent.
+    pub in_flight: u64,
+    /// Time since the handshake.
+    pub uptime_ms: u64,
+}
+
+/// What the server answers a [`PING_METHOD`] with.
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct Health {
+    /// Whether the server is ready for requests, as its readiness check
+    /// says. Always `true` without one, as answering at all shows the
+    /// server is alive.
+    pub ready: bool,
+    /// Whatever the readiness check has to add, e.g. why the server isn't
+    /// ready.
+    pub status: Option<String>,
+}
+
+impl Health {
+    pub const READY: Self = Self {
+        ready: true,
+        status: None,
+    };
+
+    pub fn not_ready(status: impl Into<String>) -> Self {
+        Self {
+            ready: false,
+            status: Some(status.into()),
+        }
+    }
+}
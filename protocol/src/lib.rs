@@ -1,16 +1,173 @@
+mod callback;
+mod chunked;
+pub mod codec;
+pub mod compression;
+mod context;
+mod error;
+pub mod frame;
+pub mod hello;
+pub mod info;
+mod partial;
+mod payload;
+pub mod schema;
+mod subscription;
+mod upload;
+
+pub use callback::Callbacks;
+pub use chunked::ChunkedResponse;
+pub use context::{Context, Peer, PeerCertificate};
+pub use error::{DecodeErrorKind, DecodeFailure, RpcError};
+pub use partial::{Partials, SendFrame};
+pub use payload::{Payload, with_frame};
+pub use subscription::{Event, Events, Subscription};
+pub use upload::Upload;
+
 use async_trait::async_trait;
 use bincode::{Decode, Encode};
+use futures::future::BoxFuture;
+use serde::{Serialize, de::DeserializeOwned};
 
+use std::borrow::Cow;
 use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Everything a type needs to go over the wire in any [`WireFormat`]:
+/// bincode's and serde's traits both ways, and `Debug` for logging.
+///
+/// Implemented for every type that has them, so deriving `Debug`,
+/// `bincode::Encode`, `bincode::Decode`, `Serialize` and `Deserialize` is
+/// enough for a type of your own, and generic code needs only this one bound,
+/// e.g. `T: WireType + Send + 'static` for what a response holds. Frames are
+/// decoded without a context, hence `Decode<()>`; the derive covers that.
+///
+/// [`WireFormat`]: codec::WireFormat
+pub trait WireType: Encode + Decode<()> + Serialize + DeserializeOwned + Debug {}
+
+impl<T: Encode + Decode<()> + Serialize + DeserializeOwned + Debug> WireType for T {}
 
 #[async_trait]
-pub trait Request: Encode + Decode<()> + Debug {
+#[diagnostic::on_unimplemented(
+    message = "`{Self}` is not a request",
+    label = "not a `Request`",
+    note = "declare requests with `#[request]`, or implement `protocol::Request` for them"
+)]
+pub trait Request: WireType + Send + Sync + 'static {
     type Resp: Response;
 
-    async fn handle(self) -> Self::Resp;
+    /// Name used to identify this request type in logs and on the wire.
+    const NAME: &'static str;
+
+    /// Bumped whenever the fields of the request change, so a peer with a
+    /// different layout is turned away instead of misreading it.
+    const VERSION: u32 = 1;
+
+    /// Whether handling the request twice has the same effect as handling it
+    /// once, so a client may send it again after a transient failure rather
+    /// than give up. Off unless declared.
+    const IDEMPOTENT: bool = false;
+
+    /// How long a server may answer a repeat of the request, with the same
+    /// fields, with the response to the first instead of handling it again,
+    /// for handlers whose response depends on nothing but the request.
+    /// Never unless declared, e.g. with
+    /// `#[request(cacheable, ttl = "60s")]`.
+    const CACHE_TTL: Option<Duration> = None;
+
+    /// Checks the request before it is handled, keeping range checks and the
+    /// like out of [`handle`](Request::handle). An error is sent to the client
+    /// in place of the response, usually an [`RpcError::ValidationFailed`].
+    /// Accepts everything unless overridden.
+    async fn validate(&self) -> Result<(), RpcError> {
+        Ok(())
+    }
+
+    /// Checks whether the caller may have the request handled, for rules
+    /// that depend on what it asks for rather than only on who is asking.
+    /// Who that is comes from `ctx`, e.g. a principal the server's
+    /// `on_connect` hook keeps in [`Context::connection_state`]. Runs before
+    /// [`validate`](Request::validate); an error is sent to the client in
+    /// place of the response, usually an [`RpcError::Unauthorized`]. Allows
+    /// everything unless overridden.
+    async fn authorize(&self, _ctx: &Context) -> Result<(), RpcError> {
+        Ok(())
+    }
+
+    async fn handle(self, ctx: Context) -> Self::Resp;
+
+    /// Splits the request into its [name](Request::name) and a future
+    /// handling it, which can be handed to `tokio::spawn` so the request runs
+    /// independently of whatever decoded it.
+    fn into_handler(self, ctx: Context) -> (&'static str, BoxFuture<'static, Self::Resp>) {
+        (self.name(), self.handle(ctx))
+    }
+
+    /// Name of this particular request. Types wrapping several requests, like
+    /// the enums generated by `#[rpc]`, return the name of the wrapped one.
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    /// Version of this particular request, see [`Request::name`].
+    fn version(&self) -> u32 {
+        Self::VERSION
+    }
+
+    /// Whether this particular request is idempotent, see
+    /// [`Request::name`].
+    fn idempotent(&self) -> bool {
+        Self::IDEMPOTENT
+    }
+
+    /// How long the response to this particular request may be kept, see
+    /// [`Request::CACHE_TTL`].
+    fn cache_ttl(&self) -> Option<Duration> {
+        Self::CACHE_TTL
+    }
+
+    /// Every version of the request called `name` this type can decode.
+    /// Empty if it doesn't know the name at all.
+    fn versions(name: &str) -> Vec<u32> {
+        if name == Self::NAME {
+            vec![Self::VERSION]
+        } else {
+            Vec::new()
+        }
+    }
+
+    /// Describes every request this type can decode. Empty unless generated
+    /// by `#[request]` or `#[rpc]`.
+    fn schema() -> schema::RpcSchema {
+        schema::RpcSchema::default()
+    }
+}
+
+/// A request type wrapping `R` as one of its variants, as the enums
+/// generated by `#[rpc]` do for every request they hold. Lets code that only
+/// knows the enum, like a mock server, get at the `R` in it and answer it.
+pub trait Wraps<R: Request>: Request + From<R> {
+    /// The wrapped request, if `self` is the variant holding an `R`.
+    fn wrapped(&self) -> Option<&R>;
+
+    /// `resp` as the response to the variant holding an `R`.
+    fn wrap_response(resp: R::Resp) -> Self::Resp;
 }
 
-pub trait Response: Encode + Decode<()> + Debug {}
+pub trait Response: WireType + Send + 'static {
+    /// Hands the response over to be streamed instead of encoded. Only
+    /// [`ChunkedResponse`] does, along with the enums generated by `#[rpc]`
+    /// when they hold one.
+    fn into_chunks(self) -> Result<ChunkedResponse, Self> {
+        Err(self)
+    }
+
+    /// Hands the response over to be sent as events instead of encoded,
+    /// like [`into_chunks`](Response::into_chunks). Only [`Subscription`]s
+    /// do, along with the enums generated by `#[rpc]` when they hold one.
+    fn into_events(self) -> Result<Events, Self> {
+        Err(self)
+    }
+}
 
 // Response impl's for basic types
 macro_rules! impl_resp {
@@ -38,10 +195,37 @@ impl_resp! { isize i8 i16 i32 i64 i128 }
 impl_resp! { f32 f64 }
 
 // miscellaneous
-impl_resp! { String bool char }
+impl_resp! { String bool char Payload }
 
-impl_resp!(Vec<T> where T: Debug + Encode + Decode<()>);
-impl_resp!(Option<T> where T: Debug + Encode + Decode<()>);
-impl_resp!(Result<T, E> where T: Debug + Encode + Decode<()>, E: Debug + Encode + Decode<()>);
+impl_resp!(Vec<T> where T: WireType + Send + 'static);
+impl_resp!(Option<T> where T: WireType + Send + 'static);
+impl_resp!(Result<T, E> where T: WireType + Send + 'static, E: WireType + Send + 'static);
 
 impl Response for () {}
+
+impl Response for ChunkedResponse {
+    fn into_chunks(self) -> Result<ChunkedResponse, Self> {
+        Ok(self)
+    }
+}
+
+impl<T> Response for Subscription<T>
+where
+    T: Encode + Serialize + Send + 'static,
+{
+    fn into_events(self) -> Result<Events, Self> {
+        Ok(self.into_events())
+    }
+}
+
+// Smart pointers. The bounds are on the pointer rather than the pointee, so
+// unsized pointees like `Box<[u8]>` and `Arc<str>` are covered as well.
+// Decoding always allocates afresh; nothing is shared with the frame.
+impl<T: ?Sized> Response for Box<T> where Box<T>: WireType + Send + 'static {}
+
+impl<T: ?Sized> Response for Arc<T> where Arc<T>: WireType + Send + 'static {}
+
+impl<B: ?Sized + ToOwned> Response for Cow<'static, B> where
+    Cow<'static, B>: WireType + Send + 'static
+{
+}
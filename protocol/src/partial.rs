@@ -0,0 +1,57 @@
+use crate::RpcError;
+use crate::codec::{CodecError, Limited, WireFormat};
+use crate::frame::{FrameKind, Header, encode_frame};
+
+use bincode::Encode;
+use futures::future::BoxFuture;
+use serde::Serialize;
+
+use std::fmt;
+use std::sync::Arc;
+
+/// Queues an encoded frame to go out on the connection, resolving to `false`
+/// once the connection has closed.
+pub type SendFrame = Arc<dyn Fn(Vec<u8>) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// Where the partial results of one call go, as
+/// [`FrameKind::Partial`] frames, see
+/// [`Context::send_partial`](crate::Context::send_partial).
+///
+/// They go out through the same queue as the call's response, so they all
+/// reach the client before it.
+#[derive(Clone)]
+pub struct Partials {
+    id: u64,
+    codec: Limited<WireFormat>,
+    send: SendFrame,
+}
+
+impl fmt::Debug for Partials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Partials")
+            .field("id", &self.id)
+            .field("codec", &self.codec)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Partials {
+    /// Partial results of call `id`, encoded with `codec` and queued with
+    /// `send`.
+    pub fn new(id: u64, codec: Limited<WireFormat>, send: SendFrame) -> Self {
+        Self { id, codec, send }
+    }
+
+    pub(crate) async fn send<T: Encode + Serialize>(&self, item: &T) -> Result<(), RpcError> {
+        let header = Header::new(self.id, FrameKind::Partial);
+        let frame = encode_frame(&self.codec, &header, item).map_err(|e| match e {
+            CodecError::TooLarge { limit } => RpcError::ResponseTooLarge {
+                limit: limit as u64,
+            },
+            e => RpcError::Internal(format!("failed to encode partial result: {e}")),
+        })?;
+        // A connection that closed has no client left to tell.
+        (self.send)(frame).await;
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+use bincode::de::Decoder;
+use bincode::de::read::Reader;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+use bytes::Bytes;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use std::cell::RefCell;
+use std::ops::Deref;
+
+thread_local! {
+    static FRAME: RefCell<Option<Bytes>> = const { RefCell::new(None) };
+}
+
+/// Makes `frame` the buffer [`Payload`]s decoded within `f` on this thread
+/// point into, instead of copying out of it.
+pub fn with_frame<R>(frame: &Bytes, f: impl FnOnce() -> R) -> R {
+    struct Restore(Option<Bytes>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            FRAME.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+
+    let _restore = Restore(FRAME.with(|current| current.replace(Some(frame.clone()))));
+    f()
+}
+
+/// A byte string that, decoded with bincode inside [`with_frame`], shares the
+/// frame's buffer rather than being copied out of it. Both the server and the
+/// client decode this way, so a large request or response body is never
+/// copied after it is read off the socket.
+///
+/// On the wire it is the same as a `Vec<u8>`, so the two can be swapped for
+/// each other without breaking peers.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Payload(pub Bytes);
+
+impl Payload {
+    pub fn into_bytes(self) -> Bytes {
+        self.0
+    }
+}
+
+impl Deref for Payload {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<Bytes> for Payload {
+    fn from(bytes: Bytes) -> Self {
+        Self(bytes)
+    }
+}
+
+impl From<Vec<u8>> for Payload {
+    fn from(bytes: Vec<u8>) -> Self {
+        Self(bytes.into())
+    }
+}
+
+impl Encode for Payload {
+    fn encode<E: Encoder>(&self, encoder: &mut E) -> Result<(), EncodeError> {
+        self.0.as_ref().encode(encoder)
+    }
+}
+
+impl<Context> Decode<Context> for Payload {
+    fn decode<D: Decoder<Context = Context>>(decoder: &mut D) -> Result<Self, DecodeError> {
+        let len = u64::decode(decoder)?;
+        let len = usize::try_from(len).map_err(|_| DecodeError::OutsideUsizeRange(len))?;
+        decoder.claim_bytes_read(len)?;
+
+        let reader = decoder.reader();
+        let shared = reader.peek_read(len).and_then(|bytes| {
+            FRAME.with(|frame| {
+                let frame = frame.borrow();
+                let frame = frame.as_ref()?;
+                // `slice_ref` panics on a slice from some other buffer.
+                let range = frame.as_ptr_range();
+                let within = range.start <= bytes.as_ptr() && bytes.as_ptr_range().end <= range.end;
+                within.then(|| frame.slice_ref(bytes))
+            })
+        });
+        match shared {
+            Some(bytes) => {
+                reader.consume(len);
+                Ok(Self(bytes))
+            }
+            None => {
+                let mut bytes = vec![0; len];
+                reader.read(&mut bytes)?;
+                Ok(Self(bytes.into()))
+            }
+        }
+    }
+}
+
+bincode::impl_borrow_decode!(Payload);
+
+impl Serialize for Payload {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Payload {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<u8>::deserialize(deserializer).map(Self::from)
+    }
+}
@@ -0,0 +1,37 @@
+//! A description of the requests a server understands, for generating
+//! bindings in other languages.
+//!
+//! Types are given as they are spelled in the Rust source, since that is all
+//! the macros get to see.
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+/// Method name a client sends to ask the server for its [`RpcSchema`].
+/// No request may be called this.
+pub const SCHEMA_METHOD: &str = "__schema";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct RpcSchema {
+    pub methods: Vec<MethodSchema>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct MethodSchema {
+    pub name: String,
+    pub version: u32,
+    pub args: Vec<ArgSchema>,
+    pub response: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Encode, Decode, Serialize, Deserialize)]
+pub struct ArgSchema {
+    pub name: String,
+    pub ty: String,
+}
+
+impl RpcSchema {
+    pub fn method(&self, name: &str) -> Option<&MethodSchema> {
+        self.methods.iter().find(|method| method.name == name)
+    }
+}
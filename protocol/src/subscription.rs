@@ -0,0 +1,142 @@
+use crate::codec::{CodecError, WireCodec, WireFormat};
+
+use bincode::de::Decoder;
+use bincode::enc::Encoder;
+use bincode::error::{DecodeError, EncodeError};
+use bincode::{Decode, Encode};
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use tokio::sync::broadcast;
+
+use std::fmt;
+
+const NOT_ENCODED: &str = "subscriptions are sent as event frames, not encoded";
+
+/// A response the server keeps sending events for, as a series of
+/// [`FrameKind::Event`](crate::frame::FrameKind::Event) frames, until it runs
+/// out of them or the client cancels.
+///
+/// Where a [`ChunkedResponse`](crate::ChunkedResponse) is a single value sent
+/// in pieces, every event here stands on its own, and they come whenever
+/// something happens on the server: a subscription to a topic, say, gets an
+/// event each time another client publishes to it.
+///
+/// Requests answered this way are called with `Client::subscribe`. The
+/// response can't take part in a batch, and has no encoding of its own.
+pub struct Subscription<T> {
+    events: BoxStream<'static, T>,
+}
+
+impl<T: Send + 'static> Subscription<T> {
+    /// Sends every item of `events` in turn, and ends once it does.
+    pub fn from_stream(events: impl Stream<Item = T> + Send + 'static) -> Self {
+        Self {
+            events: events.boxed(),
+        }
+    }
+
+    /// Sends everything published on the channel from now on, and ends once
+    /// every sender is gone. A subscriber too slow to keep up misses the
+    /// events the channel dropped for it, rather than holding up the rest.
+    pub fn from_broadcast(receiver: broadcast::Receiver<T>) -> Self
+    where
+        T: Clone,
+    {
+        Self::from_stream(stream::unfold(receiver, |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        }))
+    }
+
+    pub fn into_stream(self) -> BoxStream<'static, T> {
+        self.events
+    }
+}
+
+impl<T: Encode + Serialize + Send + 'static> Subscription<T> {
+    /// The events with their type erased, to be encoded once the
+    /// connection's codec is known.
+    pub fn into_events(self) -> Events {
+        Events {
+            events: self
+                .events
+                .map(|event| Event {
+                    encode: Box::new(move |codec, limit| codec.encode_limited(&event, limit)),
+                })
+                .boxed(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Subscription<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription").finish_non_exhaustive()
+    }
+}
+
+/// The events of a [`Subscription`], as the server sends them.
+pub struct Events {
+    events: BoxStream<'static, Event>,
+}
+
+impl Events {
+    pub fn into_stream(self) -> BoxStream<'static, Event> {
+        self.events
+    }
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events").finish_non_exhaustive()
+    }
+}
+
+/// A single event of [`Events`], not yet encoded.
+pub struct Event {
+    encode: EncodeEvent,
+}
+
+type EncodeEvent = Box<dyn FnOnce(&WireFormat, usize) -> Result<Vec<u8>, CodecError> + Send>;
+
+impl Event {
+    /// Encodes the event with `codec`, failing with [`CodecError::TooLarge`]
+    /// if it takes more than `limit` bytes.
+    pub fn encode(self, codec: &WireFormat, limit: usize) -> Result<Vec<u8>, CodecError> {
+        (self.encode)(codec, limit)
+    }
+}
+
+impl fmt::Debug for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Event").finish_non_exhaustive()
+    }
+}
+
+impl<T> Encode for Subscription<T> {
+    fn encode<E: Encoder>(&self, _: &mut E) -> Result<(), EncodeError> {
+        Err(EncodeError::Other(NOT_ENCODED))
+    }
+}
+
+impl<T, Context> Decode<Context> for Subscription<T> {
+    fn decode<D: Decoder<Context = Context>>(_: &mut D) -> Result<Self, DecodeError> {
+        Err(DecodeError::Other(NOT_ENCODED))
+    }
+}
+
+impl<T> Serialize for Subscription<T> {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(serde::ser::Error::custom(NOT_ENCODED))
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Subscription<T> {
+    fn deserialize<D: Deserializer<'de>>(_: D) -> Result<Self, D::Error> {
+        Err(serde::de::Error::custom(NOT_ENCODED))
+    }
+}
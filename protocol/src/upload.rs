@@ -0,0 +1,59 @@
+use bytes::Bytes;
+use futures::stream::{self, BoxStream, Stream, StreamExt};
+use tokio::sync::mpsc;
+
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// What a client streams to a request's handler after the request itself,
+/// as a series of
+/// [`FrameKind::UploadChunk`](crate::frame::FrameKind::UploadChunk) frames,
+/// e.g. a dataset too large to go in a single one.
+///
+/// `#[request]` passes it to functions taking an argument of this type, like
+/// a [`Context`](crate::Context); others can take it from their context with
+/// [`Context::take_upload`](crate::Context::take_upload). Requests sent this
+/// way are called with `Client::call_upload`. Every item is the body of a
+/// frame, so the client's pieces may arrive split further.
+///
+/// The stream ends once the client has sent everything, or has given up on
+/// the call, in which case the handler's context is cancelled as well. It is
+/// empty for requests sent without an upload.
+pub struct Upload {
+    chunks: BoxStream<'static, Bytes>,
+}
+
+impl Upload {
+    pub fn from_stream(chunks: impl Stream<Item = Bytes> + Send + 'static) -> Self {
+        Self {
+            chunks: chunks.boxed(),
+        }
+    }
+
+    /// Yields everything sent on the channel, and ends once every sender is
+    /// gone.
+    pub fn from_channel(mut receiver: mpsc::Receiver<Bytes>) -> Self {
+        Self::from_stream(stream::poll_fn(move |cx| receiver.poll_recv(cx)))
+    }
+}
+
+impl Default for Upload {
+    fn default() -> Self {
+        Self::from_stream(stream::empty())
+    }
+}
+
+impl Stream for Upload {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        self.chunks.poll_next_unpin(cx)
+    }
+}
+
+impl fmt::Debug for Upload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upload").finish_non_exhaustive()
+    }
+}
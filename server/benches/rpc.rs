@@ -0,0 +1,379 @@
+//! Throughput and latency of small and large requests, over an in-memory
+//! transport and loopback TCP, and of the encode/decode path on its own, as
+//! well as the writes bursts of responses take with and without write
+//! coalescing, and what decoding a large byte field as a [`Payload`] saves
+//! over copying it into a `Vec<u8>`.
+//!
+//! Run with `cargo bench -p server --bench rpc`, optionally followed by
+//! `-- <filter>` to run only the benches whose name contains it. Every
+//! result is printed as one JSON object per line, keyed by the bench name,
+//! so runs can be saved and compared line by line.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, Server, WriteCoalescing, handle_connection};
+
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{Header, decode_header, encode_frame};
+use protocol::{Payload, Request, with_frame};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use serde_json::json;
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio_util::sync::CancellationToken;
+
+use std::hint::black_box;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[rpc(response = "BenchResponse")]
+enum BenchRequest {
+    Add(Add),
+    Echo(Echo),
+}
+
+#[request]
+fn Add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[request]
+fn Echo(data: Vec<u8>) -> Vec<u8> {
+    data
+}
+
+/// A request of one size measured.
+struct Workload {
+    name: &'static str,
+    req: fn() -> BenchRequest,
+    /// Round trips timed.
+    requests: usize,
+}
+
+const WORKLOADS: [Workload; 2] = [
+    Workload {
+        name: "add",
+        req: || BenchRequest::Add(Add { a: 2, b: 3 }),
+        requests: 20_000,
+    },
+    Workload {
+        name: "echo_64k",
+        req: || {
+            BenchRequest::Echo(Echo {
+                data: vec![7; 64 * 1024],
+            })
+        },
+        requests: 1_000,
+    },
+];
+
+/// Callers sharing the connection, each waiting for its response before
+/// sending the next request.
+const CONCURRENCY: usize = 32;
+
+/// Calls made before measuring, to get connections and allocators going.
+const WARMUP: usize = 200;
+
+/// Calls made at once by the burst benches, and how many times.
+const BURST: usize = 256;
+const BURSTS: usize = 50;
+
+/// Size of the byte field decoded by the payload bench.
+const PAYLOAD_BYTES: usize = 1024 * 1024;
+
+/// Benches whose name contains the filter given on the command line.
+struct Filter(Option<String>);
+
+impl Filter {
+    fn from_args() -> Self {
+        // `cargo bench` passes flags like `--bench` along too.
+        Self(std::env::args().skip(1).find(|arg| !arg.starts_with('-')))
+    }
+
+    fn wants(&self, name: &str) -> bool {
+        self.0.as_ref().is_none_or(|filter| name.contains(filter))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let filter = Filter::from_args();
+
+    for format in [WireFormat::Bincode, WireFormat::Json] {
+        for workload in WORKLOADS {
+            let name = format!("codec/{format:?}/{}", workload.name).to_lowercase();
+            if filter.wants(&name) {
+                codec(&name, format, &(workload.req)());
+            }
+        }
+    }
+
+    if filter.wants("payload/1m") {
+        payload("payload/1m");
+    }
+
+    let addr = serve().await;
+    for transport in ["memory", "tcp"] {
+        for workload in WORKLOADS {
+            let name = format!("rpc/{transport}/{}", workload.name);
+            if !filter.wants(&name) {
+                continue;
+            }
+            let client = match transport {
+                "memory" => connect_in_memory().await,
+                _ => client::Client::connect(addr).await.unwrap(),
+            };
+            round_trips(&name, &client, &workload).await;
+            client.close().await;
+        }
+    }
+
+    let coalesced = WriteCoalescing {
+        flush_interval: Duration::from_micros(200),
+        flush_bytes: 64 * 1024,
+    };
+    for (name, coalescing) in [
+        ("rpc/burst/uncoalesced", None),
+        ("rpc/burst/coalesced", Some(coalesced)),
+    ] {
+        if filter.wants(name) {
+            bursts(name, coalescing).await;
+        }
+    }
+}
+
+/// Encodes a request frame the way the client does, and decodes it the way
+/// the server does, over and over.
+fn codec(name: &str, format: WireFormat, req: &BenchRequest) {
+    let header = Header::request(1, req.name(), req.version());
+    let frame = encode_frame(&format, &header, req).unwrap();
+    let iterations = (64 * 1024 * 1024 / frame.len()).clamp(100, 200_000);
+
+    let started = Instant::now();
+    for _ in 0..iterations {
+        black_box(encode_frame(&format, black_box(&header), black_box(req)).unwrap());
+    }
+    let encode = started.elapsed();
+
+    let frame = Bytes::from(frame);
+    let started = Instant::now();
+    for _ in 0..iterations {
+        let (_, body) = decode_header(&format, black_box(&frame)).unwrap();
+        let body = frame.slice_ref(body);
+        let req: BenchRequest = with_frame(&body, || format.decode(&body)).unwrap();
+        black_box(req);
+    }
+    let decode = started.elapsed();
+
+    report(json!({
+        "bench": name,
+        "iterations": iterations,
+        "frame_bytes": frame.len(),
+        "encode_ns": per_op(encode, iterations),
+        "decode_ns": per_op(decode, iterations),
+    }));
+}
+
+/// Decodes a [`PAYLOAD_BYTES`] byte field with bincode, copied out of the
+/// frame into a `Vec<u8>` and pointing into it as a [`Payload`]. The two are
+/// the same on the wire.
+fn payload(name: &str) {
+    let format = WireFormat::Bincode;
+    let body = Bytes::from(format.encode(&vec![7u8; PAYLOAD_BYTES]).unwrap());
+    let iterations = 1_000;
+
+    let started = Instant::now();
+    for _ in 0..iterations {
+        let copied: Vec<u8> = format.decode(black_box(&body)).unwrap();
+        black_box(copied);
+    }
+    let copied = started.elapsed();
+
+    let started = Instant::now();
+    for _ in 0..iterations {
+        let shared: Payload = with_frame(&body, || format.decode(black_box(&body))).unwrap();
+        black_box(shared);
+    }
+    let shared = started.elapsed();
+
+    report(json!({
+        "bench": name,
+        "iterations": iterations,
+        "field_bytes": PAYLOAD_BYTES,
+        "vec_decode_ns": per_op(copied, iterations),
+        "payload_decode_ns": per_op(shared, iterations),
+    }));
+}
+
+/// Makes the calls of `workload` spread over [`CONCURRENCY`] callers, timing
+/// each.
+async fn round_trips(name: &str, client: &client::Client, workload: &Workload) {
+    let Workload { req, requests, .. } = *workload;
+    for _ in 0..WARMUP {
+        client.call(req()).await.unwrap();
+    }
+
+    let started = Instant::now();
+    let callers = (0..CONCURRENCY).map(|caller| {
+        let client = client.clone();
+        // Spread as evenly as the count allows.
+        let calls = requests / CONCURRENCY + usize::from(caller < requests % CONCURRENCY);
+        tokio::spawn(async move {
+            let mut latencies = Vec::with_capacity(calls);
+            for _ in 0..calls {
+                // Built outside the timing, as a caller would have it ready.
+                let req = req();
+                let sent = Instant::now();
+                client.call(req).await.unwrap();
+                latencies.push(sent.elapsed());
+            }
+            latencies
+        })
+    });
+    let mut latencies = Vec::with_capacity(requests);
+    for caller in futures::future::join_all(callers).await {
+        latencies.extend(caller.unwrap());
+    }
+    let elapsed = started.elapsed();
+    latencies.sort_unstable();
+
+    report(json!({
+        "bench": name,
+        "requests": requests,
+        "concurrency": CONCURRENCY,
+        "requests_per_sec": (requests as f64 / elapsed.as_secs_f64()).round(),
+        "p50_us": percentile(&latencies, 50),
+        "p99_us": percentile(&latencies, 99),
+    }));
+}
+
+/// Makes [`BURST`] calls at once, [`BURSTS`] times over, counting the
+/// writes the server answers them in.
+async fn bursts(name: &str, coalescing: Option<WriteCoalescing>) {
+    let (server_end, client_end) = tokio::io::duplex(1024 * 1024);
+    let writes = Arc::new(AtomicUsize::new(0));
+    let server_end = CountingWrites {
+        inner: server_end,
+        writes: writes.clone(),
+    };
+    tokio::spawn(async move {
+        let config = ConnectionConfig {
+            max_in_flight: BURST,
+            write_queue: BURST,
+            write_coalescing: coalescing,
+            ..ConnectionConfig::default()
+        };
+        handle_connection::<BenchRequest>(server_end, &config, CancellationToken::new()).await
+    });
+    let client = client::Client::builder()
+        .handshake(client_end)
+        .await
+        .unwrap();
+    let burst = || {
+        let calls = (0..BURST).map(|_| client.call((WORKLOADS[0].req)()));
+        futures::future::join_all(calls)
+    };
+    burst().await;
+
+    let before = writes.load(Ordering::Relaxed);
+    let started = Instant::now();
+    for _ in 0..BURSTS {
+        for resp in burst().await {
+            resp.unwrap();
+        }
+    }
+    let elapsed = started.elapsed();
+    let writes = writes.load(Ordering::Relaxed) - before;
+    let requests = BURST * BURSTS;
+    client.close().await;
+
+    report(json!({
+        "bench": name,
+        "requests": requests,
+        "writes": writes,
+        "writes_per_response": writes as f64 / requests as f64,
+        "requests_per_sec": (requests as f64 / elapsed.as_secs_f64()).round(),
+    }));
+}
+
+/// A transport counting the writes made to it, each of which would be a
+/// syscall on a socket.
+struct CountingWrites {
+    inner: DuplexStream,
+    writes: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for CountingWrites {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CountingWrites {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if written.is_ready() {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+        }
+        written
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A server on a loopback port, left running.
+async fn serve() -> SocketAddr {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<BenchRequest>());
+    addr
+}
+
+/// A client connected to a connection of its own over an in-memory
+/// transport.
+async fn connect_in_memory() -> client::Client {
+    let (server_end, client_end) = tokio::io::duplex(256 * 1024);
+    tokio::spawn(async move {
+        let config = ConnectionConfig::default();
+        handle_connection::<BenchRequest>(server_end, &config, CancellationToken::new()).await
+    });
+    client::Client::builder()
+        .handshake(client_end)
+        .await
+        .unwrap()
+}
+
+/// The `p`th percentile of the sorted `latencies`, in microseconds.
+fn percentile(latencies: &[Duration], p: usize) -> u128 {
+    let at = (latencies.len() * p / 100).min(latencies.len() - 1);
+    latencies[at].as_micros()
+}
+
+fn per_op(elapsed: Duration, iterations: usize) -> u128 {
+    elapsed.as_nanos() / iterations as u128
+}
+
+fn report(result: serde_json::Value) {
+    println!("{result}");
+}
@@ -0,0 +1,69 @@
+//! A server asking the client that called it for something, over the same
+//! connection, before answering.
+//!
+//! Run with `cargo run -p server --example callbacks`; everything, server
+//! included, runs in this one process.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::Context;
+
+use macros::{request, rpc};
+
+#[rpc(response = "ShopResponse")]
+enum ShopRequest {
+    Checkout(Checkout),
+}
+
+/// Totals up `items`, asking the client to confirm before charging it.
+#[request]
+async fn Checkout(items: Vec<u32>, ctx: Context) -> Result<String, String> {
+    let total: u32 = items.iter().sum();
+    let callbacks = ctx.callbacks().ok_or("the client can't confirm")?;
+    let confirm = WalletRequest::Confirm(Confirm { total });
+    match callbacks.call(confirm).await {
+        Ok(WalletResponse::Confirm(true)) => Ok(format!("charged {total}")),
+        Ok(WalletResponse::Confirm(false)) => Err(format!("declined {total}")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// What the client answers the server with.
+#[rpc(response = "WalletResponse")]
+enum WalletRequest {
+    Confirm(Confirm),
+}
+
+/// Whether the client is fine paying `total`.
+#[request]
+fn Confirm(total: u32) -> bool {
+    println!("server asks to pay {total}");
+    total <= 100
+}
+
+#[tokio::main]
+async fn main() -> server::Result<()> {
+    let server = Server::builder().bind("127.0.0.1:0").await?;
+    let addr = server.local_addr()?;
+    let handle = server.shutdown_handle();
+    let running = tokio::spawn(server.run::<ShopRequest>());
+
+    let client = client::Client::builder()
+        .callbacks::<WalletRequest>()
+        .connect(addr)
+        .await
+        .expect("connect");
+    for items in [vec![20, 30], vec![80, 90]] {
+        let checkout = ShopRequest::Checkout(Checkout { items });
+        match client.call(checkout).await.expect("checkout") {
+            ShopResponse::Checkout(Ok(receipt)) => println!("{receipt}"),
+            ShopResponse::Checkout(Err(e)) => println!("{e}"),
+        }
+    }
+
+    client.close().await;
+    handle.shutdown().await;
+    running.await.expect("server panicked")
+}
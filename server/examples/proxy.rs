@@ -0,0 +1,102 @@
+//! A proxy passing frames between clients and a server as they are, only
+//! peeking at their headers to log what is called.
+//!
+//! Run with `cargo run -p server --example proxy`; everything, server
+//! included, runs in this one process.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::frame::{Framing, decode_header, read_frame, write_frame};
+use protocol::hello::HelloAck;
+
+use macros::{request, rpc};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::codec::Framed;
+
+use std::io;
+use std::net::SocketAddr;
+
+#[rpc(response = "MathResponse")]
+enum MathRequest {
+    Add(Add),
+}
+
+#[request]
+fn Add(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+/// Relays every connection made to `listener` to `backend`.
+async fn proxy(listener: TcpListener, backend: SocketAddr) -> io::Result<()> {
+    loop {
+        let (client, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = relay(client, TcpStream::connect(backend).await?).await {
+                println!("relay failed: {e}");
+            }
+            io::Result::Ok(())
+        });
+    }
+}
+
+async fn relay(client: TcpStream, backend: TcpStream) -> io::Result<()> {
+    let framing = Framing::default();
+    let mut client = Framed::new(client, framing.codec());
+    let mut backend = Framed::new(backend, framing.codec());
+
+    // The hello exchange, passed on as well, has the wire format in it.
+    let Some(hello) = read_frame(&mut client).await? else {
+        return Ok(());
+    };
+    write_frame(&mut backend, hello).await?;
+    let Some(ack) = read_frame(&mut backend).await? else {
+        return Ok(());
+    };
+    let wire_format = HelloAck::decode(&ack).and_then(|ack| ack.wire_format());
+    write_frame(&mut client, ack).await?;
+
+    loop {
+        tokio::select! {
+            frame = read_frame(&mut client) => {
+                let Some(frame) = frame? else { return Ok(()) };
+                if let Some(codec) = &wire_format
+                    && let Ok((header, _)) = decode_header(codec, &frame)
+                    && let Some(method) = header.method
+                {
+                    println!("proxying {}", method.name);
+                }
+                write_frame(&mut backend, frame).await?;
+            }
+            frame = read_frame(&mut backend) => {
+                let Some(frame) = frame? else { return Ok(()) };
+                write_frame(&mut client, frame).await?;
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> server::Result<()> {
+    let server = Server::builder().bind("127.0.0.1:0").await?;
+    let backend = server.local_addr()?;
+    let handle = server.shutdown_handle();
+    let running = tokio::spawn(server.run::<MathRequest>());
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(proxy(listener, backend));
+
+    let client = client::Client::connect(addr).await.expect("connect");
+    let sum = client
+        .call(MathRequest::Add(Add { lhs: 2, rhs: 3 }))
+        .await
+        .expect("add");
+    println!("{sum:?}");
+
+    client.close().await;
+    handle.shutdown().await;
+    running.await.expect("server panicked")
+}
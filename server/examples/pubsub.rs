@@ -0,0 +1,95 @@
+//! Clients subscribing to topics, and getting every message published to them
+//! by other clients from then on.
+//!
+//! Run with `cargo run -p server --example pubsub`; everything, server
+//! included, runs in this one process.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::Subscription;
+
+use macros::{request, rpc};
+
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use std::sync::LazyLock;
+
+#[rpc(response = "ChatResponse")]
+enum ChatRequest {
+    Subscribe(Subscribe),
+    Publish(Publish),
+}
+
+#[derive(Debug, Clone, bincode::Encode, bincode::Decode, Serialize, Deserialize)]
+struct Message {
+    topic: String,
+    text: String,
+}
+
+/// Every message published, to whichever subscription wants it.
+static MESSAGES: LazyLock<broadcast::Sender<Message>> = LazyLock::new(|| broadcast::channel(64).0);
+
+/// Starts getting the messages published to `topic`.
+#[request]
+fn Subscribe(topic: String) -> Subscription<Message> {
+    let messages = Subscription::from_broadcast(MESSAGES.subscribe())
+        .into_stream()
+        .filter(move |message| std::future::ready(message.topic == topic));
+    Subscription::from_stream(messages)
+}
+
+/// Publishes `text` to `topic`, returning how many subscriptions, to any
+/// topic, were open to see it.
+#[request]
+fn Publish(topic: String, text: String) -> usize {
+    MESSAGES.send(Message { topic, text }).unwrap_or(0)
+}
+
+#[tokio::main]
+async fn main() -> server::Result<()> {
+    let server = Server::builder().bind("127.0.0.1:0").await?;
+    let addr = server.local_addr()?;
+    let handle = server.shutdown_handle();
+    let running = tokio::spawn(server.run::<ChatRequest>());
+
+    let reader = client::Client::connect(addr).await.expect("connect");
+    let mut news = reader
+        .subscribe::<Message, _>(ChatRequest::Subscribe(Subscribe {
+            topic: "news".into(),
+        }))
+        .await
+        .expect("subscribe");
+
+    let writer = client::Client::connect(addr).await.expect("connect");
+    for (topic, text) in [
+        ("news", "extra, extra"),
+        ("sports", "2 - 1"),
+        ("news", "that's all"),
+    ] {
+        let publish = ChatRequest::Publish(Publish {
+            topic: topic.into(),
+            text: text.into(),
+        });
+        writer.call(publish).await.expect("publish");
+    }
+
+    for _ in 0..2 {
+        let message = news
+            .next()
+            .await
+            .expect("subscription ended")
+            .expect("event");
+        println!("{}: {}", message.topic, message.text);
+    }
+
+    // Dropping the stream leaves the topic.
+    drop(news);
+    reader.close().await;
+    writer.close().await;
+    handle.shutdown().await;
+    running.await.expect("server panicked")
+}
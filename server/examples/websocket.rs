@@ -0,0 +1,66 @@
+//! A server taking connections over WebSocket, and a client calling it with
+//! plain frames sent as binary messages, as a browser would.
+//!
+//! Run with `cargo run -p server --features ws --example websocket`;
+//! everything, server included, runs in this one process.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::Request;
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{Header, decode_header, encode_frame};
+
+use macros::{request, rpc};
+
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message;
+
+#[rpc(response = "MathResponse")]
+enum MathRequest {
+    Add(Add),
+}
+
+#[request]
+fn Add(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let server = Server::builder()
+        .websocket(true)
+        .bind("127.0.0.1:0")
+        .await?;
+    let addr = server.local_addr()?;
+    tokio::spawn(server.run::<MathRequest>());
+
+    let (mut websocket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}")).await?;
+    let codec = WireFormat::FALLBACK;
+
+    // The handshake: the wire format asked for, and the one picked.
+    websocket.send(Message::binary(vec![codec.id()])).await?;
+    let chosen = websocket
+        .next()
+        .await
+        .ok_or("closed during handshake")??
+        .into_data();
+    println!("wire format: {:?}", WireFormat::from_id(chosen[0]));
+
+    let req = MathRequest::Add(Add { lhs: 2, rhs: 3 });
+    let frame = encode_frame(&codec, &Header::request(1, Add::NAME, 1), &req)?;
+    websocket.send(Message::binary(frame)).await?;
+
+    let reply = websocket
+        .next()
+        .await
+        .ok_or("closed before answering")??
+        .into_data();
+    let (_, body) = decode_header(&codec, &reply)?;
+    let resp: MathResponse = codec.decode(body)?;
+    println!("2 + 3: {resp:?}");
+
+    websocket.close(None).await?;
+    Ok(())
+}
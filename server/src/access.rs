@@ -0,0 +1,177 @@
+use protocol::RpcError;
+use protocol::codec::WireCodec;
+use protocol::frame::{FrameKind, decode_header};
+
+use std::fmt;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// One request answered, as handed to the [`AccessLog`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccessRecord {
+    /// When the request was read.
+    pub received: SystemTime,
+    /// `None` if the server was handed the connection rather than accepting
+    /// it itself.
+    pub peer: Option<SocketAddr>,
+    /// [`Request::NAME`](protocol::Request::NAME) of the request, or
+    /// `"batch"` for a batch.
+    pub name: &'static str,
+    /// The id the client sent the request under.
+    pub id: u64,
+    pub status: AccessStatus,
+    /// From reading the request to its response being ready to send.
+    pub latency: Duration,
+    /// Size of the request frame, header included but not the length
+    /// prefix.
+    pub request_bytes: usize,
+    /// Size of the response frames, chunks and events included, counted the
+    /// same way.
+    pub response_bytes: usize,
+}
+
+/// How a request was answered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AccessStatus {
+    Ok,
+    /// The request was answered with an error rather than a response. A
+    /// handler returning `Err` still responds, and counts as `Ok`.
+    Failed(RpcError),
+}
+
+impl AccessStatus {
+    /// The status of the request answered with `frame`.
+    pub(crate) fn of(codec: &impl WireCodec, frame: &[u8]) -> Self {
+        match decode_header(codec, frame) {
+            Ok((header, body)) if header.kind == FrameKind::Error => Self::Failed(
+                codec
+                    .decode(body)
+                    .unwrap_or_else(|e| RpcError::Internal(format!("undecodable error: {e}"))),
+            ),
+            _ => Self::Ok,
+        }
+    }
+
+    /// A short name for the status, e.g. `ok` or `timeout`.
+    pub fn as_str(&self) -> &'static str {
+        let Self::Failed(err) = self else {
+            return "ok";
+        };
+        match err {
+            RpcError::Decode(_) => "decode",
+            RpcError::VersionMismatch { .. } => "version_mismatch",
+            RpcError::UnknownMethod(_) => "unknown_method",
+            RpcError::Internal(_) => "internal",
+            RpcError::Handler(_) => "handler",
+            RpcError::Busy(_) => "busy",
+            RpcError::ResponseTooLarge { .. } => "response_too_large",
+            RpcError::ValidationFailed(_) => "validation_failed",
+            RpcError::Timeout(_) => "timeout",
+            RpcError::RateLimited { .. } => "rate_limited",
+            RpcError::Unauthorized(_) => "unauthorized",
+            RpcError::DeadlineExceeded(_) => "deadline_exceeded",
+        }
+    }
+}
+
+/// Where every request answered is reported once its response is ready,
+/// see [`ServerBuilder::access_log`](crate::ServerBuilder::access_log).
+/// Apart from the debug logging of requests and their responses, so it can
+/// be kept on without it.
+#[derive(Clone)]
+pub struct AccessLog(Arc<dyn Fn(&AccessRecord) + Send + Sync>);
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AccessLog").finish_non_exhaustive()
+    }
+}
+
+impl AccessLog {
+    pub fn new(hook: impl Fn(&AccessRecord) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(hook))
+    }
+
+    /// Writes a line per request to `writer`, as `format` puts it, e.g.
+    /// [`common_log`]. Lines that fail to write are dropped.
+    pub fn lines(
+        writer: impl Write + Send + 'static,
+        format: impl Fn(&AccessRecord) -> String + Send + Sync + 'static,
+    ) -> Self {
+        let writer = Mutex::new(writer);
+        Self::new(move |record| {
+            let line = format(record);
+            let mut writer = writer.lock().unwrap_or_else(|e| e.into_inner());
+            let _ = writeln!(writer, "{line}");
+        })
+    }
+
+    /// Writes [`common_log`] lines to stdout.
+    pub fn common() -> Self {
+        Self::lines(io::stdout(), common_log)
+    }
+
+    pub fn record(&self, record: &AccessRecord) {
+        (self.0)(record)
+    }
+}
+
+/// Formats `record` after the Common Log Format, with the request name in
+/// place of the request line, its status name in place of the status code,
+/// and the latency in microseconds at the end:
+///
+/// ```text
+/// 127.0.0.1:50312 - - [15/Oct/2026:09:30:00 +0000] "Lookup" ok 48 1250
+/// ```
+pub fn common_log(record: &AccessRecord) -> String {
+    let peer = record
+        .peer
+        .map_or_else(|| "-".to_owned(), |peer| peer.to_string());
+    format!(
+        "{peer} - - [{}] \"{}\" {} {} {}",
+        clf_time(record.received),
+        record.name,
+        record.status.as_str(),
+        record.response_bytes,
+        record.latency.as_micros(),
+    )
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// `time` in UTC, as `15/Oct/2026:09:30:00 +0000`.
+fn clf_time(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{day:02}/{}/{year}:{:02}:{:02}:{:02} +0000",
+        MONTHS[month as usize - 1],
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60,
+    )
+}
+
+/// The date `days` after 1970-01-01, as year, month and day, after Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + year_of_era + u64::from(month <= 2);
+    (year, month, day)
+}
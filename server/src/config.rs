@@ -0,0 +1,143 @@
+use crate::{BindPolicy, ConnectionConfig, ExecutionMode, OverLimit};
+
+use protocol::codec::WireFormat;
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+
+/// Every [`ServerBuilder`](crate::ServerBuilder) option as plain data, for
+/// servers configured from a file rather than in code. Start one with
+/// [`Server::from_config`](crate::Server::from_config), or turn it into a
+/// builder to add what can't be written down, like hooks.
+///
+/// Missing fields take the builder's defaults. See the builder method of the
+/// same name for what each one does.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Where to listen, resolved as by [`ServerBuilder::bind`](crate::ServerBuilder::bind).
+    /// Ignored if [`addresses`](Self::addresses) are given.
+    pub address: String,
+    /// Addresses to listen on all at once, as by
+    /// [`ServerBuilder::bind_all`](crate::ServerBuilder::bind_all), each
+    /// resolved to every address it stands for.
+    pub addresses: Vec<String>,
+    pub bind_policy: BindPolicy,
+    pub wire_formats: Vec<WireFormat>,
+    pub max_in_flight: usize,
+    pub length_field_length: usize,
+    pub length_field_big_endian: bool,
+    pub max_frame_bytes: usize,
+    pub expected_frame_bytes: usize,
+    /// `None` stands for the frame limit.
+    pub max_response_bytes: Option<usize>,
+    pub max_requests_per_connection: Option<usize>,
+    pub server_info: bool,
+    pub require_sequence_numbers: bool,
+    pub strict_framing: bool,
+    pub max_rejected_requests: usize,
+    pub concurrency_limits: HashMap<String, usize>,
+    pub over_limit: OverLimit,
+    pub execution_modes: HashMap<String, ExecutionMode>,
+    pub request_timeout_ms: Option<u64>,
+    /// Overrides [`request_timeout_ms`](Self::request_timeout_ms) by
+    /// request type.
+    pub request_timeouts_ms: HashMap<String, u64>,
+    /// Logs one in every this many requests, see
+    /// [`LogSampling::every`](crate::LogSampling::every).
+    pub log_every: u64,
+    /// Overrides [`log_every`](Self::log_every) by request type.
+    pub log_every_per_type: HashMap<String, u64>,
+    /// Slots for a [`FairScheduler::round_robin`](crate::FairScheduler::round_robin)
+    /// of this server's own; `None` leaves handlers across connections
+    /// unlimited.
+    pub global_max_in_flight: Option<usize>,
+    /// Slots for a [`PriorityScheduler`](crate::PriorityScheduler) of this
+    /// server's own; `None` runs handlers in no particular priority.
+    pub priority_max_in_flight: Option<usize>,
+    /// Bytes for a [`GlobalMemoryLimit`](crate::GlobalMemoryLimit) of this
+    /// server's own; `None` leaves memory unlimited.
+    pub memory_limit: Option<usize>,
+    /// Keys for an [`IdempotencyCache`](crate::IdempotencyCache) of this
+    /// server's own; `None` leaves requests undeduplicated.
+    pub idempotency_cache_capacity: Option<usize>,
+    /// How long that cache keeps a response.
+    pub idempotency_cache_ttl_secs: u64,
+    /// Responses kept by a [`MemoryCache`](crate::MemoryCache) of this
+    /// server's own, see
+    /// [`ServerBuilder::result_cache`](crate::ServerBuilder::result_cache);
+    /// `None` caches none.
+    pub result_cache_capacity: Option<usize>,
+    /// Whether to count bytes into a [`Traffic`](crate::Traffic) of this
+    /// server's own, see [`Server::traffic`](crate::Server::traffic).
+    pub record_traffic: bool,
+    /// Whether to write an [`AccessLog::common`](crate::AccessLog::common)
+    /// line to stdout for every request answered.
+    pub access_log: bool,
+    pub write_queue: usize,
+    /// Only takes effect with compressions added to the builder.
+    pub compress_min_bytes: Option<usize>,
+    pub frame_body_timeout_secs: Option<u64>,
+    /// [`WriteCoalescing::flush_interval`](crate::WriteCoalescing::flush_interval)
+    /// in microseconds; `None` leaves responses uncoalesced.
+    pub flush_interval_micros: Option<u64>,
+    /// [`WriteCoalescing::flush_bytes`](crate::WriteCoalescing::flush_bytes),
+    /// if coalescing.
+    pub flush_bytes: usize,
+    pub nodelay: bool,
+    pub reuse_addr: bool,
+    pub reuse_port: bool,
+    pub keepalive_secs: Option<u64>,
+    pub backlog: u32,
+    pub drain_timeout_secs: Option<u64>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        let connection = ConnectionConfig::default();
+        Self {
+            address: "127.0.0.1:8080".into(),
+            addresses: Vec::new(),
+            bind_policy: BindPolicy::default(),
+            wire_formats: connection.wire_formats,
+            max_in_flight: connection.max_in_flight,
+            length_field_length: connection.framing.length_field_length,
+            length_field_big_endian: connection.framing.length_field_big_endian,
+            max_frame_bytes: connection.framing.max_frame_bytes,
+            expected_frame_bytes: connection.framing.expected_frame_bytes,
+            max_response_bytes: connection.max_response_bytes,
+            max_requests_per_connection: connection.max_requests,
+            server_info: connection.server_info,
+            require_sequence_numbers: connection.require_sequence_numbers,
+            strict_framing: connection.strict_framing,
+            max_rejected_requests: connection.max_rejected_requests,
+            concurrency_limits: HashMap::new(),
+            over_limit: OverLimit::default(),
+            execution_modes: HashMap::new(),
+            request_timeout_ms: None,
+            request_timeouts_ms: HashMap::new(),
+            log_every: 1,
+            log_every_per_type: HashMap::new(),
+            global_max_in_flight: None,
+            priority_max_in_flight: None,
+            memory_limit: None,
+            idempotency_cache_capacity: None,
+            idempotency_cache_ttl_secs: 300,
+            result_cache_capacity: None,
+            record_traffic: false,
+            access_log: false,
+            write_queue: connection.write_queue,
+            compress_min_bytes: connection.compress_min_bytes,
+            frame_body_timeout_secs: connection.frame_body_timeout.map(|t| t.as_secs()),
+            flush_interval_micros: None,
+            flush_bytes: 64 * 1024,
+            nodelay: true,
+            reuse_addr: true,
+            reuse_port: false,
+            keepalive_secs: None,
+            backlog: 1024,
+            drain_timeout_secs: None,
+        }
+    }
+}
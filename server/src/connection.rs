@@ -0,0 +1,1197 @@
+use crate::access::{AccessRecord, AccessStatus};
+use crate::dispatch::{Reply, Service, Static};
+use crate::hooks::ConnectionState;
+use crate::info::{ConnCounters, Readiness, health, server_info};
+use crate::levels::log_error;
+use crate::limits::{ConcurrencyLimits, Permits};
+use crate::memory::{GlobalMemoryLimit, Outgoing};
+use crate::traffic::{Sizes, Traffic};
+use crate::{
+    AccessLog, CacheBackend, Error, ErrorLevels, ExecutionModes, FairScheduler, IdempotencyCache,
+    LogSampling, PriorityScheduler, RequestTimeouts, Result,
+};
+
+use protocol::codec::{CodecError, Limited, WireCodec, WireFormat};
+use protocol::compression::{Compression, Compressions, Upgrade};
+use protocol::frame::{
+    FrameKind, Framing, GoAway, GoAwayReason, Header, decode_header, encode_frame, encode_raw_frame,
+};
+use protocol::hello::{CALLBACKS_FEATURE, DRAINING_FEATURE, FEATURES, Hello, PARTIALS_FEATURE};
+use protocol::info::{CONN_STATS_METHOD, INFO_METHOD, PING_METHOD};
+use protocol::{
+    Callbacks, ChunkedResponse, Context, Events, Partials, Peer, Request, RpcError, SendFrame,
+    Upload, with_frame,
+};
+
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, Sink, SinkExt, Stream, StreamExt, TryStreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tokio_util::codec::{Decoder, Encoder, Framed, LengthDelimitedCodec};
+use tokio_util::sync::CancellationToken;
+
+use bytes::{Bytes, BytesMut};
+
+use std::collections::HashMap;
+use std::future;
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tracing::{debug, info, warn};
+
+/// Pieces of an upload buffered for its handler before the connection stops
+/// reading.
+const UPLOAD_QUEUE_CAPACITY: usize = 16;
+
+/// Calls to the client queued before the handlers making them wait, see
+/// [`Callbacks`].
+const CALLBACK_QUEUE_CAPACITY: usize = 16;
+
+/// How a connection learns the server is shutting down.
+#[derive(Debug, Clone)]
+pub(crate) struct Shutdown {
+    /// Cancelled once the server starts draining: the connection reads no
+    /// more requests, but answers those it has read.
+    pub(crate) drain: CancellationToken,
+    /// Cancelled once the server stops waiting for that, closing the
+    /// connection and cancelling the requests still running on it.
+    pub(crate) abort: CancellationToken,
+}
+
+impl Shutdown {
+    /// Closes the connection as soon as `abort` is cancelled, without
+    /// draining it first.
+    pub(crate) fn immediate(abort: CancellationToken) -> Self {
+        Self {
+            drain: CancellationToken::new(),
+            abort,
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.drain.is_cancelled() || self.abort.is_cancelled()
+    }
+}
+
+/// Settings shared by every connection a server accepts.
+#[derive(Debug, Clone)]
+pub struct ConnectionConfig {
+    /// Encodings a client may pick from, see [`ServerBuilder::wire_formats`](crate::ServerBuilder::wire_formats).
+    pub wire_formats: Vec<WireFormat>,
+    /// Maximum number of handlers running at once for a single connection.
+    /// Each request in a batch counts separately.
+    pub max_in_flight: usize,
+    /// Number of responses queued for a connection before it stops taking on
+    /// new requests until the client catches up.
+    pub write_queue: usize,
+    pub framing: Framing,
+    /// Number of requests served on a connection before it is closed, see
+    /// [`ServerBuilder::max_requests_per_connection`](crate::ServerBuilder::max_requests_per_connection).
+    pub max_requests: Option<usize>,
+    /// Whether [`INFO_METHOD`] and [`CONN_STATS_METHOD`] requests are
+    /// answered, see
+    /// [`ServerBuilder::server_info`](crate::ServerBuilder::server_info).
+    pub server_info: bool,
+    /// See [`ServerBuilder::readiness`](crate::ServerBuilder::readiness).
+    pub readiness: Option<Readiness>,
+    /// See [`ServerBuilder::concurrency_limits`](crate::ServerBuilder::concurrency_limits).
+    pub concurrency_limits: ConcurrencyLimits,
+    /// See [`ServerBuilder::execution_modes`](crate::ServerBuilder::execution_modes).
+    pub execution_modes: ExecutionModes,
+    /// See [`ServerBuilder::request_timeouts`](crate::ServerBuilder::request_timeouts).
+    pub request_timeouts: RequestTimeouts,
+    /// See [`ServerBuilder::log_sampling`](crate::ServerBuilder::log_sampling).
+    pub log_sampling: LogSampling,
+    /// See [`ServerBuilder::memory_limit`](crate::ServerBuilder::memory_limit).
+    pub memory: GlobalMemoryLimit,
+    /// See [`ServerBuilder::max_response_bytes`](crate::ServerBuilder::max_response_bytes);
+    /// `None` stands for the frame limit.
+    pub max_response_bytes: Option<usize>,
+    /// See [`ServerBuilder::traffic`](crate::ServerBuilder::traffic).
+    pub traffic: Option<Traffic>,
+    /// See [`ServerBuilder::access_log`](crate::ServerBuilder::access_log).
+    pub access_log: Option<AccessLog>,
+    /// See [`ServerBuilder::idempotency_cache`](crate::ServerBuilder::idempotency_cache).
+    pub idempotency_cache: Option<IdempotencyCache>,
+    /// See [`ServerBuilder::require_sequence_numbers`](crate::ServerBuilder::require_sequence_numbers).
+    pub require_sequence_numbers: bool,
+    /// See [`ServerBuilder::compression`](crate::ServerBuilder::compression).
+    pub compressions: Compressions,
+    /// See [`ServerBuilder::compress_min_bytes`](crate::ServerBuilder::compress_min_bytes).
+    pub compress_min_bytes: Option<usize>,
+    /// See [`ServerBuilder::strict_framing`](crate::ServerBuilder::strict_framing).
+    pub strict_framing: bool,
+    /// See [`ServerBuilder::max_rejected_requests`](crate::ServerBuilder::max_rejected_requests).
+    pub max_rejected_requests: usize,
+    /// See [`ServerBuilder::scheduler`](crate::ServerBuilder::scheduler).
+    pub scheduler: Option<FairScheduler>,
+    /// See [`ServerBuilder::priority_scheduler`](crate::ServerBuilder::priority_scheduler).
+    pub priority_scheduler: Option<PriorityScheduler>,
+    /// See [`ServerBuilder::result_cache`](crate::ServerBuilder::result_cache).
+    pub result_cache: Option<Arc<dyn CacheBackend>>,
+    /// See [`ServerBuilder::error_levels`](crate::ServerBuilder::error_levels).
+    pub error_levels: ErrorLevels,
+    /// See [`ServerBuilder::write_coalescing`](crate::ServerBuilder::write_coalescing).
+    pub write_coalescing: Option<WriteCoalescing>,
+    /// See [`ServerBuilder::frame_body_timeout`](crate::ServerBuilder::frame_body_timeout).
+    pub frame_body_timeout: Option<Duration>,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        Self {
+            wire_formats: vec![WireFormat::FALLBACK],
+            max_in_flight: 64,
+            write_queue: 64,
+            framing: Framing::default(),
+            max_requests: None,
+            server_info: true,
+            readiness: None,
+            concurrency_limits: ConcurrencyLimits::default(),
+            execution_modes: ExecutionModes::default(),
+            request_timeouts: RequestTimeouts::default(),
+            log_sampling: LogSampling::default(),
+            memory: GlobalMemoryLimit::unlimited(),
+            max_response_bytes: None,
+            traffic: None,
+            access_log: None,
+            idempotency_cache: None,
+            require_sequence_numbers: false,
+            compressions: Compressions::default(),
+            compress_min_bytes: None,
+            strict_framing: false,
+            max_rejected_requests: 0,
+            scheduler: None,
+            priority_scheduler: None,
+            result_cache: None,
+            error_levels: ErrorLevels::default(),
+            write_coalescing: None,
+            frame_body_timeout: None,
+        }
+    }
+}
+
+pub async fn handle_connection<Req: Request>(
+    socket: impl AsyncRead + AsyncWrite + Unpin,
+    config: &ConnectionConfig,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let peer = Arc::default();
+    let shutdown = Shutdown::immediate(shutdown);
+    serve_connection(socket, config, shutdown, None, peer, &Static::<Req>::new()).await
+}
+
+/// Like [`handle_connection`], but splitting the stream into frames with
+/// `codec` instead of a length prefix, for peers framing messages their own
+/// way. Every item `codec` decodes is one frame, as laid out in
+/// [`protocol::frame`], and so is every item it encodes.
+/// [`ConnectionConfig::framing`] only sets the largest frame agreed on in
+/// the handshake, so `codec` has to cap frame sizes itself.
+pub async fn handle_connection_with_codec<Req, C>(
+    socket: impl AsyncRead + AsyncWrite + Unpin,
+    codec: C,
+    config: &ConnectionConfig,
+    shutdown: CancellationToken,
+) -> Result<()>
+where
+    Req: Request,
+    C: Decoder<Item = BytesMut, Error = io::Error> + Encoder<Bytes, Error = io::Error>,
+{
+    let framed = Framed::new(socket, codec).map_ok(BytesMut::freeze);
+    let peer = Arc::default();
+    let service = &Static::<Req>::new();
+    let shutdown = Shutdown::immediate(shutdown);
+    serve_transport(framed, None, config, shutdown, None, peer, service).await
+}
+
+/// Like [`handle_connection`], but over a transport that already delimits
+/// messages, e.g. WebSocket: every item read is one frame, and so is every
+/// item written, without a length prefix. [`ConnectionConfig::framing`]
+/// doesn't apply, so the transport has to cap message sizes itself.
+pub async fn handle_messages<Req, T>(
+    transport: T,
+    config: &ConnectionConfig,
+    shutdown: CancellationToken,
+) -> Result<()>
+where
+    Req: Request,
+    T: Stream<Item = io::Result<Bytes>> + Sink<Bytes, Error = io::Error> + Unpin,
+{
+    let peer = Arc::default();
+    serve_transport(
+        transport,
+        None,
+        config,
+        Shutdown::immediate(shutdown),
+        None,
+        peer,
+        &Static::<Req>::new(),
+    )
+    .await
+}
+
+pub(crate) async fn serve_connection(
+    socket: impl AsyncRead + AsyncWrite + Unpin,
+    config: &ConnectionConfig,
+    shutdown: Shutdown,
+    state: ConnectionState,
+    peer: Arc<Peer>,
+    service: &impl Service,
+) -> Result<()> {
+    let (codec, receiving) = FrameCodec::new(config.framing);
+    let mut framed = Framed::with_capacity(socket, codec, config.framing.read_buffer_capacity());
+    // Otherwise the frames held back are written out every few KiB anyway.
+    if let Some(coalescing) = config.write_coalescing {
+        framed.set_backpressure_boundary(coalescing.flush_bytes);
+    }
+    serve_transport(
+        framed,
+        Some(receiving),
+        config,
+        shutdown,
+        state,
+        peer,
+        service,
+    )
+    .await
+}
+
+/// Serves `transport`, closing it once a frame takes longer than
+/// [`ConnectionConfig::frame_body_timeout`] from when `receiving` says it
+/// started arriving.
+pub(crate) async fn serve_transport<T>(
+    mut transport: T,
+    mut receiving: Option<watch::Receiver<Option<Instant>>>,
+    config: &ConnectionConfig,
+    shutdown: Shutdown,
+    state: ConnectionState,
+    peer: Arc<Peer>,
+    service: &impl Service,
+) -> Result<()>
+where
+    T: Stream<Item = io::Result<Bytes>> + Sink<Bytes, Error = io::Error> + Unpin,
+{
+    let timeout = config.frame_body_timeout;
+    let timed_out = |timeout| {
+        let e = Error::FrameTimeout(timeout);
+        log_error!(config.error_levels, &e, %e, "closing connection trickling a frame");
+        e
+    };
+    let Negotiated {
+        codec,
+        max_frame_bytes,
+        answers_callbacks,
+        takes_partials,
+        takes_draining,
+        body_compression,
+    } = tokio::select! {
+        negotiated = negotiate(&mut transport, config) => negotiated?,
+        timeout = frame_timed_out(receiving.as_mut(), timeout) => return Err(timed_out(timeout)),
+    };
+    debug!(wire_format = ?codec, max_frame_bytes, answers_callbacks, takes_partials, takes_draining, "negotiated connection settings");
+    let responses = Limited {
+        codec,
+        limit: config.max_response_bytes.unwrap_or(max_frame_bytes),
+    };
+
+    // Responses go through a bounded queue to a separate writer, so a client
+    // that stops reading holds up this connection instead of piling up
+    // responses in memory. Reading and writing stay independent of each other
+    // otherwise, so a client blocked on sending more requests is still read
+    // from while its responses are waiting.
+    let (sink, mut stream) = transport.split();
+    let (frames, queued) = mpsc::channel(config.write_queue);
+    let counters = ConnCounters::new();
+    let writer = write_frames(sink, queued, &counters, config, codec, body_compression);
+
+    let counters = &counters;
+    let reader = async move {
+        let permits = Permits::new(config);
+        let mut in_flight = FuturesUnordered::new();
+        let mut cancellations: HashMap<u64, CancellationToken> = HashMap::new();
+        // Where the pieces of each upload still going on go.
+        let mut uploads: HashMap<u64, mpsc::Sender<Bytes>> = HashMap::new();
+        // A piece waiting for its handler to make room, holding up reading
+        // until it has.
+        let mut stalled: Option<BoxFuture<'static, ()>> = None;
+        let mut reading = true;
+        let mut served = 0;
+        let mut rejected = 0;
+        let mut sequence = Sequence::new(config.require_sequence_numbers);
+        // What frames read are compressed with, once the client has upgraded.
+        let mut compression: Option<Arc<dyn Compression>> = None;
+        // Only for clients answering them. Their answers are read like
+        // requests, so handlers waiting for them still count against the
+        // connection's in-flight limit.
+        let (callback_frames, mut callback_queue) = mpsc::channel(CALLBACK_QUEUE_CAPACITY);
+        let callbacks = answers_callbacks.then(|| {
+            let codec = Limited {
+                codec,
+                limit: max_frame_bytes,
+            };
+            Callbacks::new(codec, callback_frames)
+        });
+        // Answers to calls still waiting are never read once reading stops.
+        let stop_reading = |reading: &mut bool| {
+            *reading = false;
+            if let Some(callbacks) = &callbacks {
+                callbacks.close();
+            }
+        };
+
+        // Requests already read are still answered after the client is done
+        // sending.
+        while reading || !in_flight.is_empty() {
+            // Only counted while frames are read, so a client held up by the
+            // server's own limits isn't taken for a slow one.
+            let read_on = reading
+                && stalled.is_none()
+                && in_flight.len() < config.max_in_flight
+                && config.memory.has_room();
+            tokio::select! {
+                timeout = frame_timed_out(receiving.as_mut(), timeout), if read_on => return Err(timed_out(timeout)),
+                // Stop reading while the connection is at its limit; the client
+                // can't get further ahead than the kernel buffers allow.
+                maybe_segment = stream.next(), if read_on => {
+                    let maybe_segment = match maybe_segment.transpose() {
+                        Ok(maybe_segment) => maybe_segment,
+                        Err(e) if is_disconnect(&e) => {
+                            let e = Error::from(e);
+                            log_error!(config.error_levels, &e, %e, "client disconnected");
+                            return Ok(());
+                        }
+                        Err(e) => {
+                            let e = Error::from(e);
+                            log_error!(config.error_levels, &e, %e, "failed to get next segment");
+                            return Err(e);
+                        }
+                    };
+                    let Some(segment) = maybe_segment else {
+                        stop_reading(&mut reading);
+                        continue;
+                    };
+                    counters.read(&segment);
+                    let segment = match &compression {
+                        Some(compression) => compression.decompress(&segment, max_frame_bytes).map(Bytes::from).map_err(Error::from).inspect_err(|e| {
+                            log_error!(config.error_levels, e, %e, len = segment.len(), "failed to decompress frame")
+                        })?,
+                        None => segment,
+                    };
+                    let request_charge = config.memory.charge(segment.len());
+
+                    // Peek at the header: cancellations are handled right
+                    // here, and every other request needs its id known to
+                    // be cancellable.
+                    let (header, body) = decode_header(&codec, &segment).map_err(Error::from).inspect_err(|e| {
+                        log_error!(config.error_levels, e, %e, len = segment.len(), "failed to decode frame header")
+                    })?;
+                    sequence.check(header.seq).inspect_err(|e| {
+                        log_error!(config.error_levels, e, %e, "closing desynced connection")
+                    })?;
+                    // Answers to calls of our own.
+                    if header.reverse {
+                        if let Some(callbacks) = &callbacks {
+                            callbacks.answer(&header, segment.slice_ref(body));
+                        }
+                        continue;
+                    }
+                    match header.kind {
+                        FrameKind::Cancel => {
+                            // Ends its upload too, which lets go of the
+                            // pieces still queued for it.
+                            uploads.remove(&header.id);
+                            if let Some(token) = cancellations.remove(&header.id) {
+                                debug!(request.id = header.id, "request cancelled by client");
+                                token.cancel();
+                            }
+                            continue;
+                        }
+                        FrameKind::UploadChunk => {
+                            // Pieces of uploads that were answered, or never
+                            // started, have nowhere to go.
+                            let Some(chunks) = uploads.get(&header.id) else {
+                                continue;
+                            };
+                            match chunks.try_send(segment.slice_ref(body)) {
+                                Ok(()) => {}
+                                Err(TrySendError::Full(chunk)) => {
+                                    let chunks = chunks.clone();
+                                    stalled = Some(async move {
+                                        let _ = chunks.send(chunk).await;
+                                    }.boxed());
+                                }
+                                Err(TrySendError::Closed(_)) => {
+                                    uploads.remove(&header.id);
+                                }
+                            }
+                            continue;
+                        }
+                        FrameKind::UploadEnd => {
+                            uploads.remove(&header.id);
+                            continue;
+                        }
+                        FrameKind::Upgrade => {
+                            let frame = match codec.decode::<Upgrade>(body) {
+                                Ok(upgrade) => match config.compressions.get(&upgrade.compression) {
+                                    Some(switched) => {
+                                        debug!(compression = upgrade.compression, "upgrading connection");
+                                        compression = Some(switched.clone());
+                                        let frame = encode_frame(&codec, &Header::new(header.id, FrameKind::Upgrade), &upgrade)?;
+                                        config.memory.outgoing(frame).then_compress(switched.clone())
+                                    }
+                                    None => {
+                                        let err = RpcError::ValidationFailed(format!("unknown compression {:?}", upgrade.compression));
+                                        let frame = encode_frame(&codec, &Header::new(header.id, FrameKind::Error), &err)?;
+                                        config.memory.outgoing(frame)
+                                    }
+                                },
+                                // Answered like an unknown compression, so the
+                                // connection goes on as it was.
+                                Err(e) => {
+                                    warn!(%e, len = body.len(), "failed to decode upgrade");
+                                    let err = RpcError::from(Error::from(e));
+                                    let frame = encode_frame(&codec, &Header::new(header.id, FrameKind::Error), &err)?;
+                                    config.memory.outgoing(frame)
+                                }
+                            };
+                            if frames.send(frame).await.is_err() {
+                                return Ok(());
+                            }
+                            continue;
+                        }
+                        _ => {}
+                    }
+
+                    let resp_header = Header::new(header.id, FrameKind::Response);
+                    let frame = match header.method.as_ref().map(|m| m.name.as_str()) {
+                        Some(PING_METHOD) => Some(encode_frame(&codec, &resp_header, &health(config.readiness.as_ref()))?),
+                        Some(INFO_METHOD) if config.server_info => Some(encode_frame(&codec, &resp_header, &server_info())?),
+                        Some(CONN_STATS_METHOD) if config.server_info => Some(encode_frame(&codec, &resp_header, &counters.stats(in_flight.len()))?),
+                        _ => None,
+                    };
+                    if let Some(frame) = frame {
+                        if frames.send(config.memory.outgoing(frame)).await.is_err() {
+                            return Ok(());
+                        }
+                        continue;
+                    }
+
+                    let id = header.id;
+                    // Closing the connection cancels everything still running
+                    // on it, too.
+                    let token = shutdown.abort.child_token();
+                    // Requests are decoded right away, so `Payload`s in them
+                    // can point into the frame.
+                    let deadline = header.deadline_ms.map(|ms| std::time::Instant::now() + Duration::from_millis(ms));
+                    // A batch's requests share its id, so theirs couldn't be
+                    // told apart.
+                    let partials = (takes_partials && header.kind != FrameKind::Batch)
+                        .then(|| partials(id, responses, &frames, &config.memory));
+                    let ctx = Context::new(token.clone())
+                        .with_connection_state(state.clone())
+                        .with_peer(peer.clone())
+                        .with_callbacks(callbacks.clone())
+                        .with_deadline(deadline)
+                        .with_partials(partials);
+                    let (received, started) = (SystemTime::now(), Instant::now());
+                    let peer_addr = peer.addr;
+                    let (ctx, upload) = if header.kind == FrameKind::Upload {
+                        let (chunks, upload) = mpsc::channel(UPLOAD_QUEUE_CAPACITY);
+                        (ctx.with_upload(Upload::from_channel(upload)), Some(chunks))
+                    } else {
+                        (ctx, None)
+                    };
+                    match with_frame(&segment, || service.call(&responses, &segment, &permits, ctx)) {
+                        Ok((name, response)) => {
+                            cancellations.insert(id, token.clone());
+                            if let Some(chunks) = upload {
+                                uploads.insert(id, chunks);
+                            }
+                            let chunk_frames = frames.clone();
+                            let request_sizes = Sizes::of(&segment);
+                            in_flight.push(async move {
+                                let resp = response.await;
+                                drop(request_charge);
+                                let mut response_sizes = Sizes::default();
+                                let resp = match resp {
+                                    Ok(Reply::Frame(frame)) => Ok(frame),
+                                    Ok(Reply::Chunked { id, chunks }) => {
+                                        send_chunks(&codec, id, chunks, &chunk_frames, config, max_frame_bytes, &token)
+                                            .await
+                                            .map(|(frame, sizes)| {
+                                                response_sizes = sizes;
+                                                frame
+                                            })
+                                    }
+                                    Ok(Reply::Subscribed { id, events }) => {
+                                        send_events(&codec, id, events, &chunk_frames, config, max_frame_bytes, &token)
+                                            .await
+                                            .map(|(frame, sizes)| {
+                                                response_sizes = sizes;
+                                                frame
+                                            })
+                                    }
+                                    Err(e) => Err(e),
+                                };
+                                if let Ok(frame) = &resp {
+                                    response_sizes.add(frame);
+                                    if let Some(traffic) = &config.traffic {
+                                        traffic.record(name, request_sizes, response_sizes);
+                                    }
+                                    if let Some(access_log) = &config.access_log {
+                                        access_log.record(&AccessRecord {
+                                            received,
+                                            peer: peer_addr,
+                                            name,
+                                            id,
+                                            status: AccessStatus::of(&codec, frame),
+                                            latency: started.elapsed(),
+                                            request_bytes: request_sizes.frame,
+                                            response_bytes: response_sizes.frame,
+                                        });
+                                    }
+                                }
+                                (id, resp)
+                            });
+
+                            served += 1;
+                            if config.max_requests == Some(served) {
+                                info!(served, "request limit reached, draining connection");
+                                stop_reading(&mut reading);
+                                let frame = go_away(&codec, GoAwayReason::RequestLimit)?;
+                                if frames.send(config.memory.outgoing(frame)).await.is_err() {
+                                    return Ok(());
+                                }
+                            }
+                        }
+                        Err(Error::Rejected { id, error }) => {
+                            let frame = encode_frame(&codec, &Header::new(id, FrameKind::Error), &error)?;
+                            // The frame came whole, so the next one can still
+                            // be told apart from it.
+                            if rejected < config.max_rejected_requests {
+                                rejected += 1;
+                                log_error!(config.error_levels, &error, request.id = id, %error, rejected, "rejected request, reading on");
+                                if frames.send(config.memory.outgoing(frame)).await.is_err() {
+                                    return Ok(());
+                                }
+                                continue;
+                            }
+                            // Let the client know why before hanging up.
+                            let _ = frames.send(config.memory.outgoing(frame)).await;
+                            return Err(Error::Rejected { id, error });
+                        }
+                        Err(e) => {
+                            log_error!(config.error_levels, &e, %e, "failed to handle request");
+                            return Err(e);
+                        }
+                    }
+                }
+
+                Some((id, resp_bytes)) = in_flight.next() => {
+                    cancellations.remove(&id);
+                    uploads.remove(&id);
+                    let resp_bytes = resp_bytes.inspect_err(|e| {
+                        log_error!(config.error_levels, e, %e, "failed to handle request");
+                    })?;
+                    counters.served();
+
+                    // Waits, without reading further requests, while the
+                    // queue is full.
+                    if frames.send(config.memory.outgoing(resp_bytes)).await.is_err() {
+                        // The writer has given up and reports why.
+                        return Ok(());
+                    }
+                }
+
+                Some(frame) = callback_queue.recv(), if callbacks.is_some() => {
+                    if frames.send(config.memory.outgoing(frame)).await.is_err() {
+                        return Ok(());
+                    }
+                }
+
+                // Resumes reading once the handler has taken enough of its
+                // upload.
+                () = async { stalled.as_mut().unwrap().await }, if stalled.is_some() => stalled = None,
+
+                // Resumes reading once other connections have let go of
+                // enough memory.
+                () = config.memory.room(), if reading && !config.memory.has_room() => {}
+
+                _ = frames.closed() => return Ok(()),
+
+                // Requests already read are still answered, until the
+                // server stops waiting for them.
+                _ = shutdown.drain.cancelled(), if reading => {
+                    info!("Received shutdown signal, draining connection...");
+                    stop_reading(&mut reading);
+                    let frame = if takes_draining {
+                        encode_frame(&codec, &Header::new(0, FrameKind::Draining), &())?
+                    } else {
+                        go_away(&codec, GoAwayReason::Shutdown)?
+                    };
+                    if frames.send(config.memory.outgoing(frame)).await.is_err() {
+                        return Ok(());
+                    }
+                }
+
+                _ = shutdown.abort.cancelled() => {
+                    info!("Received shutdown signal, closing connection...");
+                    // Lets the client tell this apart from a crash. A client
+                    // too far behind to take it finds out from the close.
+                    let frame = go_away(&codec, GoAwayReason::Shutdown)?;
+                    let _ = frames.try_send(config.memory.outgoing(frame));
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    };
+
+    // The reader drops its end of the queue when it is done, which lets the
+    // writer flush what is left and close the socket.
+    let (read, write) = tokio::join!(reader, writer);
+    read.and(write)
+}
+
+fn go_away(codec: &WireFormat, reason: GoAwayReason) -> Result<Vec<u8>> {
+    let header = Header::new(0, FrameKind::GoAway);
+    Ok(encode_frame(codec, &header, &GoAway { reason })?)
+}
+
+/// Where the partial results of call `id` go: the queue its response goes
+/// through too, so they all go out before it.
+fn partials(
+    id: u64,
+    codec: Limited<WireFormat>,
+    frames: &mpsc::Sender<Outgoing>,
+    memory: &GlobalMemoryLimit,
+) -> Partials {
+    let (frames, memory) = (frames.clone(), memory.clone());
+    let send: SendFrame = Arc::new(move |frame| {
+        let (frames, frame) = (frames.clone(), memory.outgoing(frame));
+        async move { frames.send(frame).await.is_ok() }.boxed()
+    });
+    Partials::new(id, codec, send)
+}
+
+/// Queues the pieces of a chunked response as they come in, returning the
+/// frame that ends it along with the sizes of those queued. Pieces are split
+/// to fit `max_frame_bytes`.
+async fn send_chunks(
+    codec: &WireFormat,
+    id: u64,
+    chunks: ChunkedResponse,
+    frames: &mpsc::Sender<Outgoing>,
+    config: &ConnectionConfig,
+    max_frame_bytes: usize,
+    cancelled: &CancellationToken,
+) -> Result<(Vec<u8>, Sizes)> {
+    let chunk_header = Header::new(id, FrameKind::Chunk);
+    let overhead = encode_raw_frame(codec, &chunk_header, &[])?.len();
+    let max_chunk = max_frame_bytes.saturating_sub(overhead).max(1);
+
+    let mut chunks = chunks.into_stream();
+    let mut sent = Sizes::default();
+    loop {
+        let chunk = tokio::select! {
+            chunk = chunks.next() => chunk,
+            // Ending the response normally would pass what was sent so far
+            // off as all of it.
+            () = cancelled.cancelled() => {
+                debug!(sent = sent.body, "chunked response cancelled");
+                let err = RpcError::Internal("response cancelled".into());
+                return Ok((encode_frame(codec, &Header::new(id, FrameKind::Error), &err)?, sent));
+            }
+        };
+
+        let mut chunk = match chunk {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(e)) => {
+                let err = RpcError::Handler(e.to_string());
+                log_error!(config.error_levels, &err, %e, sent = sent.body, "chunked response failed");
+                let frame = encode_frame(codec, &Header::new(id, FrameKind::Error), &err)?;
+                return Ok((frame, sent));
+            }
+            None => break,
+        };
+
+        while !chunk.is_empty() {
+            let piece = chunk.split_to(max_chunk.min(chunk.len()));
+            let frame = encode_raw_frame(codec, &chunk_header, &piece)?;
+            sent.add(&frame);
+            // If the writer is gone, the connection finds out as soon as it
+            // tries to send the frame returned below.
+            if frames.send(config.memory.outgoing(frame)).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    debug!(sent = sent.body, "chunked response sent");
+    let frame = encode_frame(codec, &Header::new(id, FrameKind::ChunkEnd), &())?;
+    Ok((frame, sent))
+}
+
+/// Queues the events of a subscription as they come in, returning the frame
+/// that ends it along with the sizes of those queued. An event too large for
+/// `max_frame_bytes`, or that can't be encoded at all, ends it with an error.
+async fn send_events(
+    codec: &WireFormat,
+    id: u64,
+    events: Events,
+    frames: &mpsc::Sender<Outgoing>,
+    config: &ConnectionConfig,
+    max_frame_bytes: usize,
+    cancelled: &CancellationToken,
+) -> Result<(Vec<u8>, Sizes)> {
+    let event_header = Header::new(id, FrameKind::Event);
+    let overhead = encode_raw_frame(codec, &event_header, &[])?.len();
+    let max_event = max_frame_bytes.saturating_sub(overhead);
+
+    // Lets the client know it gets every event from here on.
+    let started = encode_frame(codec, &Header::new(id, FrameKind::Response), &())?;
+    let mut sent = Sizes::default();
+    sent.add(&started);
+    // If the writer is gone, the connection finds out as soon as it tries to
+    // send the frame returned below.
+    let mut writing = frames.send(config.memory.outgoing(started)).await.is_ok();
+
+    let mut events = events.into_stream();
+    while writing {
+        let event = tokio::select! {
+            event = events.next() => event,
+            // A client that cancelled doesn't wait for the end any more, but
+            // one whose connection is shutting down has to tell it apart
+            // from the events running out.
+            () = cancelled.cancelled() => {
+                debug!(sent = sent.body, "subscription cancelled");
+                let err = RpcError::Internal("subscription cancelled".into());
+                return Ok((encode_frame(codec, &Header::new(id, FrameKind::Error), &err)?, sent));
+            }
+        };
+        let Some(event) = event else {
+            debug!(sent = sent.body, "subscription ran out of events");
+            break;
+        };
+
+        let body = match event.encode(codec, max_event) {
+            Ok(body) => body,
+            Err(CodecError::TooLarge { limit }) => {
+                let err = RpcError::ResponseTooLarge {
+                    limit: limit as u64,
+                };
+                log_error!(
+                    config.error_levels,
+                    &err,
+                    limit,
+                    sent = sent.body,
+                    "event too large"
+                );
+                let frame = encode_frame(codec, &Header::new(id, FrameKind::Error), &err)?;
+                return Ok((frame, sent));
+            }
+            Err(e) => {
+                let err = RpcError::Internal(format!("failed to encode event: {e}"));
+                log_error!(config.error_levels, &err, %e, sent = sent.body, "failed to encode event");
+                let frame = encode_frame(codec, &Header::new(id, FrameKind::Error), &err)?;
+                return Ok((frame, sent));
+            }
+        };
+        let frame = encode_raw_frame(codec, &event_header, &body)?;
+        sent.add(&frame);
+        writing = frames.send(config.memory.outgoing(frame)).await.is_ok();
+    }
+
+    let frame = encode_frame(codec, &Header::new(id, FrameKind::SubscriptionEnd), &())?;
+    Ok((frame, sent))
+}
+
+/// Completes with `timeout` once the frame `receiving` says is on its way has
+/// taken longer than that to arrive. Never for transports that don't tell,
+/// or without a timeout.
+async fn frame_timed_out(
+    receiving: Option<&mut watch::Receiver<Option<Instant>>>,
+    timeout: Option<Duration>,
+) -> Duration {
+    let (Some(receiving), Some(timeout)) = (receiving, timeout) else {
+        return future::pending().await;
+    };
+    loop {
+        let started = *receiving.borrow_and_update();
+        let changed = match started {
+            Some(started) => tokio::select! {
+                () = tokio::time::sleep_until(started + timeout) => return timeout,
+                changed = receiving.changed() => changed,
+            },
+            None => receiving.changed().await,
+        };
+        // The codec is gone along with the connection.
+        if changed.is_err() {
+            return future::pending().await;
+        }
+    }
+}
+
+/// Holds responses back to write several at once, see
+/// [`ServerBuilder::write_coalescing`](crate::ServerBuilder::write_coalescing).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteCoalescing {
+    /// Longest a response is held back for.
+    pub flush_interval: Duration,
+    /// Bytes held back before they are written anyway.
+    pub flush_bytes: usize,
+}
+
+/// Sends every frame from `queued` until the queue closes, then shuts the
+/// socket down. Large bodies are compressed with `body_compression` on the
+/// way, see [`ConnectionConfig::compress_min_bytes`].
+async fn write_frames(
+    mut sink: impl Sink<Bytes, Error = io::Error> + Unpin,
+    mut queued: mpsc::Receiver<Outgoing>,
+    counters: &ConnCounters,
+    config: &ConnectionConfig,
+    codec: WireFormat,
+    body_compression: Option<Arc<dyn Compression>>,
+) -> Result<()> {
+    let levels = &config.error_levels;
+    let failed = |e: io::Error| {
+        let disconnected = is_disconnect(&e);
+        let e = Error::from(e);
+        if disconnected {
+            log_error!(levels, &e, %e, "client disconnected before its response was sent");
+            Ok(())
+        } else {
+            log_error!(levels, &e, %e, "failed to send response");
+            Err(e)
+        }
+    };
+
+    // See `Outgoing::switch`.
+    let mut compression: Option<Arc<dyn Compression>> = None;
+    // Bytes held back, and when they have to be written by, while
+    // coalescing.
+    let mut unflushed = 0;
+    let mut flush_by = None;
+    loop {
+        let outgoing = tokio::select! {
+            outgoing = queued.recv() => outgoing,
+            () = tokio::time::sleep_until(flush_by.unwrap_or_else(Instant::now)), if flush_by.is_some() => {
+                if let Err(e) = sink.flush().await {
+                    return failed(e);
+                }
+                unflushed = 0;
+                flush_by = None;
+                continue;
+            }
+        };
+        let Some(outgoing) = outgoing else {
+            break;
+        };
+
+        let frame = match (&body_compression, config.compress_min_bytes) {
+            (Some(compression), Some(min_bytes)) => {
+                compress_body(&codec, &outgoing.frame, &**compression, min_bytes)
+            }
+            _ => outgoing.frame.clone(),
+        };
+        let frame = match &compression {
+            Some(compression) => Bytes::from(compression.compress(&frame)),
+            None => frame,
+        };
+        let len = frame.len();
+        // The frame stays counted against the memory limit until written.
+        let sent = match config.write_coalescing {
+            None => sink.send(frame.clone()).await,
+            Some(coalescing) => match sink.feed(frame.clone()).await {
+                Ok(()) if unflushed + len >= coalescing.flush_bytes => {
+                    unflushed = 0;
+                    flush_by = None;
+                    sink.flush().await
+                }
+                Ok(()) => {
+                    unflushed += len;
+                    flush_by.get_or_insert_with(|| Instant::now() + coalescing.flush_interval);
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+        };
+        match sent {
+            Ok(()) => {
+                counters.written(&frame);
+                if let Some(switch) = outgoing.switch {
+                    compression = Some(switch);
+                }
+            }
+            Err(e) => return failed(e),
+        }
+    }
+
+    match sink.close().await {
+        Err(e) if !is_disconnect(&e) && e.kind() != io::ErrorKind::NotConnected => {
+            let e = Error::from(e);
+            log_error!(levels, &e, %e, "error shutting down socket");
+            Err(e)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// `frame` with its body compressed with `compression` and
+/// [`Header::compressed`] set, if the body takes at least `min_bytes` and
+/// gets smaller for it; otherwise `frame` as it is.
+fn compress_body(
+    codec: &WireFormat,
+    frame: &Bytes,
+    compression: &dyn Compression,
+    min_bytes: usize,
+) -> Bytes {
+    if frame.len() < min_bytes {
+        return frame.clone();
+    }
+    let Ok((header, body)) = decode_header(codec, frame) else {
+        return frame.clone();
+    };
+    if body.len() < min_bytes || header.compressed {
+        return frame.clone();
+    }
+    let compressed = compression.compress(body);
+    if compressed.len() >= body.len() {
+        return frame.clone();
+    }
+    encode_raw_frame(codec, &header.with_compressed(true), &compressed)
+        .map_or_else(|_| frame.clone(), Bytes::from)
+}
+
+/// Whether `e` just means the client went away, as opposed to something
+/// actually going wrong on this side.
+pub(crate) fn is_disconnect(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Checks the sequence numbers on the frames of a connection, see
+/// [`Header::seq`]. Once a frame has had one, every later frame needs the
+/// next.
+struct Sequence {
+    required: bool,
+    last: Option<u64>,
+}
+
+impl Sequence {
+    fn new(required: bool) -> Self {
+        Self {
+            required,
+            last: None,
+        }
+    }
+
+    fn check(&mut self, seq: Option<u64>) -> Result<()> {
+        let expected = self.last.map_or(1, |last| last.wrapping_add(1));
+        match seq {
+            Some(seq) if seq == expected => {
+                self.last = Some(seq);
+                Ok(())
+            }
+            None if !self.required && self.last.is_none() => Ok(()),
+            found => Err(Error::ProtocolDesync { expected, found }),
+        }
+    }
+}
+
+/// [`LengthDelimitedCodec`], except that a connection closing in the middle
+/// of a frame fails with [`io::ErrorKind::UnexpectedEof`] rather than a
+/// generic error, so it can be told apart from a broken frame, and that a
+/// client whose length prefixes are in the other byte order fails with
+/// [`io::ErrorKind::InvalidData`] on its first frame.
+///
+/// Tells when the frame it is in the middle of started arriving, for
+/// [`frame_timed_out`].
+#[derive(Debug)]
+struct FrameCodec {
+    codec: LengthDelimitedCodec,
+    /// Until the prefix of the first frame has been checked.
+    unchecked: Option<Framing>,
+    /// Whether the prefix of a frame not yet complete has been taken out of
+    /// the buffer already.
+    in_frame: bool,
+    started: watch::Sender<Option<Instant>>,
+}
+
+impl FrameCodec {
+    fn new(framing: Framing) -> (Self, watch::Receiver<Option<Instant>>) {
+        let (started, receiving) = watch::channel(None);
+        let codec = Self {
+            codec: framing.codec(),
+            unchecked: Some(framing),
+            in_frame: false,
+            started,
+        };
+        (codec, receiving)
+    }
+
+    /// Notes whether a frame is on its way, or `decoded` was the last of it.
+    fn track(&mut self, decoded: bool, src: &BytesMut) {
+        let receiving = !decoded && (self.in_frame || !src.is_empty());
+        self.started
+            .send_if_modified(|started| match (receiving, *started) {
+                (true, None) => {
+                    *started = Some(Instant::now());
+                    true
+                }
+                (false, Some(_)) => {
+                    *started = None;
+                    true
+                }
+                _ => false,
+            });
+    }
+
+    /// Whether the first frame's prefix has been checked by now.
+    fn check_byte_order(&mut self, src: &BytesMut) -> io::Result<bool> {
+        let Some(framing) = self.unchecked else {
+            return Ok(true);
+        };
+        let Some(checked) = framing.check_byte_order(src) else {
+            return Ok(false);
+        };
+        self.unchecked = None;
+        checked.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(true)
+    }
+}
+
+impl Decoder for FrameCodec {
+    type Item = Bytes;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Bytes>> {
+        // Read in the wrong order, the prefix could come out over the limit
+        // before there's enough of the frame to tell.
+        let frame = if self.check_byte_order(src)? {
+            let before = src.len();
+            let frame = self.codec.decode(src)?;
+            self.in_frame = frame.is_none() && (self.in_frame || src.len() < before);
+            frame.map(BytesMut::freeze)
+        } else {
+            None
+        };
+        self.track(frame.is_some(), src);
+        Ok(frame)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> io::Result<Option<Bytes>> {
+        match self.codec.decode(src)? {
+            Some(frame) => Ok(Some(frame.freeze())),
+            None if src.is_empty() => Ok(None),
+            None => Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed in the middle of a frame",
+            )),
+        }
+    }
+}
+
+impl Encoder<Bytes> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        self.codec.encode(frame, dst)
+    }
+}
+
+/// What a connection was set up with in the handshake.
+#[derive(Debug, Clone)]
+struct Negotiated {
+    codec: WireFormat,
+    /// The smaller of the two sides' frame limits.
+    max_frame_bytes: usize,
+    /// Whether the client answers calls from the server, see
+    /// [`CALLBACKS_FEATURE`].
+    answers_callbacks: bool,
+    /// Whether the client takes partial results, see [`PARTIALS_FEATURE`].
+    takes_partials: bool,
+    /// Whether the client takes draining frames, see [`DRAINING_FEATURE`].
+    takes_draining: bool,
+    /// What large bodies are compressed with, see
+    /// [`ConnectionConfig::compress_min_bytes`].
+    body_compression: Option<Arc<dyn Compression>>,
+}
+
+/// The client opens every connection with a [`Hello`], answered with a
+/// [`HelloAck`](protocol::hello::HelloAck) holding the wire format and frame
+/// limit both sides use from then on.
+///
+/// A client predating it sends the identifiers of the wire formats it speaks
+/// instead, and gets the single byte of the chosen one back.
+async fn negotiate<T>(transport: &mut T, config: &ConnectionConfig) -> Result<Negotiated>
+where
+    T: Stream<Item = io::Result<Bytes>> + Sink<Bytes, Error = io::Error> + Unpin,
+{
+    let offered = transport
+        .next()
+        .await
+        .ok_or(Error::Handshake)?
+        .map_err(Error::from)
+        .inspect_err(|e| log_error!(config.error_levels, e, %e, "failed to read handshake"))?;
+
+    let max_frame_bytes = config.framing.max_frame_bytes;
+    let (reply, negotiated) = match Hello::decode(&offered) {
+        Some(hello) => {
+            let mut ack = hello.answer(&config.wire_formats, max_frame_bytes as u64, FEATURES);
+            let body_compression = config.compress_min_bytes.and_then(|_| {
+                hello
+                    .compressions
+                    .iter()
+                    .find_map(|name| Some((name, config.compressions.get(name)?)))
+            });
+            ack.compression = body_compression.map(|(name, _)| name.clone());
+            let negotiated = Negotiated {
+                codec: ack.wire_format().unwrap_or(WireFormat::FALLBACK),
+                max_frame_bytes: usize::try_from(ack.max_frame_bytes).unwrap_or(max_frame_bytes),
+                answers_callbacks: ack.has_feature(CALLBACKS_FEATURE),
+                takes_partials: ack.has_feature(PARTIALS_FEATURE),
+                takes_draining: ack.has_feature(DRAINING_FEATURE),
+                body_compression: body_compression.map(|(_, compression)| compression.clone()),
+            };
+            // Only clients opening with the magic expect it back.
+            let reply = if offered.starts_with(&config.framing.handshake_magic()) {
+                config.framing.with_handshake_magic(&ack.encode())
+            } else {
+                ack.encode()
+            };
+            (reply, negotiated)
+        }
+        None => {
+            let codec = WireFormat::negotiate(&offered, &config.wire_formats);
+            let negotiated = Negotiated {
+                codec,
+                max_frame_bytes,
+                answers_callbacks: false,
+                takes_partials: false,
+                takes_draining: false,
+                body_compression: None,
+            };
+            (vec![codec.id()], negotiated)
+        }
+    };
+    transport
+        .send(Bytes::from(reply))
+        .await
+        .map_err(Error::from)
+        .inspect_err(
+            |e| log_error!(config.error_levels, e, %e, "failed to send handshake response"),
+        )?;
+
+    Ok(negotiated)
+}
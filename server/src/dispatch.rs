@@ -0,0 +1,621 @@
+use crate::idempotency::deduplicated;
+use crate::info::InFlight;
+use crate::levels::log_error;
+use crate::limits::Permits;
+use crate::result_cache::Cached;
+use crate::traffic::Sizes;
+use crate::{CacheBackend, Error, ErrorLevels, ExecutionMode, Result};
+
+use protocol::codec::{CodecError, Limited, WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Header, Method, Trailers, decode_header, encode_frame};
+use protocol::schema::SCHEMA_METHOD;
+use protocol::{
+    ChunkedResponse, Context, DecodeErrorKind, DecodeFailure, Events, Request, Response, RpcError,
+};
+
+use bincode::{Decode, Encode};
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use serde::{Serialize, de::DeserializeOwned};
+use tokio::runtime::Handle;
+
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{Instrument, Span, debug, field, info, info_span};
+
+/// What handling a request produced.
+pub(crate) enum Reply {
+    /// The encoded response frame.
+    Frame(Vec<u8>),
+    /// A response to stream to the client in chunks, see [`ChunkedResponse`].
+    Chunked { id: u64, chunks: ChunkedResponse },
+    /// Events to send the client until they run out or it cancels, see
+    /// [`Subscription`](protocol::Subscription).
+    Subscribed { id: u64, events: Events },
+}
+
+impl Reply {
+    /// The reply to request `id` streaming `resp` over the connection, if it
+    /// is streamed rather than encoded.
+    pub(crate) fn streamed<R: Response>(id: u64, resp: R) -> Result<Self, R> {
+        match resp.into_chunks() {
+            Ok(chunks) => Ok(Self::Chunked { id, chunks }),
+            Err(resp) => resp
+                .into_events()
+                .map(|events| Self::Subscribed { id, events }),
+        }
+    }
+
+    /// Logs the reply being streamed, if it is.
+    pub(crate) fn log_streaming(&self) {
+        match self {
+            Self::Frame(_) => {}
+            Self::Chunked { .. } => info!("streaming chunked response"),
+            Self::Subscribed { .. } => info!("subscription started"),
+        }
+    }
+}
+
+/// Turns request frames into futures producing the response frames.
+///
+/// Implemented for the enums generated by `#[rpc]` through [`Static`], and for
+/// [`Router`](crate::Router).
+pub(crate) trait Service: Send + Sync + 'static {
+    /// Decodes `frame`, returning a future that handles it once it gets hold
+    /// of its `permits`, along with the request's name. Errors are returned
+    /// right away where possible, so a bad frame doesn't have to wait its
+    /// turn. Responses are encoded within `codec`'s limit.
+    fn call<'a>(
+        &'a self,
+        codec: &'a Limited<WireFormat>,
+        frame: &[u8],
+        permits: &'a Permits,
+        ctx: Context,
+    ) -> Result<(&'static str, BoxFuture<'a, Result<Reply>>)>;
+}
+
+/// What batches are known as in logs and [`Traffic`](crate::Traffic).
+pub(crate) const BATCH_NAME: &str = "batch";
+
+/// Serves the requests known to `Req` at compile time.
+pub(crate) struct Static<Req>(PhantomData<fn() -> Req>);
+
+impl<Req> Static<Req> {
+    pub(crate) fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<Req: Request> Service for Static<Req> {
+    fn call<'a>(
+        &'a self,
+        codec: &'a Limited<WireFormat>,
+        frame: &[u8],
+        permits: &'a Permits,
+        ctx: Context,
+    ) -> Result<(&'static str, BoxFuture<'a, Result<Reply>>)> {
+        let mut call = decode_request::<Req>(codec, frame, permits, ctx)?;
+        let (name, id, key) = (call.name(), call.id, call.idempotency_key.take());
+        let reply = dispatch(codec, call, permits).boxed();
+        let cache = permits.idempotency_cache();
+        Ok((name, deduplicated(cache, codec, id, key, reply)))
+    }
+}
+
+/// A decoded request frame, ready to be handled.
+pub(crate) struct Call<Req> {
+    id: u64,
+    body: CallBody<Req>,
+    span: Span,
+    ctx: Context,
+    /// Whether the request and its response are logged, see
+    /// [`LogSampling`](crate::LogSampling).
+    logged: bool,
+    /// Set for idempotent requests only, see
+    /// [`IdempotencyCache`](crate::IdempotencyCache).
+    idempotency_key: Option<String>,
+    /// What the caller asked for, see
+    /// [`PriorityScheduler`](crate::PriorityScheduler). Shared by a batch.
+    priority: Option<u8>,
+}
+
+impl<Req: Request> Call<Req> {
+    fn name(&self) -> &'static str {
+        match &self.body {
+            CallBody::Single(req) => req.name(),
+            CallBody::Batch(_) => BATCH_NAME,
+            CallBody::Schema => SCHEMA_METHOD,
+        }
+    }
+}
+
+enum CallBody<Req> {
+    Single(Req),
+    Batch(Vec<Req>),
+    /// A request for [`Request::schema`], sent as [`SCHEMA_METHOD`].
+    Schema,
+}
+
+/// Decodes a request frame, handles it, and returns the encoded response frame.
+///
+/// A [`ChunkedResponse`] or [`Subscription`](protocol::Subscription) needs a
+/// connection to be streamed over, so it is answered with an error frame
+/// here.
+pub async fn handle_request<Req: Request>(codec: &impl WireCodec, frame: &[u8]) -> Result<Vec<u8>> {
+    let permits = Permits::unlimited();
+    let call = decode_request::<Req>(codec, frame, &permits, Context::default())?;
+    match dispatch(codec, call, &permits).await? {
+        Reply::Frame(frame) => Ok(frame),
+        Reply::Chunked { id, .. } => {
+            let err = RpcError::Internal("chunked responses need a connection".into());
+            Ok(encode_frame(
+                codec,
+                &Header::new(id, FrameKind::Error),
+                &err,
+            )?)
+        }
+        Reply::Subscribed { id, .. } => {
+            let err = RpcError::Internal("subscriptions need a connection".into());
+            Ok(encode_frame(
+                codec,
+                &Header::new(id, FrameKind::Error),
+                &err,
+            )?)
+        }
+    }
+}
+
+pub(crate) fn decode_request<Req: Request>(
+    codec: &impl WireCodec,
+    frame: &[u8],
+    permits: &Permits,
+    ctx: Context,
+) -> Result<Call<Req>> {
+    let levels = permits.error_levels();
+    let (header, req_bytes) = decode_header(codec, frame)
+        .map_err(Error::from)
+        .inspect_err(
+            |e| log_error!(levels, e, %e, len = frame.len(), "failed to decode frame header"),
+        )?;
+
+    let span = dispatch_span(&header, frame.len());
+    let _enter = span.enter();
+    span.record("request.body_size", req_bytes.len());
+
+    let reject = |e| rejection(header.id, e, req_bytes.len(), levels);
+    let strict = permits.strict_framing();
+
+    let body: CallBody<Req> = match (header.kind, &header.method) {
+        (FrameKind::VersionedRequest, Some(method)) if method.name == SCHEMA_METHOD => {
+            CallBody::Schema
+        }
+        (FrameKind::Request, _) => {
+            CallBody::Single(decode_body(codec, req_bytes, strict).map_err(reject)?)
+        }
+        (FrameKind::VersionedRequest | FrameKind::Upload, Some(method)) => {
+            check_version(header.id, method, Req::versions(&method.name), levels)?;
+            let req: Req = decode_body(codec, req_bytes, strict).map_err(reject)?;
+            check_name(header.id, method, req.name(), levels)?;
+            CallBody::Single(req)
+        }
+        (FrameKind::Batch, _) => {
+            CallBody::Batch(decode_body(codec, req_bytes, strict).map_err(reject)?)
+        }
+        (kind, _) => {
+            let err = Error::InvalidRequest;
+            log_error!(
+                levels,
+                &err,
+                ?kind,
+                "received a frame that is not a request"
+            );
+            return Err(err);
+        }
+    };
+
+    let idempotency_key = match &body {
+        CallBody::Single(req) if req.idempotent() => header.idempotency_key,
+        _ => None,
+    };
+    let mut call = Call {
+        id: header.id,
+        body,
+        span: span.clone(),
+        ctx,
+        logged: false,
+        idempotency_key,
+        priority: header.priority,
+    };
+    call.logged = permits.handling(call.name()).logged;
+    match &call.body {
+        CallBody::Single(req) => {
+            span.record("request.name", req.name());
+            if call.logged {
+                debug!(?req, "received request");
+            }
+        }
+        CallBody::Batch(reqs) => {
+            span.record("request.name", BATCH_NAME);
+            span.record("batch.len", reqs.len());
+            if call.logged {
+                debug!(?reqs, "received batch");
+            }
+        }
+        CallBody::Schema => {
+            span.record("request.name", SCHEMA_METHOD);
+        }
+    }
+
+    drop(_enter);
+    Ok(call)
+}
+
+/// Decodes the body of a request frame, which has to hold nothing but the
+/// request if `strict`.
+pub(crate) fn decode_body<T: Decode<()> + DeserializeOwned>(
+    codec: &impl WireCodec,
+    body: &[u8],
+    strict: bool,
+) -> Result<T, CodecError> {
+    if strict {
+        codec.decode_exact(body)
+    } else {
+        codec.decode(body)
+    }
+}
+
+/// The span handling the request in `header`. Requests sent from within
+/// a trace record it in `trace.id` and `trace.parent_id`, for a subscriber to
+/// link the span to the caller's, and the id the client logs the request
+/// under in `request_id`. With the `otel` feature the caller's span is the
+/// span's parent in `tracing-opentelemetry` as well.
+pub(crate) fn dispatch_span(header: &Header, frame_len: usize) -> Span {
+    // Span names have to be static, so the request name goes in a field.
+    let span = info_span!(
+        "dispatch",
+        request.id = header.id,
+        request_id = header.request_id,
+        request.name = field::Empty,
+        request.size = frame_len,
+        request.body_size = field::Empty,
+        response.size = field::Empty,
+        response.body_size = field::Empty,
+        handler.elapsed_us = field::Empty,
+        batch.len = field::Empty,
+        trace.id = field::Empty,
+        trace.parent_id = field::Empty,
+        trace.sampled = field::Empty,
+    );
+    if let Some(trace) = &header.trace {
+        span.record("trace.id", format!("{:032x}", trace.trace_id));
+        span.record("trace.parent_id", format!("{:016x}", trace.span_id));
+        span.record("trace.sampled", trace.sampled);
+        #[cfg(feature = "otel")]
+        set_parent(&span, trace);
+    }
+    span
+}
+
+/// Makes the caller's span `trace` the OpenTelemetry parent of `span`.
+#[cfg(feature = "otel")]
+fn set_parent(span: &Span, trace: &protocol::frame::TraceContext) {
+    use opentelemetry::trace::{
+        SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState,
+    };
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let flags = if trace.sampled {
+        TraceFlags::SAMPLED
+    } else {
+        TraceFlags::default()
+    };
+    let caller = SpanContext::new(
+        TraceId::from(trace.trace_id),
+        SpanId::from(trace.span_id),
+        flags,
+        true,
+        TraceState::default(),
+    );
+    // Only fails without an OpenTelemetry layer, with nothing to parent.
+    let _ = span.set_parent(opentelemetry::Context::new().with_remote_span_context(caller));
+}
+
+/// Rejects request `id` if its body of `len` bytes failed to decode.
+pub(crate) fn rejection(id: u64, e: CodecError, len: usize, levels: &ErrorLevels) -> Error {
+    let err = match DecodeFailure::from_codec_error(&e) {
+        Some(failure) => Error::Rejected {
+            id,
+            error: RpcError::Decode(failure),
+        },
+        None => e.into(),
+    };
+    log_error!(levels, &err, %err, len, "failed to decode request");
+    err
+}
+
+/// Runs the handlers for `call`, waiting for `permits` for each.
+pub(crate) async fn dispatch<Req: Request>(
+    codec: &impl WireCodec,
+    call: Call<Req>,
+    permits: &Permits,
+) -> Result<Reply> {
+    let Call {
+        id,
+        body,
+        span,
+        ctx,
+        logged,
+        priority,
+        ..
+    } = call;
+
+    let levels = permits.error_levels();
+    async move {
+        let started = Instant::now();
+        let resp_frame = match body {
+            CallBody::Single(req) => {
+                let res = run_handler(req, ctx.clone(), permits, priority).await;
+                record_elapsed(started);
+                match res.map(|resp| Reply::streamed(id, resp)) {
+                    Ok(Ok(reply)) => {
+                        reply.log_streaming();
+                        return Ok(reply);
+                    }
+                    Ok(Err(resp)) => {
+                        if logged {
+                            debug!(?resp, "sending response");
+                        }
+                        response_frame(codec, id, ctx.take_trailers(), &resp, levels)
+                    }
+                    Err(err) => encode_frame(codec, &Header::new(id, FrameKind::Error), &err),
+                }
+            }
+            CallBody::Batch(reqs) => {
+                // A failing item only fails its own slot in the response.
+                let resps = futures::future::join_all(
+                    reqs.into_iter()
+                        .map(|req| run_handler(req, ctx.clone(), permits, priority)),
+                )
+                .await;
+                record_elapsed(started);
+                if logged {
+                    debug!(?resps, "sending batch response");
+                }
+                response_frame(codec, id, ctx.take_trailers(), &resps, levels)
+            }
+            CallBody::Schema => response_frame(codec, id, Trailers::new(), &Req::schema(), levels),
+        }
+        .map_err(Error::from)
+        .inspect_err(|e| log_error!(levels, e, %e, "failed to encode response"))?;
+
+        record_response_size(&resp_frame);
+        info!("handled request");
+
+        Ok(Reply::Frame(resp_frame))
+    }
+    .instrument(span)
+    .await
+}
+
+/// Encodes the response frame for request `id`, with the handler's
+/// `trailers`, or an [`RpcError::ResponseTooLarge`] frame if `resp` goes past
+/// `codec`'s limit. A `resp` that can't be encoded at all fails only its own
+/// request, with an [`RpcError::Internal`] frame, rather than the connection.
+pub(crate) fn response_frame<T>(
+    codec: &impl WireCodec,
+    id: u64,
+    trailers: Trailers,
+    resp: &T,
+    levels: &ErrorLevels,
+) -> Result<Vec<u8>, CodecError>
+where
+    T: Encode + Serialize,
+{
+    let header = Header::new(id, FrameKind::Response).with_trailers(trailers);
+    match encode_frame(codec, &header, resp) {
+        Err(CodecError::TooLarge { limit }) => {
+            let err = RpcError::ResponseTooLarge {
+                limit: limit as u64,
+            };
+            log_error!(levels, &err, limit, "response too large");
+            encode_frame(codec, &Header::new(id, FrameKind::Error), &err)
+        }
+        Err(e) => {
+            let err = RpcError::Internal(format!("failed to encode response: {e}"));
+            log_error!(levels, &err, %e, "failed to encode response");
+            encode_frame(codec, &Header::new(id, FrameKind::Error), &err)
+        }
+        frame => frame,
+    }
+}
+
+/// Records the size of `frame`, and of the body in it, on the current
+/// dispatch span.
+pub(crate) fn record_response_size(frame: &[u8]) {
+    let span = Span::current();
+    span.record("response.size", frame.len());
+    span.record("response.body_size", Sizes::of(frame).body);
+}
+
+/// Records the time since `started` on the current dispatch span. For a batch
+/// this covers all of its requests, including waiting for the in-flight limit.
+pub(crate) fn record_elapsed(started: Instant) {
+    let elapsed = u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX);
+    Span::current().record("handler.elapsed_us", elapsed);
+}
+
+async fn run_handler<Req: Request>(
+    req: Req,
+    ctx: Context,
+    permits: &Permits,
+    priority: Option<u8>,
+) -> Result<Req::Resp, RpcError> {
+    let _permits = permits.acquire(req.name(), priority).await?;
+    let mode = permits.execution(req.name());
+    let timeout = permits.timeout(req.name());
+    let levels = permits.error_levels().clone();
+    let results = permits.result_cache().cloned();
+    spawn_handler(req, ctx, mode, timeout, levels, results).await
+}
+
+/// Runs the handler for `req`, unless `results` holds its response already,
+/// for up to `timeout`.
+pub(crate) async fn spawn_handler<Req: Request>(
+    req: Req,
+    ctx: Context,
+    mode: ExecutionMode,
+    timeout: Option<Duration>,
+    levels: ErrorLevels,
+    results: Option<Arc<dyn CacheBackend>>,
+) -> Result<Req::Resp, RpcError> {
+    let name = req.name();
+    let _in_flight = InFlight::start();
+    let deadline = ctx.deadline();
+    let exceeded = || {
+        let err = RpcError::DeadlineExceeded(name.to_owned());
+        log_error!(levels, &err, request = name, "request deadline exceeded");
+        Err(err)
+    };
+    let timed_out = || {
+        let err = RpcError::Timeout(name.to_owned());
+        log_error!(levels, &err, request = name, "request timed out");
+        Err(err)
+    };
+    // Not worth starting once the caller has given up on it.
+    if deadline.is_some_and(|deadline| deadline <= Instant::now()) {
+        return exceeded();
+    }
+    // Whichever ends first of the caller's deadline and the server's
+    // timeout, along with whether it is the timeout.
+    let timeout_at = timeout.map(|timeout| Instant::now() + timeout);
+    let ends = match (deadline, timeout_at) {
+        (Some(deadline), Some(timeout_at)) if timeout_at < deadline => Some((timeout_at, true)),
+        (Some(deadline), _) => Some((deadline, false)),
+        (None, timeout_at) => timeout_at.map(|timeout_at| (timeout_at, true)),
+    };
+
+    let handler_levels = levels.clone();
+    let handler = async move {
+        let levels = handler_levels;
+        req.authorize(&ctx)
+            .await
+            .inspect_err(|e| log_error!(levels, e, %e, "request not authorized"))?;
+        req.validate()
+            .await
+            .inspect_err(|e| log_error!(levels, e, %e, "request failed validation"))?;
+        let cached = Cached::of(results.as_ref(), &req);
+        if let Some(cached) = &cached
+            && let Some(resp) = cached.get().await
+        {
+            return Ok(resp);
+        }
+        let (_, handler) = req.into_handler(ctx);
+        let resp = handler.await;
+        if let Some(cached) = cached {
+            cached.put(&resp).await;
+        }
+        Ok(resp)
+    };
+
+    // Running the handler as its own task keeps a panic from tearing down the
+    // connection; it surfaces here as a `JoinError` instead.
+    let handler = handler.in_current_span();
+    let mut task = match mode {
+        ExecutionMode::Async => tokio::spawn(handler),
+        ExecutionMode::Blocking => {
+            let runtime = Handle::current();
+            tokio::task::spawn_blocking(move || runtime.block_on(handler))
+        }
+    };
+    let joined = match ends {
+        None => task.await,
+        Some((end, is_timeout)) => tokio::select! {
+            joined = &mut task => joined,
+            () = tokio::time::sleep_until(end.into()) => {
+                // Blocking handlers run on until they return, as threads
+                // can't be aborted.
+                task.abort();
+                return if is_timeout { timed_out() } else { exceeded() };
+            }
+        },
+    };
+    match joined {
+        Ok(resp) => resp,
+        Err(e) => {
+            let reason = panic_message(e);
+            let err = RpcError::Internal(format!("handler for {name} panicked"));
+            log_error!(levels, &err, request = name, %reason, "request handler panicked");
+            Err(err)
+        }
+    }
+}
+
+/// Turns away requests whose layout may not match ours before decoding them.
+/// Names this server doesn't know, with no `supported` versions, are turned
+/// away by name, as their bodies may well decode into another request.
+pub(crate) fn check_version(
+    id: u64,
+    method: &Method,
+    supported: Vec<u32>,
+    levels: &ErrorLevels,
+) -> Result<()> {
+    if supported.is_empty() {
+        let error = RpcError::UnknownMethod(method.name.clone());
+        log_error!(levels, &error, name = method.name, "no handler registered");
+        return Err(Error::Rejected { id, error });
+    }
+    if supported.contains(&method.version) {
+        return Ok(());
+    }
+
+    let error = RpcError::VersionMismatch {
+        name: method.name.clone(),
+        requested: method.version,
+        supported: supported.clone(),
+    };
+    log_error!(
+        levels,
+        &error,
+        name = method.name,
+        requested = method.version,
+        ?supported,
+        "request version mismatch"
+    );
+    Err(Error::Rejected { id, error })
+}
+
+/// Turns away requests that decoded into another request than the one they
+/// were sent as, which bincode does when the client orders the variants of
+/// its enum differently, rather than handling them as that one.
+fn check_name(id: u64, method: &Method, decoded: &str, levels: &ErrorLevels) -> Result<()> {
+    if method.name == decoded {
+        return Ok(());
+    }
+
+    let error = RpcError::Decode(DecodeFailure {
+        kind: DecodeErrorKind::UnknownVariant,
+        offset: Some(0),
+        message: format!("{} decoded as {decoded}", method.name),
+    });
+    log_error!(
+        levels,
+        &error,
+        name = method.name,
+        decoded,
+        "request decoded as another"
+    );
+    Err(Error::Rejected { id, error })
+}
+
+fn panic_message(err: tokio::task::JoinError) -> String {
+    match err.try_into_panic() {
+        Ok(payload) => payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic payload".into()),
+        Err(err) => err.to_string(),
+    }
+}
@@ -0,0 +1,38 @@
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Where a request's handler runs.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExecutionMode {
+    /// As a task on the runtime, like everything else.
+    #[default]
+    Async,
+    /// On the runtime's blocking thread pool, through
+    /// [`spawn_blocking`](tokio::task::spawn_blocking), for CPU-bound
+    /// handlers that would otherwise hold up every connection served by the
+    /// same worker.
+    Blocking,
+}
+
+/// The [`ExecutionMode`] of each request type, by
+/// [`Request::NAME`](protocol::Request::NAME). Types not listed run
+/// [`Async`](ExecutionMode::Async).
+#[derive(Debug, Clone, Default)]
+pub struct ExecutionModes {
+    per_type: Arc<HashMap<String, ExecutionMode>>,
+}
+
+impl ExecutionModes {
+    pub fn new(modes: &HashMap<String, ExecutionMode>) -> Self {
+        Self {
+            per_type: Arc::new(modes.clone()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> ExecutionMode {
+        self.per_type.get(name).copied().unwrap_or_default()
+    }
+}
@@ -0,0 +1,81 @@
+use crate::{Error, Result};
+
+use futures::future::BoxFuture;
+
+use std::any::Any;
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// The connection a hook is called for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    pub peer_addr: SocketAddr,
+    /// Unique within the process, and the same as in the connection's log
+    /// span.
+    pub id: u32,
+}
+
+/// Why a connection was closed.
+#[derive(Debug)]
+pub enum DisconnectReason {
+    /// The client closed it, or went away.
+    Closed,
+    /// The server closed it on shutting down.
+    Shutdown,
+    /// The connection timed out, e.g. because keepalive probes went
+    /// unanswered.
+    Timeout,
+    /// Serving the connection failed.
+    Error(Error),
+}
+
+impl DisconnectReason {
+    pub(crate) fn new(result: Result<()>, shutting_down: bool) -> Self {
+        match result {
+            Ok(()) if shutting_down => Self::Shutdown,
+            Ok(()) => Self::Closed,
+            Err(Error::Io(e)) if e.kind() == io::ErrorKind::TimedOut => Self::Timeout,
+            Err(e) => Self::Error(e),
+        }
+    }
+}
+
+/// What `on_connect` returned, handed to handlers through their
+/// [`Context`](protocol::Context).
+pub(crate) type ConnectionState = Option<Arc<dyn Any + Send + Sync>>;
+
+type OnConnect = Arc<dyn Fn(ConnectionInfo) -> BoxFuture<'static, ConnectionState> + Send + Sync>;
+type OnDisconnect =
+    Arc<dyn Fn(ConnectionInfo, DisconnectReason) -> BoxFuture<'static, ()> + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    pub(crate) on_connect: Option<OnConnect>,
+    pub(crate) on_disconnect: Option<OnDisconnect>,
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hooks")
+            .field("on_connect", &self.on_connect.is_some())
+            .field("on_disconnect", &self.on_disconnect.is_some())
+            .finish()
+    }
+}
+
+impl Hooks {
+    pub(crate) async fn connected(&self, info: ConnectionInfo) -> ConnectionState {
+        match &self.on_connect {
+            Some(on_connect) => on_connect(info).await,
+            None => None,
+        }
+    }
+
+    pub(crate) async fn disconnected(&self, info: ConnectionInfo, reason: DisconnectReason) {
+        if let Some(on_disconnect) = &self.on_disconnect {
+            on_disconnect(info, reason).await;
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use crate::Result;
+use crate::dispatch::Reply;
+
+use protocol::codec::{Limited, WireFormat};
+use protocol::frame::{FrameKind, Header, decode_header, encode_raw_frame};
+
+use futures::FutureExt;
+use futures::future::BoxFuture;
+use tokio::sync::watch;
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Responses to requests sent with an idempotency key, see
+/// [`Header::idempotency_key`](protocol::frame::Header::idempotency_key),
+/// kept so a retry with the same key gets the same response instead of
+/// running the handler again.
+///
+/// Only requests marked [idempotent](protocol::Request::IDEMPOTENT) are
+/// looked up, and only successful responses kept, so a retry after a
+/// [`Busy`](protocol::RpcError::Busy) error runs. A retry arriving while the
+/// first attempt is still being handled waits for its response.
+///
+/// Holds at most `capacity` keys, dropping the least recently used one to
+/// make room, each for at most `ttl`. Responses are kept encoded, so a retry
+/// sent in another wire format than the first attempt runs again too.
+/// Clones share the same responses, also between servers.
+#[derive(Debug, Clone)]
+pub struct IdempotencyCache {
+    inner: Arc<Mutex<Inner>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+type Key = (WireFormat, String);
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<Key, Entry>,
+    /// Every key, least recently used first.
+    by_use: BTreeMap<u64, Key>,
+    uses: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    used: u64,
+    state: State,
+}
+
+#[derive(Debug)]
+enum State {
+    /// Dropping the sender, once done, wakes whoever waits for the response.
+    Running {
+        attempt: u64,
+        done: watch::Sender<()>,
+    },
+    /// The response frame of the first attempt.
+    Done { frame: Arc<[u8]>, expires: Instant },
+}
+
+/// What to do with a request, as far as the cache is concerned.
+enum Lookup {
+    Hit(Arc<[u8]>),
+    Wait(watch::Receiver<()>),
+    /// Run it, and keep its response under this attempt.
+    Run(u64),
+}
+
+impl IdempotencyCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            inner: Arc::default(),
+            capacity: capacity.max(1),
+            ttl,
+        }
+    }
+
+    /// Number of keys held, including those still being handled.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Answers request `id`, sent with `key`, from a response kept for it,
+    /// or from `reply` otherwise, keeping what that produces.
+    pub(crate) fn deduplicate<'a>(
+        &'a self,
+        codec: &'a Limited<WireFormat>,
+        id: u64,
+        key: String,
+        reply: BoxFuture<'a, Result<Reply>>,
+    ) -> BoxFuture<'a, Result<Reply>> {
+        let key = (codec.codec, key);
+        async move {
+            let attempt = loop {
+                match self.lookup(&key) {
+                    Lookup::Hit(frame) => {
+                        let (header, body) = decode_header(codec, &frame)?;
+                        let header = Header { id, ..header };
+                        return Ok(Reply::Frame(encode_raw_frame(codec, &header, body)?));
+                    }
+                    // Looked up again once the other attempt is done, as it
+                    // may have failed.
+                    Lookup::Wait(mut done) => {
+                        let _ = done.changed().await;
+                    }
+                    Lookup::Run(attempt) => break attempt,
+                }
+            };
+
+            let mut running = Running {
+                cache: self,
+                key,
+                attempt,
+                frame: None,
+            };
+            let reply = reply.await;
+            if let Ok(Reply::Frame(frame)) = &reply
+                && decode_header(codec, frame).is_ok_and(|(h, _)| h.kind == FrameKind::Response)
+            {
+                running.frame = Some(frame.as_slice().into());
+            }
+            reply
+        }
+        .boxed()
+    }
+
+    fn lookup(&self, key: &Key) -> Lookup {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        inner.uses += 1;
+        let uses = inner.uses;
+
+        if let Some(entry) = inner.entries.get_mut(key) {
+            let lookup = match &entry.state {
+                State::Running { done, .. } => Some(Lookup::Wait(done.subscribe())),
+                State::Done { frame, expires } if *expires > Instant::now() => {
+                    Some(Lookup::Hit(frame.clone()))
+                }
+                State::Done { .. } => None,
+            };
+            if let Some(lookup) = lookup {
+                inner.by_use.remove(&entry.used);
+                inner.by_use.insert(uses, key.clone());
+                entry.used = uses;
+                return lookup;
+            }
+            inner.by_use.remove(&entry.used);
+            inner.entries.remove(key);
+        }
+
+        while inner.entries.len() >= self.capacity {
+            let Some((_, oldest)) = inner.by_use.pop_first() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+        }
+        inner.by_use.insert(uses, key.clone());
+        inner.entries.insert(
+            key.clone(),
+            Entry {
+                used: uses,
+                state: State::Running {
+                    attempt: uses,
+                    done: watch::Sender::new(()),
+                },
+            },
+        );
+        Lookup::Run(uses)
+    }
+}
+
+/// Answers request `id` through `cache` if it was sent with a `key` and
+/// there is a cache, straight from `reply` otherwise.
+pub(crate) fn deduplicated<'a>(
+    cache: Option<&'a IdempotencyCache>,
+    codec: &'a Limited<WireFormat>,
+    id: u64,
+    key: Option<String>,
+    reply: BoxFuture<'a, Result<Reply>>,
+) -> BoxFuture<'a, Result<Reply>> {
+    match (cache, key) {
+        (Some(cache), Some(key)) => cache.deduplicate(codec, id, key, reply),
+        _ => reply,
+    }
+}
+
+/// Settles the entry of an attempt once it is done: keeps its response
+/// frame if it has one worth keeping, and forgets the key otherwise, so the
+/// next attempt runs.
+struct Running<'a> {
+    cache: &'a IdempotencyCache,
+    key: Key,
+    attempt: u64,
+    frame: Option<Arc<[u8]>>,
+}
+
+impl Drop for Running<'_> {
+    fn drop(&mut self) {
+        let mut inner = self.cache.inner.lock().unwrap();
+        let inner = &mut *inner;
+        // Evicted meanwhile, and maybe taken over by another attempt.
+        let Some(entry) = inner.entries.get_mut(&self.key) else {
+            return;
+        };
+        if !matches!(entry.state, State::Running { attempt, .. } if attempt == self.attempt) {
+            return;
+        }
+        match self.frame.take() {
+            Some(frame) => {
+                entry.state = State::Done {
+                    frame,
+                    expires: Instant::now() + self.cache.ttl,
+                };
+            }
+            None => {
+                inner.by_use.remove(&entry.used);
+                inner.entries.remove(&self.key);
+            }
+        }
+    }
+}
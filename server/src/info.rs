@@ -0,0 +1,110 @@
+use protocol::info::{ConnStats, Health, ServerInfo};
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+
+static STARTED: OnceLock<Instant> = OnceLock::new();
+static IN_FLIGHT: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn mark_started() {
+    STARTED.get_or_init(Instant::now);
+}
+
+pub(crate) fn server_info() -> ServerInfo {
+    let uptime = STARTED.get().map(Instant::elapsed).unwrap_or_default();
+    ServerInfo {
+        version: env!("CARGO_PKG_VERSION").to_owned(),
+        git_hash: option_env!("GIT_HASH").map(str::to_owned),
+        uptime_ms: u64::try_from(uptime.as_millis()).unwrap_or(u64::MAX),
+        in_flight: IN_FLIGHT.load(Ordering::Relaxed),
+    }
+}
+
+/// Tells whether the server is ready for requests, for the [`Health`] it
+/// answers pings with, see
+/// [`ServerBuilder::readiness`](crate::ServerBuilder::readiness).
+#[derive(Clone)]
+pub struct Readiness(Arc<dyn Fn() -> Health + Send + Sync>);
+
+impl fmt::Debug for Readiness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Readiness").finish_non_exhaustive()
+    }
+}
+
+impl Readiness {
+    pub fn new(check: impl Fn() -> Health + Send + Sync + 'static) -> Self {
+        Self(Arc::new(check))
+    }
+}
+
+/// What to answer a ping with, asking `readiness` if there is one.
+pub(crate) fn health(readiness: Option<&Readiness>) -> Health {
+    match readiness {
+        Some(Readiness(check)) => check(),
+        None => Health::READY,
+    }
+}
+
+/// Counts a request as in flight for as long as it is alive.
+pub(crate) struct InFlight(());
+
+impl InFlight {
+    pub(crate) fn start() -> Self {
+        IN_FLIGHT.fetch_add(1, Ordering::Relaxed);
+        Self(())
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// What goes over a single connection, counted as it does, for its
+/// [`ConnStats`].
+#[derive(Debug)]
+pub(crate) struct ConnCounters {
+    started: Instant,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+    requests_served: AtomicU64,
+}
+
+impl ConnCounters {
+    pub(crate) fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            bytes_in: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            requests_served: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn read(&self, frame: &[u8]) {
+        self.bytes_in
+            .fetch_add(frame.len() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn written(&self, frame: &[u8]) {
+        self.bytes_out
+            .fetch_add(frame.len() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn served(&self) {
+        self.requests_served.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self, in_flight: usize) -> ConnStats {
+        ConnStats {
+            bytes_in: self.bytes_in.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            requests_served: self.requests_served.load(Ordering::Relaxed),
+            in_flight: in_flight as u64,
+            uptime_ms: u64::try_from(self.started.elapsed().as_millis()).unwrap_or(u64::MAX),
+        }
+    }
+}
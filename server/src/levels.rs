@@ -0,0 +1,114 @@
+use crate::Error;
+use crate::connection::is_disconnect;
+
+use protocol::{DecodeFailure, RpcError};
+
+use tracing::Level;
+
+use std::fmt;
+use std::io;
+use std::sync::Arc;
+
+/// An error about to be logged, for [`ErrorLevels`] to pick the level of.
+#[derive(Debug, Clone, Copy)]
+pub enum LoggedError<'a> {
+    /// What a request is answered with, e.g. a failed validation.
+    Rpc(&'a RpcError),
+    /// What went wrong serving a connection, e.g. the client going away.
+    Server(&'a Error),
+}
+
+impl<'a> From<&'a RpcError> for LoggedError<'a> {
+    fn from(err: &'a RpcError) -> Self {
+        Self::Rpc(err)
+    }
+}
+
+impl<'a> From<&'a Error> for LoggedError<'a> {
+    fn from(err: &'a Error) -> Self {
+        Self::Server(err)
+    }
+}
+
+/// The level every error the server runs into is logged at, see
+/// [`ServerBuilder::error_levels`](crate::ServerBuilder::error_levels).
+#[derive(Clone)]
+pub struct ErrorLevels(Arc<dyn Fn(LoggedError<'_>) -> Level + Send + Sync>);
+
+impl fmt::Debug for ErrorLevels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ErrorLevels").finish_non_exhaustive()
+    }
+}
+
+impl Default for ErrorLevels {
+    /// Picks levels with [`default_level`].
+    fn default() -> Self {
+        Self::new(default_level)
+    }
+}
+
+impl ErrorLevels {
+    pub fn new(level: impl Fn(LoggedError<'_>) -> Level + Send + Sync + 'static) -> Self {
+        Self(Arc::new(level))
+    }
+
+    pub fn level<'a>(&self, err: impl Into<LoggedError<'a>>) -> Level {
+        (self.0)(err.into())
+    }
+}
+
+/// Logs errors the client brought about, like going away or sending a
+/// request that fails to decode or validate, at `INFO`, requests turned away
+/// for the server being busy at `WARN`, and everything else, like a handler
+/// panicking, at `ERROR`.
+pub fn default_level(err: LoggedError<'_>) -> Level {
+    match err {
+        LoggedError::Rpc(err) => rpc_level(err),
+        LoggedError::Server(Error::Rejected { error, .. }) => rpc_level(error),
+        LoggedError::Server(Error::Io(e)) if is_disconnect(e) => Level::INFO,
+        // What the client sent, e.g. a frame over the limit, makes no sense.
+        LoggedError::Server(Error::Io(e)) if e.kind() == io::ErrorKind::InvalidData => Level::INFO,
+        LoggedError::Server(Error::Codec(e)) if DecodeFailure::from_codec_error(e).is_some() => {
+            Level::INFO
+        }
+        LoggedError::Server(
+            Error::InvalidRequest
+            | Error::Handshake
+            | Error::ProtocolDesync { .. }
+            | Error::FrameTimeout(_),
+        ) => Level::INFO,
+        LoggedError::Server(Error::Io(_) | Error::Codec(_) | Error::Framing(_)) => Level::ERROR,
+    }
+}
+
+fn rpc_level(err: &RpcError) -> Level {
+    match err {
+        RpcError::Decode(_)
+        | RpcError::VersionMismatch { .. }
+        | RpcError::UnknownMethod(_)
+        | RpcError::ValidationFailed(_)
+        | RpcError::RateLimited { .. }
+        | RpcError::Unauthorized(_) => Level::INFO,
+        RpcError::Busy(_) | RpcError::Timeout(_) | RpcError::DeadlineExceeded(_) => Level::WARN,
+        RpcError::Internal(_) | RpcError::Handler(_) | RpcError::ResponseTooLarge { .. } => {
+            Level::ERROR
+        }
+    }
+}
+
+/// Logs an event about `err`, at the level [`ErrorLevels`] `levels` picks
+/// for it.
+macro_rules! log_error {
+    ($levels:expr, $err:expr, $($event:tt)+) => {
+        match $levels.level($err) {
+            ::tracing::Level::ERROR => ::tracing::error!($($event)+),
+            ::tracing::Level::WARN => ::tracing::warn!($($event)+),
+            ::tracing::Level::INFO => ::tracing::info!($($event)+),
+            ::tracing::Level::DEBUG => ::tracing::debug!($($event)+),
+            _ => ::tracing::trace!($($event)+),
+        }
+    };
+}
+
+pub(crate) use log_error;
@@ -0,0 +1,103 @@
+mod access;
+mod config;
+mod connection;
+mod dispatch;
+mod execution;
+mod hooks;
+mod idempotency;
+mod info;
+mod levels;
+mod limits;
+mod memory;
+mod priority;
+mod result_cache;
+mod router;
+mod sampling;
+mod scheduler;
+mod server;
+mod shutdown;
+mod timeouts;
+mod traffic;
+#[cfg(feature = "ws")]
+mod ws;
+
+pub use access::{AccessLog, AccessRecord, AccessStatus, common_log};
+pub use config::ServerConfig;
+pub use connection::{
+    ConnectionConfig, WriteCoalescing, handle_connection, handle_connection_with_codec,
+    handle_messages,
+};
+pub use dispatch::handle_request;
+pub use execution::{ExecutionMode, ExecutionModes};
+pub use hooks::{ConnectionInfo, DisconnectReason};
+pub use idempotency::IdempotencyCache;
+pub use info::Readiness;
+pub use levels::{ErrorLevels, LoggedError, default_level};
+pub use limits::{ConcurrencyLimits, OverLimit};
+pub use memory::GlobalMemoryLimit;
+pub use priority::PriorityScheduler;
+pub use result_cache::{CacheBackend, MemoryCache};
+pub use router::Router;
+pub use sampling::LogSampling;
+pub use scheduler::{FairScheduler, FairnessPolicy, Fifo, RoundRobin, Waiting};
+pub use server::{BindPolicy, Server, ServerBuilder};
+pub use shutdown::ShutdownHandle;
+pub use timeouts::RequestTimeouts;
+pub use traffic::{ByteCounts, Traffic};
+#[cfg(feature = "ws")]
+pub use ws::handle_websocket;
+
+use protocol::codec::CodecError;
+use protocol::frame::FramingError;
+use protocol::{DecodeFailure, RpcError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("codec error: {0}")]
+    Codec(#[from] CodecError),
+
+    #[error("invalid framing: {0}")]
+    Framing(#[from] FramingError),
+
+    #[error("Unexpected request format")]
+    InvalidRequest,
+
+    #[error("Connection closed during handshake")]
+    Handshake,
+
+    #[error("request {id} rejected: {error}")]
+    Rejected { id: u64, error: RpcError },
+
+    /// A frame came with another sequence number than the one after the
+    /// last, see [`Header::seq`](protocol::frame::Header::seq), so the
+    /// connection can't be trusted to be read right anymore.
+    #[error("frame out of sequence: expected {expected}, got {found:?}")]
+    ProtocolDesync { expected: u64, found: Option<u64> },
+
+    /// A frame took longer than
+    /// [`ServerBuilder::frame_body_timeout`](crate::ServerBuilder::frame_body_timeout)
+    /// to arrive once it had started, so the connection was closed.
+    #[error("frame not received within {0:?} of its first bytes")]
+    FrameTimeout(std::time::Duration),
+}
+
+pub type Result<T, E = Error> = ::core::result::Result<T, E>;
+
+impl From<Error> for RpcError {
+    /// What to tell a client about `err`, e.g. when answering requests
+    /// through [`handle_request`] rather than a connection. Only rejections
+    /// and failures to decode say more than that the server failed.
+    fn from(err: Error) -> Self {
+        match err {
+            Error::Rejected { error, .. } => error,
+            Error::Codec(e) => match DecodeFailure::from_codec_error(&e) {
+                Some(failure) => RpcError::Decode(failure),
+                None => RpcError::Internal(e.to_string()),
+            },
+            e => RpcError::Internal(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::priority::Prioritized;
+use crate::scheduler::{Admitted, ConnectionQueue};
+use crate::{
+    CacheBackend, ConnectionConfig, ErrorLevels, ExecutionMode, ExecutionModes, IdempotencyCache,
+    LogSampling, PriorityScheduler, RequestTimeouts,
+};
+
+use protocol::RpcError;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use serde::Deserialize;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What happens to a request arriving while as many requests of its type as
+/// allowed are already running.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverLimit {
+    /// Wait for one of them to finish.
+    #[default]
+    Queue,
+    /// Answer with [`RpcError::Busy`] right away.
+    Reject,
+}
+
+/// Caps on how many requests of a type, by [`Request::NAME`], run at once.
+///
+/// The caps hold across every connection served with the same limits:
+/// clones share them. Types without a cap are only held back by the
+/// connection's in-flight limit.
+///
+/// [`Request::NAME`]: protocol::Request::NAME
+#[derive(Debug, Clone, Default)]
+pub struct ConcurrencyLimits {
+    per_type: Arc<HashMap<String, Semaphore>>,
+    over_limit: OverLimit,
+}
+
+impl ConcurrencyLimits {
+    pub fn new(limits: &HashMap<String, usize>, over_limit: OverLimit) -> Self {
+        let per_type = limits
+            .iter()
+            .map(|(name, &limit)| (name.clone(), Semaphore::new(limit.max(1))))
+            .collect();
+        Self {
+            per_type: Arc::new(per_type),
+            over_limit,
+        }
+    }
+}
+
+/// Everything a request on one connection has to get hold of before its
+/// handler runs, and how it is handled then.
+pub(crate) struct Permits {
+    in_flight: Semaphore,
+    limits: ConcurrencyLimits,
+    execution: ExecutionModes,
+    timeouts: RequestTimeouts,
+    sampling: LogSampling,
+    idempotency: Option<IdempotencyCache>,
+    strict_framing: bool,
+    scheduled: Option<ConnectionQueue>,
+    prioritized: Option<PriorityScheduler>,
+    results: Option<Arc<dyn CacheBackend>>,
+    error_levels: ErrorLevels,
+}
+
+/// How a request is handled once it may run.
+#[derive(Debug, Clone)]
+pub(crate) struct Handling {
+    pub(crate) mode: ExecutionMode,
+    /// How long its handler may run.
+    pub(crate) timeout: Option<Duration>,
+    /// Whether the request and its response are logged.
+    pub(crate) logged: bool,
+    /// Whether its body may hold nothing but the request, see
+    /// [`ServerBuilder::strict_framing`](crate::ServerBuilder::strict_framing).
+    pub(crate) strict_framing: bool,
+    /// What its errors are logged at.
+    pub(crate) error_levels: ErrorLevels,
+    /// Where its response is kept, if it is cacheable.
+    pub(crate) results: Option<Arc<dyn CacheBackend>>,
+}
+
+/// Held for as long as the handler runs.
+pub(crate) struct Held<'a> {
+    _in_flight: SemaphorePermit<'a>,
+    _per_type: Option<SemaphorePermit<'a>>,
+    _scheduled: Option<Admitted>,
+    _prioritized: Option<Prioritized>,
+}
+
+impl Permits {
+    /// The permits of a new connection served with `config`.
+    pub(crate) fn new(config: &ConnectionConfig) -> Self {
+        Self {
+            in_flight: Semaphore::new(config.max_in_flight),
+            limits: config.concurrency_limits.clone(),
+            execution: config.execution_modes.clone(),
+            timeouts: config.request_timeouts.clone(),
+            sampling: config.log_sampling.clone(),
+            idempotency: config.idempotency_cache.clone(),
+            strict_framing: config.strict_framing,
+            scheduled: config.scheduler.as_ref().map(|s| s.connection()),
+            prioritized: config.priority_scheduler.clone(),
+            results: config.result_cache.clone(),
+            error_levels: config.error_levels.clone(),
+        }
+    }
+
+    pub(crate) fn unlimited() -> Self {
+        Self::new(&ConnectionConfig {
+            max_in_flight: Semaphore::MAX_PERMITS,
+            ..ConnectionConfig::default()
+        })
+    }
+
+    /// What errors are logged at.
+    pub(crate) fn error_levels(&self) -> &ErrorLevels {
+        &self.error_levels
+    }
+
+    /// Where responses to requests sent with an idempotency key are kept, if
+    /// anywhere.
+    pub(crate) fn idempotency_cache(&self) -> Option<&IdempotencyCache> {
+        self.idempotency.as_ref()
+    }
+
+    /// Where responses to cacheable requests are kept, if anywhere.
+    pub(crate) fn result_cache(&self) -> Option<&Arc<dyn CacheBackend>> {
+        self.results.as_ref()
+    }
+
+    /// Whether request bodies may hold nothing but the request.
+    pub(crate) fn strict_framing(&self) -> bool {
+        self.strict_framing
+    }
+
+    /// Where a request called `name` runs once it may.
+    pub(crate) fn execution(&self, name: &str) -> ExecutionMode {
+        self.execution.get(name)
+    }
+
+    /// How long the handler of a request called `name` may run.
+    pub(crate) fn timeout(&self, name: &str) -> Option<Duration> {
+        self.timeouts.get(name)
+    }
+
+    /// How the next request called `name` is handled. Counts towards its
+    /// [`LogSampling`], so it is asked once per request.
+    pub(crate) fn handling(&self, name: &str) -> Handling {
+        Handling {
+            mode: self.execution(name),
+            timeout: self.timeout(name),
+            logged: self.sampling.sample(name),
+            strict_framing: self.strict_framing,
+            error_levels: self.error_levels.clone(),
+            results: self.results.clone(),
+        }
+    }
+
+    /// Waits until a request called `name`, whose caller asked for
+    /// `priority`, may run.
+    pub(crate) async fn acquire(
+        &self,
+        name: &str,
+        priority: Option<u8>,
+    ) -> Result<Held<'_>, RpcError> {
+        // The type's own limit comes first, so requests queued behind it
+        // don't take up the connection's slots in the meantime.
+        let per_type = match self.limits.per_type.get(name) {
+            None => None,
+            Some(limit) => match self.limits.over_limit {
+                OverLimit::Queue => limit.acquire().await.ok(),
+                OverLimit::Reject => match limit.try_acquire() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => return Err(RpcError::Busy(name.to_owned())),
+                },
+            },
+        };
+        let in_flight = self
+            .in_flight
+            .acquire()
+            .await
+            .map_err(|_| RpcError::Internal("connection is closing".into()))?;
+        // Last of all, as only requests ready to run are fair to take turns
+        // between.
+        let scheduled = match &self.scheduled {
+            Some(queue) => Some(queue.admit().await),
+            None => None,
+        };
+        let prioritized = match &self.prioritized {
+            Some(scheduler) => Some(scheduler.admit(name, priority).await),
+            None => None,
+        };
+
+        Ok(Held {
+            _in_flight: in_flight,
+            _per_type: per_type,
+            _scheduled: scheduled,
+            _prioritized: prioritized,
+        })
+    }
+}
@@ -0,0 +1,119 @@
+//! Where the server binary writes its log files, configured through the
+//! environment:
+//!
+//! - `LOG_DIR`: directory for the files, `logs` by default.
+//! - `LOG_PREFIX`: start of every file name, `app.log` by default.
+//! - `LOG_ROTATION`: `hourly` (the default), `daily` or `never`.
+//! - `LOG_RETENTION_DAYS`: if set, files older than this are deleted on
+//!   startup.
+
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFiles {
+    pub dir: PathBuf,
+    pub prefix: String,
+    pub rotation: Rotation,
+    pub retention: Option<Duration>,
+}
+
+impl Default for LogFiles {
+    fn default() -> Self {
+        Self {
+            dir: "logs".into(),
+            prefix: "app.log".into(),
+            rotation: Rotation::HOURLY,
+            retention: None,
+        }
+    }
+}
+
+impl LogFiles {
+    /// Reads the settings from the environment. Values that don't parse are
+    /// reported on stderr, as logging isn't set up yet, and left at their
+    /// default.
+    pub fn from_env() -> Self {
+        let mut files = Self::default();
+        if let Some(dir) = std::env::var_os("LOG_DIR") {
+            files.dir = dir.into();
+        }
+        if let Ok(prefix) = std::env::var("LOG_PREFIX") {
+            files.prefix = prefix;
+        }
+        if let Ok(rotation) = std::env::var("LOG_ROTATION") {
+            match parse_rotation(&rotation) {
+                Some(rotation) => files.rotation = rotation,
+                None => {
+                    eprintln!("ignoring LOG_ROTATION={rotation:?}: expected hourly, daily or never")
+                }
+            }
+        }
+        if let Ok(days) = std::env::var("LOG_RETENTION_DAYS") {
+            match days.parse::<u32>() {
+                Ok(days) => files.retention = Some(DAY * days),
+                Err(e) => eprintln!("ignoring LOG_RETENTION_DAYS={days:?}: {e}"),
+            }
+        }
+        files
+    }
+
+    /// Deletes files past their retention, then opens the file to log to.
+    pub fn open(&self) -> RollingFileAppender {
+        let now = SystemTime::now();
+        if let Some(retention) = self.retention
+            && let Err(e) = remove_old_logs(&self.dir, &self.prefix, retention, now)
+        {
+            eprintln!("failed to clean up {}: {e}", self.dir.display());
+        }
+        RollingFileAppender::new(self.rotation.clone(), &self.dir, &self.prefix)
+    }
+}
+
+pub fn parse_rotation(rotation: &str) -> Option<Rotation> {
+    match rotation.to_ascii_lowercase().as_str() {
+        "hourly" => Some(Rotation::HOURLY),
+        "daily" => Some(Rotation::DAILY),
+        "never" => Some(Rotation::NEVER),
+        _ => None,
+    }
+}
+
+/// Deletes the files in `dir` whose names start with `prefix` and that were
+/// last written more than `max_age` before `now`, returning their paths.
+/// Other files are left alone, and so is a `dir` that doesn't exist yet.
+pub fn remove_old_logs(
+    dir: &Path,
+    prefix: &str,
+    max_age: Duration,
+    now: SystemTime,
+) -> io::Result<Vec<PathBuf>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        if !entry.file_name().to_string_lossy().starts_with(prefix) {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let age = now.duration_since(metadata.modified()?).unwrap_or_default();
+        if age > max_age {
+            std::fs::remove_file(entry.path())?;
+            removed.push(entry.path());
+        }
+    }
+    Ok(removed)
+}
@@ -1,47 +1,40 @@
-use protocol::Request;
+mod logging;
 
-use futures::{SinkExt, StreamExt};
-use macros::{request, rpc};
-use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
-use tokio::net::TcpListener;
-use tokio_util::codec::{Framed, LengthDelimitedCodec};
-
-use tokio::sync::Notify;
-use tracing_subscriber::{EnvFilter, Layer};
+use logging::LogFiles;
 
-use std::sync::Arc;
+use server::{Result, Server, ServerConfig};
 
-use bytes::Bytes;
+use macros::{request, rpc};
 
-use tracing::info_span;
-use tracing::{debug, error, info};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_subscriber::{EnvFilter, Layer};
 
-use bincode::config::BigEndian;
-const BINCODE_CONFIG: bincode::config::Configuration<BigEndian> =
-    bincode::config::standard().with_big_endian();
+use tracing::{error, info};
 
-#[derive(thiserror::Error, Debug)]
-pub enum Error {
-    #[error("IO error: {0}")]
-    Io(#[from] std::io::Error),
+use std::time::Duration;
 
-    #[error("bincode decode error: {0}")]
-    BincodeDecode(#[from] bincode::error::DecodeError),
+/// How long handlers still running once the connections are drained get
+/// to finish, and log what they do, before the runtime goes.
+const HANDLER_GRACE: Duration = Duration::from_secs(5);
 
-    #[error("bincode encode error: {0}")]
-    BincodeEncode(#[from] bincode::error::EncodeError),
+/// Shuts down in order: the server drains its connections, the runtime
+/// winds down the handlers they left behind, and only then are the log
+/// writers flushed, so nothing any of them logs is lost.
+fn main() -> Result<()> {
+    let guards = init_tracing();
+    let runtime = tokio::runtime::Runtime::new()?;
+    let result = runtime.block_on(run());
+    runtime.shutdown_timeout(HANDLER_GRACE);
 
-    #[error("Unexpected request format")]
-    InvalidRequest,
+    match &result {
+        Ok(()) => info!("server stopped"),
+        Err(e) => error!(%e, "server stopped with an error"),
+    }
+    drop(guards);
+    result
 }
 
-type Result<T, E = Error> = ::core::result::Result<T, E>;
-
-use std::sync::atomic::{AtomicU32, Ordering};
-static CONNECTION_ID_COUNTER: AtomicU32 = AtomicU32::new(1);
-
-#[tokio::main]
-async fn main() -> Result<()> {
+async fn run() -> Result<()> {
     #[rpc(response = "AppResponse")]
     enum AppRequest {
         Ping(Ping),
@@ -64,17 +57,78 @@ async fn main() -> Result<()> {
         "The pong has been sent".into()
     }
 
+    // A new instance can bind next to a running one, which is told to go
+    // once the new one is up, see `ServerBuilder::reuse_port`. Its clients
+    // move over as it drains.
+    let config = ServerConfig {
+        reuse_port: cfg!(unix),
+        drain_timeout_secs: Some(10),
+        ..ServerConfig::default()
+    };
+    let addr = config.address.clone();
+
+    let server = Server::from_config(config)
+        .await
+        .inspect_err(|e| error!(%e, %addr, "failed to start server"))?;
+
+    // Stops accepting, drains the open connections, and only then returns.
+    server.run_until::<AppRequest>(shutdown_signal()).await
+}
+
+/// Completes on ctrl+c, or on `SIGTERM` where there is one, which is how
+/// service managers and a replacing instance ask the server to go.
+async fn shutdown_signal() {
+    // A signal that can't be listened for never comes, rather than shutting
+    // the server down right away.
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!(%e, "failed to listen for ctrl+c");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                error!(%e, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+/// Installs the global subscriber for this binary: compact logs on stdout
+/// filtered by `RUST_LOG`, and JSON logs at `INFO` and above in rolling files
+/// set up from the environment, see [`logging`]. The library itself only
+/// emits events, so embedders are free to set up tracing however they like
+/// instead.
+///
+/// The returned guards flush the non-blocking writers when dropped.
+fn init_tracing() -> LogGuards {
     use tracing_subscriber::layer::SubscriberExt;
     use tracing_subscriber::util::SubscriberInitExt;
 
-    let (writer, _stdout_guard) = tracing_appender::non_blocking(std::io::stdout());
+    let (writer, stdout_guard) = tracing_appender::non_blocking(std::io::stdout());
     let stdout_layer = tracing_subscriber::fmt::layer()
         .with_writer(writer)
         .compact()
         .with_filter(EnvFilter::from_default_env());
 
-    let logfile = tracing_appender::rolling::hourly("logs", "app.log");
-    let (writer, _file_guard) = tracing_appender::non_blocking(logfile);
+    let logfile = LogFiles::from_env().open();
+    let (writer, file_guard) = tracing_appender::non_blocking(logfile);
     let file_layer = tracing_subscriber::fmt::layer()
         .with_writer(writer)
         .json()
@@ -85,116 +139,15 @@ async fn main() -> Result<()> {
         .with(file_layer)
         .init();
 
-    let addr = "127.0.0.1:8080";
-
-    let listener = TcpListener::bind(addr)
-        .await
-        .inspect_err(|e| error!(%e, %addr, "failed to start server"))?;
-    info!(%addr, "started server");
-
-    let shutdown = Arc::new(Notify::new());
-
-    {
-        let shutdown = shutdown.clone();
-        tokio::spawn(async move {
-            tokio::signal::ctrl_c().await.unwrap_or_else(|e| {
-                error!(%e, "failed to listen for ctrl+c");
-            });
-            info!("Received shutdown signal, shutting down gracefully...");
-            shutdown.notify_one();
-        });
-    }
-
-    loop {
-        tokio::select! {
-            Ok((socket, peer_addr)) = listener.accept() => {
-                let shutdown = shutdown.clone();
-                let connection_id = CONNECTION_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
-                let span = info_span!("connection", %peer_addr, %connection_id);
-                tokio::spawn(async move {
-                    let _enter = span.enter();
-                    info!("connection opened");
-                    if handle_connection::<AppRequest>(socket, shutdown).await.is_err() {
-                        debug!("connection task ended with error");
-                    }
-                    info!("connection closed");
-                });
-            }
-
-            _ = shutdown.notified() => {
-                info!("Shutting down server...");
-                break;
-            }
-        }
-    }
-
-    Ok(())
-}
-
-pub async fn handle_connection<Req: Request>(
-    socket: impl AsyncRead + AsyncWrite + Unpin,
-    shutdown: Arc<Notify>,
-) -> Result<()> {
-    let codec = LengthDelimitedCodec::new();
-    let mut framed = Framed::new(socket, codec);
-
-    loop {
-        tokio::select! {
-            maybe_segment = framed.next() => {
-                match maybe_segment.transpose().inspect_err(|e| {
-                    error!(%e, "failed to get next segment")
-                })? {
-                    Some(segment) => {
-                        let resp_bytes = handle_request::<Req>(&segment).await.inspect_err(|e| {
-                            error!(%e, "failed to handle request");
-                        })?;
-
-                        framed.send(Bytes::from(resp_bytes)).await.inspect_err(|e| {
-                            error!(%e, "failed to send response");
-                        }).map_err(Error::Io)?;
-                    }
-                    None => { break; }
-                }
-            }
-
-
-            _ = shutdown.notified() => {
-                info!("Received shutdown signal, closing connection...");
-                break;
-            }
-        }
+    LogGuards {
+        _stdout: stdout_guard,
+        _file: file_guard,
     }
-
-    framed
-        .get_mut()
-        .shutdown()
-        .await
-        .inspect_err(|e| {
-            error!(%e, "error shutting down socket");
-        })
-        .map_err(Error::Io)?;
-
-    framed.get_mut().shutdown().await.map_err(|e| {
-        error!(%e, "error shutting down socket");
-        Error::Io(e)
-    })?;
-
-    Ok(())
 }
 
-pub async fn handle_request<Req: Request>(req_bytes: &[u8]) -> Result<Vec<u8>> {
-    let req: Req = bincode::decode_from_slice(req_bytes, BINCODE_CONFIG)
-        .map(|(val, _)| val)
-        .inspect_err(|e| error!(%e, len = req_bytes.len(), "failed to decode request"))?;
-    debug!(len = req_bytes.len(), "decoded request");
-
-    debug!(?req, "received request");
-    let resp = req.handle().await;
-    debug!(?resp, "sending response");
-
-    let resp_bytes = bincode::encode_to_vec(resp, BINCODE_CONFIG)
-        .inspect_err(|e| error!(%e, "failed to encode response"))?;
-    debug!(len = resp_bytes.len(), "encoded response");
-
-    Ok(resp_bytes)
+/// Flush the log writers when dropped, in the order of the fields, each
+/// waiting for its writer to write out what it was sent.
+struct LogGuards {
+    _stdout: WorkerGuard,
+    _file: WorkerGuard,
 }
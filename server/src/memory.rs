@@ -0,0 +1,128 @@
+use protocol::compression::Compression;
+
+use bytes::Bytes;
+use tokio::sync::Notify;
+
+use std::pin::pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A budget for the bytes of request and response frames buffered in memory,
+/// shared by every connection it is given to, across servers if need be.
+///
+/// A request frame counts from when it is read until its response is ready,
+/// and a response frame until it has been written to the socket. Connections
+/// stop reading while the budget is used up, though one already waiting for
+/// a frame still reads it, so the budget may be overshot by a frame per
+/// connection. Clones share the budget.
+#[derive(Debug, Clone)]
+pub struct GlobalMemoryLimit {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug)]
+struct Inner {
+    limit: usize,
+    used: AtomicUsize,
+    released: Notify,
+}
+
+impl Default for GlobalMemoryLimit {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+impl GlobalMemoryLimit {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                limit,
+                used: AtomicUsize::new(0),
+                released: Notify::new(),
+            }),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(usize::MAX)
+    }
+
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Bytes currently buffered against this budget.
+    pub fn used(&self) -> usize {
+        self.inner.used.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn has_room(&self) -> bool {
+        self.used() < self.inner.limit
+    }
+
+    /// Completes once there is room in the budget again.
+    pub(crate) async fn room(&self) {
+        loop {
+            // Registered before checking, so a release in between isn't
+            // missed.
+            let mut released = pin!(self.inner.released.notified());
+            released.as_mut().enable();
+            if self.has_room() {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    /// Counts `bytes` against the budget until the returned charge is dropped.
+    pub(crate) fn charge(&self, bytes: usize) -> Charge {
+        self.inner.used.fetch_add(bytes, Ordering::Relaxed);
+        Charge {
+            limit: self.clone(),
+            bytes,
+        }
+    }
+
+    /// Wraps a response frame for the write queue, counted until written.
+    pub(crate) fn outgoing(&self, frame: Vec<u8>) -> Outgoing {
+        Outgoing {
+            _charge: self.charge(frame.len()),
+            frame: frame.into(),
+            switch: None,
+        }
+    }
+}
+
+pub(crate) struct Charge {
+    limit: GlobalMemoryLimit,
+    bytes: usize,
+}
+
+impl Drop for Charge {
+    fn drop(&mut self) {
+        self.limit
+            .inner
+            .used
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+        self.limit.inner.released.notify_waiters();
+    }
+}
+
+/// A frame waiting to be written.
+pub(crate) struct Outgoing {
+    pub(crate) frame: Bytes,
+    /// What every frame written after this one is compressed with.
+    pub(crate) switch: Option<Arc<dyn Compression>>,
+    _charge: Charge,
+}
+
+impl Outgoing {
+    /// Has every frame written after this one compressed with `compression`.
+    pub(crate) fn then_compress(self, compression: Arc<dyn Compression>) -> Self {
+        Self {
+            switch: Some(compression),
+            ..self
+        }
+    }
+}
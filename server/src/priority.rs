@@ -0,0 +1,241 @@
+use tokio::sync::oneshot;
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A cap on how many handlers run at once across every connection it is
+/// given to, with requests over it admitted highest priority first.
+///
+/// A request's priority is the one its caller asked for, see
+/// [`Header::priority`](protocol::frame::Header::priority), no higher than
+/// [`PriorityScheduler::max_requested`] allows, or else the one set for its
+/// type, or [`PriorityScheduler::DEFAULT_PRIORITY`]. Requests age while they
+/// wait, moving up a level every [`PriorityScheduler::aging`], so a steady
+/// stream of higher priority ones can hold lower ones back, but not forever.
+///
+/// Requests wait for a slot once they have been decoded and got past every
+/// other limit. Clones share the slots, also between servers.
+#[derive(Clone)]
+pub struct PriorityScheduler {
+    limit: usize,
+    aging: Duration,
+    max_requested: u8,
+    per_type: Arc<HashMap<String, u8>>,
+    /// What ranks are counted from.
+    started: Instant,
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    /// Slots nobody holds. Only ever above zero while nothing is queued.
+    free: usize,
+    queue: BinaryHeap<Queued>,
+    running: usize,
+    tickets: u64,
+}
+
+/// A request waiting for a slot.
+struct Queued {
+    /// Microseconds from the scheduler's start it started waiting at, less
+    /// what its priority is worth. Lowest goes first.
+    rank: i64,
+    /// Breaks ties in the order requests started waiting.
+    ticket: u64,
+    admit: oneshot::Sender<()>,
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    /// Greatest is first to go, as the heap pops it.
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.rank, other.ticket).cmp(&(self.rank, self.ticket))
+    }
+}
+
+impl fmt::Debug for PriorityScheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PriorityScheduler")
+            .field("limit", &self.limit())
+            .field("aging", &self.aging)
+            .field("max_requested", &self.max_requested)
+            .field("running", &self.running())
+            .field("queued", &self.queued())
+            .finish_non_exhaustive()
+    }
+}
+
+impl PriorityScheduler {
+    /// What requests run at when neither they nor their type say otherwise.
+    pub const DEFAULT_PRIORITY: u8 = 100;
+
+    /// A scheduler with `limit` slots, aging requests one level every 10 ms
+    /// and taking callers at their word on priorities.
+    pub fn new(limit: usize) -> Self {
+        let limit = limit.max(1);
+        Self {
+            limit,
+            aging: Duration::from_millis(10),
+            max_requested: u8::MAX,
+            per_type: Arc::default(),
+            started: Instant::now(),
+            state: Arc::new(Mutex::new(State {
+                free: limit,
+                queue: BinaryHeap::new(),
+                running: 0,
+                tickets: 0,
+            })),
+        }
+    }
+
+    /// How much waiting a level of priority is worth: a request goes ahead
+    /// of one a level higher that started waiting more than this after it.
+    /// The longer it is, the longer lower priority requests may wait. Zero
+    /// makes priorities worth nothing, admitting requests as they came.
+    pub fn aging(mut self, aging: Duration) -> Self {
+        self.aging = aging;
+        self
+    }
+
+    /// Highest priority callers may ask for; higher ones are taken as this.
+    pub fn max_requested(mut self, max: u8) -> Self {
+        self.max_requested = max;
+        self
+    }
+
+    /// What requests called `name` run at unless their caller asks for
+    /// another priority, e.g. higher for pings and lower for reports.
+    pub fn priority(mut self, name: impl Into<String>, priority: u8) -> Self {
+        Arc::make_mut(&mut self.per_type).insert(name.into(), priority);
+        self
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Requests holding a slot.
+    pub fn running(&self) -> usize {
+        self.state.lock().unwrap().running
+    }
+
+    /// Requests waiting for a slot.
+    pub fn queued(&self) -> usize {
+        self.state.lock().unwrap().queue.len()
+    }
+
+    /// What a request called `name` runs at, its caller having asked for
+    /// `requested`.
+    fn priority_of(&self, name: &str, requested: Option<u8>) -> u8 {
+        match requested {
+            Some(requested) => requested.min(self.max_requested),
+            None => self
+                .per_type
+                .get(name)
+                .copied()
+                .unwrap_or(Self::DEFAULT_PRIORITY),
+        }
+    }
+
+    /// Waits until the scheduler admits a request called `name`, whose
+    /// caller asked for `requested`.
+    pub(crate) async fn admit(&self, name: &str, requested: Option<u8>) -> Prioritized {
+        let admitted = || Prioritized {
+            scheduler: self.clone(),
+        };
+        let receiver = {
+            let mut state = self.state.lock().unwrap();
+            if state.free > 0 {
+                state.free -= 1;
+                state.running += 1;
+                return admitted();
+            }
+
+            let waited_from = i64::try_from(self.started.elapsed().as_micros()).unwrap_or(i64::MAX);
+            let aging = i64::try_from(self.aging.as_micros()).unwrap_or(i64::MAX);
+            let head_start = aging.saturating_mul(i64::from(self.priority_of(name, requested)));
+            state.tickets += 1;
+            let (admit, receiver) = oneshot::channel();
+            let queued = Queued {
+                rank: waited_from.saturating_sub(head_start),
+                ticket: state.tickets,
+                admit,
+            };
+            state.queue.push(queued);
+            receiver
+        };
+
+        let mut waiter = Waiter {
+            scheduler: self,
+            admitted: Some(receiver),
+        };
+        // The sender is only dropped once it has sent, as the waiter takes
+        // it out of the queue otherwise.
+        let _ = waiter.admitted.as_mut().unwrap().await;
+        waiter.admitted = None;
+        admitted()
+    }
+}
+
+impl State {
+    /// Hands a slot let go of to the first request in the queue.
+    fn release(&mut self) {
+        self.running -= 1;
+        while let Some(queued) = self.queue.pop() {
+            // Waiters take themselves out when dropped, but check anyway.
+            if queued.admit.send(()).is_ok() {
+                self.running += 1;
+                return;
+            }
+        }
+        self.free += 1;
+    }
+}
+
+/// A request waiting in the queue, taking itself out if it stops waiting.
+struct Waiter<'a> {
+    scheduler: &'a PriorityScheduler,
+    admitted: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        let Some(mut admitted) = self.admitted.take() else {
+            return;
+        };
+        let mut state = self.scheduler.state.lock().unwrap();
+        // Slots are handed out with the lock held, so this can't miss one.
+        if admitted.try_recv().is_ok() {
+            state.release();
+            return;
+        }
+        drop(admitted);
+        state.queue.retain(|queued| !queued.admit.is_closed());
+    }
+}
+
+/// A request's slot, held for as long as its handler runs.
+pub(crate) struct Prioritized {
+    scheduler: PriorityScheduler,
+}
+
+impl Drop for Prioritized {
+    fn drop(&mut self) {
+        self.scheduler.state.lock().unwrap().release();
+    }
+}
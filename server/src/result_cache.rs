@@ -0,0 +1,170 @@
+use protocol::Request;
+use protocol::codec::{WireCodec, WireFormat};
+
+use async_trait::async_trait;
+use tracing::debug;
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Where responses to [cacheable](protocol::Request::CACHE_TTL) requests are
+/// kept, so a repeat of one is answered without running its handler, see
+/// [`ServerBuilder::result_cache`](crate::ServerBuilder::result_cache).
+///
+/// Keys are the request's name and its fields, values its response, both
+/// encoded with bincode whatever the wire format the request came in, so
+/// clients share responses whichever they speak. A backend may drop entries
+/// whenever it likes, e.g. to stay within a size, but shouldn't hand one out
+/// past the `ttl` it was put with.
+#[async_trait]
+pub trait CacheBackend: Debug + Send + Sync + 'static {
+    /// The value put under `key`, unless it has expired or been dropped.
+    async fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Keeps `value` under `key` for `ttl`, in place of any kept before.
+    async fn put(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration);
+}
+
+/// A [`CacheBackend`] in memory, holding at most `capacity` responses and
+/// dropping the least recently used one to make room. What a server keeps
+/// responses in unless given another. Clones share the same responses.
+#[derive(Debug, Clone)]
+pub struct MemoryCache {
+    inner: Arc<Mutex<Inner>>,
+    capacity: usize,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    entries: HashMap<Vec<u8>, Entry>,
+    /// Every key, least recently used first.
+    by_use: BTreeMap<u64, Vec<u8>>,
+    uses: u64,
+}
+
+#[derive(Debug)]
+struct Entry {
+    used: u64,
+    value: Vec<u8>,
+    expires: Instant,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::default(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Number of responses held, including expired ones not yet dropped.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every response, so the next request of each runs again.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.entries.clear();
+        inner.by_use.clear();
+    }
+}
+
+#[async_trait]
+impl CacheBackend for MemoryCache {
+    async fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        let entry = inner.entries.get_mut(key)?;
+        if entry.expires <= Instant::now() {
+            inner.by_use.remove(&entry.used);
+            inner.entries.remove(key);
+            return None;
+        }
+
+        inner.uses += 1;
+        inner.by_use.remove(&entry.used);
+        entry.used = inner.uses;
+        let value = entry.value.clone();
+        inner.by_use.insert(inner.uses, key.to_vec());
+        Some(value)
+    }
+
+    async fn put(&self, key: Vec<u8>, value: Vec<u8>, ttl: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        let inner = &mut *inner;
+        if let Some(old) = inner.entries.remove(&key) {
+            inner.by_use.remove(&old.used);
+        }
+        while inner.entries.len() >= self.capacity {
+            let Some((_, oldest)) = inner.by_use.pop_first() else {
+                break;
+            };
+            inner.entries.remove(&oldest);
+        }
+
+        inner.uses += 1;
+        inner.by_use.insert(inner.uses, key.clone());
+        inner.entries.insert(
+            key,
+            Entry {
+                used: inner.uses,
+                value,
+                expires: Instant::now() + ttl,
+            },
+        );
+    }
+}
+
+/// A cacheable request on its way to its handler, with where its response
+/// goes.
+pub(crate) struct Cached {
+    backend: Arc<dyn CacheBackend>,
+    key: Vec<u8>,
+    ttl: Duration,
+}
+
+impl Cached {
+    /// Where the response to `req` is kept in `backend`, if it may be.
+    pub(crate) fn of<Req: Request>(
+        backend: Option<&Arc<dyn CacheBackend>>,
+        req: &Req,
+    ) -> Option<Self> {
+        let ttl = req.cache_ttl()?;
+        let backend = backend?.clone();
+        // Named, as requests of different types may well encode alike.
+        let mut key = req.name().as_bytes().to_vec();
+        key.push(0);
+        key.extend(WireFormat::Bincode.encode(req).ok()?);
+        Some(Self { backend, key, ttl })
+    }
+
+    /// The response kept for the request, if any. One that no longer decodes
+    /// is as good as none, and gets replaced.
+    pub(crate) async fn get<Resp: protocol::Response>(&self) -> Option<Resp> {
+        let value = self.backend.get(&self.key).await?;
+        let resp = WireFormat::Bincode.decode(&value).ok()?;
+        debug!("answered from result cache");
+        Some(resp)
+    }
+
+    /// Keeps `resp` as the response to the request. Encodes it right away,
+    /// so the future doesn't hold on to it.
+    pub(crate) fn put<Resp: protocol::Response>(
+        self,
+        resp: &Resp,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let value = WireFormat::Bincode.encode(resp).ok();
+        async move {
+            if let Some(value) = value {
+                self.backend.put(self.key, value, self.ttl).await;
+            }
+        }
+    }
+}
@@ -0,0 +1,255 @@
+use crate::dispatch::{
+    Reply, Service, Static, check_version, decode_body, dispatch_span, record_elapsed,
+    record_response_size, rejection, response_frame, spawn_handler,
+};
+use crate::idempotency::deduplicated;
+use crate::levels::log_error;
+use crate::limits::{Handling, Permits};
+use crate::{Error, Result};
+
+use protocol::codec::{CodecError, Limited, WireFormat};
+use protocol::frame::{FrameKind, Header, Trailers, decode_header, encode_frame};
+use protocol::schema::{RpcSchema, SCHEMA_METHOD};
+use protocol::{Context, Request, RpcError};
+
+use futures::FutureExt;
+use futures::future::BoxFuture;
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+use tracing::{Instrument, debug, info};
+
+/// Decodes a request body and returns the future producing its response
+/// frame.
+type Handler = Box<
+    dyn Fn(
+            Limited<WireFormat>,
+            u64,
+            &[u8],
+            Context,
+            Handling,
+        ) -> Result<BoxFuture<'static, Result<Reply, CodecError>>, CodecError>
+        + Send
+        + Sync,
+>;
+
+struct Route {
+    version: u32,
+    idempotent: bool,
+    handler: Handler,
+}
+
+/// Requests registered at runtime, looked up by the name in each frame's
+/// header.
+///
+/// An alternative to the enums generated by `#[rpc]` for servers that only
+/// learn what they handle once running, e.g. from plugins. A router only
+/// understands versioned request frames, which is what `Client::call` sends;
+/// it has no batches.
+///
+/// A router can also host several of those enums side by side, each
+/// [mounted](Router::mount) under a namespace of its own.
+#[derive(Default)]
+pub struct Router {
+    routes: HashMap<&'static str, Route>,
+    namespaces: HashMap<String, Box<dyn Service>>,
+    schema: RpcSchema,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles requests named [`R::NAME`](Request::NAME) with `R`, replacing
+    /// any handler registered for that name before.
+    pub fn register<R: Request>(&mut self) -> &mut Self {
+        let handler: Handler = Box::new(|codec, id, body, ctx, handling| {
+            let req: R = decode_body(&codec, body, handling.strict_framing)?;
+            if handling.logged {
+                debug!(?req, "received request");
+            }
+            Ok(async move {
+                let trailers = ctx.clone();
+                let levels = handling.error_levels;
+                let results = handling.results;
+                let run = spawn_handler(
+                    req,
+                    ctx,
+                    handling.mode,
+                    handling.timeout,
+                    levels.clone(),
+                    results,
+                );
+                let frame = match run.await.map(|resp| Reply::streamed(id, resp)) {
+                    Ok(Ok(reply)) => return Ok(reply),
+                    Ok(Err(resp)) => {
+                        if handling.logged {
+                            debug!(?resp, "sending response");
+                        }
+                        response_frame(&codec, id, trailers.take_trailers(), &resp, &levels)
+                    }
+                    Err(err) => encode_frame(&codec, &Header::new(id, FrameKind::Error), &err),
+                };
+                frame.map(Reply::Frame)
+            }
+            .boxed())
+        });
+
+        self.schema.methods.retain(|method| method.name != R::NAME);
+        self.schema.methods.extend(R::schema().methods);
+        self.routes.insert(
+            R::NAME,
+            Route {
+                version: R::VERSION,
+                idempotent: R::IDEMPOTENT,
+                handler,
+            },
+        );
+        self
+    }
+
+    /// Handles requests sent under `namespace`, with `Client::namespaced`,
+    /// with `Req`, e.g. an enum generated by `#[rpc]`, as
+    /// [`Server::run`](crate::Server::run) would, batches included. That
+    /// lets services defined apart from each other share a listener.
+    /// Requests sent with no namespace go to those
+    /// [registered](Router::register) with the router itself.
+    ///
+    /// Replaces whatever was mounted under `namespace` before. Limits set
+    /// per request still go by its name alone, whichever namespace it is in.
+    pub fn mount<Req: Request>(&mut self, namespace: impl Into<String>) -> &mut Self {
+        self.namespaces
+            .insert(namespace.into(), Box::new(Static::<Req>::new()));
+        self
+    }
+
+    /// Describes every registered request, leaving out the mounted ones,
+    /// which are described to clients asking within their namespace.
+    pub fn schema(&self) -> &RpcSchema {
+        &self.schema
+    }
+}
+
+impl Service for Router {
+    fn call<'a>(
+        &'a self,
+        codec: &'a Limited<WireFormat>,
+        frame: &[u8],
+        permits: &'a Permits,
+        ctx: Context,
+    ) -> Result<(&'static str, BoxFuture<'a, Result<Reply>>)> {
+        let levels = permits.error_levels();
+        let (header, body) = decode_header(codec, frame)
+            .map_err(Error::from)
+            .inspect_err(
+                |e| log_error!(levels, e, %e, len = frame.len(), "failed to decode frame header"),
+            )?;
+        if let Some(namespace) = &header.namespace {
+            return self.call_namespaced(namespace, &header, codec, frame, permits, ctx);
+        }
+
+        let span = dispatch_span(&header, frame.len());
+        let _enter = span.enter();
+        span.record("request.body_size", body.len());
+
+        let method = match (header.kind, &header.method) {
+            (FrameKind::VersionedRequest | FrameKind::Upload, Some(method)) => method,
+            (kind, _) => {
+                let err = Error::InvalidRequest;
+                log_error!(
+                    levels,
+                    &err,
+                    ?kind,
+                    "router only handles versioned requests"
+                );
+                return Err(err);
+            }
+        };
+        span.record("request.name", method.name.as_str());
+
+        let id = header.id;
+        if method.name == SCHEMA_METHOD {
+            let frame = response_frame(codec, id, Trailers::new(), &self.schema, levels);
+            return Ok((
+                SCHEMA_METHOD,
+                async move { Ok(Reply::Frame(frame?)) }.boxed(),
+            ));
+        }
+
+        let Some((&name, route)) = self.routes.get_key_value(method.name.as_str()) else {
+            let error = RpcError::UnknownMethod(method.name.clone());
+            log_error!(levels, &error, name = method.name, "no handler registered");
+            return Err(Error::Rejected { id, error });
+        };
+        check_version(id, method, vec![route.version], levels)?;
+
+        let handling = permits.handling(name);
+        let response = (route.handler)(*codec, id, body, ctx, handling)
+            .map_err(|e| rejection(id, e, body.len(), levels))?;
+
+        drop(_enter);
+        let priority = header.priority;
+        let future = async move {
+            let _permits = match permits.acquire(name, priority).await {
+                Ok(held) => held,
+                Err(err) => {
+                    let frame = encode_frame(codec, &Header::new(id, FrameKind::Error), &err)?;
+                    return Ok(Reply::Frame(frame));
+                }
+            };
+            let started = Instant::now();
+            let reply = response
+                .await
+                .map_err(Error::from)
+                .inspect_err(|e| log_error!(levels, e, %e, "failed to encode response"))?;
+            record_elapsed(started);
+
+            match &reply {
+                Reply::Frame(frame) => {
+                    record_response_size(frame);
+                    info!("handled request");
+                }
+                Reply::Chunked { .. } | Reply::Subscribed { .. } => reply.log_streaming(),
+            }
+
+            Ok(reply)
+        }
+        .instrument(span)
+        .boxed();
+        let key = header.idempotency_key.filter(|_| route.idempotent);
+        let cache = permits.idempotency_cache();
+        Ok((name, deduplicated(cache, codec, id, key, future)))
+    }
+}
+
+impl Router {
+    /// Hands `frame`, sent under `namespace`, over to what is mounted there.
+    fn call_namespaced<'a>(
+        &'a self,
+        namespace: &str,
+        header: &Header,
+        codec: &'a Limited<WireFormat>,
+        frame: &[u8],
+        permits: &'a Permits,
+        ctx: Context,
+    ) -> Result<(&'static str, BoxFuture<'a, Result<Reply>>)> {
+        let Some(service) = self.namespaces.get(namespace) else {
+            let name = header.method.as_ref().map_or("", |m| m.name.as_str());
+            let error = RpcError::UnknownMethod(format!("{namespace}.{name}"));
+            log_error!(
+                permits.error_levels(),
+                &error,
+                namespace,
+                name,
+                "no such namespace"
+            );
+            return Err(Error::Rejected {
+                id: header.id,
+                error,
+            });
+        };
+        service.call(codec, frame, permits, ctx)
+    }
+}
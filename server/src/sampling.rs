@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Which requests have their contents logged at debug level, as
+/// `received request` and `sending response`, for servers busy enough that
+/// logging every one of them drowns out everything else.
+///
+/// One in every `n` requests of a type is logged, starting with the first,
+/// with `n` set per [`Request::NAME`](protocol::Request::NAME) or taken from
+/// the default. Everything else, errors included, is logged regardless.
+/// Clones share their counts.
+#[derive(Debug, Clone)]
+pub struct LogSampling {
+    every: u64,
+    per_type: Arc<HashMap<String, u64>>,
+    seen: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl Default for LogSampling {
+    /// Logs every request.
+    fn default() -> Self {
+        Self::every(1)
+    }
+}
+
+impl LogSampling {
+    /// Logs one in every `n` requests of every type. `n` of 0 is taken as 1.
+    pub fn every(n: u64) -> Self {
+        Self {
+            every: n.max(1),
+            per_type: Arc::default(),
+            seen: Arc::default(),
+        }
+    }
+
+    /// Logs one in every `n` requests called `name` instead, e.g. 1 for a
+    /// rarely called one that should always be logged.
+    pub fn with(mut self, name: impl Into<String>, n: u64) -> Self {
+        Arc::make_mut(&mut self.per_type).insert(name.into(), n.max(1));
+        self
+    }
+
+    /// Whether to log the next request called `name`.
+    pub fn sample(&self, name: &str) -> bool {
+        let every = self.per_type.get(name).copied().unwrap_or(self.every);
+        if every == 1 {
+            return true;
+        }
+        let mut seen = self.seen.lock().unwrap();
+        if !seen.contains_key(name) {
+            seen.insert(name.to_owned(), 0);
+        }
+        let count = seen.get_mut(name).unwrap();
+        *count += 1;
+        (*count - 1).is_multiple_of(every)
+    }
+}
@@ -0,0 +1,284 @@
+use tokio::sync::oneshot;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// A connection with requests waiting for a [`FairScheduler`] slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Waiting {
+    /// Tells the scheduler's connections apart, in the order they came in.
+    pub connection: u64,
+    /// Requests of the connection waiting for a slot.
+    pub queued: usize,
+    /// Requests of the connection holding a slot.
+    pub running: usize,
+    /// When the connection's longest waiting request started waiting, as a
+    /// number counting up with every request that has to.
+    pub oldest: u64,
+}
+
+/// Decides which connection's request gets a [`FairScheduler`] slot once
+/// one is free.
+pub trait FairnessPolicy: Send + 'static {
+    /// The index into `waiting`, which is never empty and ordered by
+    /// [`Waiting::connection`], of the connection whose longest waiting
+    /// request gets the slot. Out of range indices stand for the last one.
+    fn pick(&mut self, waiting: &[Waiting]) -> usize;
+}
+
+/// Takes turns between connections, one request each, so a connection with
+/// many requests waiting gets no more slots than one with a single request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RoundRobin {
+    last: Option<u64>,
+}
+
+impl FairnessPolicy for RoundRobin {
+    fn pick(&mut self, waiting: &[Waiting]) -> usize {
+        let next = match self.last {
+            Some(last) => waiting
+                .iter()
+                .position(|w| w.connection > last)
+                .unwrap_or(0),
+            None => 0,
+        };
+        self.last = Some(waiting[next].connection);
+        next
+    }
+}
+
+/// Admits requests in the order they started waiting, whatever connection
+/// they came on, as if there was no scheduler.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Fifo;
+
+impl FairnessPolicy for Fifo {
+    fn pick(&mut self, waiting: &[Waiting]) -> usize {
+        (0..waiting.len())
+            .min_by_key(|&i| waiting[i].oldest)
+            .unwrap_or(0)
+    }
+}
+
+/// A cap on how many handlers run at once across every connection it is
+/// given to, with requests over it admitted in the order a
+/// [`FairnessPolicy`] picks rather than the order they arrived.
+///
+/// Requests wait for a slot once they have been decoded and got past the
+/// caps of their type and connection. Clones share the slots, also between
+/// servers.
+#[derive(Clone)]
+pub struct FairScheduler {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    limit: usize,
+    connections: AtomicU64,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// Slots nobody holds. Only ever above zero while nothing is queued.
+    free: usize,
+    queues: HashMap<u64, Queue>,
+    tickets: u64,
+    policy: Box<dyn FairnessPolicy>,
+}
+
+#[derive(Default)]
+struct Queue {
+    waiting: VecDeque<(u64, oneshot::Sender<()>)>,
+    running: usize,
+}
+
+impl fmt::Debug for FairScheduler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FairScheduler")
+            .field("limit", &self.limit())
+            .field("running", &self.running())
+            .field("queued", &self.queued())
+            .finish_non_exhaustive()
+    }
+}
+
+impl FairScheduler {
+    pub fn new(limit: usize, policy: impl FairnessPolicy) -> Self {
+        let limit = limit.max(1);
+        Self {
+            inner: Arc::new(Inner {
+                limit,
+                connections: AtomicU64::new(0),
+                state: Mutex::new(State {
+                    free: limit,
+                    queues: HashMap::new(),
+                    tickets: 0,
+                    policy: Box::new(policy),
+                }),
+            }),
+        }
+    }
+
+    /// A scheduler taking turns between connections, see [`RoundRobin`].
+    pub fn round_robin(limit: usize) -> Self {
+        Self::new(limit, RoundRobin::default())
+    }
+
+    pub fn limit(&self) -> usize {
+        self.inner.limit
+    }
+
+    /// Requests holding a slot.
+    pub fn running(&self) -> usize {
+        let state = self.inner.state.lock().unwrap();
+        state.queues.values().map(|q| q.running).sum()
+    }
+
+    /// Requests waiting for a slot.
+    pub fn queued(&self) -> usize {
+        let state = self.inner.state.lock().unwrap();
+        state.queues.values().map(|q| q.waiting.len()).sum()
+    }
+
+    /// Where the requests of a new connection wait.
+    pub(crate) fn connection(&self) -> ConnectionQueue {
+        ConnectionQueue {
+            scheduler: self.clone(),
+            connection: self.inner.connections.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl State {
+    /// Hands the slot `connection` let go of to whoever the policy picks.
+    fn release(&mut self, connection: u64) {
+        if let Some(queue) = self.queues.get_mut(&connection) {
+            queue.running -= 1;
+            self.forget_if_idle(connection);
+        }
+
+        loop {
+            let mut waiting: Vec<_> = self
+                .queues
+                .iter()
+                .filter_map(|(&connection, queue)| {
+                    let &(oldest, _) = queue.waiting.front()?;
+                    Some(Waiting {
+                        connection,
+                        queued: queue.waiting.len(),
+                        running: queue.running,
+                        oldest,
+                    })
+                })
+                .collect();
+            if waiting.is_empty() {
+                self.free += 1;
+                return;
+            }
+            waiting.sort_unstable_by_key(|w| w.connection);
+
+            let picked = self.policy.pick(&waiting).min(waiting.len() - 1);
+            let connection = waiting[picked].connection;
+            let queue = self.queues.get_mut(&connection).unwrap();
+            let (_, admit) = queue.waiting.pop_front().unwrap();
+            // Waiters take themselves out when dropped, but check anyway.
+            if admit.send(()).is_ok() {
+                queue.running += 1;
+                return;
+            }
+            self.forget_if_idle(connection);
+        }
+    }
+
+    fn forget_if_idle(&mut self, connection: u64) {
+        if let Some(queue) = self.queues.get(&connection)
+            && queue.running == 0
+            && queue.waiting.is_empty()
+        {
+            self.queues.remove(&connection);
+        }
+    }
+}
+
+/// One connection's share of a [`FairScheduler`].
+pub(crate) struct ConnectionQueue {
+    scheduler: FairScheduler,
+    connection: u64,
+}
+
+impl ConnectionQueue {
+    /// Waits until the scheduler admits another request of this connection.
+    pub(crate) async fn admit(&self) -> Admitted {
+        let admitted = || Admitted {
+            scheduler: self.scheduler.clone(),
+            connection: self.connection,
+        };
+        let receiver = {
+            let mut state = self.scheduler.inner.state.lock().unwrap();
+            let state = &mut *state;
+            let queue = state.queues.entry(self.connection).or_default();
+            if state.free > 0 {
+                state.free -= 1;
+                queue.running += 1;
+                return admitted();
+            }
+
+            state.tickets += 1;
+            let (sender, receiver) = oneshot::channel();
+            queue.waiting.push_back((state.tickets, sender));
+            receiver
+        };
+
+        let mut waiter = Waiter {
+            queue: self,
+            admitted: Some(receiver),
+        };
+        // The sender is only dropped once it has sent, as the waiter takes
+        // it out of the queue otherwise.
+        let _ = waiter.admitted.as_mut().unwrap().await;
+        waiter.admitted = None;
+        admitted()
+    }
+}
+
+/// A request waiting in its connection's queue, taking itself out if it
+/// stops waiting.
+struct Waiter<'a> {
+    queue: &'a ConnectionQueue,
+    admitted: Option<oneshot::Receiver<()>>,
+}
+
+impl Drop for Waiter<'_> {
+    fn drop(&mut self) {
+        let Some(mut admitted) = self.admitted.take() else {
+            return;
+        };
+        let connection = self.queue.connection;
+        let mut state = self.queue.scheduler.inner.state.lock().unwrap();
+        // Slots are handed out with the lock held, so this can't miss one.
+        if admitted.try_recv().is_ok() {
+            state.release(connection);
+            return;
+        }
+        drop(admitted);
+        if let Some(queue) = state.queues.get_mut(&connection) {
+            queue.waiting.retain(|(_, admit)| !admit.is_closed());
+        }
+        state.forget_if_idle(connection);
+    }
+}
+
+/// A request's slot, held for as long as its handler runs.
+pub(crate) struct Admitted {
+    scheduler: FairScheduler,
+    connection: u64,
+}
+
+impl Drop for Admitted {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.inner.state.lock().unwrap();
+        state.release(self.connection);
+    }
+}
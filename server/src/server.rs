@@ -0,0 +1,942 @@
+use crate::connection::{Shutdown, serve_connection};
+use crate::dispatch::{Service, Static};
+use crate::hooks::Hooks;
+use crate::info;
+use crate::{
+    AccessLog, CacheBackend, ConcurrencyLimits, ConnectionConfig, ConnectionInfo, DisconnectReason,
+    ErrorLevels, ExecutionMode, ExecutionModes, FairScheduler, GlobalMemoryLimit, IdempotencyCache,
+    LogSampling, LoggedError, MemoryCache, OverLimit, PriorityScheduler, Readiness,
+    RequestTimeouts, Result, Router, ServerConfig, ShutdownHandle, Traffic, WriteCoalescing,
+};
+
+use protocol::codec::WireFormat;
+use protocol::compression::Compression;
+use protocol::info::Health;
+use protocol::{Peer, Request};
+
+use futures::FutureExt;
+use tokio::net::{TcpListener, TcpSocket, TcpStream, ToSocketAddrs, lookup_host};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+use serde::Deserialize;
+use socket2::{SockRef, TcpKeepalive};
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::{self, Future};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+use tracing::{Instrument, Level, debug, info, info_span, warn};
+
+static CONNECTION_ID_COUNTER: AtomicU32 = AtomicU32::new(1);
+
+/// What [`ServerBuilder::bind_all`] does when some of its addresses can't be
+/// bound.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BindPolicy {
+    /// Fail unless every address is bound, so a server never runs without
+    /// an interface it was meant to listen on.
+    #[default]
+    All,
+    /// Go on with the addresses that could be bound, logging the others, and
+    /// fail only if none could, e.g. for `[::]` alongside `0.0.0.0` on hosts
+    /// without IPv6.
+    Any,
+}
+
+#[derive(Debug)]
+pub struct ServerBuilder {
+    connection: ConnectionConfig,
+    nodelay: bool,
+    reuse_addr: bool,
+    reuse_port: bool,
+    keepalive: Option<Duration>,
+    backlog: u32,
+    bind_policy: BindPolicy,
+    drain_timeout: Option<Duration>,
+    #[cfg(feature = "ws")]
+    websocket: bool,
+    concurrency_limits: HashMap<String, usize>,
+    over_limit: OverLimit,
+    execution_modes: HashMap<String, ExecutionMode>,
+    request_timeout: Option<Duration>,
+    request_timeouts: HashMap<String, Duration>,
+    hooks: Hooks,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        ServerConfig::default().into()
+    }
+}
+
+impl From<ServerConfig> for ServerBuilder {
+    /// Everything but [`ServerConfig::address`] and
+    /// [`ServerConfig::addresses`], which are for [`ServerBuilder::bind`] and
+    /// [`ServerBuilder::bind_all`].
+    fn from(config: ServerConfig) -> Self {
+        let builder = Self {
+            connection: ConnectionConfig::default(),
+            nodelay: config.nodelay,
+            reuse_addr: config.reuse_addr,
+            reuse_port: config.reuse_port,
+            keepalive: config.keepalive_secs.map(Duration::from_secs),
+            backlog: config.backlog,
+            bind_policy: config.bind_policy,
+            drain_timeout: config.drain_timeout_secs.map(Duration::from_secs),
+            #[cfg(feature = "ws")]
+            websocket: false,
+            concurrency_limits: config.concurrency_limits,
+            over_limit: config.over_limit,
+            execution_modes: config.execution_modes,
+            request_timeout: config.request_timeout_ms.map(Duration::from_millis),
+            request_timeouts: config
+                .request_timeouts_ms
+                .into_iter()
+                .map(|(name, ms)| (name, Duration::from_millis(ms)))
+                .collect(),
+            hooks: Hooks::default(),
+        }
+        .wire_formats(config.wire_formats)
+        .max_in_flight(config.max_in_flight)
+        .length_field_length(config.length_field_length)
+        .length_field_is_big_endian(config.length_field_big_endian)
+        .max_frame_bytes(config.max_frame_bytes)
+        .expected_frame_bytes(config.expected_frame_bytes)
+        .max_requests_per_connection(config.max_requests_per_connection)
+        .server_info(config.server_info)
+        .require_sequence_numbers(config.require_sequence_numbers)
+        .strict_framing(config.strict_framing)
+        .max_rejected_requests(config.max_rejected_requests)
+        .log_sampling(config.log_every_per_type.into_iter().fold(
+            LogSampling::every(config.log_every),
+            |sampling, (name, n)| sampling.with(name, n),
+        ))
+        .write_queue(config.write_queue)
+        .compress_min_bytes(config.compress_min_bytes)
+        .frame_body_timeout(config.frame_body_timeout_secs.map(Duration::from_secs));
+
+        let builder = match config.max_response_bytes {
+            Some(max) => builder.max_response_bytes(max),
+            None => builder,
+        };
+        let builder = if config.record_traffic {
+            builder.traffic(Traffic::new())
+        } else {
+            builder
+        };
+        let builder = if config.access_log {
+            builder.access_log(AccessLog::common())
+        } else {
+            builder
+        };
+        let builder = match config.idempotency_cache_capacity {
+            Some(capacity) => builder.idempotency_cache(IdempotencyCache::new(
+                capacity,
+                Duration::from_secs(config.idempotency_cache_ttl_secs),
+            )),
+            None => builder,
+        };
+        let builder = match config.flush_interval_micros {
+            Some(micros) => builder.write_coalescing(WriteCoalescing {
+                flush_interval: Duration::from_micros(micros),
+                flush_bytes: config.flush_bytes,
+            }),
+            None => builder,
+        };
+        let builder = match config.global_max_in_flight {
+            Some(limit) => builder.scheduler(FairScheduler::round_robin(limit)),
+            None => builder,
+        };
+        let builder = match config.result_cache_capacity {
+            Some(capacity) => builder.result_cache(MemoryCache::new(capacity)),
+            None => builder,
+        };
+        let builder = match config.priority_max_in_flight {
+            Some(limit) => builder.priority_scheduler(PriorityScheduler::new(limit)),
+            None => builder,
+        };
+        match config.memory_limit {
+            Some(limit) => builder.memory_limit(GlobalMemoryLimit::new(limit)),
+            None => builder,
+        }
+    }
+}
+
+impl ServerBuilder {
+    /// Encodings a client may pick from when it connects.
+    ///
+    /// [`WireFormat::FALLBACK`] is used whenever the client offers nothing
+    /// from this list, so it is always accepted.
+    pub fn wire_formats(mut self, wire_formats: impl IntoIterator<Item = WireFormat>) -> Self {
+        let formats = &mut self.connection.wire_formats;
+        *formats = wire_formats.into_iter().collect();
+        if !formats.contains(&WireFormat::FALLBACK) {
+            formats.push(WireFormat::FALLBACK);
+        }
+        self
+    }
+
+    /// Maximum number of requests handled at once on a single connection,
+    /// counting every request in a batch. Once reached, the connection stops
+    /// reading until a handler finishes. Defaults to 64.
+    pub fn max_in_flight(mut self, max_in_flight: usize) -> Self {
+        self.connection.max_in_flight = max_in_flight.max(1);
+        self
+    }
+
+    /// Width in bytes of the length prefix on every frame: 2, 3, 4 or 8.
+    /// Clients have to use the same width. Defaults to 4.
+    pub fn length_field_length(mut self, length_field_length: usize) -> Self {
+        self.connection.framing.length_field_length = length_field_length;
+        self
+    }
+
+    /// Whether the length prefix on every frame is big-endian, rather than
+    /// little-endian. Clients have to use the same byte order; a client
+    /// using the other one is told apart by its first frame, and
+    /// disconnected. Defaults to `true`.
+    pub fn length_field_is_big_endian(mut self, big_endian: bool) -> Self {
+        self.connection.framing.length_field_big_endian = big_endian;
+        self
+    }
+
+    /// Largest frame accepted from a client. Has to fit the length field.
+    /// Defaults to 8 MiB.
+    pub fn max_frame_bytes(mut self, max_frame_bytes: usize) -> Self {
+        self.connection.framing.max_frame_bytes = max_frame_bytes;
+        self
+    }
+
+    /// How large requests are expected to be, so the buffer each connection
+    /// reads them into starts out large enough for one rather than growing
+    /// into it. Only a hint, limiting nothing. Defaults to 8 KiB.
+    pub fn expected_frame_bytes(mut self, expected_frame_bytes: usize) -> Self {
+        self.connection.framing.expected_frame_bytes = expected_frame_bytes;
+        self
+    }
+
+    /// Largest encoded response body sent to a client. Encoding a larger
+    /// response stops as soon as it goes past the limit, and the client gets
+    /// [`RpcError::ResponseTooLarge`](protocol::RpcError::ResponseTooLarge)
+    /// instead. Defaults to [`max_frame_bytes`](ServerBuilder::max_frame_bytes),
+    /// as a larger response couldn't be sent anyway.
+    pub fn max_response_bytes(mut self, max: usize) -> Self {
+        self.connection.max_response_bytes = Some(max);
+        self
+    }
+
+    /// Closes connections after serving `max` requests, so clients get spread
+    /// over servers again when they reconnect. The client is told with a
+    /// [`FrameKind::GoAway`](protocol::frame::FrameKind::GoAway) frame once
+    /// the last request is read. Requests read until then are still
+    /// answered; later ones fail when the connection closes. Off by default.
+    pub fn max_requests_per_connection(mut self, max: Option<usize>) -> Self {
+        self.connection.max_requests = max.map(|max| max.max(1));
+        self
+    }
+
+    /// Answers [`INFO_METHOD`](protocol::info::INFO_METHOD) requests with the
+    /// server's version, uptime and load, and
+    /// [`CONN_STATS_METHOD`](protocol::info::CONN_STATS_METHOD) requests with
+    /// what has gone over the connection they come in on, ahead of any other
+    /// request and without counting towards the in-flight limit. On by
+    /// default; turn it off to not give this away to anyone who can connect.
+    pub fn server_info(mut self, enabled: bool) -> Self {
+        self.connection.server_info = enabled;
+        self
+    }
+
+    /// Asks `check` whether the server is ready for requests whenever a
+    /// client pings it, see [`PING_METHOD`](protocol::info::PING_METHOD).
+    /// Pings are answered like [`server_info`](ServerBuilder::server_info)
+    /// requests, but always, so `check` is called on the connection's own
+    /// task and has to be quick, e.g. reading a flag set elsewhere. Without
+    /// one, every ping is answered with [`Health::READY`].
+    pub fn readiness(mut self, check: impl Fn() -> Health + Send + Sync + 'static) -> Self {
+        self.connection.readiness = Some(Readiness::new(check));
+        self
+    }
+
+    /// Caps how many requests of each type, keyed by
+    /// [`Request::NAME`](protocol::Request::NAME), are handled at once across
+    /// all connections, so a flood of expensive requests can't starve cheap
+    /// ones. Types not listed are only held back by
+    /// [`max_in_flight`](ServerBuilder::max_in_flight).
+    pub fn concurrency_limits(
+        mut self,
+        limits: impl IntoIterator<Item = (impl Into<String>, usize)>,
+    ) -> Self {
+        self.concurrency_limits = limits
+            .into_iter()
+            .map(|(name, limit)| (name.into(), limit))
+            .collect();
+        self
+    }
+
+    /// Whether requests over their type's limit wait their turn or are
+    /// turned away. Defaults to [`OverLimit::Queue`].
+    pub fn over_limit(mut self, over_limit: OverLimit) -> Self {
+        self.over_limit = over_limit;
+        self
+    }
+
+    /// Where handlers of each type, keyed by
+    /// [`Request::NAME`](protocol::Request::NAME), run. CPU-bound ones can be
+    /// moved off the runtime's workers with [`ExecutionMode::Blocking`];
+    /// types not listed run [`Async`](ExecutionMode::Async).
+    pub fn execution_modes(
+        mut self,
+        modes: impl IntoIterator<Item = (impl Into<String>, ExecutionMode)>,
+    ) -> Self {
+        self.execution_modes = modes
+            .into_iter()
+            .map(|(name, mode)| (name.into(), mode))
+            .collect();
+        self
+    }
+
+    /// How long handlers of each type, keyed by
+    /// [`Request::NAME`](protocol::Request::NAME), may run before their
+    /// callers get [`RpcError::Timeout`](protocol::RpcError::Timeout), e.g.
+    /// 100 milliseconds for a ping but 30 seconds for a report. Only counts
+    /// from when the handler starts, not while it waits for a limit. Types
+    /// not listed get the [`request_timeout`](ServerBuilder::request_timeout).
+    /// Blocking handlers run on until they return anyway, but their callers
+    /// stop waiting.
+    pub fn request_timeouts(
+        mut self,
+        timeouts: impl IntoIterator<Item = (impl Into<String>, Duration)>,
+    ) -> Self {
+        self.request_timeouts = timeouts
+            .into_iter()
+            .map(|(name, timeout)| (name.into(), timeout))
+            .collect();
+        self
+    }
+
+    /// How long handlers of types without a timeout of their own in
+    /// [`request_timeouts`](ServerBuilder::request_timeouts) may run. `None`,
+    /// the default, lets them run for as long as their callers wait.
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Logs the contents of only some requests and their responses, rather
+    /// than every one. Logs them all by default.
+    pub fn log_sampling(mut self, sampling: LogSampling) -> Self {
+        self.connection.log_sampling = sampling;
+        self
+    }
+
+    /// Logs every error the server runs into, from a client going away to a
+    /// handler panicking, at the level `level` picks for it, e.g. to quiet
+    /// errors expected of the clients at hand while keeping real faults
+    /// loud. Falling back on [`default_level`](crate::default_level), which
+    /// is used without one, keeps the levels of everything else.
+    pub fn error_levels(
+        mut self,
+        level: impl Fn(LoggedError<'_>) -> Level + Send + Sync + 'static,
+    ) -> Self {
+        self.connection.error_levels = ErrorLevels::new(level);
+        self
+    }
+
+    /// Lets clients switch their connection over to `compression` once it is
+    /// going, by its [`name`](Compression::name), see
+    /// [`protocol::compression`]. Call it once for every compression to
+    /// offer; none are by default.
+    pub fn compression(mut self, compression: impl Compression) -> Self {
+        self.connection.compressions.insert(compression);
+        self
+    }
+
+    /// Compresses the body of every frame written of at least `min_bytes`,
+    /// like a large response, leaving smaller ones as they are, with the
+    /// first of the client's compressions that was also given to
+    /// [`ServerBuilder::compression`]. Flagged per frame in
+    /// [`Header::compressed`](protocol::frame::Header::compressed), so
+    /// clients take compressed and uncompressed frames alike. Bodies that
+    /// don't get smaller are sent uncompressed. Off by default.
+    pub fn compress_min_bytes(mut self, min_bytes: Option<usize>) -> Self {
+        self.connection.compress_min_bytes = min_bytes;
+        self
+    }
+
+    /// Counts the bytes of every request answered, and of its response, by
+    /// request type. Give several servers clones of the same counts to add
+    /// them up across servers. Off by default.
+    pub fn traffic(mut self, traffic: Traffic) -> Self {
+        self.connection.traffic = Some(traffic);
+        self
+    }
+
+    /// Reports every request answered to `access_log` once its response is
+    /// ready, with who sent it, how it went, how long it took and how many
+    /// bytes it moved, e.g. [`AccessLog::common`] for a line per request on
+    /// stdout. Off by default.
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.connection.access_log = Some(access_log);
+        self
+    }
+
+    /// Runs `on_connect` for every accepted connection before reading from
+    /// it. What it returns is shared by the connection's requests, whose
+    /// handlers get it from [`Context::connection_state`].
+    ///
+    /// [`Context::connection_state`]: protocol::Context::connection_state
+    pub fn on_connect<F, Fut, T>(mut self, on_connect: F) -> Self
+    where
+        F: Fn(ConnectionInfo) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = T> + Send + 'static,
+        T: Any + Send + Sync,
+    {
+        self.hooks.on_connect = Some(Arc::new(move |info| {
+            let state = on_connect(info);
+            async move { Some(Arc::new(state.await) as Arc<dyn Any + Send + Sync>) }.boxed()
+        }));
+        self
+    }
+
+    /// Runs `on_disconnect` once a connection is closed and every request on
+    /// it has been answered.
+    pub fn on_disconnect<F, Fut>(mut self, on_disconnect: F) -> Self
+    where
+        F: Fn(ConnectionInfo, DisconnectReason) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.hooks.on_disconnect = Some(Arc::new(move |info, reason| {
+            on_disconnect(info, reason).boxed()
+        }));
+        self
+    }
+
+    /// Answers requests [marked idempotent](protocol::Request::IDEMPOTENT)
+    /// that come with an idempotency key the cache has seen before with the
+    /// response kept for it, rather than handling them again. Give several
+    /// servers clones of the same cache to share it between them. Off by
+    /// default.
+    pub fn idempotency_cache(mut self, cache: IdempotencyCache) -> Self {
+        self.connection.idempotency_cache = Some(cache);
+        self
+    }
+
+    /// Closes connections whose frames come without sequence numbers, see
+    /// [`Header::seq`](protocol::frame::Header::seq), instead of only those
+    /// whose sequence numbers skip or repeat, so a client that doesn't send
+    /// them isn't served at all. Off by default.
+    pub fn require_sequence_numbers(mut self, required: bool) -> Self {
+        self.connection.require_sequence_numbers = required;
+        self
+    }
+
+    /// Rejects requests whose body goes on past the value it holds, with a
+    /// [`DecodeErrorKind::TrailingBytes`](protocol::DecodeErrorKind::TrailingBytes)
+    /// error, instead of ignoring the rest. Those come from a client whose
+    /// encoder is broken or out of step with the server's, so they can't be
+    /// trusted to hold what the client meant. Off by default, as clients
+    /// may have come to rely on it, and only bincode bodies have room for
+    /// anything after the value.
+    pub fn strict_framing(mut self, strict: bool) -> Self {
+        self.connection.strict_framing = strict;
+        self
+    }
+
+    /// Number of requests a connection may have rejected, for a body that
+    /// fails to decode or a method or version the server doesn't serve,
+    /// before the next one closes it. Each is answered with an error and the
+    /// connection reads on, so one bad request doesn't fail the others a
+    /// client has in flight. Frames whose header can't be read, or that
+    /// break the framing, still close the connection right away, as nothing
+    /// after them can be trusted to be read right. Defaults to 0, closing
+    /// the connection on the first.
+    pub fn max_rejected_requests(mut self, max: usize) -> Self {
+        self.connection.max_rejected_requests = max;
+        self
+    }
+
+    /// Bytes of request and response frames the server buffers at once,
+    /// across all connections, before it stops reading requests. Give
+    /// several servers clones of the same limit to share it between them.
+    /// Unlimited by default.
+    pub fn memory_limit(mut self, limit: GlobalMemoryLimit) -> Self {
+        self.connection.memory = limit;
+        self
+    }
+
+    /// Caps how many handlers run at once across all connections, letting
+    /// the requests over the cap in as `scheduler`'s policy picks, so a few
+    /// busy connections can't take up every slot while others wait. Give
+    /// several servers clones of the same scheduler to share it between
+    /// them. Unlimited by default.
+    pub fn scheduler(mut self, scheduler: FairScheduler) -> Self {
+        self.connection.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Caps how many handlers run at once across all connections, letting
+    /// the requests over the cap in highest priority first, e.g. so pings
+    /// get through while reports pile up. Requests get past every other
+    /// limit, [`ServerBuilder::scheduler`]'s included, before they wait for
+    /// it. Unlimited by default.
+    pub fn priority_scheduler(mut self, scheduler: PriorityScheduler) -> Self {
+        self.connection.priority_scheduler = Some(scheduler);
+        self
+    }
+
+    /// Keeps responses to [cacheable](protocol::Request::CACHE_TTL) requests
+    /// in `backend`, answering repeats of them from it without running their
+    /// handlers, e.g. a [`MemoryCache`](crate::MemoryCache), or a backend
+    /// shared between servers. Off by default, so every request runs its
+    /// handler whatever its `CACHE_TTL`. Two repeats arriving together may
+    /// both run, as nothing is kept until the first is done. Not for
+    /// requests whose responses are streamed.
+    pub fn result_cache(mut self, backend: impl CacheBackend) -> Self {
+        self.connection.result_cache = Some(Arc::new(backend));
+        self
+    }
+
+    /// Closes connections whose frames take longer than `timeout` to arrive
+    /// once they have started, counting from the first of their bytes, so
+    /// clients trickling them in can't tie connections up for good. Only
+    /// counts while the connection is read from, and only for frames split
+    /// by their length prefix. Off by default, waiting forever; something
+    /// like 30 seconds suits servers open to untrusted clients.
+    pub fn frame_body_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.connection.frame_body_timeout = timeout;
+        self
+    }
+
+    /// Number of responses queued for a connection before it stops reading
+    /// new requests, so a client that doesn't keep up with its responses
+    /// can't make the server buffer them without bound. Defaults to 64.
+    pub fn write_queue(mut self, capacity: usize) -> Self {
+        self.connection.write_queue = capacity.max(1);
+        self
+    }
+
+    /// Holds responses back to write several to the socket at once, for up
+    /// to [`flush_interval`](WriteCoalescing::flush_interval) or until
+    /// [`flush_bytes`](WriteCoalescing::flush_bytes) of them are waiting,
+    /// whichever comes first. Saves syscalls when many small responses are
+    /// ready in a burst, at the cost of that much latency. Off by default, so
+    /// every response is written as soon as it is ready.
+    pub fn write_coalescing(mut self, coalescing: WriteCoalescing) -> Self {
+        self.connection.write_coalescing = Some(coalescing);
+        self
+    }
+
+    /// Sets `TCP_NODELAY` on accepted connections. On by default, since
+    /// Nagle's algorithm only adds latency for small request/response frames.
+    pub fn nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// Sets `SO_REUSEADDR` on the listening socket. On by default.
+    pub fn reuse_addr(mut self, reuse_addr: bool) -> Self {
+        self.reuse_addr = reuse_addr;
+        self
+    }
+
+    /// Sets `SO_REUSEPORT` on the listening socket, so other sockets with it
+    /// set, by the same user, can listen on the same address at the same
+    /// time, with the kernel spreading new connections between them. Off by
+    /// default, and only available on Unix; binding fails elsewhere.
+    ///
+    /// This is what lets a new server process take over from an old one
+    /// without refusing anyone in between:
+    ///
+    /// 1. The new process binds the same address, with this set in both.
+    /// 2. Once it is serving, the old process is sent `SIGTERM`, and shuts
+    ///    down as through a [`ShutdownHandle`]: it closes its listener, so new
+    ///    connections only go to the new process, and, with a
+    ///    [`drain_timeout`](ServerBuilder::drain_timeout), tells every client
+    ///    it is draining and waits for the requests already read to be
+    ///    answered.
+    /// 3. The old process exits once [`Server::run_until`] returns.
+    ///
+    /// Connections the kernel had queued for the old listener but that were
+    /// not accepted yet are reset when it closes; clients retrying on
+    /// connection errors get the new process then.
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    /// Enables TCP keepalive on accepted connections, probing after they
+    /// have been idle for `idle`. Off by default.
+    pub fn keepalive(mut self, idle: Option<Duration>) -> Self {
+        self.keepalive = idle;
+        self
+    }
+
+    /// Maximum number of connections the kernel queues before they are
+    /// accepted.
+    pub fn backlog(mut self, backlog: u32) -> Self {
+        self.backlog = backlog;
+        self
+    }
+
+    /// What [`ServerBuilder::bind_all`] does when only some of its addresses
+    /// can be bound. Defaults to [`BindPolicy::All`].
+    pub fn bind_policy(mut self, policy: BindPolicy) -> Self {
+        self.bind_policy = policy;
+        self
+    }
+
+    /// Drains connections for up to `timeout` on shutdown before closing
+    /// them: each reads no more requests, tells its client with a
+    /// [`FrameKind::Draining`](protocol::frame::FrameKind::Draining) frame,
+    /// and answers those it has read. Whatever is still running once the
+    /// timeout passes is cancelled, as without draining, and its client
+    /// sent a [`GoAway`](protocol::frame::GoAway). Off by default, closing
+    /// connections right away.
+    pub fn drain_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Serves connections over WebSocket rather than plain TCP: each is taken
+    /// through the WebSocket handshake first, after which every binary
+    /// message is one frame, without a length prefix. See
+    /// [`handle_websocket`](crate::handle_websocket). Off by default.
+    #[cfg(feature = "ws")]
+    pub fn websocket(mut self, websocket: bool) -> Self {
+        self.websocket = websocket;
+        self
+    }
+
+    /// Binds to the first of the resolved addresses that works.
+    pub async fn bind(self, addr: impl ToSocketAddrs) -> Result<Server> {
+        self.connection.framing.validate()?;
+
+        let mut last_err = None;
+        for addr in lookup_host(addr).await? {
+            match self.listen(addr) {
+                Ok(listener) => return Ok(self.build(vec![listener])),
+                Err(e) => last_err = Some(e),
+            }
+        }
+
+        Err(last_err.unwrap_or_else(no_addresses).into())
+    }
+
+    /// Binds to every one of `addrs`, e.g. an IPv6 and an IPv4 address, or
+    /// several interfaces. Connections on all of them are served alike, and
+    /// share the same limits and shutdown. Addresses failing to bind are
+    /// handled according to the [`BindPolicy`].
+    pub async fn bind_all(self, addrs: impl IntoIterator<Item = SocketAddr>) -> Result<Server> {
+        self.connection.framing.validate()?;
+
+        let mut listeners = Vec::new();
+        let mut last_err = None;
+        for addr in addrs {
+            match self.listen(addr) {
+                Ok(listener) => listeners.push(listener),
+                Err(e) if self.bind_policy == BindPolicy::Any => {
+                    warn!(%e, %addr, "failed to bind, going on without it");
+                    last_err = Some(e);
+                }
+                Err(e) => {
+                    return Err(
+                        io::Error::new(e.kind(), format!("failed to bind {addr}: {e}")).into(),
+                    );
+                }
+            }
+        }
+
+        if listeners.is_empty() {
+            return Err(last_err.unwrap_or_else(no_addresses).into());
+        }
+        Ok(self.build(listeners))
+    }
+
+    fn build(mut self, listeners: Vec<TcpListener>) -> Server {
+        info::mark_started();
+        self.connection.concurrency_limits =
+            ConcurrencyLimits::new(&self.concurrency_limits, self.over_limit);
+        self.connection.execution_modes = ExecutionModes::new(&self.execution_modes);
+        self.connection.request_timeouts =
+            RequestTimeouts::new(self.request_timeout, &self.request_timeouts);
+
+        let (shutdown, finished) = ShutdownHandle::new();
+        Server {
+            listeners,
+            shutdown,
+            finished,
+            connection: Arc::new(self.connection),
+            hooks: self.hooks,
+            drain_timeout: self.drain_timeout,
+            #[cfg(feature = "ws")]
+            websocket: self.websocket,
+            stream_options: StreamOptions {
+                nodelay: self.nodelay,
+                keepalive: self.keepalive,
+            },
+        }
+    }
+
+    fn listen(&self, addr: SocketAddr) -> io::Result<TcpListener> {
+        let socket = match addr {
+            SocketAddr::V4(_) => TcpSocket::new_v4()?,
+            SocketAddr::V6(_) => TcpSocket::new_v6()?,
+        };
+        socket.set_reuseaddr(self.reuse_addr)?;
+        if self.reuse_port {
+            #[cfg(unix)]
+            socket.set_reuseport(true)?;
+            #[cfg(not(unix))]
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "SO_REUSEPORT is only available on Unix",
+            ));
+        }
+        socket.bind(addr)?;
+        socket.listen(self.backlog)
+    }
+}
+
+fn no_addresses() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "no addresses to bind to")
+}
+
+/// Options applied to every accepted connection.
+#[derive(Debug, Clone, Copy)]
+struct StreamOptions {
+    nodelay: bool,
+    keepalive: Option<Duration>,
+}
+
+impl StreamOptions {
+    fn apply(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+        if let Some(idle) = self.keepalive {
+            SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
+        }
+        Ok(())
+    }
+}
+
+pub struct Server {
+    listeners: Vec<TcpListener>,
+    shutdown: ShutdownHandle,
+    finished: watch::Sender<bool>,
+    connection: Arc<ConnectionConfig>,
+    hooks: Hooks,
+    drain_timeout: Option<Duration>,
+    #[cfg(feature = "ws")]
+    websocket: bool,
+    stream_options: StreamOptions,
+}
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    /// Binds to [`ServerConfig::addresses`], or [`ServerConfig::address`] if
+    /// there are none, with the rest of `config` applied as through the
+    /// builder.
+    pub async fn from_config(config: ServerConfig) -> Result<Server> {
+        if config.addresses.is_empty() {
+            let addr = config.address.clone();
+            return ServerBuilder::from(config).bind(addr.as_str()).await;
+        }
+
+        let mut addrs = Vec::new();
+        for addr in &config.addresses {
+            addrs.extend(lookup_host(addr.as_str()).await?);
+        }
+        ServerBuilder::from(config).bind_all(addrs).await
+    }
+
+    /// The counts set with [`ServerBuilder::traffic`], if any.
+    pub fn traffic(&self) -> Option<&Traffic> {
+        self.connection.traffic.as_ref()
+    }
+
+    /// Sets the socket options given to the builder, like
+    /// [`nodelay`](ServerBuilder::nodelay) and
+    /// [`keepalive`](ServerBuilder::keepalive), on `stream`, as is done for
+    /// every connection the server accepts. For connections accepted
+    /// elsewhere and served with [`handle_connection`](crate::handle_connection).
+    pub fn configure_stream(&self, stream: &TcpStream) -> Result<()> {
+        Ok(self.stream_options.apply(stream)?)
+    }
+
+    /// The address of the first listener, the only one unless bound with
+    /// [`ServerBuilder::bind_all`].
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.listeners[0].local_addr()?)
+    }
+
+    /// The addresses of every listener, in the order they were bound.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        self.listeners
+            .iter()
+            .map(|listener| Ok(listener.local_addr()?))
+            .collect()
+    }
+
+    /// Handle to shut the server down once it is running.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Serves connections until shut down through a [`ShutdownHandle`].
+    pub async fn run<Req: Request>(self) -> Result<()> {
+        self.run_until::<Req>(future::pending()).await
+    }
+
+    /// Serves connections until `signal` completes or the server is shut down
+    /// through a [`ShutdownHandle`], whichever happens first.
+    ///
+    /// Either way, open connections are closed and waited for before this
+    /// returns.
+    pub async fn run_until<Req: Request>(self, signal: impl Future<Output = ()>) -> Result<()> {
+        self.serve_service(Arc::new(Static::<Req>::new()), signal)
+            .await
+    }
+
+    /// Like [`Server::run`], but handles requests with `router` instead of a
+    /// type known at compile time.
+    pub async fn serve(self, router: Router) -> Result<()> {
+        self.serve_until(router, future::pending()).await
+    }
+
+    /// Like [`Server::run_until`], but handles requests with `router`.
+    pub async fn serve_until(self, router: Router, signal: impl Future<Output = ()>) -> Result<()> {
+        self.serve_service(Arc::new(router), signal).await
+    }
+
+    async fn serve_service(
+        self,
+        service: Arc<impl Service>,
+        signal: impl Future<Output = ()>,
+    ) -> Result<()> {
+        let addrs = self.local_addrs()?;
+        info!(
+            ?addrs,
+            wire_formats = ?self.connection.wire_formats,
+            max_in_flight = self.connection.max_in_flight,
+            "started server"
+        );
+
+        // Every listener gets an accept loop of its own, all feeding the one
+        // below. Dropping the set when the server stops ends them.
+        let (accepted, mut incoming) = mpsc::channel(self.listeners.len());
+        let mut accept_loops = JoinSet::new();
+        for listener in self.listeners {
+            accept_loops.spawn(accept(listener, accepted.clone()));
+        }
+        drop(accepted);
+
+        let token = self.shutdown.token().clone();
+        // Cancelled once connections are no longer waited for, see
+        // `ServerBuilder::drain_timeout`.
+        let abort = CancellationToken::new();
+        let connections = TaskTracker::new();
+        let mut signal = std::pin::pin!(signal);
+
+        loop {
+            tokio::select! {
+                Some((socket, peer_addr)) = incoming.recv() => {
+                    if let Err(e) = self.stream_options.apply(&socket) {
+                        warn!(%e, %peer_addr, "failed to set socket options");
+                    }
+                    let shutdown = Shutdown {
+                        drain: match self.drain_timeout {
+                            Some(_) => token.child_token(),
+                            None => CancellationToken::new(),
+                        },
+                        abort: abort.child_token(),
+                    };
+                    let config = self.connection.clone();
+                    let service = service.clone();
+                    let hooks = self.hooks.clone();
+                    #[cfg(feature = "ws")]
+                    let websocket = self.websocket;
+                    let connection_id = CONNECTION_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+                    let span = info_span!("connection", %peer_addr, %connection_id);
+                    connections.spawn(async move {
+                        info!("connection opened");
+                        let info = ConnectionInfo { peer_addr, id: connection_id };
+                        let state = hooks.connected(info).await;
+                        let peer = Arc::new(Peer { addr: Some(peer_addr), certificate: None });
+                        #[cfg(feature = "ws")]
+                        let result = if websocket {
+                            crate::ws::serve_websocket(socket, &config, shutdown.clone(), state, peer, &*service).await
+                        } else {
+                            serve_connection(socket, &config, shutdown.clone(), state, peer, &*service).await
+                        };
+                        #[cfg(not(feature = "ws"))]
+                        let result = serve_connection(socket, &config, shutdown.clone(), state, peer, &*service).await;
+                        if result.is_err() {
+                            debug!("connection task ended with error");
+                        }
+                        info!("connection closed");
+                        hooks.disconnected(info, DisconnectReason::new(result, shutdown.is_cancelled())).await;
+                    }.instrument(span));
+                }
+
+                _ = &mut signal => {
+                    info!("Received shutdown signal, shutting down gracefully...");
+                    break;
+                }
+
+                _ = token.cancelled() => break,
+            }
+        }
+
+        info!("Shutting down server...");
+        token.cancel();
+
+        accept_loops.shutdown().await;
+        connections.close();
+        if let Some(timeout) = self.drain_timeout
+            && tokio::time::timeout(timeout, connections.wait())
+                .await
+                .is_err()
+        {
+            info!("drain timed out, closing the connections left");
+        }
+        abort.cancel();
+        connections.wait().await;
+        self.finished.send_replace(true);
+
+        Ok(())
+    }
+}
+
+/// How long [`accept`] waits after failing before trying again, so errors
+/// that persist for a while, like running out of file descriptors, don't
+/// spin it.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Hands connections accepted on `listener` over to `accepted`, until that
+/// is closed.
+async fn accept(listener: TcpListener, accepted: mpsc::Sender<(TcpStream, SocketAddr)>) {
+    loop {
+        match listener.accept().await {
+            Ok(connection) => {
+                if accepted.send(connection).await.is_err() {
+                    break;
+                }
+            }
+            Err(e) => {
+                warn!(%e, "failed to accept connection");
+                tokio::time::sleep(ACCEPT_BACKOFF).await;
+            }
+        }
+    }
+}
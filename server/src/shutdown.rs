@@ -0,0 +1,46 @@
+use tokio::sync::watch;
+use tokio_util::sync::CancellationToken;
+
+/// Triggers a graceful shutdown of a [`Server`](crate::Server) from anywhere.
+///
+/// Handles are cheap to clone, and any clone can trigger the shutdown.
+#[derive(Debug, Clone)]
+pub struct ShutdownHandle {
+    token: CancellationToken,
+    finished: watch::Receiver<bool>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new() -> (Self, watch::Sender<bool>) {
+        let (finished_tx, finished) = watch::channel(false);
+        let handle = Self {
+            token: CancellationToken::new(),
+            finished,
+        };
+        (handle, finished_tx)
+    }
+
+    /// Stops accepting connections, closes the open ones, draining them
+    /// first if the server has a
+    /// [`drain_timeout`](crate::ServerBuilder::drain_timeout), and waits
+    /// until the server has finished doing so.
+    pub async fn shutdown(&self) {
+        self.token.cancel();
+        self.finished().await;
+    }
+
+    /// Waits for the server to stop, without triggering the shutdown.
+    pub async fn finished(&self) {
+        let mut finished = self.finished.clone();
+        // An error means the server is gone, which is just as finished.
+        let _ = finished.wait_for(|finished| *finished).await;
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub(crate) fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
@@ -0,0 +1,26 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long handlers of each request type, by
+/// [`Request::NAME`](protocol::Request::NAME), may run before their callers
+/// get [`RpcError::Timeout`](protocol::RpcError::Timeout). Types not listed
+/// get the default, if there is one.
+#[derive(Debug, Clone, Default)]
+pub struct RequestTimeouts {
+    default: Option<Duration>,
+    per_type: Arc<HashMap<String, Duration>>,
+}
+
+impl RequestTimeouts {
+    pub fn new(default: Option<Duration>, per_type: &HashMap<String, Duration>) -> Self {
+        Self {
+            default,
+            per_type: Arc::new(per_type.clone()),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Duration> {
+        self.per_type.get(name).copied().or(self.default)
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Bytes moved per request type, keyed by
+/// [`Request::NAME`](protocol::Request::NAME), for capacity planning.
+///
+/// Batches are counted as a whole under `"batch"`. Clones share the same
+/// counts.
+#[derive(Debug, Clone, Default)]
+pub struct Traffic {
+    counts: Arc<Mutex<HashMap<&'static str, ByteCounts>>>,
+}
+
+/// What requests of one type added up to so far. Frame sizes cover the whole
+/// frame, header included but not the length prefix; body sizes just what
+/// the codec decoded or encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ByteCounts {
+    pub requests: u64,
+    pub request_frame_bytes: u64,
+    pub request_body_bytes: u64,
+    pub response_frame_bytes: u64,
+    pub response_body_bytes: u64,
+}
+
+impl Traffic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Counts for requests named `name`, if any were answered.
+    pub fn get(&self, name: &str) -> Option<ByteCounts> {
+        self.counts.lock().unwrap().get(name).copied()
+    }
+
+    /// Counts for every request type answered so far.
+    pub fn snapshot(&self) -> HashMap<&'static str, ByteCounts> {
+        self.counts.lock().unwrap().clone()
+    }
+
+    pub(crate) fn record(&self, name: &'static str, request: Sizes, response: Sizes) {
+        let mut counts = self.counts.lock().unwrap();
+        let counts = counts.entry(name).or_default();
+        counts.requests += 1;
+        counts.request_frame_bytes += request.frame as u64;
+        counts.request_body_bytes += request.body as u64;
+        counts.response_frame_bytes += response.frame as u64;
+        counts.response_body_bytes += response.body as u64;
+    }
+}
+
+/// Size of one or more frames, and of the bodies in them.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Sizes {
+    pub(crate) frame: usize,
+    pub(crate) body: usize,
+}
+
+impl Sizes {
+    pub(crate) fn of(frame: &[u8]) -> Self {
+        let header_len = frame
+            .first_chunk::<2>()
+            .map_or(0, |len| 2 + u16::from_be_bytes(*len) as usize);
+        Self {
+            frame: frame.len(),
+            body: frame.len().saturating_sub(header_len),
+        }
+    }
+
+    pub(crate) fn add(&mut self, frame: &[u8]) {
+        let sizes = Self::of(frame);
+        self.frame += sizes.frame;
+        self.body += sizes.body;
+    }
+}
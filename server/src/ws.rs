@@ -0,0 +1,71 @@
+use crate::connection::{Shutdown, serve_transport};
+use crate::dispatch::{Service, Static};
+use crate::hooks::ConnectionState;
+use crate::{ConnectionConfig, Result};
+
+use protocol::{Peer, Request};
+
+use futures::{Sink, SinkExt, Stream, TryStreamExt, future};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_util::sync::CancellationToken;
+
+use bytes::Bytes;
+
+use std::io;
+use std::sync::Arc;
+
+/// Like [`handle_connection`](crate::handle_connection), but for a client
+/// connecting over WebSocket, e.g. from a browser: `socket` is taken
+/// through the WebSocket handshake, after which every binary message is one
+/// frame, as with [`handle_messages`](crate::handle_messages). Messages are
+/// capped at [`Framing::max_frame_bytes`](protocol::frame::Framing::max_frame_bytes).
+pub async fn handle_websocket<Req: Request>(
+    socket: impl AsyncRead + AsyncWrite + Unpin,
+    config: &ConnectionConfig,
+    shutdown: CancellationToken,
+) -> Result<()> {
+    let peer = Arc::default();
+    let shutdown = Shutdown::immediate(shutdown);
+    serve_websocket(socket, config, shutdown, None, peer, &Static::<Req>::new()).await
+}
+
+pub(crate) async fn serve_websocket(
+    socket: impl AsyncRead + AsyncWrite + Unpin,
+    config: &ConnectionConfig,
+    shutdown: Shutdown,
+    state: ConnectionState,
+    peer: Arc<Peer>,
+    service: &impl Service,
+) -> Result<()> {
+    let limits = WebSocketConfig::default()
+        .max_message_size(Some(config.framing.max_frame_bytes))
+        .max_frame_size(Some(config.framing.max_frame_bytes));
+    let websocket = tokio_tungstenite::accept_async_with_config(socket, Some(limits))
+        .await
+        .map_err(io::Error::other)?;
+    let transport = frames(websocket);
+    serve_transport(transport, None, config, shutdown, state, peer, service).await
+}
+
+/// The binary messages of `websocket` as frames. Text messages are ignored,
+/// and pings answered by the WebSocket itself.
+fn frames<T, E>(
+    websocket: T,
+) -> impl Stream<Item = io::Result<Bytes>> + Sink<Bytes, Error = io::Error> + Unpin
+where
+    T: Stream<Item = Result<Message, E>> + Sink<Message, Error = E> + Unpin,
+    E: std::error::Error + Send + Sync + 'static,
+{
+    websocket
+        .sink_map_err(io::Error::other)
+        .with(|frame| future::ok::<_, io::Error>(Message::Binary(frame)))
+        .map_err(io::Error::other)
+        .try_filter_map(|message| {
+            future::ok(match message {
+                Message::Binary(frame) => Some(frame),
+                _ => None,
+            })
+        })
+}
@@ -0,0 +1,107 @@
+//! Access records reported for every request answered.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::{AccessLog, AccessRecord, AccessStatus, Server, common_log};
+
+use protocol::RpcError;
+
+use macros::{request, rpc};
+
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+    Slow(Slow),
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+#[request]
+async fn Slow(ms: u64) -> u64 {
+    tokio::time::sleep(Duration::from_millis(ms)).await;
+    ms
+}
+
+async fn serve(records: Arc<Mutex<Vec<AccessRecord>>>) -> SocketAddr {
+    let builder = Server::builder()
+        .request_timeout(Some(Duration::from_millis(20)))
+        .access_log(AccessLog::new(move |record| {
+            records.lock().unwrap().push(record.clone())
+        }));
+    common::serve::<AppRequest>(builder).await
+}
+
+#[tokio::test]
+async fn records_every_request_answered() {
+    let records = Arc::default();
+    let addr = serve(Arc::clone(&records)).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let before = SystemTime::now();
+    let req = AppRequest::Echo(Echo {
+        text: "hello".into(),
+    });
+    client.call(req).await.unwrap();
+    let resp = client.call(AppRequest::Slow(Slow { ms: 10_000 })).await;
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::Timeout(_)))),
+        "{resp:?}"
+    );
+
+    let records = records.lock().unwrap().clone();
+    let [echo, slow] = records.as_slice() else {
+        panic!("expected two records, got {records:?}");
+    };
+
+    assert_eq!((echo.name, echo.id), ("Echo", 1));
+    assert_eq!(echo.status, AccessStatus::Ok);
+    assert_eq!(
+        echo.peer.map(|peer| peer.ip()),
+        Some(Ipv4Addr::LOCALHOST.into())
+    );
+    assert!(echo.received >= before);
+    assert!(echo.request_bytes > "hello".len());
+    assert!(echo.response_bytes > "hello".len());
+
+    assert_eq!((slow.name, slow.id), ("Slow", 2));
+    assert_eq!(
+        slow.status,
+        AccessStatus::Failed(RpcError::Timeout("Slow".into()))
+    );
+    assert!(slow.latency >= Duration::from_millis(20));
+}
+
+#[test]
+fn common_log_lines() {
+    let record = AccessRecord {
+        received: UNIX_EPOCH + Duration::from_secs(1_792_022_400 + 9 * 3600 + 30 * 60 + 5),
+        peer: Some("127.0.0.1:50312".parse().unwrap()),
+        name: "Lookup",
+        id: 7,
+        status: AccessStatus::Ok,
+        latency: Duration::from_micros(1250),
+        request_bytes: 30,
+        response_bytes: 48,
+    };
+    assert_eq!(
+        common_log(&record),
+        r#"127.0.0.1:50312 - - [15/Oct/2026:09:30:05 +0000] "Lookup" ok 48 1250"#
+    );
+
+    let record = AccessRecord {
+        peer: None,
+        status: AccessStatus::Failed(RpcError::Busy("Lookup".into())),
+        ..record
+    };
+    assert!(common_log(&record).starts_with("- - - ["));
+    assert!(common_log(&record).ends_with(r#""Lookup" busy 48 1250"#));
+}
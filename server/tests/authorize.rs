@@ -0,0 +1,131 @@
+//! Requests checked against who sent them before being handled.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::{Context, RpcError};
+
+use macros::{request, rpc};
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Edit(Edit),
+    Read(Read),
+}
+
+/// Set up by `on_connect`, standing in for what a real server would learn
+/// from a token or certificate.
+struct Principal {
+    user: String,
+}
+
+static EDITED: AtomicUsize = AtomicUsize::new(0);
+
+#[request]
+#[authorize(owns_doc)]
+#[validate(not_empty)]
+fn Edit(doc: String, text: String) -> String {
+    EDITED.fetch_add(1, Ordering::SeqCst);
+    format!("{doc}: {text}")
+}
+
+#[request]
+fn Read(doc: String) -> String {
+    doc
+}
+
+/// Documents belong to the user they are named after, as in `alice/notes`.
+async fn owns_doc(req: &Edit, ctx: &Context) -> Result<(), String> {
+    let user = match ctx.connection_state::<Principal>() {
+        Some(principal) => &principal.user,
+        None => return Err("nobody signed in".into()),
+    };
+    match req.doc.split_once('/') {
+        Some((owner, _)) if owner == user => Ok(()),
+        _ => Err(format!("{user} may not edit {}", req.doc)),
+    }
+}
+
+async fn not_empty(req: &Edit) -> Result<(), String> {
+    if req.text.is_empty() {
+        return Err("nothing to write".into());
+    }
+    Ok(())
+}
+
+/// A server whose first connection is alice's and every later one bob's.
+async fn serve() -> SocketAddr {
+    let connections = AtomicUsize::new(0);
+    let builder = Server::builder().on_connect(move |_| {
+        let user = match connections.fetch_add(1, Ordering::SeqCst) {
+            0 => "alice",
+            _ => "bob",
+        };
+        async move { Principal { user: user.into() } }
+    });
+    common::serve::<AppRequest>(builder).await
+}
+
+fn edit(doc: &str, text: &str) -> AppRequest {
+    AppRequest::Edit(Edit {
+        doc: doc.into(),
+        text: text.into(),
+    })
+}
+
+#[tokio::test]
+async fn denies_requests_the_caller_may_not_make() {
+    let addr = serve().await;
+    let alice = client::Client::connect(addr).await.unwrap();
+    let bob = client::Client::connect(addr).await.unwrap();
+
+    let resp = alice.call(edit("alice/notes", "hi")).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Edit(s)) if s == "alice/notes: hi"),
+        "{resp:?}"
+    );
+    let edited = EDITED.load(Ordering::SeqCst);
+
+    let resp = bob.call(edit("alice/notes", "mine now")).await;
+    assert!(
+        matches!(&resp, Err(client::Error::Rpc(RpcError::Unauthorized(msg))) if msg == "bob may not edit alice/notes"),
+        "{resp:?}"
+    );
+    assert_eq!(EDITED.load(Ordering::SeqCst), edited);
+
+    // Only the request type with an authorizer is checked.
+    let resp = bob
+        .call(AppRequest::Read(Read {
+            doc: "alice/notes".into(),
+        }))
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Read(_))), "{resp:?}");
+
+    // The connection is still good for what bob may do.
+    let resp = bob.call(edit("bob/todo", "milk")).await;
+    assert!(matches!(resp, Ok(AppResponse::Edit(_))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn authorizes_before_validating() {
+    let addr = serve().await;
+    let alice = client::Client::connect(addr).await.unwrap();
+    let bob = client::Client::connect(addr).await.unwrap();
+
+    let resp = bob.call(edit("alice/notes", "")).await;
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::Unauthorized(_)))),
+        "{resp:?}"
+    );
+    let resp = alice.call(edit("alice/notes", "")).await;
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::ValidationFailed(_)))),
+        "{resp:?}"
+    );
+}
@@ -0,0 +1,120 @@
+//! Servers listening on several addresses at once.
+
+#![allow(non_snake_case)]
+
+use server::{BindPolicy, Server};
+
+use macros::{request, rpc};
+
+use tokio::net::TcpStream;
+
+use std::net::SocketAddr;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Ping(Ping),
+}
+
+#[request]
+fn Ping() -> String {
+    "pong".into()
+}
+
+fn ephemeral() -> SocketAddr {
+    "127.0.0.1:0".parse().unwrap()
+}
+
+#[tokio::test]
+async fn serves_every_address() {
+    let server = Server::builder()
+        .bind_all([ephemeral(), ephemeral()])
+        .await
+        .unwrap();
+    let addrs = server.local_addrs().unwrap();
+    assert_eq!(addrs.len(), 2);
+    assert_ne!(addrs[0], addrs[1]);
+    assert_eq!(server.local_addr().unwrap(), addrs[0]);
+
+    let handle = server.shutdown_handle();
+    let running = tokio::spawn(server.run::<AppRequest>());
+
+    for addr in &addrs {
+        let client = client::Client::connect(addr).await.unwrap();
+        let resp = client.call(AppRequest::Ping(Ping {})).await;
+        assert!(
+            matches!(&resp, Ok(AppResponse::Ping(s)) if s == "pong"),
+            "{resp:?}"
+        );
+    }
+
+    handle.shutdown().await;
+    running.await.unwrap().unwrap();
+    for addr in &addrs {
+        assert!(TcpStream::connect(addr).await.is_err(), "{addr} still open");
+    }
+}
+
+#[tokio::test]
+async fn fails_if_any_address_is_taken_by_default() {
+    let taken = std::net::TcpListener::bind(ephemeral()).unwrap();
+    let taken = taken.local_addr().unwrap();
+
+    let res = Server::builder().bind_all([ephemeral(), taken]).await;
+    let err = res.err().expect("bound an address already in use");
+    assert!(err.to_string().contains(&taken.to_string()), "{err}");
+}
+
+#[tokio::test]
+async fn goes_on_without_taken_addresses_if_allowed() {
+    let taken = std::net::TcpListener::bind(ephemeral()).unwrap();
+    let taken = taken.local_addr().unwrap();
+
+    let server = Server::builder()
+        .bind_policy(BindPolicy::Any)
+        .bind_all([taken, ephemeral()])
+        .await
+        .unwrap();
+    let addrs = server.local_addrs().unwrap();
+    assert_eq!(addrs.len(), 1);
+    assert_ne!(addrs[0], taken);
+
+    let res = Server::builder()
+        .bind_policy(BindPolicy::Any)
+        .bind_all([taken])
+        .await;
+    assert!(res.is_err());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn shares_a_port_with_reuse_port() {
+    let first = Server::builder()
+        .reuse_port(true)
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = first.local_addr().unwrap();
+
+    // Still taken for servers without the flag.
+    let res = Server::builder().bind(addr).await;
+    assert!(res.is_err(), "bound a port in use without SO_REUSEPORT");
+
+    let second = Server::builder().reuse_port(true).bind(addr).await.unwrap();
+    assert_eq!(second.local_addr().unwrap(), addr);
+
+    // The old server drains and goes, the new one keeps serving the port.
+    let old = first.shutdown_handle();
+    let old_running = tokio::spawn(first.run::<AppRequest>());
+    tokio::spawn(second.run::<AppRequest>());
+    old.shutdown().await;
+    old_running.await.unwrap().unwrap();
+
+    for _ in 0..4 {
+        let client = client::Client::connect(addr).await.unwrap();
+        let resp = client.call(AppRequest::Ping(Ping {})).await;
+        assert!(
+            matches!(&resp, Ok(AppResponse::Ping(s)) if s == "pong"),
+            "{resp:?}"
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! Calls made from synchronous code, through a blocking client.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use client::BlockingClient;
+
+use macros::{request, rpc};
+
+use std::net::SocketAddr;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+}
+
+#[request]
+fn Add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+/// Serves from a runtime of its own, as the test itself isn't async.
+fn serve() -> (tokio::runtime::Runtime, SocketAddr) {
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let server = runtime
+        .block_on(Server::builder().bind("127.0.0.1:0"))
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    runtime.spawn(server.run::<AppRequest>());
+    (runtime, addr)
+}
+
+#[test]
+fn calls_synchronously() {
+    let (_server, addr) = serve();
+    let client = BlockingClient::connect(addr).unwrap();
+
+    let resp = client.call(AppRequest::Add(Add { a: 2, b: 3 }));
+    assert!(matches!(resp, Ok(AppResponse::Add(5))), "{resp:?}");
+
+    let resps = client
+        .call_batch(vec![
+            AppRequest::Add(Add { a: 1, b: 1 }),
+            AppRequest::Add(Add { a: 4, b: 4 }),
+        ])
+        .unwrap();
+    assert!(
+        matches!(
+            resps[..],
+            [Ok(AppResponse::Add(2)), Ok(AppResponse::Add(8))]
+        ),
+        "{resps:?}"
+    );
+    client.close();
+}
+
+#[test]
+fn shares_one_connection_across_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<BlockingClient>();
+
+    let (_server, addr) = serve();
+    let client = BlockingClient::connect(addr).unwrap();
+    std::thread::scope(|scope| {
+        for a in 0..4 {
+            let client = &client;
+            scope.spawn(move || {
+                for b in 0..10 {
+                    let resp = client.call(AppRequest::Add(Add { a, b }));
+                    assert!(
+                        matches!(resp, Ok(AppResponse::Add(sum)) if sum == a + b),
+                        "{resp:?}"
+                    );
+                }
+            });
+        }
+    });
+}
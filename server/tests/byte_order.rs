@@ -0,0 +1,126 @@
+//! Length prefixes in either byte order, matched on both ends.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, Error, Server, handle_connection};
+
+use protocol::frame::{Framing, FramingError};
+use protocol::hello::HelloAck;
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::SinkExt;
+use tokio_util::codec::FramedWrite;
+use tokio_util::sync::CancellationToken;
+
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+}
+
+#[request]
+fn Add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[tokio::test]
+async fn round_trips_with_little_endian_length_fields() {
+    for length_field_length in [2, 3, 4, 8] {
+        let server = Server::builder()
+            .length_field_length(length_field_length)
+            .max_frame_bytes(64 * 1024 - 1)
+            .length_field_is_big_endian(false)
+            .bind("127.0.0.1:0")
+            .await
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+        tokio::spawn(server.run::<AppRequest>());
+
+        let client = client::Client::builder()
+            .length_field_length(length_field_length)
+            .max_frame_bytes(64 * 1024 - 1)
+            .length_field_is_big_endian(false)
+            .connect(addr)
+            .await
+            .unwrap();
+        let resp = client.call(AppRequest::Add(Add { a: 2, b: 3 })).await;
+        assert!(
+            matches!(resp, Ok(AppResponse::Add(5))),
+            "{length_field_length}: {resp:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn mismatched_byte_orders_fail_the_handshake() {
+    for (length_field_length, server_big_endian) in [(2, true), (4, false), (8, true)] {
+        let framing = Framing {
+            length_field_length,
+            length_field_big_endian: server_big_endian,
+            max_frame_bytes: 64 * 1024 - 1,
+            ..Framing::default()
+        };
+        let config = ConnectionConfig {
+            framing,
+            ..ConnectionConfig::default()
+        };
+        let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+        let serving = tokio::spawn(async move {
+            handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+        });
+
+        let connecting = client::Client::builder()
+            .length_field_length(length_field_length)
+            .max_frame_bytes(64 * 1024 - 1)
+            .length_field_is_big_endian(!server_big_endian)
+            .handshake(client_end);
+        let client = tokio::time::timeout(Duration::from_secs(5), connecting)
+            .await
+            .expect("handshake hung");
+        assert!(client.is_err(), "{server_big_endian}");
+
+        let res = serving.await.unwrap();
+        let mismatch = res.as_ref().err().and_then(|e| match e {
+            Error::Io(e) => e.get_ref()?.downcast_ref::<FramingError>(),
+            _ => None,
+        });
+        assert!(
+            matches!(mismatch, Some(FramingError::ByteOrderMismatch { big_endian }) if *big_endian != server_big_endian),
+            "{server_big_endian}: {res:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn clients_catch_servers_answering_in_the_other_byte_order() {
+    let framing = Framing {
+        length_field_big_endian: false,
+        ..Framing::default()
+    };
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let mut server = FramedWrite::new(server_end, framing.codec());
+    let ack = framing.with_handshake_magic(&HelloAck::default().encode());
+    server.send(Bytes::from(ack)).await.unwrap();
+
+    let connecting = client::Client::builder()
+        .length_field_is_big_endian(true)
+        .handshake(client_end);
+    let res = tokio::time::timeout(Duration::from_secs(5), connecting)
+        .await
+        .expect("handshake hung");
+    let mismatch = res.as_ref().err().and_then(|e| match e {
+        client::Error::Io(e) => e.get_ref()?.downcast_ref::<FramingError>(),
+        _ => None,
+    });
+    assert!(
+        matches!(
+            mismatch,
+            Some(FramingError::ByteOrderMismatch { big_endian: false })
+        ),
+        "{:?}",
+        res.err()
+    );
+}
@@ -0,0 +1,66 @@
+//! Handlers calling back into the client over the connection it called on.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::{Context, RpcError};
+
+use macros::{request, rpc};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Greet(Greet),
+}
+
+/// Greets the client by the name it answers with.
+#[request]
+async fn Greet(ctx: Context) -> Result<String, String> {
+    let callbacks = ctx.callbacks().ok_or("client answers no calls")?;
+    match callbacks.call(ClientRequest::Name(Name {})).await {
+        Ok(ClientResponse::Name(name)) => Ok(format!("hello {name}")),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+#[rpc(response = "ClientResponse")]
+enum ClientRequest {
+    Name(Name),
+}
+
+#[request]
+fn Name() -> String {
+    "ada".into()
+}
+
+#[tokio::test]
+async fn handlers_call_the_client_back() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::builder()
+        .callbacks::<ClientRequest>()
+        .connect(addr)
+        .await
+        .unwrap();
+
+    let calls = (0..3).map(|_| client.call(AppRequest::Greet(Greet {})));
+    for resp in futures::future::join_all(calls).await {
+        assert!(
+            matches!(&resp, Ok(AppResponse::Greet(Ok(s))) if s == "hello ada"),
+            "{resp:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn clients_without_handlers_take_no_calls() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client.call(AppRequest::Greet(Greet {})).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Greet(Err(RpcError::Handler(msg)))) if msg == "client answers no calls"),
+        "{resp:?}"
+    );
+}
@@ -0,0 +1,58 @@
+//! Handlers told when their caller gives up on them.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::Context;
+
+use macros::{request, rpc};
+
+use tokio::sync::{Notify, oneshot};
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Wait(Wait),
+}
+
+static STARTED: Notify = Notify::const_new();
+static CANCELLED: Mutex<Option<oneshot::Sender<()>>> = Mutex::new(None);
+
+/// Waits until cancelled, then reports it.
+#[request]
+async fn Wait(ctx: Context) -> u32 {
+    STARTED.notify_one();
+    ctx.cancelled().await;
+    if let Some(cancelled) = CANCELLED.lock().unwrap().take() {
+        let _ = cancelled.send(());
+    }
+    0
+}
+
+#[tokio::test]
+async fn dropping_a_call_cancels_its_handler() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let (cancelled, observed) = oneshot::channel();
+    *CANCELLED.lock().unwrap() = Some(cancelled);
+
+    let call = client.call(AppRequest::Wait(Wait {}));
+    let gave_up = tokio::time::timeout(Duration::from_millis(50), async {
+        tokio::join!(call, STARTED.notified()).0
+    })
+    .await;
+    assert!(gave_up.is_err(), "{gave_up:?}");
+
+    tokio::time::timeout(Duration::from_secs(5), observed)
+        .await
+        .expect("handler wasn't cancelled")
+        .unwrap();
+    assert_eq!(client.in_flight(), 0);
+    assert!(!client.is_closed());
+}
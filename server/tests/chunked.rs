@@ -0,0 +1,115 @@
+//! Chunked responses, streamed from a real server.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::ChunkedResponse;
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use tokio::io::AsyncReadExt;
+
+use std::io;
+
+const CHUNK: usize = 64 * 1024;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Download(Download),
+    Broken(Broken),
+}
+
+/// `len` bytes in pieces of [`CHUNK`], each filled with its index.
+#[request]
+fn Download(len: usize) -> ChunkedResponse {
+    let chunks = (0..len.div_ceil(CHUNK)).map(move |i| {
+        let piece = CHUNK.min(len - i * CHUNK);
+        Ok(Bytes::from(vec![i as u8; piece]))
+    });
+    ChunkedResponse::from_stream(futures::stream::iter(chunks))
+}
+
+#[request]
+fn Broken() -> ChunkedResponse {
+    let chunks = [
+        Ok(Bytes::from_static(b"partial")),
+        Err(io::Error::other("disk gone")),
+    ];
+    ChunkedResponse::from_stream(futures::stream::iter(chunks))
+}
+
+async fn connect() -> client::Client {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    client::Client::connect(addr).await.unwrap()
+}
+
+#[tokio::test]
+async fn streams_large_responses() {
+    let client = connect().await;
+    let len = 10 * 1024 * 1024;
+
+    let mut reader = client
+        .call_chunked(AppRequest::Download(Download { len }))
+        .await
+        .unwrap();
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body).await.unwrap();
+
+    assert_eq!(body.len(), len);
+    for (i, chunk) in body.chunks(CHUNK).enumerate() {
+        assert!(chunk.iter().all(|&b| b == i as u8), "chunk {i} is corrupt");
+    }
+}
+
+#[tokio::test]
+async fn fails_on_stream_error() {
+    let client = connect().await;
+
+    let mut reader = client
+        .call_chunked(AppRequest::Broken(Broken {}))
+        .await
+        .unwrap();
+    let mut body = Vec::new();
+    let err = reader.read_to_end(&mut body).await.unwrap_err();
+
+    assert_eq!(body, b"partial");
+    let err = err
+        .into_inner()
+        .unwrap()
+        .downcast::<client::Error>()
+        .unwrap();
+    assert!(
+        matches!(*err, client::Error::Rpc(protocol::RpcError::Handler(ref msg)) if msg == "disk gone"),
+        "{err:?}"
+    );
+}
+
+#[tokio::test]
+async fn dropping_the_reader_cancels() {
+    let client = connect().await;
+
+    let mut reader = client
+        .call_chunked(AppRequest::Download(Download {
+            len: 10 * 1024 * 1024,
+        }))
+        .await
+        .unwrap();
+    let mut start = [0; 16];
+    reader.read_exact(&mut start).await.unwrap();
+    drop(reader);
+
+    // The connection is still good for the next call.
+    let mut reader = client
+        .call_chunked(AppRequest::Download(Download { len: 10 }))
+        .await
+        .unwrap();
+    let mut body = Vec::new();
+    reader.read_to_end(&mut body).await.unwrap();
+    assert_eq!(body, [0; 10]);
+    drop(reader);
+    assert_eq!(client.in_flight(), 0);
+}
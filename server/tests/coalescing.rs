@@ -0,0 +1,154 @@
+//! Responses ready in a burst written to the socket together.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, WriteCoalescing, handle_connection};
+
+use macros::{request, rpc};
+
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio_util::sync::CancellationToken;
+
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+}
+
+#[request]
+fn Add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+/// A transport counting the writes made to it, each of which would be a
+/// syscall on a socket.
+struct CountingWrites {
+    inner: DuplexStream,
+    writes: Arc<AtomicUsize>,
+}
+
+impl AsyncRead for CountingWrites {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CountingWrites {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let written = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if written.is_ready() {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+        }
+        written
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// A client connected to a server configured with `coalescing`, and the
+/// count of the server's writes.
+async fn connect(coalescing: Option<WriteCoalescing>) -> (client::Client, Arc<AtomicUsize>) {
+    let (server_end, client_end) = tokio::io::duplex(1024 * 1024);
+    let writes = Arc::new(AtomicUsize::new(0));
+    let server_end = CountingWrites {
+        inner: server_end,
+        writes: writes.clone(),
+    };
+    tokio::spawn(async move {
+        let config = ConnectionConfig {
+            write_coalescing: coalescing,
+            ..ConnectionConfig::default()
+        };
+        handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+    });
+    let client = client::Client::builder()
+        .handshake(client_end)
+        .await
+        .unwrap();
+    (client, writes)
+}
+
+/// Makes `n` calls at once, checking every response.
+async fn burst(client: &client::Client, n: i32) {
+    let calls = (0..n).map(|a| client.call(AppRequest::Add(Add { a, b: 1 })));
+    for (a, resp) in (0..n).zip(futures::future::join_all(calls).await) {
+        assert!(
+            matches!(resp, Ok(AppResponse::Add(sum)) if sum == a + 1),
+            "{resp:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn bursts_of_responses_share_writes() {
+    let (uncoalesced, writes_each) = connect(None).await;
+    burst(&uncoalesced, 64).await;
+
+    let (coalesced, writes_together) = connect(Some(WriteCoalescing {
+        flush_interval: Duration::from_millis(20),
+        flush_bytes: 64 * 1024,
+    }))
+    .await;
+    burst(&coalesced, 64).await;
+
+    let each = writes_each.load(Ordering::Relaxed);
+    let together = writes_together.load(Ordering::Relaxed);
+    assert!(each > 64, "{each}");
+    assert!(
+        together * 4 <= each,
+        "{together} writes coalesced, {each} not"
+    );
+}
+
+#[tokio::test]
+async fn responses_wait_no_longer_than_the_interval() {
+    let interval = Duration::from_millis(50);
+    let (client, _) = connect(Some(WriteCoalescing {
+        flush_interval: interval,
+        flush_bytes: 64 * 1024,
+    }))
+    .await;
+
+    let started = Instant::now();
+    let resp = client.call(AppRequest::Add(Add { a: 2, b: 3 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Add(5))), "{resp:?}");
+    let waited = started.elapsed();
+    assert!(waited >= interval, "{waited:?}");
+    assert!(waited < interval * 10, "{waited:?}");
+}
+
+#[tokio::test]
+async fn full_buffers_are_written_right_away() {
+    let (client, _) = connect(Some(WriteCoalescing {
+        flush_interval: Duration::from_secs(60),
+        flush_bytes: 1,
+    }))
+    .await;
+
+    let resp = tokio::time::timeout(
+        Duration::from_secs(5),
+        client.call(AppRequest::Add(Add { a: 2, b: 3 })),
+    )
+    .await;
+    assert!(matches!(resp, Ok(Ok(AppResponse::Add(5)))), "{resp:?}");
+}
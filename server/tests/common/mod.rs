@@ -0,0 +1,34 @@
+//! Fixtures shared by the tests, each of which uses only some of them.
+
+#![allow(dead_code)]
+
+use server::{Router, ServerBuilder, ShutdownHandle};
+
+use protocol::Request;
+
+use std::net::SocketAddr;
+
+/// Serves `Req` with `builder` on an ephemeral port of localhost, for as
+/// long as the test runs.
+pub async fn serve<Req: Request>(builder: ServerBuilder) -> SocketAddr {
+    serve_with_shutdown::<Req>(builder).await.0
+}
+
+/// Like [`serve`], also returning the handle to shut the server down with.
+pub async fn serve_with_shutdown<Req: Request>(
+    builder: ServerBuilder,
+) -> (SocketAddr, ShutdownHandle) {
+    let server = builder.bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle();
+    tokio::spawn(server.run::<Req>());
+    (addr, handle)
+}
+
+/// Like [`serve`], handling requests with `router`.
+pub async fn serve_router(builder: ServerBuilder, router: Router) -> SocketAddr {
+    let server = builder.bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.serve(router));
+    addr
+}
@@ -0,0 +1,152 @@
+//! Servers compressing the bodies of large responses only, flagging each one
+//! in its header.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::Request;
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::compression::{Compression, Deflate};
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+use protocol::hello::{Hello, HelloAck};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Blob(Blob),
+}
+
+#[request]
+fn Blob(len: usize) -> Vec<u8> {
+    vec![7; len]
+}
+
+const MIN_BYTES: usize = 256;
+
+/// [`Deflate`], counting the bodies it decompresses.
+#[derive(Debug, Clone, Default)]
+struct Counted {
+    decompressed: Arc<AtomicUsize>,
+}
+
+impl Compression for Counted {
+    fn name(&self) -> &str {
+        Deflate.name()
+    }
+
+    fn compress(&self, body: &[u8]) -> Vec<u8> {
+        Deflate.compress(body)
+    }
+
+    fn decompress(&self, body: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+        let body = Deflate.decompress(body, limit)?;
+        self.decompressed.fetch_add(1, Ordering::Relaxed);
+        Ok(body)
+    }
+}
+
+async fn serve() -> SocketAddr {
+    let builder = Server::builder()
+        .compression(Deflate)
+        .compress_min_bytes(Some(MIN_BYTES));
+    common::serve::<AppRequest>(builder).await
+}
+
+#[tokio::test]
+async fn only_large_bodies_are_compressed() {
+    let addr = serve().await;
+    let codec = WireFormat::FALLBACK;
+
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    let hello = Hello {
+        compressions: vec!["deflate".into()],
+        ..Hello::default()
+    };
+    framed.send(Bytes::from(hello.encode())).await.unwrap();
+    let ack = HelloAck::decode(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(ack.compression.as_deref(), Some("deflate"));
+
+    for (id, len) in [(1, 16), (2, 4096)] {
+        let req = AppRequest::Blob(Blob { len });
+        let frame = encode_frame(&codec, &Header::request(id, Blob::NAME, 1), &req).unwrap();
+        framed.send(Bytes::from(frame)).await.unwrap();
+    }
+
+    let mut compressed = Vec::new();
+    for _ in 0..2 {
+        let frame = framed.next().await.unwrap().unwrap();
+        let (header, body) = decode_header(&codec, &frame).unwrap();
+        assert_eq!(header.kind, FrameKind::Response);
+        if header.compressed {
+            let body = Deflate.decompress(body, usize::MAX).unwrap();
+            let resp: AppResponse = codec.decode(&body).unwrap();
+            assert!(
+                matches!(&resp, AppResponse::Blob(b) if b.len() == 4096),
+                "{resp:?}"
+            );
+        }
+        compressed.push((header.id, header.compressed));
+    }
+    compressed.sort_unstable();
+    assert_eq!(compressed, [(1, false), (2, true)]);
+}
+
+#[tokio::test]
+async fn clients_take_compressed_and_uncompressed_responses_alike() {
+    let addr = serve().await;
+    let deflate = Counted::default();
+    let client = client::Client::builder()
+        .compression(deflate.clone())
+        .connect(addr)
+        .await
+        .unwrap();
+
+    for len in [16, 4096, 0, MIN_BYTES * 2, 1] {
+        let resp = client.call(AppRequest::Blob(Blob { len })).await;
+        assert!(
+            matches!(&resp, Ok(AppResponse::Blob(b)) if *b == vec![7; len]),
+            "{resp:?}"
+        );
+    }
+    assert_eq!(deflate.decompressed.load(Ordering::Relaxed), 2);
+}
+
+#[tokio::test]
+async fn clients_without_the_compression_get_uncompressed_responses() {
+    let addr = serve().await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client.call(AppRequest::Blob(Blob { len: 4096 })).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Blob(b)) if b.len() == 4096),
+        "{resp:?}"
+    );
+}
+
+#[test]
+fn deflate_refuses_bodies_over_the_limit() {
+    let body = vec![7; 4096];
+    let compressed = Deflate.compress(&body);
+    assert!(compressed.len() < body.len());
+
+    assert_eq!(Deflate.decompress(&compressed, 4096).unwrap(), body);
+    let err = Deflate.decompress(&compressed, 4095).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    let err = Deflate.decompress(b"not deflate", 4096).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
@@ -0,0 +1,116 @@
+//! Starting a server from a deserialized `ServerConfig`.
+
+#![allow(non_snake_case)]
+
+use server::{BindPolicy, ExecutionMode, OverLimit, Server, ServerConfig};
+
+use protocol::RpcError;
+use protocol::codec::WireFormat;
+
+use macros::{request, rpc};
+
+use std::collections::HashMap;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Blob(Blob),
+}
+
+#[request]
+fn Blob(len: usize) -> Vec<u8> {
+    vec![7; len]
+}
+
+const SAMPLE: &str = r#"{
+    "address": "127.0.0.1:0",
+    "wire_formats": ["json"],
+    "max_in_flight": 8,
+    "max_response_bytes": 1024,
+    "concurrency_limits": { "Blob": 2 },
+    "over_limit": "reject",
+    "execution_modes": { "Blob": "blocking" },
+    "log_every": 100,
+    "priority_max_in_flight": 4,
+    "keepalive_secs": 30
+}"#;
+
+#[test]
+fn missing_fields_take_the_defaults() {
+    let config: ServerConfig = serde_json::from_str(SAMPLE).unwrap();
+
+    assert_eq!(config.address, "127.0.0.1:0");
+    assert!(config.addresses.is_empty());
+    assert_eq!(config.bind_policy, BindPolicy::All);
+    assert_eq!(config.wire_formats, [WireFormat::Json]);
+    assert_eq!(config.max_in_flight, 8);
+    assert_eq!(config.max_response_bytes, Some(1024));
+    assert_eq!(
+        config.concurrency_limits,
+        HashMap::from([("Blob".into(), 2)])
+    );
+    assert_eq!(config.over_limit, OverLimit::Reject);
+    assert_eq!(
+        config.execution_modes,
+        HashMap::from([("Blob".into(), ExecutionMode::Blocking)])
+    );
+    assert_eq!(config.log_every, 100);
+    assert!(config.log_every_per_type.is_empty());
+    assert_eq!(config.priority_max_in_flight, Some(4));
+    assert_eq!(config.keepalive_secs, Some(30));
+
+    let defaults = ServerConfig::default();
+    assert_eq!(config.max_frame_bytes, defaults.max_frame_bytes);
+    assert_eq!(config.write_queue, defaults.write_queue);
+    assert_eq!(config.backlog, defaults.backlog);
+    assert!(config.server_info && config.nodelay);
+}
+
+#[test]
+fn rejects_unknown_fields() {
+    let res = serde_json::from_str::<ServerConfig>(r#"{ "max_in_fligth": 8 }"#);
+    assert!(res.is_err(), "{res:?}");
+}
+
+#[tokio::test]
+async fn serves_with_the_configured_options() {
+    let config = serde_json::from_str(SAMPLE).unwrap();
+    let server = Server::from_config(config).await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let client = client::Client::connect(addr).await.unwrap();
+    let small = client.call(AppRequest::Blob(Blob { len: 16 })).await;
+    assert!(matches!(small, Ok(AppResponse::Blob(_))), "{small:?}");
+
+    let large = client.call(AppRequest::Blob(Blob { len: 4096 })).await;
+    assert!(
+        matches!(
+            large,
+            Err(client::Error::Rpc(RpcError::ResponseTooLarge {
+                limit: 1024
+            }))
+        ),
+        "{large:?}"
+    );
+}
+
+#[tokio::test]
+async fn listens_on_every_configured_address() {
+    let config = serde_json::from_str(
+        r#"{
+            "addresses": ["127.0.0.1:0", "localhost:0"],
+            "bind_policy": "any"
+        }"#,
+    )
+    .unwrap();
+    let server = Server::from_config(config).await.unwrap();
+    let addrs = server.local_addrs().unwrap();
+    assert!(addrs.len() >= 2, "{addrs:?}");
+    tokio::spawn(server.run::<AppRequest>());
+
+    for addr in addrs {
+        let client = client::Client::connect(addr).await.unwrap();
+        let resp = client.call(AppRequest::Blob(Blob { len: 1 })).await;
+        assert!(matches!(resp, Ok(AppResponse::Blob(_))), "{resp:?}");
+    }
+}
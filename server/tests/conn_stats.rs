@@ -0,0 +1,109 @@
+//! Counters for a single connection, asked for over that connection.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::Context;
+
+use macros::{request, rpc};
+
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+    Hang(Hang),
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+#[request]
+async fn Hang(ctx: Context) {
+    ctx.cancelled().await;
+}
+
+#[tokio::test]
+async fn counts_what_went_over_the_connection() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let fresh = client.conn_stats().await.unwrap();
+    assert_eq!((fresh.requests_served, fresh.in_flight), (0, 0));
+    assert!(fresh.bytes_in > 0, "{fresh:?}");
+
+    for i in 0..5 {
+        let text = "x".repeat(100 * i);
+        let resp = client.call(AppRequest::Echo(Echo { text })).await;
+        assert!(matches!(resp, Ok(AppResponse::Echo(_))), "{resp:?}");
+    }
+    let stats = client.conn_stats().await.unwrap();
+    assert_eq!((stats.requests_served, stats.in_flight), (5, 0));
+    // At least the 1000 bytes of text each way, on top of what came before.
+    assert!(stats.bytes_in >= fresh.bytes_in + 1000, "{stats:?}");
+    assert!(stats.bytes_out >= fresh.bytes_out + 1000, "{stats:?}");
+    assert!(stats.uptime_ms >= fresh.uptime_ms, "{stats:?}");
+
+    // Every connection counts for itself.
+    let other = client::Client::connect(addr).await.unwrap();
+    let stats = other.conn_stats().await.unwrap();
+    assert_eq!(stats.requests_served, 0, "{stats:?}");
+}
+
+#[tokio::test]
+async fn counts_requests_still_running() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let hanging = tokio::spawn({
+        let client = client.clone();
+        async move { client.call(AppRequest::Hang(Hang {})).await }
+    });
+    let stats = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let stats = client.conn_stats().await.unwrap();
+            if stats.in_flight > 0 {
+                return stats;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("request never started");
+    assert_eq!((stats.requests_served, stats.in_flight), (0, 1));
+
+    // Cancelling lets the handler finish, which answers it.
+    hanging.abort();
+    let stats = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let stats = client.conn_stats().await.unwrap();
+            if stats.in_flight == 0 {
+                return stats;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("request never ended");
+    assert_eq!(stats.requests_served, 1, "{stats:?}");
+}
+
+#[tokio::test]
+async fn goes_unanswered_with_server_info_off() {
+    let server = Server::builder()
+        .server_info(false)
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let stats = client.conn_stats().await;
+    assert!(matches!(stats, Err(client::Error::Rpc(_))), "{stats:?}");
+}
@@ -0,0 +1,89 @@
+//! Handlers working to the deadline callers set, and aborted past it.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::{Context, RpcError};
+
+use macros::{request, rpc};
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Budget(Budget),
+    Stuck(Stuck),
+}
+
+/// What is left of the deadline, in milliseconds.
+#[request]
+fn Budget(ctx: Context) -> Option<u64> {
+    ctx.deadline_remaining()
+        .map(|remaining| remaining.as_millis() as u64)
+}
+
+/// Set once a `Stuck` handler is dropped without finishing.
+static ABORTED: AtomicBool = AtomicBool::new(false);
+
+#[request]
+async fn Stuck() -> u8 {
+    struct Aborted;
+    impl Drop for Aborted {
+        fn drop(&mut self) {
+            ABORTED.store(true, Ordering::Relaxed);
+        }
+    }
+    let aborted = Aborted;
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    std::mem::forget(aborted);
+    1
+}
+
+#[tokio::test]
+async fn handlers_see_what_is_left_of_the_deadline() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client
+        .call_with_deadline(AppRequest::Budget(Budget {}), Duration::from_secs(10))
+        .await;
+    assert!(
+        matches!(resp, Ok(AppResponse::Budget(Some(ms))) if ms > 9_000 && ms <= 10_000),
+        "{resp:?}"
+    );
+    let resp = client.call(AppRequest::Budget(Budget {})).await;
+    assert!(matches!(resp, Ok(AppResponse::Budget(None))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn handlers_past_the_deadline_are_aborted() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client
+        .call_with_deadline(AppRequest::Stuck(Stuck {}), Duration::from_millis(50))
+        .await;
+    assert!(
+        matches!(
+            &resp,
+            Err(client::Error::Rpc(RpcError::DeadlineExceeded(name))) if name == "Stuck"
+        ),
+        "{resp:?}"
+    );
+    for _ in 0..100 {
+        if ABORTED.load(Ordering::Relaxed) {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(ABORTED.load(Ordering::Relaxed));
+
+    let resp = client
+        .call_with_deadline(AppRequest::Budget(Budget {}), Duration::from_secs(1))
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Budget(Some(_)))), "{resp:?}");
+}
@@ -0,0 +1,111 @@
+//! Requests `#[request]` can't declare, like generic ones, with the trait
+//! implemented by `#[derive(Request)]` for handlers kept elsewhere.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::{Context, Request, RpcError};
+
+use macros::{Request, rpc};
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use std::net::SocketAddr;
+use std::ops::Add;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Sum(Sum<i64>),
+    Shout(Shout),
+}
+
+#[derive(Debug, Encode, Decode, Serialize, Deserialize, Request)]
+#[resp(T)]
+#[handler(sum)]
+struct Sum<T: Add<Output = T> + Default> {
+    values: Vec<T>,
+}
+
+async fn sum<T: Add<Output = T> + Default>(req: Sum<T>, _ctx: Context) -> T {
+    req.values
+        .into_iter()
+        .fold(T::default(), |sum, value| sum + value)
+}
+
+#[derive(Debug, Encode, Decode, Serialize, Deserialize, Request)]
+#[resp(String)]
+#[handler(handlers::shout)]
+#[validate(handlers::not_empty)]
+#[request_args(name = "text.shout", version = 2, idempotent)]
+struct Shout {
+    text: String,
+}
+
+mod handlers {
+    use super::Shout;
+
+    use protocol::Context;
+
+    pub async fn shout(req: Shout, _ctx: Context) -> String {
+        req.text.to_uppercase()
+    }
+
+    pub async fn not_empty(req: &Shout) -> Result<(), &'static str> {
+        if req.text.is_empty() {
+            return Err("nothing to shout");
+        }
+        Ok(())
+    }
+}
+
+async fn serve() -> SocketAddr {
+    common::serve::<AppRequest>(Server::builder().max_rejected_requests(8)).await
+}
+
+#[tokio::test]
+async fn derived_requests_reach_their_handlers() {
+    let client = client::Client::connect(serve().await).await.unwrap();
+
+    let resp = client
+        .call(AppRequest::Sum(Sum {
+            values: vec![1, 2, 39],
+        }))
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Sum(42))), "{resp:?}");
+
+    let resp = client
+        .call(AppRequest::Shout(Shout { text: "hi".into() }))
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Shout(text)) if text == "HI"),
+        "{resp:?}"
+    );
+    let resp = client
+        .call(AppRequest::Shout(Shout { text: "".into() }))
+        .await;
+    assert!(
+        matches!(&resp, Err(client::Error::Rpc(RpcError::ValidationFailed(msg))) if msg == "nothing to shout"),
+        "{resp:?}"
+    );
+}
+
+#[test]
+fn derived_requests_take_their_arguments() {
+    assert_eq!(Sum::<u8>::NAME, "Sum");
+    assert_eq!(
+        (Shout::NAME, Shout::VERSION, Shout::IDEMPOTENT),
+        ("text.shout", 2, true)
+    );
+
+    let schema = Sum::<u8>::schema();
+    let method = &schema.methods[0];
+    assert_eq!(
+        (method.args[0].name.as_str(), method.args[0].ty.as_str()),
+        ("values", "Vec<T>")
+    );
+    assert_eq!(method.response, "T");
+}
@@ -0,0 +1,154 @@
+//! Connections drained on shutdown: clients are told first, and still get
+//! the responses to the requests already read.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::{Server, ShutdownHandle};
+
+use protocol::Request;
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{
+    FrameKind, Framing, GoAway, GoAwayReason, Header, decode_header, encode_frame,
+};
+use protocol::hello::{DRAINING_FEATURE, Hello, HelloAck};
+
+use client::ConnectionState;
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio::sync::Notify;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Slow(Slow),
+}
+
+/// One pair per test, so they don't release each other's handlers.
+static STARTED: [Notify; 3] = [
+    Notify::const_new(),
+    Notify::const_new(),
+    Notify::const_new(),
+];
+static RELEASED: [Notify; 3] = [
+    Notify::const_new(),
+    Notify::const_new(),
+    Notify::const_new(),
+];
+
+/// Returns `gate` once it is released.
+#[request]
+async fn Slow(gate: usize) -> usize {
+    STARTED[gate].notify_one();
+    RELEASED[gate].notified().await;
+    gate
+}
+
+async fn serve(drain_timeout: Duration) -> (SocketAddr, ShutdownHandle) {
+    let builder = Server::builder().drain_timeout(Some(drain_timeout));
+    common::serve_with_shutdown::<AppRequest>(builder).await
+}
+
+/// A connection taking `Draining` frames, with a `Slow` request for `gate`
+/// running on it.
+async fn connect_slow(addr: SocketAddr, gate: usize) -> Framed<TcpStream, LengthDelimitedCodec> {
+    let codec = WireFormat::FALLBACK;
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    let hello = Hello {
+        features: vec![DRAINING_FEATURE.into()],
+        ..Hello::default()
+    };
+    framed.send(Bytes::from(hello.encode())).await.unwrap();
+    let ack = HelloAck::decode(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert!(ack.has_feature(DRAINING_FEATURE));
+
+    let req = AppRequest::Slow(Slow { gate });
+    let frame = encode_frame(&codec, &Header::request(1, Slow::NAME, 1), &req).unwrap();
+    framed.send(Bytes::from(frame)).await.unwrap();
+    STARTED[gate].notified().await;
+    framed
+}
+
+#[tokio::test]
+async fn draining_is_announced_before_the_last_responses() {
+    let (addr, handle) = serve(Duration::from_secs(10)).await;
+    let codec = WireFormat::FALLBACK;
+    let mut framed = connect_slow(addr, 0).await;
+
+    let shutdown = tokio::spawn(async move { handle.shutdown().await });
+
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, _) = decode_header(&codec, &frame).unwrap();
+    assert_eq!((header.id, header.kind), (0, FrameKind::Draining));
+
+    RELEASED[0].notify_one();
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&codec, &frame).unwrap();
+    assert_eq!((header.id, header.kind), (1, FrameKind::Response));
+    let resp: AppResponse = codec.decode(body).unwrap();
+    assert!(matches!(resp, AppResponse::Slow(0)), "{resp:?}");
+
+    assert!(framed.next().await.is_none());
+    shutdown.await.unwrap();
+}
+
+#[tokio::test]
+async fn requests_still_running_past_the_drain_timeout_are_dropped() {
+    let (addr, handle) = serve(Duration::from_millis(50)).await;
+    let codec = WireFormat::FALLBACK;
+    let mut framed = connect_slow(addr, 1).await;
+
+    handle.shutdown().await;
+
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, _) = decode_header(&codec, &frame).unwrap();
+    assert_eq!(header.kind, FrameKind::Draining);
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&codec, &frame).unwrap();
+    assert_eq!(header.kind, FrameKind::GoAway);
+    let go_away: GoAway = codec.decode(body).unwrap();
+    assert_eq!(go_away.reason, GoAwayReason::Shutdown);
+
+    assert!(framed.next().await.is_none());
+}
+
+#[tokio::test]
+async fn clients_see_the_server_draining() {
+    let (addr, handle) = serve(Duration::from_secs(10)).await;
+    let client = client::Client::connect(addr).await.unwrap();
+    let mut states = client.state_changes();
+    assert_eq!(client.state(), ConnectionState::Open);
+
+    let call = tokio::spawn({
+        let client = client.clone();
+        async move { client.call(AppRequest::Slow(Slow { gate: 2 })).await }
+    });
+    STARTED[2].notified().await;
+    let shutdown = tokio::spawn(async move { handle.shutdown().await });
+
+    states.changed().await.unwrap();
+    assert_eq!(*states.borrow_and_update(), ConnectionState::Draining);
+    assert!(client.is_closed());
+    let resp = client.call(AppRequest::Slow(Slow { gate: 2 })).await;
+    assert!(
+        matches!(resp, Err(client::Error::ServerShuttingDown)),
+        "{resp:?}"
+    );
+
+    RELEASED[2].notify_one();
+    let resp = call.await.unwrap();
+    assert!(matches!(resp, Ok(AppResponse::Slow(2))), "{resp:?}");
+
+    states.changed().await.unwrap();
+    assert_eq!(*states.borrow_and_update(), ConnectionState::Closed);
+    shutdown.await.unwrap();
+}
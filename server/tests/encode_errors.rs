@@ -0,0 +1,81 @@
+//! Responses that fail to encode fail their own request, not the connection.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::codec::WireFormat;
+use protocol::{Response, RpcError};
+
+use macros::{request, rpc};
+
+use bincode::enc::Encoder;
+use bincode::error::EncodeError;
+use serde::{Deserialize, Serialize, Serializer};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Broken(Broken),
+    Echo(Echo),
+}
+
+/// A response neither wire format can encode.
+#[derive(Debug, bincode::Decode, Deserialize)]
+struct Unencodable;
+
+impl bincode::Encode for Unencodable {
+    fn encode<E: Encoder>(&self, _: &mut E) -> Result<(), EncodeError> {
+        Err(EncodeError::Other("unencodable"))
+    }
+}
+
+impl Serialize for Unencodable {
+    fn serialize<S: Serializer>(&self, _: S) -> Result<S::Ok, S::Error> {
+        Err(serde::ser::Error::custom("unencodable"))
+    }
+}
+
+impl Response for Unencodable {}
+
+#[request]
+fn Broken() -> Unencodable {
+    Unencodable
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+#[tokio::test]
+async fn connection_survives_encode_failures() {
+    let server = Server::builder()
+        .wire_formats([WireFormat::Bincode, WireFormat::Json])
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    for format in [WireFormat::Bincode, WireFormat::Json] {
+        let client = client::Client::builder()
+            .wire_formats([format])
+            .connect(addr)
+            .await
+            .unwrap();
+
+        let resp = client.call(AppRequest::Broken(Broken {})).await;
+        assert!(
+            matches!(&resp, Err(client::Error::Rpc(RpcError::Internal(msg))) if msg.starts_with("failed to encode response")),
+            "{format:?}: {resp:?}"
+        );
+
+        let resp = client
+            .call(AppRequest::Echo(Echo { text: "hi".into() }))
+            .await;
+        assert!(
+            matches!(&resp, Ok(AppResponse::Echo(s)) if s == "hi"),
+            "{format:?}: {resp:?}"
+        );
+    }
+}
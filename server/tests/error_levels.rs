@@ -0,0 +1,165 @@
+//! Errors logged at the level picked for them.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, LoggedError, Server, default_level, handle_connection};
+
+use protocol::RpcError;
+use protocol::frame::Framing;
+use protocol::hello::{Hello, HelloAck};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::AsyncWriteExt;
+use tokio_util::codec::Framed;
+use tokio_util::sync::CancellationToken;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::{Layer, Registry};
+
+use std::sync::{Arc, Mutex};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Rename(Rename),
+}
+
+#[request]
+#[validate(not_empty)]
+fn Rename(name: String) -> String {
+    name
+}
+
+async fn not_empty(req: &Rename) -> Result<(), String> {
+    if req.name.is_empty() {
+        return Err("no name".into());
+    }
+    Ok(())
+}
+
+/// Every event logged, as its level and message.
+#[derive(Clone, Default)]
+struct Events(Arc<Mutex<Vec<(Level, String)>>>);
+
+struct Message(Option<String>);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+impl<S: tracing::Subscriber> Layer<S> for Events {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        let mut message = Message(None);
+        event.record(&mut message);
+        if let Some(message) = message.0 {
+            let level = *event.metadata().level();
+            self.0.lock().unwrap().push((level, message));
+        }
+    }
+}
+
+impl Events {
+    /// The levels `message` was logged at.
+    fn levels(&self, message: &str) -> Vec<Level> {
+        let events = self.0.lock().unwrap();
+        events
+            .iter()
+            .filter(|(_, m)| m == message)
+            .map(|&(level, _)| level)
+            .collect()
+    }
+
+    fn errors(&self) -> Vec<String> {
+        let events = self.0.lock().unwrap();
+        events
+            .iter()
+            .filter(|(level, _)| *level == Level::ERROR)
+            .map(|(_, m)| m.clone())
+            .collect()
+    }
+}
+
+#[tokio::test]
+async fn disconnects_log_below_error() {
+    let events = Events::default();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(events.clone()));
+
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let serving = tokio::spawn(async move {
+        let config = ConnectionConfig::default();
+        handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+    });
+    let mut framed = Framed::new(client_end, Framing::default().codec());
+    framed
+        .send(Bytes::from(Hello::default().encode()))
+        .await
+        .unwrap();
+    HelloAck::decode(&framed.next().await.unwrap().unwrap()).unwrap();
+
+    // Gone halfway through a frame.
+    let mut client_end = framed.into_inner();
+    client_end.write_all(&100u32.to_be_bytes()).await.unwrap();
+    client_end.write_all(&[0; 10]).await.unwrap();
+    drop(client_end);
+
+    let res = serving.await.unwrap();
+    assert!(res.is_ok(), "{res:?}");
+    assert_eq!(events.levels("client disconnected"), [Level::INFO]);
+    assert!(events.errors().is_empty(), "{:?}", events.errors());
+}
+
+#[tokio::test]
+async fn embedders_pick_the_levels() {
+    let events = Events::default();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(events.clone()));
+
+    let server = Server::builder()
+        .error_levels(|err| match err {
+            LoggedError::Rpc(RpcError::ValidationFailed(_)) => Level::DEBUG,
+            err => default_level(err),
+        })
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client
+        .call(AppRequest::Rename(Rename {
+            name: String::new(),
+        }))
+        .await;
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::ValidationFailed(_)))),
+        "{resp:?}"
+    );
+    assert_eq!(events.levels("request failed validation"), [Level::DEBUG]);
+    assert!(events.errors().is_empty(), "{:?}", events.errors());
+}
+
+#[test]
+fn client_errors_are_quiet_by_default() {
+    let quiet = [
+        RpcError::ValidationFailed("no name".into()),
+        RpcError::Unauthorized("nobody".into()),
+        RpcError::UnknownMethod("Nope".into()),
+    ];
+    for err in &quiet {
+        assert_eq!(default_level(err.into()), Level::INFO, "{err:?}");
+    }
+
+    let loud = RpcError::Internal("handler for Rename panicked".into());
+    assert_eq!(default_level((&loud).into()), Level::ERROR);
+    let disconnect = server::Error::Io(std::io::ErrorKind::ConnectionReset.into());
+    assert_eq!(default_level((&disconnect).into()), Level::INFO);
+    let broken = server::Error::Io(std::io::ErrorKind::PermissionDenied.into());
+    assert_eq!(default_level((&broken).into()), Level::ERROR);
+}
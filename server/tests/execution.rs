@@ -0,0 +1,71 @@
+//! CPU-bound handlers moved off the runtime's workers.
+
+#![allow(non_snake_case)]
+
+use server::{ExecutionMode, Server};
+
+use protocol::Request;
+
+use macros::{request, rpc};
+
+use std::time::{Duration, Instant};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Hash(Hash),
+    Ping(Ping),
+}
+
+const HASHING: Duration = Duration::from_millis(500);
+
+/// Keeps its thread busy for [`HASHING`], never yielding.
+#[request]
+fn Hash(seed: u64) -> u64 {
+    let started = Instant::now();
+    let mut hash = seed;
+    while started.elapsed() < HASHING {
+        hash = hash.wrapping_mul(0x100000001b3) ^ 0xcbf29ce484222325;
+    }
+    hash
+}
+
+#[request]
+fn Ping() -> String {
+    "pong".into()
+}
+
+// A single thread runs the server and both clients, so a handler blocking it
+// would hold up the ping until the hash is done.
+#[tokio::test(flavor = "current_thread")]
+async fn blocking_handlers_leave_the_reactor_responsive() {
+    let server = Server::builder()
+        .execution_modes([(Hash::NAME, ExecutionMode::Blocking)])
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let hasher = client::Client::connect(addr).await.unwrap();
+    let pinger = client::Client::connect(addr).await.unwrap();
+
+    let hashing =
+        tokio::spawn(async move { hasher.call(AppRequest::Hash(Hash { seed: 1 })).await });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let started = Instant::now();
+    let resp = pinger.call(AppRequest::Ping(Ping {})).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Ping(s)) if s == "pong"),
+        "{resp:?}"
+    );
+    assert!(
+        started.elapsed() < HASHING / 2,
+        "ping took {:?}",
+        started.elapsed()
+    );
+    assert!(!hashing.is_finished());
+
+    let resp = hashing.await.unwrap();
+    assert!(matches!(resp, Ok(AppResponse::Hash(_))), "{resp:?}");
+}
@@ -0,0 +1,104 @@
+//! Requests over a cap shared by every connection, let in by turns.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::{FairScheduler, FairnessPolicy, Fifo, Server, Waiting};
+
+use macros::{request, rpc};
+
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Work(Work),
+}
+
+/// Who ran, in the order they did.
+static RAN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[request]
+async fn Work(who: String) {
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    RAN.lock().unwrap().push(who);
+}
+
+async fn serve(scheduler: &FairScheduler) -> SocketAddr {
+    common::serve::<AppRequest>(Server::builder().scheduler(scheduler.clone())).await
+}
+
+/// Floods the server with 20 requests from `flooder` on one connection,
+/// then sends one from `other` on another once the flood is queued,
+/// returning how many of the flood ran before it.
+async fn flood_then_call(scheduler: &FairScheduler, flooder: &str, other: &str) -> usize {
+    let addr = serve(scheduler).await;
+    let flooding = client::Client::connect(addr).await.unwrap();
+    let calling = client::Client::connect(addr).await.unwrap();
+
+    let flood: Vec<_> = (0..20)
+        .map(|_| {
+            let client = flooding.clone();
+            let who = flooder.to_owned();
+            tokio::spawn(async move { client.call(AppRequest::Work(Work { who })).await })
+        })
+        .collect();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while scheduler.queued() < 19 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("flood never queued");
+
+    let resp = calling
+        .call(AppRequest::Work(Work { who: other.into() }))
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Work(()))), "{resp:?}");
+    let before = RAN
+        .lock()
+        .unwrap()
+        .iter()
+        .take_while(|who| *who != other)
+        .filter(|who| *who == flooder)
+        .count();
+
+    for resp in flood {
+        let resp = resp.await.unwrap();
+        assert!(matches!(resp, Ok(AppResponse::Work(()))), "{resp:?}");
+    }
+    assert_eq!((scheduler.running(), scheduler.queued()), (0, 0));
+    before
+}
+
+#[tokio::test]
+async fn a_flooding_connection_takes_turns() {
+    let scheduler = FairScheduler::round_robin(1);
+    let before = flood_then_call(&scheduler, "flood", "quiet").await;
+    // The one running when it came in, and at most one more.
+    assert!(before <= 2, "{before} ran first");
+}
+
+#[tokio::test]
+async fn policies_decide_who_is_next() {
+    let scheduler = FairScheduler::new(1, Fifo);
+    let before = flood_then_call(&scheduler, "fifo-flood", "fifo-quiet").await;
+    assert_eq!(before, 20);
+
+    /// Lets the connection with the fewest requests waiting in first.
+    struct Shortest;
+
+    impl FairnessPolicy for Shortest {
+        fn pick(&mut self, waiting: &[Waiting]) -> usize {
+            (0..waiting.len())
+                .min_by_key(|&i| waiting[i].queued)
+                .unwrap()
+        }
+    }
+
+    let scheduler = FairScheduler::new(1, Shortest);
+    let before = flood_then_call(&scheduler, "short-flood", "short-quiet").await;
+    assert!(before <= 2, "{before} ran first");
+}
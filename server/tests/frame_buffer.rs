@@ -0,0 +1,93 @@
+//! Read buffers sized for the frames expected, counting allocations to
+//! compare against buffers left to grow.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use macros::{request, rpc};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// Counts allocations, including growing one, on the current thread.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count() {
+    let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count();
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count();
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Upload(Upload),
+}
+
+#[request]
+fn Upload(data: Vec<u8>) -> usize {
+    data.len()
+}
+
+const MIB: usize = 1024 * 1024;
+
+/// Allocations made on this thread, by the client and the server alike, for
+/// four 1 MiB requests over a connection that has already been used once.
+async fn allocations(expected_frame_bytes: Option<usize>) -> usize {
+    let mut server = Server::builder();
+    let mut client = client::Client::builder();
+    if let Some(bytes) = expected_frame_bytes {
+        server = server.expected_frame_bytes(bytes);
+        client = client.expected_frame_bytes(bytes);
+    }
+    let server = server.bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client.connect(addr).await.unwrap();
+    client
+        .call(AppRequest::Upload(Upload { data: Vec::new() }))
+        .await
+        .unwrap();
+
+    let data = vec![7; MIB];
+    let before = ALLOCATIONS.with(Cell::get);
+    for _ in 0..4 {
+        let resp = client
+            .call(AppRequest::Upload(Upload { data: data.clone() }))
+            .await;
+        assert!(matches!(resp, Ok(AppResponse::Upload(MIB))), "{resp:?}");
+    }
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+// A runtime of its own, so everything allocates on this thread.
+#[tokio::test(flavor = "current_thread")]
+async fn hinted_buffers_allocate_less() {
+    let grown = allocations(None).await;
+    let hinted = allocations(Some(2 * MIB)).await;
+    assert!(
+        hinted < grown,
+        "{hinted} allocations with the hint, {grown} without"
+    );
+}
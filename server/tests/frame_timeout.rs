@@ -0,0 +1,135 @@
+//! Connections closed on clients trickling frames in too slowly to be doing
+//! anything but tying them up.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, Error, handle_connection};
+
+use protocol::Request;
+use protocol::codec::WireFormat;
+use protocol::frame::{Framing, Header, encode_frame};
+use protocol::hello::{Hello, HelloAck};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::task::JoinHandle;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::sync::CancellationToken;
+
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+type Peer = Framed<DuplexStream, LengthDelimitedCodec>;
+
+/// A connection to a server giving frames `timeout` to arrive, past the
+/// handshake.
+async fn connect(timeout: Duration) -> (Peer, JoinHandle<server::Result<()>>) {
+    let config = ConnectionConfig {
+        frame_body_timeout: Some(timeout),
+        ..ConnectionConfig::default()
+    };
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let serving = tokio::spawn(async move {
+        handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+    });
+
+    let mut framed = Framed::new(client_end, Framing::default().codec());
+    framed
+        .send(Bytes::from(Hello::default().encode()))
+        .await
+        .unwrap();
+    HelloAck::decode(&framed.next().await.unwrap().unwrap()).unwrap();
+    (framed, serving)
+}
+
+/// An echo request framed as sent, length prefix first.
+fn echo_frame(text: &str) -> Vec<u8> {
+    let req = AppRequest::Echo(Echo { text: text.into() });
+    let header = Header::request(1, req.name(), req.version());
+    let frame = encode_frame(&WireFormat::Bincode, &header, &req).unwrap();
+    let mut framed = u32::try_from(frame.len()).unwrap().to_be_bytes().to_vec();
+    framed.extend(frame);
+    framed
+}
+
+/// Writes `bytes` one at a time, `every` apart.
+async fn trickle(framed: &mut Peer, bytes: &[u8], every: Duration) -> std::io::Result<()> {
+    for byte in bytes {
+        framed.get_mut().write_all(&[*byte]).await?;
+        tokio::time::sleep(every).await;
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn trickled_frames_close_the_connection() {
+    let (mut framed, serving) = connect(Duration::from_millis(200)).await;
+
+    // The prefix comes whole, then the rest slower than it may.
+    let frame = echo_frame(&"x".repeat(64));
+    framed.get_mut().write_all(&frame[..4]).await.unwrap();
+    let _ = trickle(&mut framed, &frame[4..], Duration::from_millis(20)).await;
+
+    let served = tokio::time::timeout(Duration::from_secs(5), serving)
+        .await
+        .expect("connection never closed")
+        .unwrap();
+    assert!(
+        matches!(served, Err(Error::FrameTimeout(timeout)) if timeout == Duration::from_millis(200)),
+        "{served:?}"
+    );
+    let next = framed.next().await;
+    assert!(next.is_none(), "{next:?}");
+}
+
+#[tokio::test]
+async fn frames_stalled_after_their_prefix_close_the_connection() {
+    let (mut framed, serving) = connect(Duration::from_millis(100)).await;
+
+    framed
+        .get_mut()
+        .write_all(&echo_frame("hi")[..4])
+        .await
+        .unwrap();
+
+    let served = tokio::time::timeout(Duration::from_secs(5), serving)
+        .await
+        .expect("connection never closed")
+        .unwrap();
+    assert!(matches!(served, Err(Error::FrameTimeout(_))), "{served:?}");
+}
+
+#[tokio::test]
+async fn slow_frames_in_time_and_idle_connections_are_fine() {
+    let (mut framed, serving) = connect(Duration::from_millis(500)).await;
+
+    // Idle between frames for longer than a frame may take.
+    tokio::time::sleep(Duration::from_millis(700)).await;
+    trickle(&mut framed, &echo_frame("hi"), Duration::from_millis(5))
+        .await
+        .unwrap();
+    let frame = framed.next().await;
+    assert!(matches!(frame, Some(Ok(_))), "{frame:?}");
+    assert!(!serving.is_finished());
+}
+
+#[test]
+fn frames_are_waited_for_forever_by_default() {
+    assert_eq!(ConnectionConfig::default().frame_body_timeout, None);
+    assert_eq!(
+        server::ServerConfig::default().frame_body_timeout_secs,
+        None
+    );
+}
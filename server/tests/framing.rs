@@ -0,0 +1,115 @@
+//! Connections framed by a codec of the caller's choosing.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, handle_connection_with_codec};
+
+use protocol::Request;
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Header, decode_header, encode_frame};
+use protocol::hello::{Hello, HelloAck};
+
+use macros::{request, rpc};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{SinkExt, StreamExt};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+use tokio_util::sync::CancellationToken;
+
+use std::io;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+}
+
+#[request]
+fn Add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+/// Frames as netstrings: the length in decimal, a colon, the frame and a
+/// comma.
+struct Netstring;
+
+const MAX_LEN: usize = 1 << 20;
+
+impl Decoder for Netstring {
+    type Item = BytesMut;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<BytesMut>> {
+        let Some(colon) = src.iter().position(|&b| b == b':') else {
+            return Ok(None);
+        };
+        let len: usize = std::str::from_utf8(&src[..colon])
+            .ok()
+            .and_then(|len| len.parse().ok())
+            .filter(|&len| len <= MAX_LEN)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "bad netstring length"))?;
+        if src.len() < colon + 1 + len + 1 {
+            return Ok(None);
+        }
+        src.advance(colon + 1);
+        let frame = src.split_to(len);
+        if src.get_u8() != b',' {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "netstring missing its comma",
+            ));
+        }
+        Ok(Some(frame))
+    }
+}
+
+impl Encoder<Bytes> for Netstring {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        dst.put_slice(format!("{}:", frame.len()).as_bytes());
+        dst.put_slice(&frame);
+        dst.put_u8(b',');
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn serves_over_a_custom_codec() {
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let shutdown = CancellationToken::new();
+    let serving = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            let config = ConnectionConfig {
+                wire_formats: vec![WireFormat::Bincode, WireFormat::Json],
+                ..ConnectionConfig::default()
+            };
+            handle_connection_with_codec::<AppRequest, _>(server_end, Netstring, &config, shutdown)
+                .await
+        }
+    });
+
+    let mut framed = Framed::new(client_end, Netstring);
+    let hello = Hello {
+        wire_formats: vec![WireFormat::Json.id()],
+        ..Hello::default()
+    };
+    framed.send(Bytes::from(hello.encode())).await.unwrap();
+    let ack = HelloAck::decode(&framed.next().await.unwrap().unwrap()).unwrap();
+    let codec = ack.wire_format().unwrap();
+    assert_eq!(codec, WireFormat::Json);
+
+    let req = AppRequest::Add(Add { a: 2, b: 3 });
+    let header = Header::request(1, req.name(), req.version());
+    let frame = encode_frame(&codec, &header, &req).unwrap();
+    framed.send(Bytes::from(frame)).await.unwrap();
+
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&codec, &frame).unwrap();
+    assert_eq!((header.id, header.kind), (1, FrameKind::Response));
+    let resp: AppResponse = codec.decode(body).unwrap();
+    assert!(matches!(resp, AppResponse::Add(5)), "{resp:?}");
+
+    shutdown.cancel();
+    serving.await.unwrap().unwrap();
+}
@@ -0,0 +1,153 @@
+//! Feeds malformed frames to the request decoder, which has to answer every
+//! one of them without panicking or hanging.
+//!
+//! Deterministic: the inputs come from a fixed seed, mutating a corpus of
+//! valid requests, so a failure reproduces on every run.
+
+#![allow(non_snake_case)]
+
+use protocol::Request;
+use protocol::codec::{BincodeCodec, JsonCodec, WireCodec};
+use protocol::frame::{FrameKind, Header, encode_frame};
+
+use macros::{request, rpc};
+
+use std::time::Duration;
+
+const ROUNDS: usize = 5_000;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Ping(Ping),
+    Echo(Echo),
+    Sum(Sum),
+}
+
+#[request]
+fn Ping() {}
+
+#[request]
+fn Echo(text: String, tags: Vec<String>) -> String {
+    format!("{text} {tags:?}")
+}
+
+#[request]
+fn Sum(values: Vec<u64>) -> u64 {
+    values.into_iter().fold(0, u64::wrapping_add)
+}
+
+/// xorshift64*, so the inputs don't depend on anything outside this file.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// Up to `max_len` random bytes.
+    fn bytes(&mut self, max_len: usize) -> Vec<u8> {
+        let len = self.below(max_len + 1);
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+}
+
+/// Valid frames, one of every kind the decoder understands.
+fn corpus(codec: &impl WireCodec) -> Vec<Vec<u8>> {
+    let echo = AppRequest::Echo(Echo {
+        text: "hello".into(),
+        tags: vec!["a".into(), "b".into()],
+    });
+    let sum = AppRequest::Sum(Sum {
+        values: vec![1, 2, u64::MAX],
+    });
+    vec![
+        encode_frame(codec, &Header::new(1, FrameKind::Request), &echo).unwrap(),
+        encode_frame(codec, &Header::request(2, Sum::NAME, 1), &sum).unwrap(),
+        encode_frame(
+            codec,
+            &Header::request(3, Ping::NAME, 1),
+            &AppRequest::Ping(Ping {}),
+        )
+        .unwrap(),
+        encode_frame(codec, &Header::new(4, FrameKind::Batch), &vec![echo, sum]).unwrap(),
+    ]
+}
+
+/// Changes `frame` in one of the ways a broken or hostile peer might.
+fn mutate(rng: &mut Rng, mut frame: Vec<u8>) -> Vec<u8> {
+    match rng.below(5) {
+        0 => {
+            let len = rng.below(frame.len() + 1);
+            frame.truncate(len);
+        }
+        1 => {
+            for _ in 0..=rng.below(4) {
+                let i = rng.below(frame.len());
+                frame[i] ^= 1 << rng.below(8);
+            }
+        }
+        2 => {
+            let i = rng.below(frame.len());
+            frame[i] = [0x00, 0xff, 0xfb, 0xfc, 0xfd][rng.below(5)];
+        }
+        3 => {
+            let i = rng.below(frame.len() + 1);
+            let extra = rng.bytes(16);
+            frame.splice(i..i, extra);
+        }
+        _ => frame = rng.bytes(64),
+    }
+    frame
+}
+
+async fn survives(codec: &impl WireCodec, seed: u64) {
+    let corpus = corpus(codec);
+    let mut rng = Rng(seed);
+
+    for round in 0..ROUNDS {
+        let frame = corpus[rng.below(corpus.len())].clone();
+        let frame = mutate(&mut rng, frame);
+
+        let handled = tokio::time::timeout(
+            Duration::from_secs(1),
+            server::handle_request::<AppRequest>(codec, &frame),
+        )
+        .await;
+        assert!(handled.is_ok(), "round {round} hung on {frame:02x?}");
+    }
+}
+
+#[tokio::test]
+async fn valid_corpus_is_answered() {
+    for frame in corpus(&BincodeCodec) {
+        let handled = server::handle_request::<AppRequest>(&BincodeCodec, &frame).await;
+        assert!(handled.is_ok(), "{handled:?}");
+    }
+}
+
+#[tokio::test]
+async fn bincode_decoder_survives_malformed_frames() {
+    survives(&BincodeCodec, 0x5eed_0001).await;
+}
+
+#[tokio::test]
+async fn json_decoder_survives_malformed_frames() {
+    survives(&JsonCodec, 0x5eed_0002).await;
+}
+
+#[tokio::test]
+async fn random_bytes_are_rejected() {
+    let mut rng = Rng(0x5eed_0003);
+    for _ in 0..ROUNDS {
+        let frame = rng.bytes(64);
+        let handled = server::handle_request::<AppRequest>(&BincodeCodec, &frame).await;
+        assert!(handled.is_err(), "accepted {frame:02x?}");
+    }
+}
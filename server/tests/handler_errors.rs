@@ -0,0 +1,79 @@
+//! Errors returned by handlers, as the client sees them.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::RpcError;
+
+use macros::{request, rpc};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Withdraw(Withdraw),
+    Parse(Parse),
+}
+
+#[request]
+#[validate(positive)]
+fn Withdraw(amount: i64) -> Result<i64, RpcError> {
+    if amount > 100 {
+        return Err(RpcError::Unauthorized("over the daily limit".into()));
+    }
+    Ok(100 - amount)
+}
+
+async fn positive(req: &Withdraw) -> Result<(), String> {
+    if req.amount <= 0 {
+        return Err(format!("can't withdraw {}", req.amount));
+    }
+    Ok(())
+}
+
+#[request]
+fn Parse(text: String) -> Result<i32, std::num::ParseIntError> {
+    text.parse()
+}
+
+#[tokio::test]
+async fn handler_errors_come_back_in_the_response() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client
+        .call(AppRequest::Withdraw(Withdraw { amount: 30 }))
+        .await;
+    assert!(
+        matches!(resp, Ok(AppResponse::Withdraw(Ok(70)))),
+        "{resp:?}"
+    );
+
+    // An `RpcError` comes through as is.
+    let resp = client
+        .call(AppRequest::Withdraw(Withdraw { amount: 500 }))
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Withdraw(Err(RpcError::Unauthorized(msg)))) if msg == "over the daily limit"),
+        "{resp:?}"
+    );
+
+    // Any other error as `RpcError::Handler`, formatted with `Display`.
+    let resp = client
+        .call(AppRequest::Parse(Parse { text: "x".into() }))
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Parse(Err(RpcError::Handler(msg)))) if msg == "invalid digit found in string"),
+        "{resp:?}"
+    );
+
+    // Requests failing before their handler runs fail the call instead.
+    let resp = client
+        .call(AppRequest::Withdraw(Withdraw { amount: -1 }))
+        .await;
+    assert!(
+        matches!(&resp, Err(client::Error::Rpc(RpcError::ValidationFailed(msg))) if msg == "can't withdraw -1"),
+        "{resp:?}"
+    );
+}
@@ -0,0 +1,83 @@
+//! Requests declared by their signature, handled by a function elsewhere.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::{Context, Request, RpcError};
+
+use macros::{request, rpc};
+
+mod handlers {
+    use protocol::Context;
+
+    pub fn add(a: i32, b: i32) -> i32 {
+        a + b
+    }
+
+    pub async fn greet(name: String, ctx: Context) -> String {
+        tokio::task::yield_now().await;
+        format!("hello {name}, cancelled: {}", ctx.is_cancelled())
+    }
+
+    pub fn halve(n: u32) -> Result<u32, String> {
+        if n % 2 == 1 {
+            return Err(format!("{n} is odd"));
+        }
+        Ok(n / 2)
+    }
+}
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+    Greet(Greet),
+    Halve(Halve),
+}
+
+#[request(handler = "handlers::add")]
+fn Add(a: i32, b: i32) -> i32;
+
+#[request(name = "greetings.greet", handler = "crate::handlers::greet")]
+async fn Greet(name: String, ctx: Context) -> String;
+
+#[request(handler = "handlers::halve")]
+fn Halve(n: u32) -> Result<u32, String>;
+
+#[tokio::test]
+async fn calls_handlers_from_another_module() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client.call(AppRequest::Add(Add { a: 2, b: 3 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Add(5))), "{resp:?}");
+
+    let resp = client
+        .call(AppRequest::Greet(Greet { name: "ada".into() }))
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Greet(s)) if s == "hello ada, cancelled: false"),
+        "{resp:?}"
+    );
+
+    let resp = client.call(AppRequest::Halve(Halve { n: 3 })).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Halve(Err(RpcError::Handler(msg)))) if msg == "3 is odd"),
+        "{resp:?}"
+    );
+}
+
+#[test]
+fn declarations_describe_the_request() {
+    assert_eq!(Greet::NAME, "greetings.greet");
+    let schema = Add::schema();
+    let args: Vec<_> = schema.methods[0]
+        .args
+        .iter()
+        .map(|arg| (arg.name.as_str(), arg.ty.as_str()))
+        .collect();
+    assert_eq!(args, [("a", "i32"), ("b", "i32")]);
+    assert_eq!(schema.methods[0].response, "i32");
+}
@@ -0,0 +1,270 @@
+//! The `Hello`/`HelloAck` exchange opening every connection.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::codec::WireFormat;
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+use protocol::hello::{
+    CALLBACKS_FEATURE, FEATURES, HELLO_VERSION, Hello, HelloAck, PROTOCOL_VERSION,
+};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+#[test]
+fn answer_takes_what_both_sides_have() {
+    let hello = Hello {
+        wire_formats: vec![WireFormat::Json.id(), WireFormat::Bincode.id()],
+        max_frame_bytes: 1024,
+        features: vec!["batch".into(), "chunked".into()],
+        ..Hello::default()
+    };
+    let ack = hello.answer(&[WireFormat::Bincode, WireFormat::Json], 4096, FEATURES);
+
+    assert_eq!(ack.version, HELLO_VERSION);
+    assert_eq!(ack.wire_format(), Some(WireFormat::Json));
+    assert_eq!(ack.max_frame_bytes, 1024);
+    assert_eq!(ack.features, ["batch", "chunked"]);
+}
+
+#[test]
+fn answer_falls_back_where_sides_differ() {
+    let hello = Hello {
+        version: HELLO_VERSION + 1,
+        wire_formats: vec![WireFormat::Json.id(), 200],
+        max_frame_bytes: 1 << 20,
+        features: vec!["compression".into(), "cancel".into()],
+        compressions: vec!["zstd".into()],
+    };
+    let ack = hello.answer(&[WireFormat::Bincode], 4096, &["cancel"]);
+
+    assert_eq!(ack.version, HELLO_VERSION);
+    assert_eq!(ack.wire_format(), Some(WireFormat::FALLBACK));
+    assert_eq!(ack.max_frame_bytes, 4096);
+    assert!(ack.has_feature("cancel"));
+    assert!(!ack.has_feature("compression"));
+    assert_eq!(ack.compression, None);
+}
+
+#[test]
+fn unknown_fields_are_ignored_and_missing_ones_defaulted() {
+    let hello =
+        Hello::decode(br#"{"version": 7, "auth": {"token": "x"}, "wire_formats": [1]}"#).unwrap();
+    assert_eq!(hello.version, 7);
+    assert_eq!(hello.wire_formats, [1]);
+    assert_eq!(hello.max_frame_bytes, Hello::default().max_frame_bytes);
+    assert!(hello.features.is_empty());
+
+    let ack = HelloAck::decode(br#"{"wire_format": 1, "compression": "zstd"}"#).unwrap();
+    assert_eq!(ack.wire_format(), Some(WireFormat::Json));
+    assert_eq!(ack.protocol_version, 1);
+
+    // What an older client sends instead: the raw identifiers of its formats.
+    assert_eq!(Hello::decode(&[WireFormat::Json.id()]), None);
+}
+
+#[tokio::test]
+async fn client_and_server_agree_on_settings() {
+    let addr = common::serve::<AppRequest>(
+        Server::builder()
+            .wire_formats([WireFormat::Bincode, WireFormat::Json])
+            .max_frame_bytes(64 * 1024),
+    )
+    .await;
+
+    let client = client::Client::builder()
+        .wire_formats([WireFormat::Json])
+        .connect(addr)
+        .await
+        .unwrap();
+    assert_eq!(client.wire_format(), WireFormat::Json);
+    assert_eq!(client.max_frame_bytes(), 64 * 1024);
+    // Everything but callbacks, which it registered no handlers for.
+    let features: Vec<_> = FEATURES
+        .iter()
+        .filter(|&&f| f != CALLBACKS_FEATURE)
+        .collect();
+    assert_eq!(client.features().iter().collect::<Vec<_>>(), features);
+
+    let resp = client
+        .call(AppRequest::Echo(Echo { text: "hi".into() }))
+        .await
+        .unwrap();
+    assert!(
+        matches!(&resp, AppResponse::Echo(s) if s == "hi"),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn mismatched_formats_fall_back() {
+    let addr =
+        common::serve::<AppRequest>(Server::builder().wire_formats([WireFormat::Bincode])).await;
+
+    let client = client::Client::builder()
+        .wire_formats([WireFormat::Json])
+        .max_frame_bytes(4096)
+        .connect(addr)
+        .await
+        .unwrap();
+    assert_eq!(client.wire_format(), WireFormat::FALLBACK);
+    assert_eq!(client.max_frame_bytes(), 4096);
+
+    let resp = client
+        .call(AppRequest::Echo(Echo { text: "hi".into() }))
+        .await
+        .unwrap();
+    assert!(
+        matches!(&resp, AppResponse::Echo(s) if s == "hi"),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn server_answers_a_hello_with_extra_fields() {
+    let addr =
+        common::serve::<AppRequest>(Server::builder().wire_formats([WireFormat::Json])).await;
+
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    let hello =
+        br#"{"version": 1, "wire_formats": [1], "features": ["batch", "auth"], "auth": null}"#;
+    framed.send(Bytes::from_static(hello)).await.unwrap();
+
+    let ack = HelloAck::decode(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(ack.wire_format(), Some(WireFormat::Json));
+    assert_eq!(ack.features, ["batch"]);
+    assert_eq!(
+        ack.max_frame_bytes,
+        Framing::default().max_frame_bytes as u64
+    );
+}
+
+#[tokio::test]
+async fn client_assumes_defaults_from_a_silent_server() {
+    let (socket, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let codec = WireFormat::FALLBACK;
+        let mut framed = Framed::new(server, Framing::default().codec());
+        // Read the `Hello`, but leave it unanswered.
+        framed.next().await.unwrap().unwrap();
+
+        let frame = framed.next().await.unwrap().unwrap();
+        let (header, _) = decode_header(&codec, &frame).unwrap();
+        let resp = AppResponse::Echo("from a legacy server".into());
+        let frame = encode_frame(&codec, &Header::new(header.id, FrameKind::Response), &resp);
+        framed.send(Bytes::from(frame.unwrap())).await.unwrap();
+        // Keep the connection open until the client hangs up.
+        while framed.next().await.is_some() {}
+    });
+
+    let client = client::Client::builder()
+        .wire_formats([WireFormat::Json])
+        .hello_timeout(Some(Duration::from_millis(50)))
+        .handshake(socket)
+        .await
+        .unwrap();
+    assert_eq!(client.wire_format(), WireFormat::FALLBACK);
+    assert!(client.features().is_empty());
+
+    let resp = client
+        .call(AppRequest::Echo(Echo { text: "hi".into() }))
+        .await
+        .unwrap();
+    assert!(
+        matches!(&resp, AppResponse::Echo(s) if s == "from a legacy server"),
+        "{resp:?}"
+    );
+}
+
+/// A server answering every `Hello` as one speaking `protocol_version`.
+fn server_speaking(protocol_version: u32) -> tokio::io::DuplexStream {
+    let (socket, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let mut framed = Framed::new(server, Framing::default().codec());
+        let hello = framed.next().await.unwrap().unwrap();
+        let ack = HelloAck {
+            protocol_version,
+            ..Hello::decode(&hello)
+                .unwrap()
+                .answer(&[WireFormat::FALLBACK], u64::MAX, &[])
+        };
+        framed.send(Bytes::from(ack.encode())).await.unwrap();
+        while framed.next().await.is_some() {}
+    });
+    socket
+}
+
+#[tokio::test]
+async fn clients_pinned_to_versions_refuse_servers_outside_them() {
+    for (server, supported) in [(9, 1..=2), (1, 2..=3)] {
+        let err = client::Client::builder()
+            .protocol_versions(supported.clone())
+            .handshake(server_speaking(server))
+            .await
+            .err();
+        assert!(
+            matches!(
+                &err,
+                Some(client::Error::IncompatibleVersion { server: s, supported: r })
+                    if *s == server && *r == supported
+            ),
+            "{err:?}"
+        );
+    }
+
+    client::Client::builder()
+        .protocol_versions(1..=2)
+        .handshake(server_speaking(2))
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn servers_tell_clients_their_protocol_version() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let pinned = PROTOCOL_VERSION..=PROTOCOL_VERSION;
+    let client = client::Client::builder()
+        .protocol_versions(pinned)
+        .connect(addr)
+        .await
+        .unwrap();
+    let resp = client
+        .call(AppRequest::Echo(Echo { text: "hi".into() }))
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Echo(s)) if s == "hi"),
+        "{resp:?}"
+    );
+
+    let newer = PROTOCOL_VERSION + 1..=PROTOCOL_VERSION + 1;
+    let err = client::Client::builder()
+        .protocol_versions(newer)
+        .connect(addr)
+        .await
+        .err();
+    assert!(
+        matches!(&err, Some(client::Error::IncompatibleVersion { .. })),
+        "{err:?}"
+    );
+}
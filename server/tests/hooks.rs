@@ -0,0 +1,86 @@
+//! Connection open and close hooks.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionInfo, DisconnectReason, Server};
+
+use protocol::Context;
+
+use macros::{request, rpc};
+
+use tokio::sync::mpsc;
+
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Whoami(Whoami),
+}
+
+/// Whatever `on_connect` set up for this connection.
+#[request]
+fn Whoami(ctx: Context) -> Option<String> {
+    ctx.connection_state::<String>().cloned()
+}
+
+enum Event {
+    Connected(ConnectionInfo),
+    Disconnected(ConnectionInfo, DisconnectReason),
+}
+
+#[tokio::test]
+async fn hooks_fire_on_open_and_close() {
+    let (events, mut rx) = mpsc::unbounded_channel();
+    let on_disconnect = events.clone();
+    let server = Server::builder()
+        .on_connect(move |info| {
+            let _ = events.send(Event::Connected(info));
+            async move { format!("connection {}", info.id) }
+        })
+        .on_disconnect(move |info, reason| {
+            let _ = on_disconnect.send(Event::Disconnected(info, reason));
+            async {}
+        })
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let client = client::Client::connect(addr).await.unwrap();
+    let resp = client.call(AppRequest::Whoami(Whoami {})).await.unwrap();
+
+    let Some(Event::Connected(opened)) = rx.recv().await else {
+        panic!("on_connect didn't run first");
+    };
+    assert_eq!(opened.peer_addr.ip(), addr.ip());
+    assert_ne!(opened.peer_addr.port(), addr.port());
+    let AppResponse::Whoami(state) = resp;
+    assert_eq!(state, Some(format!("connection {}", opened.id)));
+
+    // A second connection gets its own id and state.
+    let other = client::Client::connect(addr).await.unwrap();
+    let Some(Event::Connected(second)) = rx.recv().await else {
+        panic!("on_connect didn't run for the second connection");
+    };
+    assert_ne!(second.id, opened.id);
+
+    drop(client);
+    let event = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap();
+    let Some(Event::Disconnected(closed, reason)) = event else {
+        panic!("on_disconnect didn't run");
+    };
+    assert_eq!(closed, opened);
+    assert!(matches!(reason, DisconnectReason::Closed), "{reason:?}");
+
+    handle.shutdown().await;
+    let Some(Event::Disconnected(closed, reason)) = rx.recv().await else {
+        panic!("on_disconnect didn't run on shutdown");
+    };
+    assert_eq!(closed, second);
+    assert!(matches!(reason, DisconnectReason::Shutdown), "{reason:?}");
+    drop(other);
+}
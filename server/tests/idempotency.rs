@@ -0,0 +1,141 @@
+//! Repeats of requests sent with an idempotency key, answered from the
+//! server's cache.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::{IdempotencyCache, Server};
+
+use macros::{request, rpc};
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Charge(Charge),
+    Log(Log),
+}
+
+static RUNS: Mutex<Option<HashMap<String, usize>>> = Mutex::new(None);
+
+/// Counts the times a handler ran for `tag`, returning the count so far.
+fn run(tag: &str) -> usize {
+    let mut runs = RUNS.lock().unwrap();
+    let count = runs.get_or_insert_default().entry(tag.into()).or_default();
+    *count += 1;
+    *count
+}
+
+fn runs(tag: &str) -> usize {
+    RUNS.lock()
+        .unwrap()
+        .as_ref()
+        .and_then(|runs| runs.get(tag).copied())
+        .unwrap_or(0)
+}
+
+#[request(idempotent)]
+async fn Charge(tag: String, delay_ms: u64) -> usize {
+    tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+    run(&tag)
+}
+
+#[request]
+fn Log(tag: String) -> usize {
+    run(&tag)
+}
+
+async fn serve(cache: IdempotencyCache) -> client::Client {
+    let addr = common::serve::<AppRequest>(Server::builder().idempotency_cache(cache)).await;
+    client::Client::connect(addr).await.unwrap()
+}
+
+fn charge(tag: &str) -> AppRequest {
+    AppRequest::Charge(Charge {
+        tag: tag.into(),
+        delay_ms: 0,
+    })
+}
+
+fn cache() -> IdempotencyCache {
+    IdempotencyCache::new(16, Duration::from_secs(60))
+}
+
+#[tokio::test]
+async fn handles_a_keyed_request_once() {
+    let client = serve(cache()).await;
+
+    for _ in 0..2 {
+        let resp = client
+            .call_with_idempotency_key(charge("once"), "key-1")
+            .await;
+        assert!(matches!(resp, Ok(AppResponse::Charge(1))), "{resp:?}");
+    }
+    assert_eq!(runs("once"), 1);
+
+    let resp = client
+        .call_with_idempotency_key(charge("once"), "key-2")
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Charge(2))), "{resp:?}");
+
+    let resp = client.call(charge("once")).await;
+    assert!(matches!(resp, Ok(AppResponse::Charge(3))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn ignores_keys_on_requests_not_marked_idempotent() {
+    let client = serve(cache()).await;
+
+    for _ in 0..2 {
+        let log = AppRequest::Log(Log { tag: "log".into() });
+        client.call_with_idempotency_key(log, "key").await.unwrap();
+    }
+    assert_eq!(runs("log"), 2);
+}
+
+#[tokio::test]
+async fn repeats_in_flight_wait_for_the_first() {
+    let client = serve(cache()).await;
+    let slow = || {
+        AppRequest::Charge(Charge {
+            tag: "slow".into(),
+            delay_ms: 50,
+        })
+    };
+
+    let (first, second) = tokio::join!(
+        client.call_with_idempotency_key(slow(), "key"),
+        client.call_with_idempotency_key(slow(), "key"),
+    );
+    assert!(matches!(first, Ok(AppResponse::Charge(1))), "{first:?}");
+    assert!(matches!(second, Ok(AppResponse::Charge(1))), "{second:?}");
+    assert_eq!(runs("slow"), 1);
+}
+
+#[tokio::test]
+async fn forgets_the_least_recently_used_and_expired_keys() {
+    let lru = IdempotencyCache::new(2, Duration::from_secs(60));
+    let client = serve(lru.clone()).await;
+
+    for key in ["a", "b", "a", "c", "a", "b"] {
+        client
+            .call_with_idempotency_key(charge("lru"), key)
+            .await
+            .unwrap();
+    }
+    // "b" was dropped for "c", then "c" for "b".
+    assert_eq!(runs("lru"), 4);
+    assert_eq!(lru.len(), 2);
+
+    let client = serve(IdempotencyCache::new(16, Duration::from_millis(20))).await;
+    client
+        .call_with_idempotency_key(charge("ttl"), "key")
+        .await
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let resp = client.call_with_idempotency_key(charge("ttl"), "key").await;
+    assert!(matches!(resp, Ok(AppResponse::Charge(2))), "{resp:?}");
+}
@@ -0,0 +1,49 @@
+//! Requests turned into the enum wrapping them with `.into()`.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use macros::{request, rpc};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+    Negate(Negate),
+}
+
+#[request]
+fn Add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[request]
+fn Negate(n: i32) -> i32 {
+    -n
+}
+
+#[test]
+fn picks_the_variant_wrapping_the_request() {
+    let req: AppRequest = Add { a: 2, b: 3 }.into();
+    assert!(
+        matches!(req, AppRequest::Add(Add { a: 2, b: 3 })),
+        "{req:?}"
+    );
+    assert!(matches!(
+        AppRequest::from(Negate { n: 4 }),
+        AppRequest::Negate(Negate { n: 4 })
+    ));
+}
+
+#[tokio::test]
+async fn converted_requests_are_handled_alike() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client.call(AppRequest::from(Negate { n: 4 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Negate(-4))), "{resp:?}");
+    let resp = client.call::<AppRequest>(Add { a: 2, b: 3 }.into()).await;
+    assert!(matches!(resp, Ok(AppResponse::Add(5))), "{resp:?}");
+}
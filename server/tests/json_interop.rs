@@ -0,0 +1,54 @@
+//! Requests as a client in another language would write them in JSON, and
+//! the JSON it gets back.
+
+#![allow(non_snake_case)]
+
+use protocol::codec::{BincodeCodec, JsonCodec, WireCodec};
+use protocol::frame::{FrameKind, Header, decode_header, encode_frame, encode_raw_frame};
+
+use macros::{request, rpc};
+
+use serde_json::json;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+}
+
+#[request]
+fn Add(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+#[tokio::test]
+async fn answers_requests_written_as_json_text() {
+    let header = Header::new(1, FrameKind::Request);
+    let frame = encode_raw_frame(&JsonCodec, &header, br#"{"Add":{"lhs":2,"rhs":3}}"#).unwrap();
+
+    let reply = server::handle_request::<AppRequest>(&JsonCodec, &frame)
+        .await
+        .unwrap();
+    let (header, body) = decode_header(&JsonCodec, &reply).unwrap();
+    assert_eq!((header.id, header.kind), (1, FrameKind::Response));
+    let body: serde_json::Value = serde_json::from_slice(body).unwrap();
+    assert_eq!(body, json!({ "Add": 5 }));
+}
+
+#[tokio::test]
+async fn json_and_bincode_answer_alike() {
+    async fn add(codec: &impl WireCodec) -> AppResponse {
+        let req = AppRequest::Add(Add { lhs: 40, rhs: 2 });
+        let frame = encode_frame(codec, &Header::new(1, FrameKind::Request), &req).unwrap();
+        let reply = server::handle_request::<AppRequest>(codec, &frame)
+            .await
+            .unwrap();
+        codec
+            .decode(decode_header(codec, &reply).unwrap().1)
+            .unwrap()
+    }
+
+    let json = add(&JsonCodec).await;
+    let bincode = add(&BincodeCodec).await;
+    assert!(matches!(json, AppResponse::Add(42)), "{json:?}");
+    assert!(matches!(bincode, AppResponse::Add(42)), "{bincode:?}");
+}
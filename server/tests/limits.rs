@@ -0,0 +1,124 @@
+//! Per-type concurrency limits, shared across connections.
+
+#![allow(non_snake_case)]
+
+use server::{OverLimit, Server};
+
+use protocol::{Request, RpcError};
+
+use macros::{request, rpc};
+
+use futures::future::join_all;
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Report(Report),
+    Export(Export),
+    Ping(Ping),
+}
+
+static REPORTS: Running = Running::new();
+static EXPORTS: Running = Running::new();
+
+/// Counts handlers running at once, remembering the most seen.
+struct Running {
+    now: AtomicUsize,
+    max: AtomicUsize,
+}
+
+impl Running {
+    const fn new() -> Self {
+        Self {
+            now: AtomicUsize::new(0),
+            max: AtomicUsize::new(0),
+        }
+    }
+
+    async fn run_for(&self, duration: Duration) {
+        let now = self.now.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max.fetch_max(now, Ordering::SeqCst);
+        tokio::time::sleep(duration).await;
+        self.now.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[request]
+async fn Report() {
+    REPORTS.run_for(Duration::from_millis(50)).await;
+}
+
+#[request]
+async fn Export() {
+    EXPORTS.run_for(Duration::from_millis(200)).await;
+}
+
+#[request]
+fn Ping() -> String {
+    "pong".into()
+}
+
+async fn spawn_server(limit: (&'static str, usize), over_limit: OverLimit) -> SocketAddr {
+    let server = Server::builder()
+        .concurrency_limits(HashMap::from([limit]))
+        .over_limit(over_limit)
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    addr
+}
+
+#[tokio::test]
+async fn queues_requests_over_the_limit() {
+    let addr = spawn_server((Report::NAME, 2), OverLimit::Queue).await;
+    let clients = [
+        client::Client::connect(addr).await.unwrap(),
+        client::Client::connect(addr).await.unwrap(),
+    ];
+
+    let reports = join_all((0..8).map(|i| clients[i % 2].call(AppRequest::Report(Report {}))));
+    let ping = async {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        clients[0].call(AppRequest::Ping(Ping {})).await
+    };
+    let (reports, ping) = tokio::time::timeout(Duration::from_secs(5), async {
+        tokio::join!(reports, ping)
+    })
+    .await
+    .unwrap();
+
+    assert!(reports.iter().all(Result::is_ok), "{reports:?}");
+    assert_eq!(REPORTS.max.load(Ordering::SeqCst), 2);
+    // Other types aren't held up behind the queued ones.
+    assert!(matches!(ping, Ok(AppResponse::Ping(_))), "{ping:?}");
+}
+
+#[tokio::test]
+async fn rejects_requests_over_the_limit() {
+    let addr = spawn_server((Export::NAME, 1), OverLimit::Reject).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let first = client.call(AppRequest::Export(Export {}));
+    let second = async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.call(AppRequest::Export(Export {})).await
+    };
+    let (first, second) = tokio::join!(first, second);
+
+    assert!(first.is_ok(), "{first:?}");
+    assert!(
+        matches!(&second, Err(client::Error::Rpc(RpcError::Busy(name))) if name == Export::NAME),
+        "{second:?}"
+    );
+    assert_eq!(EXPORTS.max.load(Ordering::SeqCst), 1);
+
+    // Once the first is done there is room again.
+    let third = client.call(AppRequest::Export(Export {})).await;
+    assert!(third.is_ok(), "{third:?}");
+}
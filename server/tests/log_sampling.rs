@@ -0,0 +1,104 @@
+//! Requests logged one in every so many, by type.
+
+#![allow(non_snake_case)]
+
+use server::{LogSampling, Server};
+
+use protocol::Request;
+
+use macros::{request, rpc};
+
+use tracing::field::{Field, Visit};
+use tracing::{Event, Level};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::{Layer, Registry};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Hot(Hot),
+    Rare(Rare),
+}
+
+#[request]
+fn Hot() -> u8 {
+    1
+}
+
+#[request]
+fn Rare() -> u8 {
+    2
+}
+
+/// Counts debug events by message.
+#[derive(Clone, Default)]
+struct Messages(Arc<Mutex<HashMap<String, usize>>>);
+
+struct Message(Option<String>);
+
+impl Visit for Message {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+}
+
+impl<S: tracing::Subscriber> Layer<S> for Messages {
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        if *event.metadata().level() != Level::DEBUG {
+            return;
+        }
+        let mut message = Message(None);
+        event.record(&mut message);
+        if let Some(message) = message.0 {
+            *self.0.lock().unwrap().entry(message).or_default() += 1;
+        }
+    }
+}
+
+impl Messages {
+    fn count(&self, message: &str) -> usize {
+        self.0.lock().unwrap().get(message).copied().unwrap_or(0)
+    }
+}
+
+#[test]
+fn samples_the_configured_fraction() {
+    let sampling = LogSampling::every(10).with(Rare::NAME, 1);
+
+    let hot = (0..1000).filter(|_| sampling.sample(Hot::NAME)).count();
+    assert_eq!(hot, 100);
+    let rare = (0..50).filter(|_| sampling.sample(Rare::NAME)).count();
+    assert_eq!(rare, 50);
+
+    let all = LogSampling::default();
+    assert!((0..20).all(|_| all.sample(Hot::NAME)));
+}
+
+#[tokio::test]
+async fn logs_only_sampled_requests() {
+    let messages = Messages::default();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(messages.clone()));
+
+    let server = Server::builder()
+        .log_sampling(LogSampling::every(10).with(Rare::NAME, 1))
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    for _ in 0..40 {
+        client.call(AppRequest::Hot(Hot {})).await.unwrap();
+    }
+    for _ in 0..3 {
+        client.call(AppRequest::Rare(Rare {})).await.unwrap();
+    }
+
+    assert_eq!(messages.count("received request"), 4 + 3);
+    assert_eq!(messages.count("sending response"), 4 + 3);
+}
@@ -0,0 +1,75 @@
+//! Log file retention in the server binary.
+
+#[allow(dead_code)]
+#[path = "../src/logging.rs"]
+mod logging;
+
+use logging::{parse_rotation, remove_old_logs};
+
+use tracing_appender::rolling::Rotation;
+
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// A fresh directory of its own under the system's temporary directory.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tcp-rpc-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn touch(dir: &Path, name: &str, modified: SystemTime) {
+    File::create(dir.join(name))
+        .unwrap()
+        .set_modified(modified)
+        .unwrap();
+}
+
+#[test]
+fn removes_logs_past_retention() {
+    let dir = scratch_dir("retention");
+    let now = SystemTime::now();
+    touch(&dir, "app.log.2024-01-01-00", now - DAY * 10);
+    touch(&dir, "app.log.2024-01-08-00", now - DAY * 3);
+    touch(&dir, "app.log.2024-01-10-00", now - DAY / 2);
+    // Not ours, however old.
+    touch(&dir, "other.log", now - DAY * 30);
+
+    let removed = remove_old_logs(&dir, "app.log", DAY * 7, now).unwrap();
+    assert_eq!(removed, [dir.join("app.log.2024-01-01-00")]);
+
+    let mut left: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    left.sort();
+    assert_eq!(
+        left,
+        [
+            "app.log.2024-01-08-00",
+            "app.log.2024-01-10-00",
+            "other.log"
+        ]
+    );
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn missing_directory_is_not_an_error() {
+    let dir = std::env::temp_dir().join("tcp-rpc-does-not-exist");
+    let removed = remove_old_logs(&dir, "app.log", DAY, SystemTime::now()).unwrap();
+    assert!(removed.is_empty());
+}
+
+#[test]
+fn parses_rotations() {
+    assert_eq!(parse_rotation("hourly"), Some(Rotation::HOURLY));
+    assert_eq!(parse_rotation("Daily"), Some(Rotation::DAILY));
+    assert_eq!(parse_rotation("never"), Some(Rotation::NEVER));
+    assert_eq!(parse_rotation("weekly"), None);
+}
@@ -0,0 +1,51 @@
+//! Connections closed after serving as many requests as the server allows.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::frame::GoAwayReason;
+
+use macros::{request, rpc};
+
+const LIMIT: usize = 3;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+}
+
+#[request]
+fn Echo(n: usize) -> usize {
+    n
+}
+
+#[tokio::test]
+async fn closes_with_go_away_after_the_limit() {
+    let server = Server::builder()
+        .max_requests_per_connection(Some(LIMIT))
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    for n in 0..LIMIT {
+        let resp = client.call(AppRequest::Echo(Echo { n })).await;
+        assert!(
+            matches!(resp, Ok(AppResponse::Echo(echoed)) if echoed == n),
+            "{resp:?}"
+        );
+    }
+
+    let resp = client.call(AppRequest::Echo(Echo { n: LIMIT })).await;
+    assert!(resp.is_err(), "{resp:?}");
+    assert_eq!(client.going_away(), Some(GoAwayReason::RequestLimit));
+    assert!(client.is_closed());
+
+    // A new connection gets a fresh count.
+    let client = client::Client::connect(addr).await.unwrap();
+    let resp = client.call(AppRequest::Echo(Echo { n: 7 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Echo(7))), "{resp:?}");
+}
@@ -0,0 +1,88 @@
+//! The memory limit shared by all connections.
+
+#![allow(non_snake_case)]
+
+use server::{GlobalMemoryLimit, Server};
+
+use protocol::Payload;
+
+use macros::{request, rpc};
+
+use tokio::sync::Semaphore;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Hold(Hold),
+}
+
+static STARTED: AtomicUsize = AtomicUsize::new(0);
+static GATE: Semaphore = Semaphore::const_new(0);
+
+/// Holds on to its request until let through [`GATE`].
+#[request]
+async fn Hold(data: Payload) -> usize {
+    STARTED.fetch_add(1, Ordering::SeqCst);
+    GATE.acquire().await.unwrap().forget();
+    data.len()
+}
+
+async fn eventually(what: &str, mut f: impl FnMut() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !f() {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("timed out waiting until {what}"));
+}
+
+#[tokio::test]
+async fn stops_reading_past_the_limit() {
+    const LEN: usize = 600 * 1024;
+
+    let limit = GlobalMemoryLimit::new(1024 * 1024);
+    let server = Server::builder()
+        .memory_limit(limit.clone())
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let hold = || {
+        AppRequest::Hold(Hold {
+            data: vec![0; LEN].into(),
+        })
+    };
+    let client = client::Client::connect(addr).await.unwrap();
+    let mut calls = Vec::new();
+    for _ in 0..2 {
+        let (client, req) = (client.clone(), hold());
+        calls.push(tokio::spawn(async move { client.call(req).await }));
+    }
+    // The second request takes the server past the limit.
+    eventually("two requests are read", || {
+        STARTED.load(Ordering::SeqCst) == 2
+    })
+    .await;
+    assert!(limit.used() >= 2 * LEN, "only {} bytes used", limit.used());
+
+    // The limit holds across connections, so one opened now isn't read from
+    // until memory is freed.
+    let other = client::Client::connect(addr).await.unwrap();
+    let req = hold();
+    calls.push(tokio::spawn(async move { other.call(req).await }));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(STARTED.load(Ordering::SeqCst), 2);
+
+    GATE.add_permits(3);
+    for call in calls {
+        let resp = call.await.unwrap();
+        assert!(matches!(resp, Ok(AppResponse::Hold(LEN))), "{resp:?}");
+    }
+    assert_eq!(STARTED.load(Ordering::SeqCst), 3);
+    eventually("all memory is released", || limit.used() == 0).await;
+}
@@ -0,0 +1,121 @@
+//! Services defined apart from each other, hosted side by side on one
+//! listener under namespaces of their own.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::{Router, Server};
+
+use protocol::RpcError;
+
+use macros::{request, rpc};
+
+use std::net::SocketAddr;
+
+#[rpc(response = "AuthResponse")]
+enum AuthRequest {
+    Login(Login),
+}
+
+#[rpc(response = "BillingResponse")]
+enum BillingRequest {
+    Charge(Charge),
+    Balance(Balance),
+}
+
+#[request]
+fn Login(user: String) -> String {
+    format!("token for {user}")
+}
+
+#[request]
+fn Charge(cents: u64) -> u64 {
+    cents
+}
+
+#[request]
+fn Balance() -> u64 {
+    1200
+}
+
+#[request]
+fn Version() -> u32 {
+    3
+}
+
+async fn serve() -> SocketAddr {
+    let mut router = Router::new();
+    router
+        .register::<Version>()
+        .mount::<AuthRequest>("auth")
+        .mount::<BillingRequest>("billing");
+    common::serve_router(Server::builder().max_rejected_requests(8), router).await
+}
+
+#[tokio::test]
+async fn requests_go_to_the_service_of_their_namespace() {
+    let client = client::Client::connect(serve().await).await.unwrap();
+    let auth = client.namespaced("auth");
+    let billing = client.namespaced("billing");
+
+    let resp = auth
+        .call(AuthRequest::Login(Login { user: "ada".into() }))
+        .await;
+    assert!(
+        matches!(&resp, Ok(AuthResponse::Login(token)) if token == "token for ada"),
+        "{resp:?}"
+    );
+    let resp = billing
+        .call(BillingRequest::Charge(Charge { cents: 250 }))
+        .await;
+    assert!(matches!(resp, Ok(BillingResponse::Charge(250))), "{resp:?}");
+    let resp = billing
+        .call_batch(vec![
+            BillingRequest::Balance(Balance {}),
+            BillingRequest::Charge(Charge { cents: 5 }),
+        ])
+        .await;
+    assert!(
+        matches!(
+            resp.as_deref(),
+            Ok([
+                Ok(BillingResponse::Balance(1200)),
+                Ok(BillingResponse::Charge(5))
+            ])
+        ),
+        "{resp:?}"
+    );
+
+    // Requests with no namespace go to the router's own.
+    let resp = client.call(Version {}).await;
+    assert!(matches!(resp, Ok(3)), "{resp:?}");
+}
+
+#[tokio::test]
+async fn requests_are_only_known_in_their_own_namespace() {
+    let client = client::Client::connect(serve().await).await.unwrap();
+
+    let login = || AuthRequest::Login(Login { user: "ada".into() });
+    let resp = client.namespaced("billing").call(login()).await;
+    assert!(
+        matches!(
+            &resp,
+            Err(client::Error::Rpc(RpcError::UnknownMethod(name))) if name == "Login"
+        ),
+        "{resp:?}"
+    );
+    let resp = client.namespaced("reporting").call(login()).await;
+    assert!(
+        matches!(
+            &resp,
+            Err(client::Error::Rpc(RpcError::UnknownMethod(name))) if name == "reporting.Login"
+        ),
+        "{resp:?}"
+    );
+    let resp = client.call(login()).await;
+    assert!(
+        matches!(&resp, Err(client::Error::Rpc(RpcError::UnknownMethod(_)))),
+        "{resp:?}"
+    );
+}
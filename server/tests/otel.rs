@@ -0,0 +1,101 @@
+//! Dispatch spans parented to the caller's span in OpenTelemetry.
+
+#![cfg(feature = "otel")]
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use macros::{request, rpc};
+
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+use tracing::Instrument;
+use tracing_subscriber::Registry;
+use tracing_subscriber::layer::SubscriberExt;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Ping(Ping),
+}
+
+#[request]
+fn Ping() -> String {
+    "pong".into()
+}
+
+/// Exports every span ended while the returned guard is held to the
+/// returned exporter.
+fn export_spans() -> (
+    InMemorySpanExporter,
+    SdkTracerProvider,
+    tracing::subscriber::DefaultGuard,
+) {
+    let exporter = InMemorySpanExporter::default();
+    let provider = SdkTracerProvider::builder()
+        .with_simple_exporter(exporter.clone())
+        .build();
+    let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("otel"));
+    let guard = tracing::subscriber::set_default(Registry::default().with(layer));
+    (exporter, provider, guard)
+}
+
+fn exported(exporter: &InMemorySpanExporter, name: &str) -> SpanData {
+    let spans = exporter.get_finished_spans().unwrap();
+    let mut named = spans.iter().filter(|span| span.name == name);
+    let span = named.next().unwrap_or_else(|| panic!("no {name} span"));
+    assert!(named.next().is_none(), "more than one {name} span");
+    span.clone()
+}
+
+#[tokio::test]
+async fn dispatch_spans_are_children_of_the_callers() {
+    let (exporter, provider, _guard) = export_spans();
+
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+
+    let client = client::Client::builder()
+        .trace_context(client::otel::current_trace)
+        .connect(addr)
+        .await
+        .unwrap();
+    let resp = client
+        .call(AppRequest::Ping(Ping {}))
+        .instrument(tracing::info_span!("caller"))
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Ping(s)) if s == "pong"),
+        "{resp:?}"
+    );
+    drop(client);
+    provider.force_flush().unwrap();
+
+    let caller = exported(&exporter, "caller");
+    let dispatch = exported(&exporter, "dispatch");
+    assert_eq!(dispatch.parent_span_id, caller.span_context.span_id());
+    assert!(dispatch.parent_span_is_remote);
+    assert_eq!(
+        dispatch.span_context.trace_id(),
+        caller.span_context.trace_id()
+    );
+}
+
+#[tokio::test]
+async fn untraced_requests_keep_their_local_parent() {
+    let (exporter, provider, _guard) = export_spans();
+
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+
+    let client = client::Client::builder()
+        .trace_context(client::otel::current_trace)
+        .connect(addr)
+        .await
+        .unwrap();
+    client.call(AppRequest::Ping(Ping {})).await.unwrap();
+    drop(client);
+    provider.force_flush().unwrap();
+
+    let dispatch = exported(&exporter, "dispatch");
+    assert!(!dispatch.parent_span_is_remote);
+}
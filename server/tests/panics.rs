@@ -0,0 +1,42 @@
+//! A handler panicking fails only its own request.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::RpcError;
+
+use macros::{request, rpc};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Explode(Explode),
+}
+
+#[request]
+fn Explode(boom: bool) -> u32 {
+    assert!(!boom, "boom");
+    1
+}
+
+#[tokio::test]
+async fn panics_become_internal_errors_and_the_connection_lives_on() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client
+        .call(AppRequest::Explode(Explode { boom: true }))
+        .await;
+    assert!(
+        matches!(&resp, Err(client::Error::Rpc(RpcError::Internal(msg))) if msg.contains("Explode")),
+        "{resp:?}"
+    );
+    assert!(!client.is_closed());
+
+    let resp = client
+        .call(AppRequest::Explode(Explode { boom: false }))
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Explode(1))), "{resp:?}");
+}
@@ -0,0 +1,87 @@
+//! Handlers sending partial results as they go, ahead of the value they
+//! return.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::Context;
+
+use macros::{request, rpc};
+
+use futures::StreamExt;
+use tokio::sync::Notify;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Search(Search),
+}
+
+/// Lets a waiting search return.
+static DONE_SENDING: Notify = Notify::const_new();
+
+/// Sends every match as a partial result, returning how many there were.
+#[request]
+async fn Search(matches: Vec<String>, wait: bool, ctx: Context) -> usize {
+    for found in &matches {
+        ctx.send_partial(found).await.unwrap();
+    }
+    if wait {
+        DONE_SENDING.notified().await;
+    }
+    matches.len()
+}
+
+fn search(wait: bool) -> AppRequest {
+    AppRequest::Search(Search {
+        matches: vec!["first".into(), "second".into()],
+        wait,
+    })
+}
+
+#[tokio::test]
+async fn partials_arrive_before_the_handler_returns() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let mut call = client
+        .call_with_partials::<String, _>(search(true))
+        .await
+        .unwrap();
+    for expected in ["first", "second"] {
+        let partial = call.next().await;
+        assert!(
+            matches!(&partial, Some(Ok(found)) if found == expected),
+            "{partial:?}"
+        );
+    }
+
+    DONE_SENDING.notify_one();
+    let resp = call.finish().await;
+    assert!(
+        matches!(&resp, Ok((rest, AppResponse::Search(2))) if rest.is_empty()),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn partials_are_collected_along_with_the_response() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let call = client
+        .call_with_partials::<String, _>(search(false))
+        .await
+        .unwrap();
+    let resp = call.finish().await;
+    assert!(
+        matches!(&resp, Ok((found, AppResponse::Search(2))) if found == &["first", "second"]),
+        "{resp:?}"
+    );
+
+    // Plain calls skip over them.
+    let resp = client.call(search(false)).await;
+    assert!(matches!(resp, Ok(AppResponse::Search(2))), "{resp:?}");
+}
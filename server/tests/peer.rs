@@ -0,0 +1,64 @@
+//! Handlers finding out who sent their request.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, Server, handle_connection};
+
+use protocol::Context;
+
+use macros::{request, rpc};
+
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+use std::net::SocketAddr;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    WhoAmI(WhoAmI),
+}
+
+/// The caller's address, if known, and whether it presented a
+/// certificate.
+#[request]
+fn WhoAmI(ctx: Context) -> String {
+    let peer = ctx.peer();
+    let addr = peer.addr.map_or("unknown".into(), |addr| addr.to_string());
+    format!("{addr}, certificate: {}", peer.certificate.is_some())
+}
+
+#[tokio::test]
+async fn handlers_see_the_peer_address() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let ours: SocketAddr = socket.local_addr().unwrap();
+    let client = client::Client::builder().handshake(socket).await.unwrap();
+
+    let resp = client.call(AppRequest::WhoAmI(WhoAmI {})).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::WhoAmI(s)) if *s == format!("{ours}, certificate: false")),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn connections_handed_over_have_no_address() {
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let config = ConnectionConfig::default();
+        handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+    });
+    let client = client::Client::builder()
+        .handshake(client_end)
+        .await
+        .unwrap();
+
+    let resp = client.call(AppRequest::WhoAmI(WhoAmI {})).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::WhoAmI(s)) if s == "unknown, certificate: false"),
+        "{resp:?}"
+    );
+}
@@ -0,0 +1,100 @@
+//! Health checks every server answers, whatever requests it handles.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, Readiness, handle_connection};
+
+use protocol::info::Health;
+
+use macros::{request, rpc};
+
+use tokio_util::sync::CancellationToken;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+/// A client connected over an in-memory transport to a server configured
+/// by `config`.
+async fn connect(config: ConnectionConfig) -> client::Client {
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+    });
+    client::Client::builder()
+        .handshake(client_end)
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn measures_the_round_trip() {
+    let client = connect(ConnectionConfig::default()).await;
+
+    for _ in 0..3 {
+        let rtt = client.ping().await.unwrap();
+        assert!(
+            rtt > Duration::ZERO && rtt < Duration::from_secs(5),
+            "{rtt:?}"
+        );
+    }
+    let health = client.health().await.unwrap();
+    assert_eq!(health, Health::READY);
+
+    // The connection is still good for requests of the server's own.
+    let resp = client
+        .call(AppRequest::Echo(Echo { text: "hi".into() }))
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Echo(_))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn reports_what_the_readiness_check_says() {
+    let warm = Arc::new(AtomicBool::new(false));
+    let check = warm.clone();
+    let config = ConnectionConfig {
+        readiness: Some(Readiness::new(move || {
+            if check.load(Ordering::SeqCst) {
+                Health::READY
+            } else {
+                Health::not_ready("warming up")
+            }
+        })),
+        ..ConnectionConfig::default()
+    };
+    let client = connect(config).await;
+
+    let health = client.health().await.unwrap();
+    assert!(
+        matches!(&health, Health { ready: false, status: Some(s) } if s == "warming up"),
+        "{health:?}"
+    );
+    // Still alive, so pings are answered all the same.
+    client.ping().await.unwrap();
+
+    warm.store(true, Ordering::SeqCst);
+    assert_eq!(client.health().await.unwrap(), Health::READY);
+}
+
+#[tokio::test]
+async fn answers_with_server_info_off() {
+    let config = ConnectionConfig {
+        server_info: false,
+        ..ConnectionConfig::default()
+    };
+    let client = connect(config).await;
+
+    client.ping().await.unwrap();
+    let info = client.server_info().await;
+    assert!(matches!(info, Err(client::Error::Rpc(_))), "{info:?}");
+}
@@ -0,0 +1,146 @@
+//! Requests over a cap shared by every connection, let in highest priority
+//! first, with those left waiting moving up as they do.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::{PriorityScheduler, Server};
+
+use macros::{request, rpc};
+
+use std::sync::Mutex;
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Work(Work),
+    Ping(Ping),
+}
+
+/// Who ran, in the order they did.
+static RAN: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+#[request]
+async fn Work(who: String, ms: u64) {
+    RAN.lock().unwrap().push(who);
+    tokio::time::sleep(Duration::from_millis(ms)).await;
+}
+
+#[request]
+fn Ping(who: String) {
+    RAN.lock().unwrap().push(who);
+}
+
+async fn serve(scheduler: &PriorityScheduler) -> client::Client {
+    let builder = Server::builder().priority_scheduler(scheduler.clone());
+    let addr = common::serve::<AppRequest>(builder).await;
+    client::Client::connect(addr).await.unwrap()
+}
+
+/// Sends `req` at `priority`, or its type's, on a task of its own.
+fn send(
+    client: &client::Client,
+    req: AppRequest,
+    priority: Option<u8>,
+) -> tokio::task::JoinHandle<()> {
+    let client = client.clone();
+    tokio::spawn(async move {
+        let resp = match priority {
+            Some(priority) => client.call_with_priority(req, priority).await,
+            None => client.call(req).await,
+        };
+        assert!(resp.is_ok(), "{resp:?}");
+    })
+}
+
+fn work(who: &str, ms: u64) -> AppRequest {
+    AppRequest::Work(Work {
+        who: who.into(),
+        ms,
+    })
+}
+
+async fn queued(scheduler: &PriorityScheduler, n: usize) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while scheduler.queued() < n {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("requests never queued");
+}
+
+/// What ran of those whose name starts with `prefix`, in order.
+fn ran(prefix: &str) -> Vec<String> {
+    let ran = RAN.lock().unwrap();
+    ran.iter()
+        .filter(|who| who.starts_with(prefix))
+        .cloned()
+        .collect()
+}
+
+#[tokio::test]
+async fn higher_priorities_go_first_under_load() {
+    let scheduler = PriorityScheduler::new(1)
+        .aging(Duration::from_secs(60))
+        .max_requested(150)
+        .priority("Ping", 200);
+    let client = serve(&scheduler).await;
+
+    let mut calls: Vec<_> = (0..10)
+        .map(|i| send(&client, work(&format!("load-low-{i}"), 50), Some(10)))
+        .collect();
+    queued(&scheduler, 9).await;
+    // Asking for more than the server allows gets what it does allow,
+    // which is less than pings get.
+    calls.push(send(&client, work("load-greedy", 1), Some(255)));
+    queued(&scheduler, 10).await;
+    calls.push(send(
+        &client,
+        AppRequest::Ping(Ping {
+            who: "load-ping".into(),
+        }),
+        None,
+    ));
+    calls.push(send(&client, work("load-high", 1), Some(120)));
+    for call in calls {
+        call.await.unwrap();
+    }
+
+    let ran = ran("load-");
+    let at = |who: &str| ran.iter().position(|w| w == who).unwrap();
+    let (ping, greedy, high) = (at("load-ping"), at("load-greedy"), at("load-high"));
+    assert!(ping < greedy && greedy < high, "{ran:?}");
+    let lows_first = ran[..high]
+        .iter()
+        .filter(|w| w.starts_with("load-low"))
+        .count();
+    assert!(lows_first <= 2, "{ran:?}");
+    assert_eq!((scheduler.running(), scheduler.queued()), (0, 0));
+}
+
+#[tokio::test]
+async fn waiting_requests_move_up() {
+    let scheduler = PriorityScheduler::new(1).aging(Duration::from_millis(5));
+    let client = serve(&scheduler).await;
+
+    let block = send(&client, work("aging-block", 300), Some(0));
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let old = send(&client, work("aging-old", 1), Some(0));
+    queued(&scheduler, 1).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // 10 levels are worth 50 ms, less than the old one has waited; 30 are
+    // worth more.
+    let newer = send(&client, work("aging-newer", 1), Some(10));
+    let urgent = send(&client, work("aging-urgent", 1), Some(30));
+    for call in [block, old, newer, urgent] {
+        call.await.unwrap();
+    }
+
+    assert_eq!(
+        ran("aging-"),
+        ["aging-block", "aging-urgent", "aging-old", "aging-newer"]
+    );
+}
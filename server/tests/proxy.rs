@@ -0,0 +1,101 @@
+//! Frames relayed as they are, by proxies that only peek at their headers,
+//! with neither end any the wiser.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::frame::{Framing, decode_header, read_frame, write_frame};
+use protocol::hello::HelloAck;
+
+use macros::{request, rpc};
+
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_util::codec::Framed;
+
+use std::io;
+use std::net::SocketAddr;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+}
+
+#[request]
+fn Add(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+/// Relays the next connection made to it to `backend`, telling `seen` the
+/// name of every request on the way.
+async fn hop(backend: SocketAddr, seen: mpsc::UnboundedSender<String>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        let (client, _) = listener.accept().await?;
+        let backend = TcpStream::connect(backend).await?;
+        relay(client, backend, seen).await
+    });
+    addr
+}
+
+async fn relay(
+    client: TcpStream,
+    backend: TcpStream,
+    seen: mpsc::UnboundedSender<String>,
+) -> io::Result<()> {
+    let framing = Framing::default();
+    let mut client = Framed::new(client, framing.codec());
+    let mut backend = Framed::new(backend, framing.codec());
+
+    let Some(hello) = read_frame(&mut client).await? else {
+        return Ok(());
+    };
+    write_frame(&mut backend, hello).await?;
+    let Some(ack) = read_frame(&mut backend).await? else {
+        return Ok(());
+    };
+    let wire_format = HelloAck::decode(&ack).and_then(|ack| ack.wire_format());
+    write_frame(&mut client, ack).await?;
+
+    loop {
+        tokio::select! {
+            frame = read_frame(&mut client) => {
+                let Some(frame) = frame? else { return Ok(()) };
+                if let Some(codec) = &wire_format
+                    && let Ok((header, _)) = decode_header(codec, &frame)
+                    && let Some(method) = header.method
+                {
+                    let _ = seen.send(method.name);
+                }
+                write_frame(&mut backend, frame).await?;
+            }
+            frame = read_frame(&mut backend) => {
+                let Some(frame) = frame? else { return Ok(()) };
+                write_frame(&mut client, frame).await?;
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn requests_go_through_two_hops() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let backend = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let (seen, mut inner_seen) = mpsc::unbounded_channel();
+    let inner = hop(backend, seen).await;
+    let (seen, mut outer_seen) = mpsc::unbounded_channel();
+    let outer = hop(inner, seen).await;
+
+    let client = client::Client::connect(outer).await.unwrap();
+    let resp = client.call(AppRequest::Add(Add { lhs: 2, rhs: 3 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Add(5))), "{resp:?}");
+
+    for seen in [&mut outer_seen, &mut inner_seen] {
+        let name = seen.recv().await;
+        assert_eq!(name.as_deref(), Some("Add"));
+    }
+}
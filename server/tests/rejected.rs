@@ -0,0 +1,126 @@
+//! Connections reading on past requests they had to reject.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, Error, handle_connection};
+
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame, encode_raw_frame};
+use protocol::hello::{Hello, HelloAck};
+use protocol::{DecodeFailure, Request, RpcError};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio::task::JoinHandle;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::sync::CancellationToken;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+type Peer = Framed<DuplexStream, LengthDelimitedCodec>;
+
+/// A connection to a server forgiving `max` rejected requests, past the
+/// handshake.
+async fn connect(max: usize) -> (Peer, JoinHandle<server::Result<()>>) {
+    let config = ConnectionConfig {
+        max_rejected_requests: max,
+        ..ConnectionConfig::default()
+    };
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let serving = tokio::spawn(async move {
+        handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+    });
+
+    let mut framed = Framed::new(client_end, Framing::default().codec());
+    framed
+        .send(Bytes::from(Hello::default().encode()))
+        .await
+        .unwrap();
+    let ack = HelloAck::decode(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(ack.wire_format(), Some(WireFormat::Bincode));
+    (framed, serving)
+}
+
+/// Sends request `id` with a body no request decodes from.
+async fn send_garbage(framed: &mut Peer, id: u64) {
+    let header = Header::new(id, FrameKind::Request);
+    let frame = encode_raw_frame(&WireFormat::Bincode, &header, &[0xff; 8]).unwrap();
+    framed.send(Bytes::from(frame)).await.unwrap();
+}
+
+async fn send_echo(framed: &mut Peer, id: u64) {
+    let req = AppRequest::Echo(Echo { text: "hi".into() });
+    let header = Header::request(id, req.name(), req.version());
+    let frame = encode_frame(&WireFormat::Bincode, &header, &req).unwrap();
+    framed.send(Bytes::from(frame)).await.unwrap();
+}
+
+/// Reads the next frame as an error to request `id`.
+async fn expect_error(framed: &mut Peer, id: u64) -> RpcError {
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&WireFormat::Bincode, &frame).unwrap();
+    assert_eq!((header.id, header.kind), (id, FrameKind::Error));
+    WireFormat::Bincode.decode(body).unwrap()
+}
+
+#[tokio::test]
+async fn answers_the_next_request_after_a_bad_one() {
+    let (mut framed, serving) = connect(1).await;
+    send_garbage(&mut framed, 1).await;
+    send_echo(&mut framed, 2).await;
+
+    let err = expect_error(&mut framed, 1).await;
+    assert!(
+        matches!(err, RpcError::Decode(DecodeFailure { .. })),
+        "{err:?}"
+    );
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&WireFormat::Bincode, &frame).unwrap();
+    assert_eq!((header.id, header.kind), (2, FrameKind::Response));
+    let resp: AppResponse = WireFormat::Bincode.decode(body).unwrap();
+    assert!(
+        matches!(&resp, AppResponse::Echo(s) if s == "hi"),
+        "{resp:?}"
+    );
+
+    // That was the one it may have.
+    send_garbage(&mut framed, 3).await;
+    expect_error(&mut framed, 3).await;
+    let res = serving.await.unwrap();
+    assert!(matches!(res, Err(Error::Rejected { id: 3, .. })), "{res:?}");
+    assert!(framed.next().await.is_none());
+}
+
+#[tokio::test]
+async fn closes_on_the_first_by_default() {
+    let (mut framed, serving) = connect(0).await;
+    send_garbage(&mut framed, 1).await;
+    send_echo(&mut framed, 2).await;
+
+    expect_error(&mut framed, 1).await;
+    let res = serving.await.unwrap();
+    assert!(matches!(res, Err(Error::Rejected { id: 1, .. })), "{res:?}");
+    assert!(framed.next().await.is_none());
+}
+
+#[tokio::test]
+async fn unreadable_headers_still_close_the_connection() {
+    let (mut framed, serving) = connect(8).await;
+    framed.send(Bytes::from_static(&[0xff; 8])).await.unwrap();
+    send_echo(&mut framed, 2).await;
+
+    let res = serving.await.unwrap();
+    assert!(matches!(res, Err(Error::Codec(_))), "{res:?}");
+    assert!(framed.next().await.is_none());
+}
@@ -0,0 +1,135 @@
+//! The id a client names each call by, logged on both sides.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Header, Method, TraceContext, Trailers};
+
+use macros::{request, rpc};
+
+use tracing::Event;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::{Layer, Registry};
+
+use std::sync::{Arc, Mutex};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Ping(Ping),
+}
+
+#[request]
+fn Ping() -> String {
+    "pong".into()
+}
+
+/// Keeps the `request_id` of every client event and server dispatch span.
+#[derive(Clone, Default)]
+struct RequestIds {
+    client: Arc<Mutex<Vec<String>>>,
+    server: Arc<Mutex<Vec<String>>>,
+}
+
+struct RequestId(Option<String>);
+
+impl Visit for RequestId {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "request_id" {
+            self.0 = Some(format!("{value:?}"));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "request_id" {
+            self.0 = Some(value.to_owned());
+        }
+    }
+}
+
+impl<S: tracing::Subscriber> Layer<S> for RequestIds {
+    fn on_new_span(&self, attrs: &Attributes<'_>, _: &Id, _: Context<'_, S>) {
+        if attrs.metadata().name() == "dispatch" {
+            let mut id = RequestId(None);
+            attrs.record(&mut id);
+            self.server.lock().unwrap().extend(id.0);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, _: Context<'_, S>) {
+        if event.metadata().target().starts_with("client") {
+            let mut id = RequestId(None);
+            event.record(&mut id);
+            self.client.lock().unwrap().extend(id.0);
+        }
+    }
+}
+
+#[tokio::test]
+async fn client_and_server_log_the_same_id() {
+    let ids = RequestIds::default();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(ids.clone()));
+
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let client = client::Client::connect(addr).await.unwrap();
+    for _ in 0..2 {
+        let resp = client.call(AppRequest::Ping(Ping {})).await;
+        assert!(
+            matches!(&resp, Ok(AppResponse::Ping(s)) if s == "pong"),
+            "{resp:?}"
+        );
+    }
+
+    let client_ids = ids.client.lock().unwrap().clone();
+    let server_ids = ids.server.lock().unwrap().clone();
+    assert_eq!(client_ids.len(), 2, "{client_ids:?}");
+    assert_eq!(client_ids, server_ids);
+    assert_ne!(client_ids[0], client_ids[1]);
+    // A ULID.
+    for id in &client_ids {
+        assert_eq!(id.len(), 26, "{id}");
+        assert!(id.bytes().all(|b| b.is_ascii_alphanumeric()), "{id}");
+    }
+}
+
+#[test]
+fn headers_round_trip_with_and_without_request_id() {
+    for codec in [WireFormat::Bincode, WireFormat::Json] {
+        let named = Header::request(3, "Ping", 1)
+            .with_request_id(Some("01ARZ3NDEKTSV4RRFFQ69G5FAV".into()));
+        let decoded: Header = codec.decode(&codec.encode(&named).unwrap()).unwrap();
+        assert_eq!(decoded, named, "{codec:?}");
+
+        // What a peer predating request ids sends.
+        let legacy = match codec {
+            WireFormat::Bincode => {
+                let method = Some(Method {
+                    name: "Ping".into(),
+                    version: 1,
+                });
+                let (trace, key) = (None::<TraceContext>, None::<String>);
+                let fields = (3u64, FrameKind::VersionedRequest, method, trace);
+                codec
+                    .encode(&(fields, Trailers::new(), key, Some(1u64)))
+                    .unwrap()
+            }
+            WireFormat::Json => {
+                br#"{"id":3,"kind":"VersionedRequest","method":{"name":"Ping","version":1},"seq":1}"#
+                    .to_vec()
+            }
+            WireFormat::MessagePack => unreachable!("not among the formats tried"),
+        };
+        let decoded: Header = codec.decode(&legacy).unwrap();
+        assert_eq!(
+            decoded,
+            Header::request(3, "Ping", 1).with_seq(Some(1)),
+            "{codec:?}"
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Handlers timed out after however long the server allows their type.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::{Request, RpcError};
+
+use macros::{request, rpc};
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Ping(Ping),
+    Report(Report),
+    Lookup(Lookup),
+}
+
+#[request]
+async fn Ping(ms: u64) -> u64 {
+    tokio::time::sleep(Duration::from_millis(ms)).await;
+    ms
+}
+
+#[request]
+async fn Report(ms: u64) -> u64 {
+    tokio::time::sleep(Duration::from_millis(ms)).await;
+    ms
+}
+
+#[request]
+async fn Lookup(ms: u64) -> u64 {
+    tokio::time::sleep(Duration::from_millis(ms)).await;
+    ms
+}
+
+async fn serve() -> SocketAddr {
+    let builder = Server::builder()
+        .request_timeout(Some(Duration::from_millis(200)))
+        .request_timeouts([
+            (Ping::NAME, Duration::from_millis(50)),
+            (Report::NAME, Duration::from_secs(10)),
+        ]);
+    common::serve::<AppRequest>(builder).await
+}
+
+fn timed_out<T: std::fmt::Debug>(resp: &client::Result<T>, name: &str) -> bool {
+    matches!(resp, Err(client::Error::Rpc(RpcError::Timeout(timed_out))) if timed_out == name)
+}
+
+#[tokio::test]
+async fn each_type_gets_its_own_timeout() {
+    let client = client::Client::connect(serve().await).await.unwrap();
+
+    let resp = client.call(AppRequest::Ping(Ping { ms: 0 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Ping(0))), "{resp:?}");
+    let resp = client.call(AppRequest::Ping(Ping { ms: 150 })).await;
+    assert!(timed_out(&resp, "Ping"), "{resp:?}");
+
+    // Well past the default, but within its own.
+    let resp = client.call(AppRequest::Report(Report { ms: 400 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Report(400))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn types_without_one_get_the_default() {
+    let client = client::Client::connect(serve().await).await.unwrap();
+
+    let resp = client.call(AppRequest::Lookup(Lookup { ms: 150 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Lookup(150))), "{resp:?}");
+    let resp = client.call(AppRequest::Lookup(Lookup { ms: 400 })).await;
+    assert!(timed_out(&resp, "Lookup"), "{resp:?}");
+}
+
+#[tokio::test]
+async fn callers_deadlines_still_apply_within_the_timeout() {
+    let client = client::Client::connect(serve().await).await.unwrap();
+
+    let resp = client
+        .call_with_deadline(
+            AppRequest::Report(Report { ms: 400 }),
+            Duration::from_millis(50),
+        )
+        .await;
+    assert!(
+        matches!(&resp, Err(client::Error::Rpc(RpcError::DeadlineExceeded(name))) if name == "Report"),
+        "{resp:?}"
+    );
+}
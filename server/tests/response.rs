@@ -0,0 +1,132 @@
+//! Response types of one's own, bound by `WireType` alone.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::{Payload, Response, WireType};
+
+use macros::{request, rpc};
+
+use serde::{Deserialize, Serialize};
+
+use std::borrow::Cow;
+use std::sync::Arc;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    List(List),
+    Measure(Measure),
+    Motd(Motd),
+}
+
+/// One page of a longer list of `T`s.
+#[derive(Debug, bincode::Encode, bincode::Decode, Serialize, Deserialize)]
+struct Page<T> {
+    items: Vec<T>,
+    next: Option<u32>,
+}
+
+impl<T: WireType + Send + 'static> Response for Page<T> {}
+
+#[derive(Debug, bincode::Encode, bincode::Decode, Serialize, Deserialize)]
+enum Shape {
+    Circle { radius: u32 },
+    Square(u32),
+}
+
+impl Response for Shape {}
+
+#[request]
+fn List(from: u32) -> Page<String> {
+    Page {
+        items: (from..from + 2).map(|i| format!("#{i}")).collect(),
+        next: Some(from + 2),
+    }
+}
+
+#[request]
+fn Measure(side: u32) -> Shape {
+    if side == 0 {
+        Shape::Circle { radius: 1 }
+    } else {
+        Shape::Square(side)
+    }
+}
+
+#[request]
+fn Motd() -> Arc<String> {
+    Arc::new("welcome".to_owned())
+}
+
+/// Compiles only if `T` is a response.
+fn response<T: Response>() {}
+
+#[test]
+fn builtin_responses_still_qualify() {
+    response::<u8>();
+    response::<f64>();
+    response::<String>();
+    response::<()>();
+    response::<Payload>();
+    response::<Vec<Option<i32>>>();
+    response::<Result<String, u32>>();
+    response::<Box<[u8]>>();
+    response::<Arc<str>>();
+    response::<Arc<String>>();
+    response::<Cow<'static, str>>();
+    response::<Page<Page<bool>>>();
+}
+
+#[tokio::test]
+async fn answers_with_custom_responses() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client.call(AppRequest::List(List { from: 3 })).await;
+    assert!(
+        matches!(
+            &resp,
+            Ok(AppResponse::List(Page { items, next: Some(5) })) if items == &["#3", "#4"]
+        ),
+        "{resp:?}"
+    );
+
+    let resp = client.call(AppRequest::Measure(Measure { side: 0 })).await;
+    assert!(
+        matches!(resp, Ok(AppResponse::Measure(Shape::Circle { radius: 1 }))),
+        "{resp:?}"
+    );
+    let resp = client.call(AppRequest::Measure(Measure { side: 4 })).await;
+    assert!(
+        matches!(resp, Ok(AppResponse::Measure(Shape::Square(4)))),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn arc_responses_round_trip() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client.call(AppRequest::Motd(Motd {})).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Motd(motd)) if motd.as_str() == "welcome"),
+        "{resp:?}"
+    );
+
+    let motd = Arc::new("welcome".to_owned());
+    for codec in [
+        WireFormat::Bincode,
+        WireFormat::Json,
+        WireFormat::MessagePack,
+    ] {
+        let decoded: Arc<String> = codec.decode(&codec.encode(&motd).unwrap()).unwrap();
+        assert_eq!(decoded, motd, "{codec:?}");
+    }
+}
@@ -0,0 +1,70 @@
+//! Responses over the configured size are answered with an error instead.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::RpcError;
+
+use macros::{request, rpc};
+
+use std::net::SocketAddr;
+
+const MAX_RESPONSE_BYTES: usize = 64 * 1024;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Blob(Blob),
+    Ping(Ping),
+}
+
+#[request]
+fn Blob(len: usize) -> Vec<u8> {
+    vec![7; len]
+}
+
+#[request]
+fn Ping() -> String {
+    "pong".into()
+}
+
+async fn spawn_server() -> SocketAddr {
+    let server = Server::builder()
+        .max_response_bytes(MAX_RESPONSE_BYTES)
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    addr
+}
+
+#[tokio::test]
+async fn rejects_oversized_responses() {
+    let addr = spawn_server().await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let small = client.call(AppRequest::Blob(Blob { len: 1024 })).await;
+    assert!(
+        matches!(&small, Ok(AppResponse::Blob(blob)) if blob.len() == 1024),
+        "{small:?}"
+    );
+
+    let large = client
+        .call(AppRequest::Blob(Blob {
+            len: 4 * MAX_RESPONSE_BYTES,
+        }))
+        .await;
+    assert!(
+        matches!(
+            large,
+            Err(client::Error::Rpc(RpcError::ResponseTooLarge { limit }))
+                if limit == MAX_RESPONSE_BYTES as u64
+        ),
+        "{large:?}"
+    );
+
+    // The connection carries on.
+    let ping = client.call(AppRequest::Ping(Ping {})).await;
+    assert!(matches!(ping, Ok(AppResponse::Ping(_))), "{ping:?}");
+}
@@ -0,0 +1,101 @@
+//! Responses to cacheable requests kept and handed out again to repeats of
+//! them, without running their handlers.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::{MemoryCache, Server};
+
+use macros::{request, rpc};
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Square(Square),
+    Cube(Cube),
+    Count(Count),
+}
+
+static SQUARED: AtomicUsize = AtomicUsize::new(0);
+static CUBED: AtomicUsize = AtomicUsize::new(0);
+static COUNTED: AtomicUsize = AtomicUsize::new(0);
+
+#[request(cacheable, ttl = "60s")]
+fn Square(n: u64) -> u64 {
+    SQUARED.fetch_add(1, Ordering::SeqCst);
+    n * n
+}
+
+#[request(cacheable, ttl = "60s")]
+fn Cube(n: u64) -> u64 {
+    CUBED.fetch_add(1, Ordering::SeqCst);
+    n * n * n
+}
+
+#[request]
+fn Count() -> usize {
+    COUNTED.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+async fn serve(cache: MemoryCache) -> SocketAddr {
+    common::serve::<AppRequest>(Server::builder().result_cache(cache)).await
+}
+
+#[tokio::test]
+async fn cacheable_handlers_run_once_per_request() {
+    let cache = MemoryCache::new(16);
+    let addr = serve(cache.clone()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    for _ in 0..2 {
+        let resp = client.call(AppRequest::Square(Square { n: 12 })).await;
+        assert!(matches!(resp, Ok(AppResponse::Square(144))), "{resp:?}");
+    }
+    assert_eq!(SQUARED.load(Ordering::SeqCst), 1);
+
+    // Other connections get the same response, but other fields don't.
+    let other = client::Client::connect(addr).await.unwrap();
+    let resp = other.call(AppRequest::Square(Square { n: 12 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Square(144))), "{resp:?}");
+    assert_eq!(SQUARED.load(Ordering::SeqCst), 1);
+    let resp = other.call(AppRequest::Square(Square { n: 3 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Square(9))), "{resp:?}");
+    assert_eq!((SQUARED.load(Ordering::SeqCst), cache.len()), (2, 2));
+
+    cache.clear();
+    let resp = client.call(AppRequest::Square(Square { n: 12 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Square(144))), "{resp:?}");
+    assert_eq!(SQUARED.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn other_handlers_run_every_time() {
+    let client = client::Client::connect(serve(MemoryCache::new(16)).await)
+        .await
+        .unwrap();
+
+    for expected in 1..=2 {
+        let resp = client.call(AppRequest::Count(Count {})).await;
+        assert!(
+            matches!(resp, Ok(AppResponse::Count(n)) if n == expected),
+            "{resp:?}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn nothing_is_cached_by_default() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    for _ in 0..2 {
+        let resp = client.call(AppRequest::Cube(Cube { n: 2 })).await;
+        assert!(matches!(resp, Ok(AppResponse::Cube(8))), "{resp:?}");
+    }
+    assert_eq!(CUBED.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,301 @@
+//! Runs a real server and client against each other over loopback.
+
+#![allow(non_snake_case)]
+
+use server::{Server, ShutdownHandle};
+
+use protocol::codec::{BincodeCodec, JsonCodec, MessagePackCodec, WireCodec, WireFormat};
+use protocol::frame::{
+    FrameKind, Framing, GoAway, GoAwayReason, Header, decode_header, encode_frame,
+};
+use protocol::{Request, RpcError};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+use std::net::SocketAddr;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Ping(Ping),
+    Pong(Pong),
+    Add(Add),
+    Sum(Sum),
+}
+
+#[request]
+fn Add(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+/// Dispatched by its dotted wire name rather than `Sum`.
+#[request(name = "math.add")]
+fn Sum(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+#[request]
+fn Ping() -> String {
+    "You have been pinged".into()
+}
+
+#[request]
+fn Pong() -> String {
+    "The pong has been sent".into()
+}
+
+/// Starts a server on its own ephemeral port.
+async fn spawn_server() -> (SocketAddr, ShutdownHandle) {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle();
+    tokio::spawn(server.run::<AppRequest>());
+    (addr, handle)
+}
+
+#[tokio::test]
+async fn answers_requests() {
+    let (addr, _handle) = spawn_server().await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client.call(AppRequest::Add(Add { lhs: 2, rhs: 3 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Add(5))), "{resp:?}");
+
+    let resp = client.call(AppRequest::Ping(Ping {})).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Ping(s)) if s == "You have been pinged"),
+        "{resp:?}"
+    );
+
+    let resp = client.call(AppRequest::Pong(Pong {})).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Pong(s)) if s == "The pong has been sent"),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn dispatches_on_dotted_names() {
+    let (addr, _handle) = spawn_server().await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let req = AppRequest::Sum(Sum { lhs: 2, rhs: 3 });
+    assert_eq!(req.name(), "math.add");
+    assert_eq!(Sum::NAME, "math.add");
+
+    let resp = client.call(req).await;
+    assert!(matches!(resp, Ok(AppResponse::Sum(5))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn answers_requests_over_json() {
+    let server = Server::builder()
+        .wire_formats([WireFormat::Json])
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let client = client::Client::builder()
+        .wire_formats([WireFormat::Json])
+        .connect(addr)
+        .await
+        .unwrap();
+    assert_eq!(client.wire_format(), WireFormat::Json);
+
+    let resp = client.call(AppRequest::Add(Add { lhs: -1, rhs: 1 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Add(0))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn answers_requests_over_messagepack() {
+    let server = Server::builder()
+        .wire_formats([WireFormat::Bincode, WireFormat::MessagePack])
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let client = client::Client::builder()
+        .wire_formats([WireFormat::MessagePack, WireFormat::Bincode])
+        .connect(addr)
+        .await
+        .unwrap();
+    assert_eq!(client.wire_format(), WireFormat::MessagePack);
+
+    let resp = client.call(AppRequest::Add(Add { lhs: 2, rhs: 3 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Add(5))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn falls_back_to_bincode_without_messagepack() {
+    let (addr, _handle) = spawn_server().await;
+    let client = client::Client::builder()
+        .wire_formats([WireFormat::MessagePack])
+        .connect(addr)
+        .await
+        .unwrap();
+    assert_eq!(client.wire_format(), WireFormat::FALLBACK);
+
+    let resp = client.call(AppRequest::Add(Add { lhs: 2, rhs: 3 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Add(5))), "{resp:?}");
+}
+
+#[test]
+fn add_round_trips_through_messagepack() {
+    let req = AppRequest::Add(Add { lhs: -7, rhs: 9 });
+    let bytes = MessagePackCodec.encode(&req).unwrap();
+    let decoded: AppRequest = MessagePackCodec.decode(&bytes).unwrap();
+    assert!(
+        matches!(decoded, AppRequest::Add(Add { lhs: -7, rhs: 9 })),
+        "{decoded:?}"
+    );
+}
+
+#[tokio::test]
+async fn shutdown_closes_connections() {
+    let (addr, handle) = spawn_server().await;
+    let client = client::Client::connect(addr).await.unwrap();
+    client.call(AppRequest::Ping(Ping {})).await.unwrap();
+
+    handle.shutdown().await;
+
+    assert!(handle.is_shutting_down());
+    let resp = client.call(AppRequest::Ping(Ping {})).await;
+    assert!(
+        matches!(resp, Err(client::Error::ServerShuttingDown)),
+        "{resp:?}"
+    );
+    assert!(client.is_closed());
+    assert_eq!(client.going_away(), Some(GoAwayReason::Shutdown));
+}
+
+#[tokio::test]
+async fn announces_shutdown_before_closing() {
+    let (addr, handle) = spawn_server().await;
+    let codec = WireFormat::FALLBACK;
+
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    framed.send(Bytes::from(vec![codec.id()])).await.unwrap();
+    framed.next().await.unwrap().unwrap();
+
+    handle.shutdown().await;
+
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&codec, &frame).unwrap();
+    assert_eq!(header.kind, FrameKind::GoAway);
+    let go_away: GoAway = codec.decode(body).unwrap();
+    assert_eq!(go_away.reason, GoAwayReason::Shutdown);
+
+    assert!(framed.next().await.is_none());
+}
+
+#[tokio::test]
+async fn rejects_malformed_requests() {
+    let (addr, _handle) = spawn_server().await;
+    let codec = WireFormat::FALLBACK;
+
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    framed.send(Bytes::from(vec![codec.id()])).await.unwrap();
+    let chosen = framed.next().await.unwrap().unwrap();
+    assert_eq!(*chosen, [codec.id()]);
+
+    // A well-formed header followed by a body that isn't an `AppRequest`.
+    let mut frame = encode_frame(&codec, &Header::request(7, Add::NAME, 1), &()).unwrap();
+    frame.push(0xff);
+    framed.send(Bytes::from(frame)).await.unwrap();
+
+    let reply = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&codec, &reply).unwrap();
+    assert_eq!(header.id, 7);
+    assert_eq!(header.kind, FrameKind::Error);
+    let error: RpcError = codec.decode(body).unwrap();
+    assert!(matches!(error, RpcError::Decode(_)), "{error:?}");
+
+    // The server hangs up on a client it can't make sense of.
+    assert!(framed.next().await.is_none());
+}
+
+/// Encodes `req`, runs it through [`server::handle_request`] and decodes the
+/// response, all with `codec`.
+async fn round_trip(codec: &impl WireCodec, req: AppRequest) -> AppResponse {
+    let frame = encode_frame(codec, &Header::new(1, FrameKind::Request), &req).unwrap();
+    let reply = server::handle_request::<AppRequest>(codec, &frame)
+        .await
+        .unwrap();
+    let (header, body) = decode_header(codec, &reply).unwrap();
+    assert_eq!(header.kind, FrameKind::Response);
+    codec.decode(body).unwrap()
+}
+
+#[tokio::test]
+async fn handles_requests_with_any_codec() {
+    let req = || AppRequest::Add(Add { lhs: 20, rhs: 22 });
+    let bincode = round_trip(&BincodeCodec, req()).await;
+    let json = round_trip(&JsonCodec, req()).await;
+    let messagepack = round_trip(&MessagePackCodec, req()).await;
+
+    assert!(matches!(bincode, AppResponse::Add(42)), "{bincode:?}");
+    assert!(matches!(json, AppResponse::Add(42)), "{json:?}");
+    assert!(
+        matches!(messagepack, AppResponse::Add(42)),
+        "{messagepack:?}"
+    );
+}
+
+#[test]
+fn errors_round_trip_in_every_format() {
+    let errors = [
+        RpcError::UnknownMethod("Missing".into()),
+        RpcError::Internal("oops".into()),
+        RpcError::Handler("3 is odd".into()),
+        RpcError::Busy("Export".into()),
+        RpcError::ResponseTooLarge { limit: 1024 },
+        RpcError::ValidationFailed("too long".into()),
+        RpcError::Timeout("took over 5 s".into()),
+        RpcError::RateLimited {
+            retry_after_ms: Some(250),
+        },
+        RpcError::RateLimited {
+            retry_after_ms: None,
+        },
+        RpcError::Unauthorized("no token".into()),
+        RpcError::DeadlineExceeded("Add".into()),
+    ];
+    for codec in [
+        WireFormat::Bincode,
+        WireFormat::Json,
+        WireFormat::MessagePack,
+    ] {
+        for error in &errors {
+            let decoded: RpcError = codec.decode(&codec.encode(error).unwrap()).unwrap();
+            assert_eq!(&decoded, error, "{codec:?}");
+        }
+    }
+
+    let error: &dyn std::error::Error = &errors[7];
+    assert_eq!(error.to_string(), "rate limited, retry after 250 ms");
+}
+
+#[tokio::test]
+async fn server_errors_convert_to_what_clients_see() {
+    let codec = WireFormat::FALLBACK;
+    let mut frame = encode_frame(&codec, &Header::request(7, Add::NAME, 1), &()).unwrap();
+    frame.push(0xff);
+    let err = server::handle_request::<AppRequest>(&codec, &frame)
+        .await
+        .unwrap_err();
+    let error = RpcError::from(err);
+    assert!(matches!(error, RpcError::Decode(_)), "{error:?}");
+
+    let error = RpcError::from(server::Error::Handshake);
+    assert!(matches!(error, RpcError::Internal(_)), "{error:?}");
+}
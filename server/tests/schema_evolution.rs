@@ -0,0 +1,172 @@
+//! Messages keeping their meaning across peers whose types have since
+//! gained or lost fields, in the MessagePack format keying fields by name.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::codec::{BincodeCodec, CodecError, MessagePackCodec, WireCodec, WireFormat};
+
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+
+use std::net::SocketAddr;
+
+/// The requests as first released.
+mod v1 {
+    use macros::{request, rpc};
+
+    #[rpc(response = "AppResponse")]
+    pub enum AppRequest {
+        Greet(Greet),
+    }
+
+    #[request]
+    pub fn Greet(name: String) -> String {
+        format!("Hello, {name}")
+    }
+}
+
+/// The requests once `Greet` took an optional title.
+mod v2 {
+    use macros::{request, rpc};
+
+    #[rpc(response = "AppResponse")]
+    pub enum AppRequest {
+        Greet(Greet),
+    }
+
+    #[request]
+    pub fn Greet(name: String, title: Option<String>) -> String {
+        match title {
+            Some(title) => format!("Hello, {title} {name}"),
+            None => format!("Hello, {name}"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Encode, Decode, Serialize, Deserialize)]
+struct Old {
+    id: u32,
+    name: String,
+}
+
+#[derive(Debug, PartialEq, Encode, Decode, Serialize, Deserialize)]
+struct New {
+    id: u32,
+    nickname: Option<String>,
+    name: String,
+}
+
+#[test]
+fn optional_fields_can_be_added() {
+    let old = Old {
+        id: 7,
+        name: "Ada".into(),
+    };
+    let new = New {
+        id: 7,
+        nickname: Some("Countess".into()),
+        name: "Ada".into(),
+    };
+    let codec = MessagePackCodec;
+
+    // New peers read what old ones sent, without the field.
+    let decoded: New = codec.decode_exact(&codec.encode(&old).unwrap()).unwrap();
+    assert_eq!(
+        decoded,
+        New {
+            nickname: None,
+            ..new
+        }
+    );
+
+    // Old peers read what new ones sent, skipping the field.
+    let new = New {
+        nickname: Some("Countess".into()),
+        ..decoded
+    };
+    let decoded: Old = codec.decode_exact(&codec.encode(&new).unwrap()).unwrap();
+    assert_eq!(decoded, old);
+}
+
+#[test]
+fn bincode_misreads_the_same_change() {
+    let old = Old {
+        id: 7,
+        name: "Ada".into(),
+    };
+    let decoded = BincodeCodec.decode_exact::<New>(&BincodeCodec.encode(&old).unwrap());
+    assert!(decoded.is_err(), "{decoded:?}");
+}
+
+#[test]
+fn renamed_fields_still_fail_to_decode() {
+    #[derive(Debug, Encode, Decode, Serialize, Deserialize)]
+    struct Renamed {
+        id: u32,
+        full_name: String,
+    }
+
+    let codec = MessagePackCodec;
+    let old = Old {
+        id: 7,
+        name: "Ada".into(),
+    };
+    let decoded = codec.decode::<Renamed>(&codec.encode(&old).unwrap());
+    assert!(
+        matches!(decoded, Err(CodecError::MessagePackDecode { .. })),
+        "{decoded:?}"
+    );
+}
+
+async fn serve<Req: protocol::Request>() -> SocketAddr {
+    let server = Server::builder()
+        .wire_formats([WireFormat::MessagePack])
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<Req>());
+    addr
+}
+
+async fn connect(addr: SocketAddr) -> client::Client {
+    let client = client::Client::builder()
+        .wire_formats([WireFormat::MessagePack])
+        .connect(addr)
+        .await
+        .unwrap();
+    assert_eq!(client.wire_format(), WireFormat::MessagePack);
+    client
+}
+
+#[tokio::test]
+async fn new_clients_call_old_servers() {
+    let client = connect(serve::<v1::AppRequest>().await).await;
+
+    let resp = client
+        .call(v2::AppRequest::Greet(v2::Greet {
+            name: "Ada".into(),
+            title: Some("Countess".into()),
+        }))
+        .await;
+    // The old server skips the title it doesn't know.
+    assert!(
+        matches!(&resp, Ok(v2::AppResponse::Greet(text)) if text == "Hello, Ada"),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn old_clients_call_new_servers() {
+    let client = connect(serve::<v2::AppRequest>().await).await;
+
+    let resp = client
+        .call(v1::AppRequest::Greet(v1::Greet { name: "Ada".into() }))
+        .await;
+    assert!(
+        matches!(&resp, Ok(v1::AppResponse::Greet(text)) if text == "Hello, Ada"),
+        "{resp:?}"
+    );
+}
@@ -0,0 +1,171 @@
+//! Frames numbered by the client, checked by the server for gaps and repeats.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, Error, Server, handle_connection};
+
+use protocol::Request;
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+use protocol::hello::{Hello, HelloAck};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio::task::JoinHandle;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::sync::CancellationToken;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+type Peer = Framed<DuplexStream, LengthDelimitedCodec>;
+
+/// A connection to a server configured by `config`, past the handshake.
+async fn connect(config: ConnectionConfig) -> (Peer, WireFormat, JoinHandle<server::Result<()>>) {
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let serving = tokio::spawn(async move {
+        handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+    });
+
+    let mut framed = Framed::new(client_end, Framing::default().codec());
+    framed
+        .send(Bytes::from(Hello::default().encode()))
+        .await
+        .unwrap();
+    let ack = HelloAck::decode(&framed.next().await.unwrap().unwrap()).unwrap();
+    (framed, ack.wire_format().unwrap(), serving)
+}
+
+async fn echo(framed: &mut Peer, codec: &WireFormat, id: u64, seq: Option<u64>) {
+    let req = AppRequest::Echo(Echo {
+        text: format!("#{id}"),
+    });
+    let header = Header::request(id, req.name(), req.version()).with_seq(seq);
+    let frame = encode_frame(codec, &header, &req).unwrap();
+    framed.send(Bytes::from(frame)).await.unwrap();
+}
+
+async fn expect_echo(framed: &mut Peer, codec: &WireFormat, id: u64) {
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(codec, &frame).unwrap();
+    assert_eq!((header.id, header.kind), (id, FrameKind::Response));
+    let resp: AppResponse = codec.decode(body).unwrap();
+    let expected = format!("#{id}");
+    assert!(
+        matches!(&resp, AppResponse::Echo(s) if *s == expected),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn a_gap_closes_the_connection() {
+    let (mut framed, codec, serving) = connect(ConnectionConfig::default()).await;
+
+    echo(&mut framed, &codec, 1, Some(1)).await;
+    expect_echo(&mut framed, &codec, 1).await;
+    echo(&mut framed, &codec, 2, Some(2)).await;
+    expect_echo(&mut framed, &codec, 2).await;
+
+    // Frame 3 went missing.
+    echo(&mut framed, &codec, 3, Some(4)).await;
+    let res = serving.await.unwrap();
+    assert!(
+        matches!(
+            res,
+            Err(Error::ProtocolDesync {
+                expected: 3,
+                found: Some(4)
+            })
+        ),
+        "{res:?}"
+    );
+    assert!(framed.next().await.is_none());
+}
+
+#[tokio::test]
+async fn a_repeat_closes_the_connection() {
+    let (mut framed, codec, serving) = connect(ConnectionConfig::default()).await;
+
+    echo(&mut framed, &codec, 1, Some(1)).await;
+    expect_echo(&mut framed, &codec, 1).await;
+    echo(&mut framed, &codec, 2, Some(1)).await;
+
+    let res = serving.await.unwrap();
+    assert!(
+        matches!(
+            res,
+            Err(Error::ProtocolDesync {
+                expected: 2,
+                found: Some(1)
+            })
+        ),
+        "{res:?}"
+    );
+}
+
+#[tokio::test]
+async fn numbers_are_optional_unless_required() {
+    let (mut framed, codec, _serving) = connect(ConnectionConfig::default()).await;
+    echo(&mut framed, &codec, 1, None).await;
+    expect_echo(&mut framed, &codec, 1).await;
+
+    let config = ConnectionConfig {
+        require_sequence_numbers: true,
+        ..ConnectionConfig::default()
+    };
+    let (mut framed, codec, serving) = connect(config).await;
+    echo(&mut framed, &codec, 1, None).await;
+    let res = serving.await.unwrap();
+    assert!(
+        matches!(
+            res,
+            Err(Error::ProtocolDesync {
+                expected: 1,
+                found: None
+            })
+        ),
+        "{res:?}"
+    );
+}
+
+#[tokio::test]
+async fn a_numbering_client_satisfies_a_requiring_server() {
+    let server = Server::builder()
+        .require_sequence_numbers(true)
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let client = client::Client::builder()
+        .sequence_numbers(true)
+        .connect(addr)
+        .await
+        .unwrap();
+    let calls = (0..20).map(|i| {
+        let client = client.clone();
+        async move {
+            let text = format!("#{i}");
+            let resp = client
+                .call(AppRequest::Echo(Echo { text: text.clone() }))
+                .await;
+            assert!(
+                matches!(&resp, Ok(AppResponse::Echo(s)) if *s == text),
+                "{resp:?}"
+            );
+        }
+    });
+    futures::future::join_all(calls).await;
+    client.close().await;
+}
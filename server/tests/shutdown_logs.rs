@@ -0,0 +1,72 @@
+//! The server binary flushing every log line, down to the last, before it
+//! exits on `SIGTERM`.
+
+#![cfg(unix)]
+
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Polls `done` until it holds, for up to ten seconds.
+fn wait_for(what: &str, mut done: impl FnMut() -> bool) {
+    let started = Instant::now();
+    while !done() {
+        assert!(started.elapsed() < Duration::from_secs(10), "{what}");
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Messages of the JSON lines logged to `file` so far.
+fn messages(file: &Path) -> Vec<String> {
+    let Ok(logs) = std::fs::read_to_string(file) else {
+        return Vec::new();
+    };
+    logs.lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter_map(|line| line["fields"]["message"].as_str().map(String::from))
+        .collect()
+}
+
+fn terminate(child: &Child) {
+    let status = Command::new("kill")
+        .arg("-TERM")
+        .arg(child.id().to_string())
+        .status()
+        .unwrap();
+    assert!(status.success(), "{status:?}");
+}
+
+#[test]
+fn last_log_lines_reach_the_file() {
+    let dir = std::env::temp_dir().join(format!("tcp-rpc-shutdown-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let file = dir.join("app.log");
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+        .env("LOG_DIR", &dir)
+        .env("LOG_ROTATION", "never")
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    wait_for("server never started", || {
+        messages(&file).iter().any(|m| m == "started server")
+    });
+
+    terminate(&child);
+    wait_for("server never exited", || {
+        child.try_wait().unwrap().is_some()
+    });
+    assert!(child.wait().unwrap().success());
+
+    let messages = messages(&file);
+    let _ = std::fs::remove_dir_all(&dir);
+    assert!(
+        messages.iter().any(|m| m == "Shutting down server..."),
+        "{messages:?}"
+    );
+    assert_eq!(
+        messages.last().map(String::as_str),
+        Some("server stopped"),
+        "{messages:?}"
+    );
+}
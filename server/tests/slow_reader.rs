@@ -0,0 +1,96 @@
+//! A client that doesn't read its responses holds up its own connection
+//! rather than have the server buffer them.
+
+#![allow(non_snake_case)]
+
+use server::{GlobalMemoryLimit, Server};
+
+use protocol::Request;
+use protocol::codec::WireFormat;
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+const RESPONSE_BYTES: usize = 256 * 1024;
+const REQUESTS: u64 = 100;
+const WRITE_QUEUE: usize = 2;
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Big(Big),
+}
+
+#[request]
+fn Big() -> Vec<u8> {
+    HANDLED.fetch_add(1, Ordering::SeqCst);
+    vec![7; RESPONSE_BYTES]
+}
+
+/// Waits for the handled count to stop moving, returning where it stopped.
+async fn settled() -> usize {
+    let mut last = HANDLED.load(Ordering::SeqCst);
+    loop {
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let now = HANDLED.load(Ordering::SeqCst);
+        if now == last {
+            return now;
+        }
+        last = now;
+    }
+}
+
+#[tokio::test]
+async fn stops_reading_while_the_write_queue_is_full() {
+    let memory = GlobalMemoryLimit::new(usize::MAX);
+    let server = Server::builder()
+        .write_queue(WRITE_QUEUE)
+        .max_in_flight(1)
+        .memory_limit(memory.clone())
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let codec = WireFormat::FALLBACK;
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    framed.send(Bytes::from(vec![codec.id()])).await.unwrap();
+    framed.next().await.unwrap().unwrap();
+
+    for id in 1..=REQUESTS {
+        let header = Header::request(id, Big::NAME, Big::VERSION);
+        let frame = encode_frame(&codec, &header, &AppRequest::Big(Big {})).unwrap();
+        framed.send(Bytes::from(frame)).await.unwrap();
+    }
+
+    let handled = settled().await;
+    assert!(handled < REQUESTS as usize / 2, "handled {handled}");
+    // The queue, the frame being written and the one waiting to get into
+    // the queue; the rest sit in the socket buffers.
+    let buffered = memory.used();
+    assert!(
+        buffered <= (WRITE_QUEUE + 3) * (RESPONSE_BYTES + 1024),
+        "buffered {buffered} bytes"
+    );
+
+    let mut ids = Vec::new();
+    while ids.len() < REQUESTS as usize {
+        let frame = framed.next().await.unwrap().unwrap();
+        let (header, _) = decode_header(&codec, &frame).unwrap();
+        assert_eq!(header.kind, FrameKind::Response);
+        ids.push(header.id);
+    }
+    assert_eq!(ids, (1..=REQUESTS).collect::<Vec<_>>());
+    assert_eq!(HANDLED.load(Ordering::SeqCst), REQUESTS as usize);
+}
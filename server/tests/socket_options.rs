@@ -0,0 +1,46 @@
+//! Socket options set on accepted connections.
+
+use server::Server;
+
+use socket2::SockRef;
+use tokio::net::{TcpListener, TcpStream};
+
+use std::time::Duration;
+
+/// A connection accepted on a listener of our own, configured by `server`.
+async fn accepted(server: &Server) -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap())
+        .await
+        .unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+    server.configure_stream(&stream).unwrap();
+    (stream, client)
+}
+
+#[tokio::test]
+async fn nodelay_is_on_by_default_and_keepalive_off() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let (stream, _client) = accepted(&server).await;
+
+    let socket = SockRef::from(&stream);
+    assert!(socket.nodelay().unwrap());
+    assert!(!socket.keepalive().unwrap());
+}
+
+#[tokio::test]
+async fn applies_the_options_given() {
+    let server = Server::builder()
+        .nodelay(false)
+        .keepalive(Some(Duration::from_secs(45)))
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let (stream, _client) = accepted(&server).await;
+
+    let socket = SockRef::from(&stream);
+    assert!(!socket.nodelay().unwrap());
+    assert!(socket.keepalive().unwrap());
+    #[cfg(target_os = "linux")]
+    assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(45));
+}
@@ -0,0 +1,150 @@
+//! Request bodies with bytes left over after the request.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, Error, Server, handle_connection};
+
+use protocol::codec::{CodecError, WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame};
+use protocol::hello::{Hello, HelloAck};
+use protocol::{DecodeErrorKind, DecodeFailure, Request, RpcError};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::io::DuplexStream;
+use tokio::task::JoinHandle;
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
+use tokio_util::sync::CancellationToken;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+type Peer = Framed<DuplexStream, LengthDelimitedCodec>;
+
+/// A connection to a server configured by `config`, past the handshake.
+async fn connect(config: ConnectionConfig) -> (Peer, JoinHandle<server::Result<()>>) {
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    let serving = tokio::spawn(async move {
+        handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+    });
+
+    let mut framed = Framed::new(client_end, Framing::default().codec());
+    framed
+        .send(Bytes::from(Hello::default().encode()))
+        .await
+        .unwrap();
+    let ack = HelloAck::decode(&framed.next().await.unwrap().unwrap()).unwrap();
+    assert_eq!(ack.wire_format(), Some(WireFormat::Bincode));
+    (framed, serving)
+}
+
+/// Sends an echo of "hi" with `trailing` after it in the frame, returning
+/// how long the body is without it.
+async fn echo_with_trailing(framed: &mut Peer, trailing: &[u8]) -> usize {
+    let req = AppRequest::Echo(Echo { text: "hi".into() });
+    let header = Header::request(1, req.name(), req.version());
+    let mut frame = encode_frame(&WireFormat::Bincode, &header, &req).unwrap();
+    frame.extend_from_slice(trailing);
+    framed.send(Bytes::from(frame)).await.unwrap();
+    WireFormat::Bincode.encode(&req).unwrap().len()
+}
+
+#[tokio::test]
+async fn trailing_bytes_are_ignored_by_default() {
+    let (mut framed, _serving) = connect(ConnectionConfig::default()).await;
+    echo_with_trailing(&mut framed, b"garbage").await;
+
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&WireFormat::Bincode, &frame).unwrap();
+    assert_eq!(header.kind, FrameKind::Response);
+    let resp: AppResponse = WireFormat::Bincode.decode(body).unwrap();
+    assert!(
+        matches!(&resp, AppResponse::Echo(s) if s == "hi"),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn trailing_bytes_are_rejected_when_strict() {
+    let config = ConnectionConfig {
+        strict_framing: true,
+        ..ConnectionConfig::default()
+    };
+    let (mut framed, serving) = connect(config).await;
+    let len = echo_with_trailing(&mut framed, b"garbage").await;
+
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&WireFormat::Bincode, &frame).unwrap();
+    assert_eq!(header.kind, FrameKind::Error);
+    let err: RpcError = WireFormat::Bincode.decode(body).unwrap();
+    assert!(
+        matches!(
+            &err,
+            RpcError::Decode(DecodeFailure {
+                kind: DecodeErrorKind::TrailingBytes,
+                offset: Some(offset),
+                ..
+            }) if *offset == len as u64
+        ),
+        "{err:?}"
+    );
+
+    let res = serving.await.unwrap();
+    assert!(matches!(res, Err(Error::Rejected { id: 1, .. })), "{res:?}");
+    assert!(framed.next().await.is_none());
+}
+
+#[tokio::test]
+async fn exact_frames_pass_when_strict() {
+    let server = Server::builder()
+        .wire_formats(vec![WireFormat::Bincode, WireFormat::Json])
+        .strict_framing(true)
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    for format in [WireFormat::Bincode, WireFormat::Json] {
+        let client = client::Client::builder()
+            .wire_formats(vec![format])
+            .connect(addr)
+            .await
+            .unwrap();
+        assert_eq!(client.wire_format(), format);
+        let resp = client
+            .call(AppRequest::Echo(Echo { text: "hi".into() }))
+            .await;
+        assert!(
+            matches!(&resp, Ok(AppResponse::Echo(s)) if s == "hi"),
+            "{resp:?}"
+        );
+    }
+}
+
+#[test]
+fn decode_exact_reports_where_the_value_ended() {
+    let mut bytes = WireFormat::Bincode.encode(&7u32).unwrap();
+    let len = bytes.len();
+    assert!(matches!(
+        WireFormat::Bincode.decode_exact::<u32>(&bytes),
+        Ok(7)
+    ));
+
+    bytes.push(0);
+    assert!(matches!(WireFormat::Bincode.decode::<u32>(&bytes), Ok(7)));
+    let res = WireFormat::Bincode.decode_exact::<u32>(&bytes);
+    assert!(
+        matches!(res, Err(CodecError::TrailingBytes { consumed, len: l }) if consumed == len && l == len + 1),
+        "{res:?}"
+    );
+}
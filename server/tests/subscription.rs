@@ -0,0 +1,142 @@
+//! Events pushed by the server for as long as a client stays subscribed.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::Subscription;
+use protocol::frame::GoAwayReason;
+use protocol::hello::FEATURES;
+
+use macros::{request, rpc};
+
+use futures::StreamExt;
+use tokio::sync::broadcast;
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Subscribe(Subscribe),
+    Publish(Publish),
+    Count(Count),
+    Idle(Idle),
+}
+
+static EVENTS: LazyLock<broadcast::Sender<String>> = LazyLock::new(|| broadcast::channel(16).0);
+
+#[request]
+fn Subscribe() -> Subscription<String> {
+    Subscription::from_broadcast(EVENTS.subscribe())
+}
+
+#[request]
+fn Publish(event: String) -> usize {
+    EVENTS.send(event).unwrap_or(0)
+}
+
+#[request]
+fn Count(to: u32) -> Subscription<u32> {
+    Subscription::from_stream(futures::stream::iter(1..=to))
+}
+
+#[request]
+fn Idle() -> Subscription<u32> {
+    Subscription::from_stream(futures::stream::pending())
+}
+
+#[tokio::test]
+async fn events_arrive_until_the_client_cancels() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let subscriber = client::Client::connect(addr).await.unwrap();
+    let publisher = client::Client::connect(addr).await.unwrap();
+    assert!(FEATURES.contains(&"subscriptions"));
+
+    let mut events = subscriber
+        .subscribe::<String, _>(AppRequest::Subscribe(Subscribe {}))
+        .await
+        .unwrap();
+    assert_eq!(subscriber.in_flight(), 1);
+
+    // Started by now, so nothing published from here on is missed.
+    for event in ["first", "second"] {
+        let resp = publisher
+            .call(AppRequest::Publish(Publish {
+                event: event.into(),
+            }))
+            .await;
+        assert!(matches!(resp, Ok(AppResponse::Publish(1))), "{resp:?}");
+    }
+    for expected in ["first", "second"] {
+        let event = events.next().await.unwrap();
+        assert!(matches!(&event, Ok(e) if e == expected), "{event:?}");
+    }
+
+    // The connection stays usable alongside the subscription.
+    let resp = subscriber
+        .call(AppRequest::Publish(Publish {
+            event: "third".into(),
+        }))
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Publish(1))), "{resp:?}");
+    let event = events.next().await.unwrap();
+    assert!(matches!(&event, Ok(e) if e == "third"), "{event:?}");
+
+    drop(events);
+    assert_eq!(subscriber.in_flight(), 0);
+    // The server lets go of the subscription once told.
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while EVENTS.receiver_count() > 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+    })
+    .await
+    .expect("subscription still open on the server");
+}
+
+#[tokio::test]
+async fn ends_once_the_server_runs_out_of_events() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let events = client
+        .subscribe::<u32, _>(AppRequest::Count(Count { to: 3 }))
+        .await
+        .unwrap();
+    let events: Vec<_> = events.map(Result::unwrap).collect().await;
+    assert_eq!(events, [1, 2, 3]);
+    assert_eq!(client.in_flight(), 0);
+
+    let resp = client.call(AppRequest::Count(Count { to: 0 })).await;
+    assert!(resp.is_err(), "{resp:?}");
+}
+
+#[tokio::test]
+async fn fails_once_the_server_shuts_down() {
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle();
+    tokio::spawn(server.run::<AppRequest>());
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let mut events = client
+        .subscribe::<u32, _>(AppRequest::Idle(Idle {}))
+        .await
+        .unwrap();
+    handle.shutdown().await;
+
+    // Whether the server got to end it before closing the connection or not.
+    let event = events.next().await.unwrap();
+    assert!(
+        matches!(
+            event,
+            Err(client::Error::Rpc(_) | client::Error::ServerShuttingDown)
+        ),
+        "{event:?}"
+    );
+    assert_eq!(client.going_away(), Some(GoAwayReason::Shutdown));
+    assert!(events.next().await.is_none());
+}
@@ -0,0 +1,156 @@
+//! Trace context carried from the client to the server's dispatch span.
+
+#![allow(non_snake_case)]
+
+use server::Server;
+
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Header, Method, TraceContext};
+
+use macros::{request, rpc};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing_subscriber::layer::{Context, SubscriberExt};
+use tracing_subscriber::{Layer, Registry};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Ping(Ping),
+}
+
+#[request]
+fn Ping() -> String {
+    "pong".into()
+}
+
+const CALLER: TraceContext = TraceContext {
+    trace_id: 0x4bf92f3577b34da6a3ce929d0e0e4736,
+    span_id: 0x00f067aa0ba902b7,
+    sampled: true,
+};
+
+/// Keeps the `trace.*` fields of every dispatch span.
+#[derive(Clone, Default)]
+struct DispatchSpans(Arc<Mutex<HashMap<Id, HashMap<String, String>>>>);
+
+struct TraceFields<'a>(&'a mut HashMap<String, String>);
+
+impl Visit for TraceFields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name().starts_with("trace.") {
+            self.0.insert(field.name().to_owned(), format!("{value:?}"));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name().starts_with("trace.") {
+            self.0.insert(field.name().to_owned(), value.to_owned());
+        }
+    }
+}
+
+impl<S: tracing::Subscriber> Layer<S> for DispatchSpans {
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _: Context<'_, S>) {
+        if attrs.metadata().name() == "dispatch" {
+            let mut spans = self.0.lock().unwrap();
+            attrs.record(&mut TraceFields(spans.entry(id.clone()).or_default()));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, _: Context<'_, S>) {
+        if let Some(fields) = self.0.lock().unwrap().get_mut(id) {
+            values.record(&mut TraceFields(fields));
+        }
+    }
+}
+
+impl DispatchSpans {
+    fn fields(&self) -> Vec<HashMap<String, String>> {
+        self.0.lock().unwrap().values().cloned().collect()
+    }
+}
+
+#[tokio::test]
+async fn server_span_carries_the_callers_context() {
+    let spans = DispatchSpans::default();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(spans.clone()));
+
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let client = client::Client::builder()
+        .trace_context(|| Some(CALLER))
+        .connect(addr)
+        .await
+        .unwrap();
+    let resp = client.call(AppRequest::Ping(Ping {})).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Ping(s)) if s == "pong"),
+        "{resp:?}"
+    );
+
+    let fields = spans.fields();
+    assert_eq!(fields.len(), 1, "{fields:?}");
+    assert_eq!(fields[0]["trace.id"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(fields[0]["trace.parent_id"], "00f067aa0ba902b7");
+    assert_eq!(fields[0]["trace.sampled"], "true");
+}
+
+#[tokio::test]
+async fn untraced_requests_leave_the_fields_empty() {
+    let spans = DispatchSpans::default();
+    let _guard = tracing::subscriber::set_default(Registry::default().with(spans.clone()));
+
+    let server = Server::builder().bind("127.0.0.1:0").await.unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let client = client::Client::connect(addr).await.unwrap();
+    client.call(AppRequest::Ping(Ping {})).await.unwrap();
+
+    let fields = spans.fields();
+    assert_eq!(fields.len(), 1, "{fields:?}");
+    assert!(fields[0].is_empty(), "{fields:?}");
+}
+
+#[test]
+fn formats_as_traceparent() {
+    assert_eq!(
+        CALLER.to_string(),
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    );
+}
+
+#[test]
+fn headers_round_trip_with_and_without_trace() {
+    for codec in [WireFormat::Bincode, WireFormat::Json] {
+        let traced = Header::request(3, "Ping", 1).with_trace(Some(CALLER));
+        let decoded: Header = codec.decode(&codec.encode(&traced).unwrap()).unwrap();
+        assert_eq!(decoded, traced, "{codec:?}");
+
+        // What a peer predating trace context sends.
+        let legacy = match codec {
+            WireFormat::Bincode => {
+                let method = Some(Method {
+                    name: "Ping".into(),
+                    version: 1,
+                });
+                codec
+                    .encode(&(3u64, FrameKind::VersionedRequest, method))
+                    .unwrap()
+            }
+            WireFormat::Json => {
+                br#"{"id":3,"kind":"VersionedRequest","method":{"name":"Ping","version":1}}"#
+                    .to_vec()
+            }
+            WireFormat::MessagePack => unreachable!("not among the formats tried"),
+        };
+        let decoded: Header = codec.decode(&legacy).unwrap();
+        assert_eq!(decoded, Header::request(3, "Ping", 1), "{codec:?}");
+    }
+}
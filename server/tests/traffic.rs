@@ -0,0 +1,62 @@
+//! Byte counts per request type.
+
+#![allow(non_snake_case)]
+
+use server::{ByteCounts, Server, Traffic};
+
+use protocol::Request;
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Header, encode_frame};
+
+use macros::{request, rpc};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+}
+
+#[request]
+fn Echo(text: String) -> String {
+    text
+}
+
+#[tokio::test]
+async fn counts_frame_and_body_bytes() {
+    let traffic = Traffic::new();
+    let server = Server::builder()
+        .traffic(traffic.clone())
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let client = client::Client::connect(addr).await.unwrap();
+    let texts = ["hello", "a somewhat longer message"];
+    for text in texts {
+        let req = AppRequest::Echo(Echo { text: text.into() });
+        client.call(req).await.unwrap();
+    }
+
+    // The same frames the client and server sent, ids and all, but for the
+    // request ids, which only have the same length.
+    let codec = WireFormat::FALLBACK;
+    let mut expected = ByteCounts::default();
+    for (id, text) in (1..).zip(texts) {
+        let req = AppRequest::Echo(Echo { text: text.into() });
+        let header =
+            Header::request(id, Echo::NAME, Echo::VERSION).with_request_id(Some("0".repeat(26)));
+        let request = encode_frame(&codec, &header, &req).unwrap();
+        let resp = AppResponse::Echo(text.into());
+        let response = encode_frame(&codec, &Header::new(id, FrameKind::Response), &resp).unwrap();
+
+        expected.requests += 1;
+        expected.request_frame_bytes += request.len() as u64;
+        expected.request_body_bytes += codec.encode(&req).unwrap().len() as u64;
+        expected.response_frame_bytes += response.len() as u64;
+        expected.response_body_bytes += codec.encode(&resp).unwrap().len() as u64;
+    }
+
+    assert_eq!(traffic.get(Echo::NAME), Some(expected));
+    assert_eq!(traffic.snapshot().len(), 1);
+}
@@ -0,0 +1,74 @@
+//! Metadata handlers attach to their responses, read apart from them.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::Context;
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Header, Trailers};
+
+use macros::{request, rpc};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Page(Page),
+    Plain(Plain),
+}
+
+#[request]
+fn Page(from: u32, ctx: Context) -> Vec<u32> {
+    ctx.set_trailer("cursor", (from + 3).to_string());
+    ctx.set_trailer("cache", "miss");
+    ctx.set_trailer("cache", "hit");
+    (from..from + 3).collect()
+}
+
+#[request]
+fn Plain() -> u8 {
+    1
+}
+
+#[tokio::test]
+async fn client_reads_the_handlers_trailers() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let (resp, trailers) = client
+        .call_with_trailers(AppRequest::Page(Page { from: 4 }))
+        .await
+        .unwrap();
+    assert!(
+        matches!(&resp, AppResponse::Page(page) if page == &[4, 5, 6]),
+        "{resp:?}"
+    );
+    assert_eq!(
+        trailers,
+        Trailers::from([
+            ("cursor".into(), "7".into()),
+            ("cache".into(), "hit".into())
+        ])
+    );
+
+    let (_, trailers) = client
+        .call_with_trailers(AppRequest::Plain(Plain {}))
+        .await
+        .unwrap();
+    assert!(trailers.is_empty(), "{trailers:?}");
+
+    // Callers not asking for them get the response as ever.
+    let resp = client.call(AppRequest::Page(Page { from: 0 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Page(_))), "{resp:?}");
+}
+
+#[test]
+fn headers_round_trip_with_trailers() {
+    for codec in [WireFormat::Bincode, WireFormat::Json] {
+        let header = Header::new(9, FrameKind::Response)
+            .with_trailers(Trailers::from([("cache".into(), "hit".into())]));
+        let decoded: Header = codec.decode(&codec.encode(&header).unwrap()).unwrap();
+        assert_eq!(decoded, header, "{codec:?}");
+    }
+}
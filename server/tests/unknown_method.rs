@@ -0,0 +1,88 @@
+//! Requests a newer client sends that this server doesn't have, turned away
+//! by name rather than decoded into whichever request shares their place.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::codec::WireFormat;
+use protocol::frame::{Header, encode_frame};
+use protocol::{DecodeErrorKind, Request, RpcError};
+
+use macros::{request, rpc};
+
+use std::net::SocketAddr;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+    Mul(Mul),
+}
+
+/// What a newer client has, with `Sub` where the server has `Mul`.
+#[rpc(response = "NewerResponse")]
+enum NewerRequest {
+    Add(Add),
+    Sub(Sub),
+    Mul(Mul),
+}
+
+#[request]
+fn Add(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+#[request]
+fn Sub(lhs: i32, rhs: i32) -> i32 {
+    lhs - rhs
+}
+
+#[request]
+fn Mul(lhs: i32, rhs: i32) -> i32 {
+    lhs * rhs
+}
+
+async fn serve() -> SocketAddr {
+    common::serve::<AppRequest>(Server::builder().max_rejected_requests(8)).await
+}
+
+#[tokio::test]
+async fn unknown_methods_are_turned_away_by_name() {
+    let client = client::Client::connect(serve().await).await.unwrap();
+
+    let resp = client.call(NewerRequest::Sub(Sub { lhs: 5, rhs: 3 })).await;
+    assert!(
+        matches!(
+            &resp,
+            Err(client::Error::Rpc(RpcError::UnknownMethod(name))) if name == "Sub"
+        ),
+        "{resp:?}"
+    );
+
+    // The connection goes on for the requests both have.
+    let resp = client.call(NewerRequest::Add(Add { lhs: 5, rhs: 3 })).await;
+    assert!(matches!(resp, Ok(NewerResponse::Add(8))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn requests_decoding_as_another_are_turned_away() {
+    // Sent as `Mul`, laid out as the server's `Add`.
+    let codec = WireFormat::Bincode;
+    let req = AppRequest::Add(Add { lhs: 5, rhs: 3 });
+    let header = Header::request(1, Mul::NAME, Mul::VERSION);
+    let frame = encode_frame(&codec, &header, &req).unwrap();
+
+    let err = server::handle_request::<AppRequest>(&codec, &frame)
+        .await
+        .unwrap_err();
+    let error = RpcError::from(err);
+    assert!(
+        matches!(
+            &error,
+            RpcError::Decode(failure) if failure.kind == DecodeErrorKind::UnknownVariant
+        ),
+        "{error:?}"
+    );
+}
@@ -0,0 +1,215 @@
+//! Connections switching over to compressed frames once they are going.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::compression::Compression;
+use protocol::frame::{FrameKind, Framing, Header, decode_header, encode_frame, encode_raw_frame};
+use protocol::hello::Hello;
+use protocol::{Request, RpcError};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
+
+use std::io;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Echo(Echo),
+    SlowEcho(SlowEcho),
+}
+
+#[request]
+fn Echo(data: Vec<u8>) -> Vec<u8> {
+    data
+}
+
+#[request]
+async fn SlowEcho(data: Vec<u8>) -> Vec<u8> {
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    data
+}
+
+/// Run-length encoding, as pairs of a count and the byte repeated, counting
+/// the frames it decompresses.
+#[derive(Debug, Clone)]
+struct Rle {
+    name: &'static str,
+    decompressed: Arc<AtomicUsize>,
+}
+
+impl Rle {
+    fn named(name: &'static str) -> Self {
+        Self {
+            name,
+            decompressed: Arc::default(),
+        }
+    }
+
+    fn decompressed(&self) -> usize {
+        self.decompressed.load(Ordering::Relaxed)
+    }
+}
+
+impl Compression for Rle {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn compress(&self, frame: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for run in frame.chunk_by(|a, b| a == b) {
+            for piece in run.chunks(usize::from(u8::MAX)) {
+                out.extend([piece.len() as u8, piece[0]]);
+            }
+        }
+        out
+    }
+
+    fn decompress(&self, frame: &[u8], limit: usize) -> io::Result<Vec<u8>> {
+        let invalid = |msg| io::Error::new(io::ErrorKind::InvalidData, msg);
+        if !frame.len().is_multiple_of(2) {
+            return Err(invalid("truncated run"));
+        }
+        let mut out = Vec::new();
+        for pair in frame.chunks(2) {
+            out.extend(std::iter::repeat_n(pair[1], usize::from(pair[0])));
+            if out.len() > limit {
+                return Err(invalid("frame over the limit"));
+            }
+        }
+        self.decompressed.fetch_add(1, Ordering::Relaxed);
+        Ok(out)
+    }
+}
+
+async fn serve(rle: &Rle) -> std::net::SocketAddr {
+    common::serve::<AppRequest>(Server::builder().compression(rle.clone())).await
+}
+
+fn echo(len: usize) -> AppRequest {
+    AppRequest::Echo(Echo { data: vec![7; len] })
+}
+
+#[tokio::test]
+async fn calls_go_on_compressed_after_upgrading() {
+    let on_server = Rle::named("rle");
+    let on_client = Rle::named("rle");
+    let addr = serve(&on_server).await;
+    let client = client::Client::builder()
+        .compression(on_client.clone())
+        .connect(addr)
+        .await
+        .unwrap();
+
+    let resp = client.call(echo(1024)).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Echo(d)) if d.len() == 1024),
+        "{resp:?}"
+    );
+    assert_eq!(on_server.decompressed(), 0);
+
+    // Sent before the switch, answered after it.
+    let slow = client.call(AppRequest::SlowEcho(SlowEcho {
+        data: vec![3; 4096],
+    }));
+    let upgrade = async {
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        client.upgrade("rle").await
+    };
+    let (slow, upgraded) = tokio::join!(slow, upgrade);
+    assert!(upgraded.is_ok(), "{upgraded:?}");
+    assert!(
+        matches!(&slow, Ok(AppResponse::SlowEcho(d)) if d == &[3; 4096]),
+        "{slow:?}"
+    );
+
+    let calls = (0..8).map(|_| client.call(echo(64 * 1024)));
+    for resp in futures::future::join_all(calls).await {
+        assert!(
+            matches!(&resp, Ok(AppResponse::Echo(d)) if d == &[7; 64 * 1024]),
+            "{resp:?}"
+        );
+    }
+    assert_eq!(on_server.decompressed(), 8);
+    assert_eq!(on_client.decompressed(), 9);
+}
+
+#[tokio::test]
+async fn unknown_compressions_leave_the_connection_as_it_was() {
+    let addr = serve(&Rle::named("rle")).await;
+    let other = Rle::named("other");
+    let client = client::Client::builder()
+        .compression(other.clone())
+        .connect(addr)
+        .await
+        .unwrap();
+
+    let upgraded = client.upgrade("other").await;
+    assert!(
+        matches!(
+            &upgraded,
+            Err(client::Error::Rpc(RpcError::ValidationFailed(_)))
+        ),
+        "{upgraded:?}"
+    );
+    let upgraded = client.upgrade("rle").await;
+    assert!(
+        matches!(&upgraded, Err(client::Error::UpgradeUnsupported(name)) if name == "rle"),
+        "{upgraded:?}"
+    );
+
+    let resp = client.call(echo(16)).await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Echo(d)) if d.len() == 16),
+        "{resp:?}"
+    );
+    assert_eq!(other.decompressed(), 0);
+}
+
+#[tokio::test]
+async fn malformed_upgrades_leave_the_connection_as_it_was() {
+    let addr = serve(&Rle::named("rle")).await;
+    let codec = WireFormat::FALLBACK;
+
+    let socket = TcpStream::connect(addr).await.unwrap();
+    let mut framed = Framed::new(socket, Framing::default().codec());
+    framed
+        .send(Bytes::from(Hello::default().encode()))
+        .await
+        .unwrap();
+    framed.next().await.unwrap().unwrap();
+
+    let upgrade = Header::new(1, FrameKind::Upgrade);
+    let frame = encode_raw_frame(&codec, &upgrade, &[0xff; 3]).unwrap();
+    framed.send(Bytes::from(frame)).await.unwrap();
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&codec, &frame).unwrap();
+    assert_eq!((header.id, header.kind), (1, FrameKind::Error));
+    let err: RpcError = codec.decode(body).unwrap();
+    assert!(matches!(err, RpcError::Decode(_)), "{err:?}");
+
+    // Still uncompressed both ways.
+    let req = echo(16);
+    let frame = encode_frame(&codec, &Header::request(2, Echo::NAME, 1), &req).unwrap();
+    framed.send(Bytes::from(frame)).await.unwrap();
+    let frame = framed.next().await.unwrap().unwrap();
+    let (header, body) = decode_header(&codec, &frame).unwrap();
+    assert_eq!((header.id, header.kind), (2, FrameKind::Response));
+    let resp: AppResponse = codec.decode(body).unwrap();
+    assert!(
+        matches!(&resp, AppResponse::Echo(d) if d.len() == 16),
+        "{resp:?}"
+    );
+}
@@ -0,0 +1,185 @@
+//! Handlers reading what the client streams after the request.
+
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, handle_connection};
+
+use protocol::frame::Framing;
+use protocol::{Context, RpcError, Upload};
+
+use macros::{request, rpc};
+
+use bytes::Bytes;
+use futures::{StreamExt, stream};
+use tokio_util::sync::CancellationToken;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Sum(Sum),
+    Drain(Drain),
+    Store(Store),
+}
+
+/// How many pieces and bytes came in under `label`.
+#[request]
+async fn Sum(label: String, upload: Upload) -> String {
+    let (pieces, bytes) = upload
+        .fold((0, 0), |(pieces, bytes), chunk| async move {
+            (pieces + 1, bytes + chunk.len())
+        })
+        .await;
+    format!("{label}: {pieces} pieces, {bytes} bytes")
+}
+
+/// Set once a `Drain` upload ended with the call given up on.
+static ABANDONED: AtomicBool = AtomicBool::new(false);
+
+#[request]
+async fn Drain(ctx: Context, upload: Upload) {
+    upload.for_each(|_| async {}).await;
+    if ctx.is_cancelled() {
+        ABANDONED.store(true, Ordering::SeqCst);
+    }
+}
+
+#[request]
+#[validate(has_name)]
+async fn Store(name: String, upload: Upload) -> String {
+    let bytes = upload
+        .fold(0, |bytes, chunk| async move { bytes + chunk.len() })
+        .await;
+    format!("{name}: {bytes} bytes")
+}
+
+async fn has_name(req: &Store) -> Result<(), String> {
+    if req.name.is_empty() {
+        return Err("unnamed".into());
+    }
+    Ok(())
+}
+
+/// A client connected over an in-memory transport to a server with frames
+/// of at most `max_frame_bytes`.
+async fn connect(max_frame_bytes: usize) -> client::Client {
+    let config = ConnectionConfig {
+        framing: Framing {
+            max_frame_bytes,
+            ..Framing::default()
+        },
+        ..ConnectionConfig::default()
+    };
+    let (server_end, client_end) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        handle_connection::<AppRequest>(server_end, &config, CancellationToken::new()).await
+    });
+    client::Client::builder()
+        .max_frame_bytes(max_frame_bytes)
+        .handshake(client_end)
+        .await
+        .unwrap()
+}
+
+/// A stream yielding a piece every few milliseconds, forever.
+fn endless() -> impl futures::Stream<Item = Bytes> + Send {
+    stream::unfold((), |()| async {
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        Some((Bytes::from_static(b"more"), ()))
+    })
+}
+
+#[tokio::test]
+async fn handlers_read_the_whole_upload() {
+    let client = connect(1024).await;
+
+    let pieces = ["a", "bb", "ccc"].map(|s| Bytes::from(s.repeat(10)));
+    let resp = client
+        .call_upload(
+            AppRequest::Sum(Sum {
+                label: "small".into(),
+            }),
+            stream::iter(pieces),
+        )
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Sum(s)) if s == "small: 3 pieces, 60 bytes"),
+        "{resp:?}"
+    );
+
+    // Pieces too large for a frame arrive split, but all there.
+    let resp = client
+        .call_upload(
+            AppRequest::Sum(Sum {
+                label: "large".into(),
+            }),
+            stream::iter([Bytes::from(vec![7; 10_000])]),
+        )
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Sum(s)) if s.starts_with("large: ") && s.ends_with(" pieces, 10000 bytes") && s != "large: 1 pieces, 10000 bytes"),
+        "{resp:?}"
+    );
+
+    // Requests sent without one read an empty upload.
+    let resp = client
+        .call(AppRequest::Sum(Sum {
+            label: "none".into(),
+        }))
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Sum(s)) if s == "none: 0 pieces, 0 bytes"),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn giving_up_on_the_call_ends_the_upload() {
+    let client = connect(1024).await;
+
+    let resp = tokio::time::timeout(
+        Duration::from_millis(50),
+        client.call_upload(AppRequest::Drain(Drain {}), endless()),
+    )
+    .await;
+    assert!(resp.is_err(), "{resp:?}");
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !ABANDONED.load(Ordering::SeqCst) {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+    })
+    .await
+    .expect("upload never ended");
+
+    // The connection is still good for other calls.
+    let resp = client
+        .call_upload(
+            AppRequest::Sum(Sum { label: "x".into() }),
+            stream::iter([Bytes::from_static(b"y")]),
+        )
+        .await;
+    assert!(matches!(resp, Ok(AppResponse::Sum(_))), "{resp:?}");
+}
+
+#[tokio::test]
+async fn an_early_answer_stops_the_upload() {
+    let client = connect(1024).await;
+
+    let resp = tokio::time::timeout(
+        Duration::from_secs(5),
+        client.call_upload(
+            AppRequest::Store(Store {
+                name: String::new(),
+            }),
+            endless(),
+        ),
+    )
+    .await
+    .expect("upload went on");
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::ValidationFailed(_)))),
+        "{resp:?}"
+    );
+}
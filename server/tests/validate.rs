@@ -0,0 +1,122 @@
+//! Requests checked by their validator before being handled.
+
+#![allow(non_snake_case)]
+
+mod common;
+
+use server::Server;
+
+use protocol::{Context, Request, RpcError};
+
+use macros::{request, rpc};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Sqrt(Sqrt),
+    Label(Label),
+}
+
+static HANDLED: AtomicUsize = AtomicUsize::new(0);
+
+#[request]
+#[validate(non_negative)]
+fn Sqrt(x: i64) -> i64 {
+    HANDLED.fetch_add(1, Ordering::SeqCst);
+    (x as f64).sqrt() as i64
+}
+
+async fn non_negative(req: &Sqrt) -> Result<(), String> {
+    if req.x < 0 {
+        return Err(format!("{} is negative", req.x));
+    }
+    Ok(())
+}
+
+/// Validated by hand rather than through `#[validate]`.
+#[derive(Debug, bincode::Encode, bincode::Decode, Serialize, Deserialize)]
+struct Label {
+    text: String,
+}
+
+#[async_trait]
+impl Request for Label {
+    type Resp = String;
+
+    const NAME: &'static str = "Label";
+
+    async fn validate(&self) -> Result<(), RpcError> {
+        if self.text.len() > 8 {
+            return Err(RpcError::ValidationFailed("label is too long".into()));
+        }
+        Ok(())
+    }
+
+    async fn handle(self, _ctx: Context) -> String {
+        self.text.to_uppercase()
+    }
+}
+
+#[tokio::test]
+async fn rejects_negative_inputs_without_handling_them() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client.call(AppRequest::Sqrt(Sqrt { x: 16 })).await;
+    assert!(matches!(resp, Ok(AppResponse::Sqrt(4))), "{resp:?}");
+    let handled = HANDLED.load(Ordering::SeqCst);
+
+    let resp = client.call(AppRequest::Sqrt(Sqrt { x: -4 })).await;
+    assert!(
+        matches!(&resp, Err(client::Error::Rpc(RpcError::ValidationFailed(msg))) if msg == "-4 is negative"),
+        "{resp:?}"
+    );
+    assert_eq!(HANDLED.load(Ordering::SeqCst), handled);
+}
+
+#[tokio::test]
+async fn hand_written_validators_run_too() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resp = client
+        .call(AppRequest::Label(Label { text: "ok".into() }))
+        .await;
+    assert!(
+        matches!(&resp, Ok(AppResponse::Label(s)) if s == "OK"),
+        "{resp:?}"
+    );
+
+    let resp = client
+        .call(AppRequest::Label(Label {
+            text: "far too long".into(),
+        }))
+        .await;
+    assert!(
+        matches!(resp, Err(client::Error::Rpc(RpcError::ValidationFailed(_)))),
+        "{resp:?}"
+    );
+}
+
+#[tokio::test]
+async fn invalid_batch_items_only_fail_their_own_slot() {
+    let addr = common::serve::<AppRequest>(Server::builder()).await;
+    let client = client::Client::connect(addr).await.unwrap();
+
+    let resps = client
+        .call_batch(vec![
+            AppRequest::Sqrt(Sqrt { x: 9 }),
+            AppRequest::Sqrt(Sqrt { x: -1 }),
+        ])
+        .await
+        .unwrap();
+    assert!(matches!(resps[0], Ok(AppResponse::Sqrt(3))), "{resps:?}");
+    assert!(
+        matches!(resps[1], Err(RpcError::ValidationFailed(_))),
+        "{resps:?}"
+    );
+}
@@ -0,0 +1,85 @@
+//! Serving connections over WebSocket, with frames sent as binary messages.
+
+#![cfg(feature = "ws")]
+#![allow(non_snake_case)]
+
+use server::{ConnectionConfig, Server};
+
+use protocol::Request;
+use protocol::codec::{WireCodec, WireFormat};
+use protocol::frame::{FrameKind, Header, decode_header, encode_frame};
+
+use macros::{request, rpc};
+
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tokio_util::sync::CancellationToken;
+
+#[rpc(response = "AppResponse")]
+enum AppRequest {
+    Add(Add),
+}
+
+#[request]
+fn Add(lhs: i32, rhs: i32) -> i32 {
+    lhs + rhs
+}
+
+/// Shakes hands over `websocket` and calls `Add` on it.
+async fn add<W>(mut websocket: W, lhs: i32, rhs: i32) -> AppResponse
+where
+    W: Stream<Item = Result<Message, Error>> + Sink<Message, Error = Error> + Unpin,
+{
+    let codec = WireFormat::FALLBACK;
+    websocket
+        .send(Message::binary(vec![codec.id()]))
+        .await
+        .unwrap();
+    let chosen = websocket.next().await.unwrap().unwrap().into_data();
+    assert_eq!(*chosen, [codec.id()]);
+
+    // Ignored, as not a frame.
+    websocket.send(Message::text("hello")).await.unwrap();
+
+    let req = AppRequest::Add(Add { lhs, rhs });
+    let frame = encode_frame(&codec, &Header::request(7, Add::NAME, 1), &req).unwrap();
+    websocket.send(Message::binary(frame)).await.unwrap();
+
+    let reply = websocket.next().await.unwrap().unwrap().into_data();
+    let (header, body) = decode_header(&codec, &reply).unwrap();
+    assert_eq!(header.id, 7);
+    assert_eq!(header.kind, FrameKind::Response);
+    codec.decode(body).unwrap()
+}
+
+#[tokio::test]
+async fn server_answers_over_websocket() {
+    let server = Server::builder()
+        .websocket(true)
+        .bind("127.0.0.1:0")
+        .await
+        .unwrap();
+    let addr = server.local_addr().unwrap();
+    tokio::spawn(server.run::<AppRequest>());
+
+    let (websocket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+        .await
+        .unwrap();
+    let resp = add(websocket, 2, 3).await;
+    assert!(matches!(resp, AppResponse::Add(5)), "{resp:?}");
+}
+
+#[tokio::test]
+async fn handles_a_websocket_connection() {
+    let (client, server) = tokio::io::duplex(4096);
+    tokio::spawn(async move {
+        let config = ConnectionConfig::default();
+        server::handle_websocket::<AppRequest>(server, &config, CancellationToken::new()).await
+    });
+
+    let (websocket, _) = tokio_tungstenite::client_async("ws://localhost/", client)
+        .await
+        .unwrap();
+    let resp = add(websocket, -1, 1).await;
+    assert!(matches!(resp, AppResponse::Add(0)), "{resp:?}");
+}